@@ -0,0 +1,108 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rgdrive::checksum::md5_file;
+use rgdrive::state::State;
+use rgdrive::{get_subpaths, TrackedFile, Tracker};
+use tempfile::TempDir;
+
+// A directory tree of width^depth files, each a few bytes long.
+fn make_tree(width: usize, depth: usize) -> TempDir {
+    fn fill(dir: &Path, width: usize, depth: usize) {
+        for i in 0..width {
+            if depth > 1 {
+                let sub = dir.join(format!("dir{}", i));
+                fs::create_dir(&sub).unwrap();
+                fill(&sub, width, depth - 1);
+            } else {
+                fs::write(dir.join(format!("file{}", i)), b"contents").unwrap();
+            }
+        }
+    }
+    let dir = tempfile::tempdir().unwrap();
+    fill(dir.path(), width, depth);
+    dir
+}
+
+fn tracked_files(n: usize) -> Vec<TrackedFile> {
+    (0..n)
+        .map(|i| {
+            TrackedFile::new(
+                PathBuf::from(format!("/home/user/documents/project/file{}.txt", i)),
+                format!("https://drive.google.com/open?id=file{}", i),
+            )
+        })
+        .collect()
+}
+
+fn directory_traversal(c: &mut Criterion) {
+    let tree = make_tree(10, 3);
+    let root = tree.path().to_path_buf();
+    c.bench_function("get_subpaths 1000 files", |b| {
+        b.iter(|| get_subpaths(&root))
+    });
+}
+
+fn checksum(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("blob");
+    fs::write(&path, vec![7u8; 8 * 1024 * 1024]).unwrap();
+    c.bench_function("md5_file 8MiB", |b| b.iter(|| md5_file(&path).unwrap()));
+}
+
+fn state_serialization(c: &mut Criterion) {
+    let files = tracked_files(10_000);
+    let buf = TrackedFile::encode_all(&files);
+    c.bench_function("serialize 10k tracked files", |b| {
+        b.iter(|| TrackedFile::encode_all(&files))
+    });
+    c.bench_function("deserialize 10k tracked files", |b| {
+        b.iter(|| TrackedFile::decode_all(&buf).unwrap())
+    });
+}
+
+fn event_dispatch(c: &mut Criterion) {
+    // Tracker keeps its state under $HOME, point it somewhere disposable.
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    let tree = make_tree(10, 3);
+    let mut tracker = Tracker::init().unwrap();
+    for (i, p) in get_subpaths(&tree.path().to_path_buf()).iter().enumerate() {
+        tracker.add_path(p, format!("url{}", i)).unwrap();
+    }
+    let last = tracker.tracked_files.last().unwrap().wd.clone().unwrap();
+    c.bench_function("find_by_wd 1000 tracked files", |b| {
+        b.iter(|| tracker.find_by_wd(&last).is_some())
+    });
+}
+
+fn startup(c: &mut Criterion) {
+    let home = tempfile::tempdir().unwrap();
+    env::set_var("HOME", home.path());
+    let tree = make_tree(10, 3);
+    let files: Vec<TrackedFile> = get_subpaths(&tree.path().to_path_buf())
+        .into_iter()
+        .enumerate()
+        .map(|(i, p)| TrackedFile::new(p, format!("url{}", i)))
+        .collect();
+    let path = home.path().join(".config/cameron-williams/state.db");
+    State::open(&path).unwrap().upsert_all(&files).unwrap();
+    c.bench_function("Tracker::load 1000 tracked files", |b| {
+        b.iter(|| Tracker::load().unwrap())
+    });
+    c.bench_function("Tracker::init 1000 tracked files", |b| {
+        b.iter(|| Tracker::init().unwrap())
+    });
+}
+
+criterion_group!(
+    benches,
+    directory_traversal,
+    checksum,
+    state_serialization,
+    event_dispatch,
+    startup
+);
+criterion_main!(benches);
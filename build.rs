@@ -0,0 +1,57 @@
+// Renders the clap definitions in src/cli.rs into a man page at $OUT_DIR/rgdrive.1.
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+include!("src/cli.rs");
+
+// Escape text for roff. Backslashes become \e and lines starting with a control character are guarded with \&.
+fn roff_escape(line: &str) -> String {
+    let line = line.replace('\\', "\\e").replace('-', "\\-");
+    if line.starts_with('.') || line.starts_with('\'') {
+        format!("\\&{}", line)
+    } else {
+        line
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/cli.rs");
+
+    let mut help: Vec<u8> = Vec::new();
+    build_app().write_long_help(&mut help).unwrap();
+    let help = String::from_utf8(help).unwrap();
+
+    let mut sections = vec![(String::from("DESCRIPTION"), help.clone())];
+    // clap 2 doesn't expose subcommands, so find them in the SUBCOMMANDS section of the help and ask each for its own.
+    let subcommands: Vec<String> = help
+        .lines()
+        .skip_while(|l| !l.starts_with("SUBCOMMANDS:"))
+        .skip(1)
+        .take_while(|l| l.starts_with(' '))
+        .filter_map(|l| l.split_whitespace().next())
+        .filter(|s| *s != "help")
+        .map(String::from)
+        .collect();
+    for sub in subcommands {
+        if let Err(e) = build_app().get_matches_from_safe(vec!["rgdrive", &sub, "--help"]) {
+            sections.push((format!("rgdrive {}", sub).to_uppercase(), e.message));
+        }
+    }
+
+    let mut man = format!(
+        ".TH RGDRIVE 1 \"\" \"rgdrive {}\" \"User Commands\"\n.SH NAME\nrgdrive \\- sync local files with Google Drive\n",
+        env::var("CARGO_PKG_VERSION").unwrap()
+    );
+    for (title, text) in sections {
+        man.push_str(&format!(".SH \"{}\"\n.nf\n", title));
+        for line in text.lines() {
+            man.push_str(&roff_escape(line.trim_end()));
+            man.push('\n');
+        }
+        man.push_str(".fi\n");
+    }
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("rgdrive.1");
+    fs::write(out, man).unwrap();
+}
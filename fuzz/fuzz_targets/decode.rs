@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use rgdrive::{decode, DCommand, DResult, MAX_FRAME_BYTES};
+
+// Decoding arbitrary bytes off the socket must never panic.
+fuzz_target!(|data: &[u8]| {
+    let _ = DCommand::from_stream(data, MAX_FRAME_BYTES);
+    let _ = decode::<DResult>(data, MAX_FRAME_BYTES);
+});
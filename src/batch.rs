@@ -0,0 +1,112 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checksum::md5_file;
+use crate::{pending_dir, write_atomic, TrackedFile, Tracker};
+
+// Write-ahead record of a multi-file push. Each upload is recorded as it finishes, and the record is removed once every
+// entry has been committed to the tracker in one go. A record left behind (daemon died, commit failed) is replayed by
+// recover() at startup, so files are never left uploaded but untracked.
+pub struct Batch {
+    path: PathBuf,
+    entries: Vec<TrackedFile>,
+}
+
+impl Batch {
+    pub fn begin() -> Batch {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        Batch {
+            path: pending_dir().join(format!("{}-{}", process::id(), nanos)),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Note that path was uploaded as url, with md5 the md5 of what was sent (the file is hashed now if None). Persisted
+    // before returning.
+    pub fn record<P: Into<PathBuf>, U: Into<String>>(
+        &mut self,
+        p: P,
+        u: U,
+        md5: Option<String>,
+    ) -> Result<(), Error> {
+        let p = p.into();
+        // Just uploaded, so this is its md5 as of the last sync.
+        let md5 = md5.or_else(|| md5_file(&p).ok());
+        self.entries.push(TrackedFile {
+            md5,
+            ..TrackedFile::new(p, u)
+        });
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&self.path, &TrackedFile::encode_all(&self.entries))
+    }
+
+    // Track every recorded file, or none of them. On failure the record stays on disk to be retried at next startup.
+    pub fn commit(self, tracker: &mut Tracker) -> Result<usize, Error> {
+        let n = self.entries.len();
+        tracker.add_paths(self.entries)?;
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(n)
+    }
+}
+
+fn load(p: &Path) -> Result<Vec<TrackedFile>, Error> {
+    let mut buf = Vec::new();
+    File::open(p)?.read_to_end(&mut buf)?;
+    TrackedFile::decode_all(&buf).map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+// Commit any batches left over from a previous run. Returns the number of files that were recovered.
+pub fn recover(tracker: &mut Tracker) -> usize {
+    let dir = match fs::read_dir(pending_dir()) {
+        Ok(d) => d,
+        Err(_) => return 0,
+    };
+    let mut recovered = 0;
+    for entry in dir.filter_map(|e| e.ok()) {
+        let p = entry.path();
+        let entries = match load(&p) {
+            Ok(e) => e,
+            Err(e) => {
+                log::error!(
+                    "Unreadable pending batch {:?}, leaving it in place: {:?}",
+                    p,
+                    e
+                );
+                continue;
+            }
+        };
+        // Files removed since the batch was written can't be watched, leave them out rather than failing the batch.
+        let entries: Vec<TrackedFile> = entries.into_iter().filter(|tf| tf.path.exists()).collect();
+        let n = entries.len();
+        match tracker.add_paths(entries) {
+            Ok(_) => {
+                log::info!("Recovered {} files from pending batch {:?}", n, p);
+                recovered += n;
+                if let Err(e) = fs::remove_file(&p) {
+                    log::error!("Failed to remove pending batch {:?}: {:?}", p, e);
+                }
+            }
+            Err(e) => log::error!("Failed to recover pending batch {:?}: {:?}", p, e),
+        }
+    }
+    recovered
+}
@@ -0,0 +1,54 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+use crate::{canonical_path, TrackedFile};
+
+// [cache]: synced files beneath the cache paths stand in for their Drive copies, like placeholders (see placeholder)
+// that were hydrated. Once they take up more than max_bytes, the least recently used are turned back into placeholders
+// until the rest fit, and `rgdrive hydrate` downloads them again when they're needed. So a Drive bigger than the disk
+// can be mirrored, a part of it at a time.
+
+// A synced file in the cache.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cached {
+    pub path: PathBuf,
+    pub drive_url: String,
+    // md5 of its last sync, what Drive has.
+    pub md5: Option<String>,
+    pub size: u64,
+    // Unix timestamp (seconds) it was last read or written. Reads only count as well as the filesystem keeps atime
+    // (relatime, the usual default, still updates it once a day).
+    pub used: i64,
+}
+
+// Synced files beneath paths, least recently used first. Only files that could be downloaded again count: exports,
+// absent files, hard links and files never synced don't, and neither do empty files (placeholders among them).
+pub fn cached(tracked: &[TrackedFile], paths: &[PathBuf]) -> Vec<Cached> {
+    let roots: Vec<PathBuf> = paths.iter().map(|p| canonical_path(p)).collect();
+    let mut files: Vec<Cached> = tracked
+        .iter()
+        .filter(|tf| !tf.is_export() && !tf.absent && tf.md5.is_some())
+        .filter(|tf| roots.iter().any(|r| tf.path.starts_with(r)))
+        .filter_map(|tf| {
+            let m = fs::metadata(&tf.path).ok()?;
+            if !m.is_file() || m.nlink() > 1 || m.len() == 0 {
+                return None;
+            }
+            Some(Cached {
+                path: tf.path.clone(),
+                drive_url: tf.drive_url.clone(),
+                md5: tf.md5.clone(),
+                size: m.len(),
+                used: m.atime().max(m.mtime()),
+            })
+        })
+        .collect();
+    files.sort_by_key(|c| c.used);
+    files
+}
+
+// Bytes files take up.
+pub fn total(files: &[Cached]) -> u64 {
+    files.iter().map(|c| c.size).sum()
+}
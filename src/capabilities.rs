@@ -0,0 +1,138 @@
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use crate::remote::Remote;
+
+// Optional remote features. Which ones work depends on the Drive client, the account (consumer or Workspace) and the
+// scope rgdrive was granted, so they're probed at startup instead of failing with an api error on first use.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capability {
+    Metadata,
+    Activity,
+    Export,
+    Starred,
+    Folders,
+    Share,
+    Rename,
+    Quota,
+    Drives,
+    Shortcuts,
+    Delete,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 11] = [
+        Capability::Metadata,
+        Capability::Activity,
+        Capability::Export,
+        Capability::Starred,
+        Capability::Folders,
+        Capability::Share,
+        Capability::Rename,
+        Capability::Quota,
+        Capability::Drives,
+        Capability::Shortcuts,
+        Capability::Delete,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Metadata => "metadata",
+            Capability::Activity => "activity",
+            Capability::Export => "export",
+            Capability::Starred => "starred",
+            Capability::Folders => "folders",
+            Capability::Share => "share",
+            Capability::Rename => "rename",
+            Capability::Quota => "quota",
+            Capability::Drives => "drives",
+            Capability::Shortcuts => "shortcuts",
+            Capability::Delete => "delete",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        Capability::ALL.iter().copied().find(|c| c.name() == name)
+    }
+
+    // What stops working without it.
+    pub fn feature(self) -> &'static str {
+        match self {
+            Capability::Metadata => "polling for remote changes, --overwrite-mode remote-newer",
+            Capability::Activity => "--activity",
+            Capability::Export => "--export-format",
+            Capability::Starred => "--pull-starred",
+            Capability::Folders => "--dest, --pull-dir, drive: paths and computer folders",
+            Capability::Share => "--share",
+            Capability::Rename => "--rename-remote",
+            Capability::Quota => "storage checks in push plans",
+            Capability::Drives => "--drives",
+            Capability::Shortcuts => "--shortcut",
+            Capability::Delete => {
+                "trashing or deleting the Drive copies of files deleted locally ([deletes])"
+            }
+        }
+    }
+}
+
+// What the daemon's remote was found to support. Everything is assumed available until the first probe.
+pub struct Capabilities {
+    probed: Mutex<Option<Vec<Capability>>>,
+}
+
+lazy_static! {
+    pub static ref CAPABILITIES: Capabilities = Capabilities {
+        probed: Mutex::new(None),
+    };
+}
+
+impl Capabilities {
+    // Ask remote what it supports, at startup and whenever the remote is replaced.
+    pub fn probe(&self, remote: &mut dyn Remote) -> Vec<Capability> {
+        let caps = remote.capabilities();
+        *self.probed.lock().unwrap() = Some(caps.clone());
+        caps
+    }
+
+    pub fn has(&self, cap: Capability) -> bool {
+        match self.probed.lock().unwrap().as_ref() {
+            Some(caps) => caps.contains(&cap),
+            None => true,
+        }
+    }
+
+    // Fail fast with an explanation, instead of an opaque api error halfway through.
+    pub fn require(&self, cap: Capability) -> Result<(), String> {
+        if self.has(cap) {
+            return Ok(());
+        }
+        Err(format!(
+            "{} is not available with the current account/scope (no {} support).",
+            cap.feature(),
+            cap.name()
+        ))
+    }
+
+    // Available and unavailable features, for --status.
+    pub fn report(&self) -> String {
+        let (available, unavailable): (Vec<Capability>, Vec<Capability>) =
+            Capability::ALL.iter().partition(|c| self.has(**c));
+        let mut lines = vec![format!("available: {}", names(&available))];
+        for c in unavailable {
+            lines.push(format!("unavailable: {} ({})", c.name(), c.feature()));
+        }
+        lines.join("\n")
+    }
+}
+
+// Comma separated names, "none" if there aren't any.
+pub fn names(caps: &[Capability]) -> String {
+    if caps.is_empty() {
+        return String::from("none");
+    }
+    caps.iter()
+        .map(|c| c.name())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
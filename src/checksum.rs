@@ -0,0 +1,76 @@
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+// Hex md5 of the file at p, the same digest Drive reports as md5Checksum. Read in chunks so large files aren't loaded at once.
+pub fn md5_file<P: AsRef<Path>>(p: P) -> Result<String, Error> {
+    let mut f = File::open(p)?;
+    copy_hashed(&mut f, &mut io::sink()).map(|(_, md5)| md5)
+}
+
+// Copy reader to writer in chunks, hashing each one on the way through. Returns the bytes copied and their hex md5, so
+// a transfer is verified without reading the file a second time.
+pub fn copy_hashed<R: Read + ?Sized, W: Write + ?Sized>(
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<(u64, String), Error> {
+    let mut ctx = md5::Context::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        ctx.consume(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    Ok((copied, format!("{:x}", ctx.compute())))
+}
+
+// Hashes what's read through it, for transfers that hand the reader to something else to copy from (e.g. an upload's
+// request body).
+pub struct Hashing<R> {
+    inner: R,
+    ctx: md5::Context,
+}
+
+impl<R: Read> Hashing<R> {
+    pub fn new(inner: R) -> Hashing<R> {
+        Hashing {
+            inner,
+            ctx: md5::Context::new(),
+        }
+    }
+
+    // Hex md5 of everything read so far.
+    pub fn md5(self) -> String {
+        format!("{:x}", self.ctx.compute())
+    }
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.ctx.consume(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// Write reader to dest by way of a hidden file beside it, so a failed download never leaves half a file behind. Returns
+// the md5 of what was written.
+pub fn save_hashed<R: Read + ?Sized>(reader: &mut R, dest: &Path) -> Result<String, Error> {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let part = dest.with_file_name(format!(".{}.part", name));
+    let written = File::create(&part)
+        .and_then(|mut f| copy_hashed(reader, &mut f))
+        .and_then(|(_, md5)| fs::rename(&part, dest).map(|_| md5));
+    if written.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+    written
+}
@@ -0,0 +1,762 @@
+// The rgdrive command line definition. Also included by build.rs, which renders it into the man page.
+use clap::{App, Arg, SubCommand};
+
+const EXAMPLES: &str = "EXAMPLES:
+    Start the daemon:
+        rgdrive --start
+
+    Push a file to Drive and keep it synced:
+        rgdrive --push ~/notes.txt
+
+    Pull a Drive file into a directory and keep it synced:
+        rgdrive --pull https://drive.google.com/open?id=<id> ~/Downloads
+
+    Export last month's operations as json:
+        rgdrive --audit-export 2020-01-01 2020-01-31 --audit-format json";
+
+pub fn build_app() -> App<'static, 'static> {
+    App::new("rgdrive")
+        .version("1.0")
+        .author("Cameron W. <cam@camwilliams.ca>")
+        .about("Sync local files with Google Drive. Commands are carried out by the rgdrived background daemon.")
+        .after_help(EXAMPLES)
+        .arg(
+            Arg::with_name("start")
+                .long("start")
+                .help("Start the background daemon.")
+                .long_help(
+                    "Start the background daemon. $GOOGLE_CLIENT_ID and $GOOGLE_CLIENT_SECRET must be set. \
+                    Waits until the daemon reports ready. If it gives up during startup its reason is printed, and the \
+                    exit code says which: 3 the socket is taken, 4 the config is invalid, 5 the credentials are missing \
+                    or malformed, 6 Drive refused them. The daemon's log is written to /tmp/rgdrived.err and can be \
+                    viewed with --log. If it panics, a crash report goes to /tmp/rgdrived.crash and the next --status \
+                    points to it.",
+                )
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .value_name("name")
+                .global(true)
+                .help("Talk to (or --start) the daemon of this profile instead of the default one.")
+                .long_help(
+                    "Run against a profile, e.g. to sync a work and a personal account side by side. Each profile has its \
+                    own daemon, socket (/tmp/rgdrive-<name>.sock), config, tracked files and journal (under \
+                    ~/.config/cameron-williams/profiles/<name>). Also read from $RGDRIVE_PROFILE. `rgdrive daemons` lists \
+                    the running ones.",
+                ),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .value_name("text|json")
+                .possible_values(&["text", "json"])
+                .global(true)
+                .help("Print results, errors, --list and --status as json instead of colored text.")
+                .long_help(
+                    "Print results and errors as json objects, one per line: {\"ok\": true, \"message\": ...} on stdout, \
+                    or {\"ok\": false, \"error\": ...} on stderr. Pushes and pulls add the path and drive_url. --list, \
+                    --status, --drives and --share print a single object with their fields instead.",
+                ),
+        )
+        .arg(
+            Arg::with_name("stop")
+                .long("stop")
+                .help("Stop the background daemon.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("status")
+                .long("status")
+                .help("Check the current status of the background daemon.")
+        )
+        .arg(
+            Arg::with_name("auth")
+                .long("auth")
+                .help("Set up Drive access: save the OAuth client credentials and sign in.")
+                .long_help(
+                    "Ask for the OAuth client id and secret (unless $GOOGLE_CLIENT_ID and $GOOGLE_CLIENT_SECRET are set), \
+                    and sign in to Drive in the browser (the sign in page's url is printed too, for a browser on another \
+                    machine). The credentials and the sign in are saved to ~/.config/cameron-williams/credentials, \
+                    readable only by you. From then on the daemon starts without the environment variables. A running \
+                    daemon picks the new sign-in up like with --login.",
+                ),
+        )
+        .arg(
+            Arg::with_name("login")
+                .long("login")
+                .help("Sign in to Drive again, e.g. after --status says re-authentication is required.")
+                .long_help(
+                    "Sign in to Drive again in the browser, with the OAuth client saved by --auth (or $GOOGLE_CLIENT_ID \
+                    and $GOOGLE_CLIENT_SECRET). When Drive refuses the daemon's token (revoked, or expired by a password \
+                    change) syncing is paused and --status, --health and the [health] alerts say re-authentication is \
+                    required. Once signed in, a running daemon picks the new token up, resumes syncing and retries \
+                    everything queued meanwhile.",
+                ),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .takes_value(false)
+                .help("Show daemon counters (inotify events read, coalesced, dropped).")
+        )
+        .arg(
+            Arg::with_name("pins")
+                .long("pins")
+                .help("List the pinned files (see rgdrive pin), when each was last refreshed and why it last failed."),
+        )
+        .arg(
+            Arg::with_name("queue")
+                .long("queue")
+                .help("List failed operations waiting to be retried (id, op, path, age, attempts, last error).")
+                .long_help(
+                    "List the operations that failed against Drive and are waiting to be retried, with their id, op, path, \
+                    age, number of attempts and last error. Retries back off from 30 seconds up to an hour between attempts.",
+                ),
+        )
+        .arg(
+            Arg::with_name("queue-drop")
+                .long("queue-drop")
+                .value_name("id")
+                .help("Drop a queued operation, it won't be retried."),
+        )
+        .arg(
+            Arg::with_name("queue-retry")
+                .long("queue-retry")
+                .value_name("id")
+                .help("Retry a queued operation now."),
+        )
+        .arg(
+            Arg::with_name("health")
+                .long("health")
+                .takes_value(false)
+                .help("Check daemon health. Exits 0 if healthy, 1 if unhealthy, 2 if the daemon isn't running.")
+                .long_help(
+                    "Check the daemon against the [health] thresholds in the config (failure rate, queue age). \
+                    Exits 0 if healthy, 1 if unhealthy and 2 if the daemon isn't running, so it can be used from monitoring scripts.",
+                ),
+        )
+        .arg(
+            Arg::with_name("pull")
+                .long("pull")
+                .value_names(&["gdrive_url", "/path/to/file"])
+                .number_of_values(2)
+                .help("Pull specified drive_url to given path, and sync it's contents.")
+                .long_help(
+                    "Download the Drive file at gdrive_url to the given path, then keep the local copy synced back up to Drive whenever it changes. \
+                    If the path is a directory the Drive file name is kept. Refuses to replace an existing file unless --overwrite is given.",
+                )
+        )
+        .arg(
+            Arg::with_name("export-format")
+                .long("export-format")
+                .takes_value(true)
+                .value_name("format")
+                .requires("pull")
+                .possible_values(&[
+                    "csv", "tsv", "xlsx", "ods", "docx", "odt", "txt", "md", "html", "rtf", "epub", "pptx", "odp", "pdf",
+                ])
+                .help("With --pull, export a Google Doc, Sheet or Slides file in this format instead of downloading it.")
+                .long_help(
+                    "With --pull, export a Google Doc, Sheet or Slides file in the given format. Sheets export as \
+                    csv/tsv/xlsx/ods/pdf, Docs as docx/odt/pdf/txt/md/html/rtf/epub and Slides as pptx/odp/pdf/txt. The export \
+                    is one way: local edits aren't uploaded, but the file is re-exported whenever it changes on Drive \
+                    (requires [poll] interval_secs).",
+                ),
+        )
+        .arg(
+            Arg::with_name("sheet")
+                .long("sheet")
+                .takes_value(true)
+                .value_name("name")
+                .requires("export-format")
+                .help("Sheet (tab) to export, the first one by default. csv/tsv only."),
+        )
+        .arg(
+            Arg::with_name("range")
+                .long("range")
+                .takes_value(true)
+                .value_name("A1:D20")
+                .requires("export-format")
+                .help("Cell range to export, in A1 notation. csv/tsv only."),
+        )
+        .arg(
+            Arg::with_name("pull-starred")
+                .long("pull-starred")
+                .takes_value(true)
+                .value_name("/path/to/dir")
+                .help("Pull every starred Drive file into the given directory, and sync them.")
+                .long_help(
+                    "Pull every file starred in Drive into the given directory and keep them synced, so starring in the Drive UI decides what this machine keeps offline. \
+                    Files that are already synced or already present are skipped, so it's safe to rerun.",
+                ),
+        )
+        .arg(
+            Arg::with_name("pull-dir")
+                .long("pull-dir")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["folder_url", "/path/to/dir"])
+                .help("Pull a Drive folder and everything in it into the given directory, and sync the files.")
+                .long_help(
+                    "Recreate a Drive folder, subfolders included, in the given directory: every file is pulled and kept \
+                    synced. Files that are already synced or already present are skipped, so it's safe to rerun. Docs \
+                    editors files are skipped, pull them with --export-format.",
+                ),
+        )
+        .arg(
+            Arg::with_name("placeholders")
+                .long("placeholders")
+                .requires("pull-dir")
+                .help("With --pull-dir, create empty placeholders instead of downloading, see `rgdrive hydrate`.")
+                .long_help(
+                    "With --pull-dir, create an empty placeholder for every file instead of downloading it. Placeholders \
+                    keep the Drive id (and size) in extended attributes, user.rgdrive.id and user.rgdrive.size, and \
+                    take no space until `rgdrive hydrate` downloads them. They aren't synced until then.",
+                ),
+        )
+        .arg(
+            Arg::with_name("rebuild-state")
+                .long("rebuild-state")
+                .takes_value(true)
+                .value_name("/path/to/dir")
+                .help("Sync the files stamped as synced in the given directory again, after rgdrive's state was lost.")
+                .long_help(
+                    "Synced files are stamped with their Drive id and the md5 they were last synced at, in user.rgdrive.* \
+                    extended attributes (unless [xattrs] enabled = false). If the tracked files are lost, this syncs \
+                    every stamped file in the given directory again, and reconciles what changed meanwhile on either side.",
+                ),
+        )
+        .arg(
+            Arg::with_name("push")
+                .long("push")
+                .takes_value(true)
+                .value_name("/path/to/file")
+                .help("Push given file to drive, and sync it's contents.")
+                .long_help(
+                    "Upload the given file to Drive and keep the Drive copy updated whenever the local file changes. \
+                    If the path is a directory every file beneath it is pushed and synced individually.",
+                )
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .takes_value(false)
+                .requires("push")
+                .help("With --push of a directory, only show the push plan (files, size, quota) and upload nothing."),
+        )
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .takes_value(false)
+                .help("With --push of a directory, go ahead even if the push plan has warnings. With --migrate-import, replace this profile's config and tracked files."),
+        )
+        .arg(
+            Arg::with_name("no-default-excludes")
+                .long("no-default-excludes")
+                .takes_value(false)
+                .requires("push")
+                .help("With --push of a directory, also push build output, VCS metadata and editor temp files.")
+                .long_help(
+                    "Directory pushes skip .git/, target/, node_modules/, __pycache__/, *.o, *.pyc, editor swap and \
+                    backup files (*.swp, *~, .#*) and the like by default. Push them too.",
+                ),
+        )
+        .arg(
+            Arg::with_name("recursive")
+                .long("recursive")
+                .takes_value(false)
+                .requires("push")
+                .conflicts_with_all(&["as", "once"])
+                .help("With --push of a directory, also push files created in it (or its subdirectories) later on.")
+                .long_help(
+                    "With --push of a directory, keep watching it: files created or moved into it, or into any \
+                    subdirectory, are pushed into the same Drive folder and synced like the rest. --unsync the \
+                    directory to stop, the files already pushed stay synced.",
+                ),
+        )
+        .arg(
+            Arg::with_name("allow-own-state")
+                .long("allow-own-state")
+                .takes_value(false)
+                .help("Sync rgdrive's own config, state, log or socket anyway.")
+                .long_help(
+                    "--push, --pull, --pull-starred, --pull-dir and --sync refuse rgdrive's own files: the config directory, saved \
+                    versions, the daemon's log and socket and its temp files, and directories holding them. Syncing \
+                    them makes the daemon upload its own state every time it changes. Do it anyway.",
+                ),
+        )
+        .arg(
+            Arg::with_name("dest")
+                .long("dest")
+                .takes_value(true)
+                .value_name("gdrive_folder_url")
+                .requires("push")
+                .help("With --push, upload into this Drive folder instead of the Drive root."),
+        )
+        .arg(
+            Arg::with_name("as")
+                .long("as")
+                .takes_value(true)
+                .value_name("remote_name")
+                .requires("push")
+                .help("With --push of a file, name the Drive copy remote_name instead of the local file name."),
+        )
+        .arg(
+            Arg::with_name("wait")
+                .long("wait")
+                .takes_value(false)
+                .requires("push")
+                .conflicts_with_all(&["recursive", "once"])
+                .help("With --push, retry Drive's temporary failures and exit with a failure status if the push still fails.")
+                .long_help(
+                    "With --push, answer only once the upload has definitively succeeded or failed: rate limits and \
+                    Drive's temporary errors are retried a few times, waiting longer each time, first. rgdrive exits \
+                    with status 1 if the push failed.",
+                ),
+        )
+        .arg(
+            Arg::with_name("porcelain")
+                .long("porcelain")
+                .takes_value(false)
+                .requires("push")
+                .help("With --push of a file, print only the Drive url on success, for scripts.")
+                .long_help(
+                    "With --push of a file, print nothing but the Drive url of the file on stdout, and errors alone on \
+                    stderr, exiting with status 1 if the push failed. E.g. URL=$(rgdrive --push report.pdf --wait --porcelain)",
+                ),
+        )
+        .arg(Arg::with_name("msg").long("msg").takes_value(true))
+        .arg(
+            Arg::with_name("overwrite")
+                .long("overwrite")
+                .takes_value(false)
+                .help("Optional flag to overwrite file contents when pulling a file if it already exists.")
+        )
+        .arg(
+            Arg::with_name("overwrite-mode")
+                .long("overwrite-mode")
+                .takes_value(true)
+                .possible_values(&["never", "remote-newer", "local-unmodified", "always"])
+                .conflicts_with("overwrite")
+                .requires("pull")
+                .help("With --pull, when to replace an existing destination file (default never).")
+                .long_help(
+                    "With --pull, when to replace a file already at the destination: never (the default), remote-newer \
+                    (only if the Drive copy was modified after it), local-unmodified (only if it hasn't changed since it \
+                    was last synced, so tracked files only) or always (same as --overwrite). The replaced file is kept \
+                    in the trash and [versions] either way.",
+                ),
+        )
+        .arg(
+            Arg::with_name("once")
+                .long("once")
+                .takes_value(false)
+                .conflicts_with_all(&["export-format", "relink", "dry-run"])
+                .help("With --push or --pull, transfer right away from this process, without the daemon. Nothing is synced afterwards.")
+                .long_help(
+                    "Do a --push or --pull directly, without a running daemon, using the config file and the sign in \
+                    saved by --auth (or GOOGLE_CLIENT_ID, GOOGLE_CLIENT_SECRET and GOOGLE_REFRESH_TOKEN from the \
+                    environment). The file isn't tracked or watched afterwards. \
+                    Handy in containers, CI jobs and scripts.",
+                ),
+        )
+        .arg(
+            Arg::with_name("anonymous")
+                .long("anonymous")
+                .takes_value(false)
+                .requires("pull")
+                .conflicts_with_all(&["once", "relink", "dry-run"])
+                .help("With --pull, download a file shared with anyone with the link, without signing in. It isn't synced.")
+                .long_help(
+                    "Download a file shared with anyone with the link through Drive's public download links, without \
+                    credentials or a running daemon, e.g. on a fresh machine. Docs, Sheets and Slides need --export-format \
+                    (without --sheet or --range). The file isn't synced afterwards, there's no account to sync it with.",
+                ),
+        )
+        .arg(
+            Arg::with_name("relink")
+                .long("relink")
+                .takes_value(false)
+                .requires("pull")
+                .help("With --pull, sync the destination with the pulled file even if it's already synced with another.")
+        )
+        .arg(
+            Arg::with_name("log")
+                .long("log")
+                .takes_value(false) // maybe change to take a value to limit log lines? --log 5 -> last 5 log lines
+                .help("Optional flag to display daemon log.")
+        )
+        .arg(
+            Arg::with_name("activity")
+                .long("activity")
+                .takes_value(true)
+                .value_name("path or gdrive_url")
+                .help("Show who changed a synced file on Drive, and when.")
+                .long_help(
+                    "Show the Drive Activity history (who did what, and when) for a synced file, given either its local path or its Drive url. \
+                    Useful for tracking down surprise remote modifications.",
+                ),
+        )
+        .arg(
+            Arg::with_name("ls")
+                .long("ls")
+                .min_values(0)
+                .max_values(1)
+                .value_name("gdrive_folder_url")
+                .help("List what's in a Drive folder (the Drive root by default): modified time, size, name and id.")
+                .long_help(
+                    "List what's in a Drive folder (the Drive root by default), folders first: each file's modified time, size, \
+                    name and id, to find what to --pull without opening Drive. Shortcuts are shown with what they point to.",
+                ),
+        )
+        .arg(
+            Arg::with_name("drives")
+                .long("drives")
+                .help("List the shared drives the account can access (id, name, role).")
+                .long_help(
+                    "List the shared drives the account can access, one per line as tab separated id, name and role \
+                    (organizer, fileOrganizer, writer, commenter or reader). Pass \
+                    https://drive.google.com/drive/folders/<id> to --push --dest to push into a shared drive's root.",
+                ),
+        )
+        .arg(
+            Arg::with_name("share")
+                .long("share")
+                .takes_value(true)
+                .value_name("path or gdrive_url")
+                .help("Share a synced file by link and print the link.")
+                .long_help(
+                    "Give anyone with the link read access to a synced file, given either its local path or its Drive url, \
+                    and print the link.",
+                ),
+        )
+        .arg(
+            Arg::with_name("qr")
+                .long("qr")
+                .takes_value(false)
+                .requires("share")
+                .help("With --share, also print the link as a QR code, e.g. to open it on a phone."),
+        )
+        .arg(
+            Arg::with_name("shortcut")
+                .long("shortcut")
+                .min_values(1)
+                .max_values(2)
+                .value_names(&["path or gdrive_url", "gdrive_folder_url"])
+                .help("Create a Drive shortcut to a synced file, in a Drive folder or the Drive root.")
+                .long_help(
+                    "Create a Drive shortcut to a synced file, given either its local path or its Drive url, in the given \
+                    Drive folder (the Drive root by default), and print the shortcut's url.",
+                ),
+        )
+        .arg(
+            Arg::with_name("list")
+                .long("list")
+                .takes_value(false)
+                .help("List all currently synced paths, with each one's sync state, last sync and last error.")
+        )
+        .arg(
+            Arg::with_name("versions")
+                .long("versions")
+                .takes_value(true)
+                .value_name("/path/to/file")
+                .help("List the local versions kept of a file, oldest first.")
+                .long_help(
+                    "List the versions kept of a local file, oldest first. A version is saved each time a pull overwrites \
+                    the file (see [versions] keep in the config).",
+                ),
+        )
+        .arg(
+            Arg::with_name("rollback")
+                .long("rollback")
+                .value_names(&["/path/to/file", "version"])
+                .number_of_values(2)
+                .help("Restore a file to one of its --versions.")
+                .long_help(
+                    "Restore a file to one of the versions listed by --versions. The current contents are saved as a \
+                    version first, and a synced file is uploaded again as usual.",
+                ),
+        )
+        .arg(
+            Arg::with_name("sync")
+                .long("sync")
+                .value_names(&["/path/to/file", "drive_url"])
+                .number_of_values(2)
+                .help("Manually add a sync between given path and drive url.")
+                .long_help(
+                    "Manually link a local file to an existing Drive file. Future local changes are uploaded to drive_url.",
+                )
+        )
+        .arg(
+            Arg::with_name("events")
+                .long("events")
+                .value_name("event,...")
+                .requires("sync")
+                .help("With --sync, the file events to react to instead of the configured ones.")
+                .long_help(
+                    "With --sync, watch the file for these events instead of the ones in [events] (by default \
+                    modify,delete_self,move_self). Comma separated, any of modify, close_write, attrib (uploads \
+                    permission and timestamp changes too), delete_self and move_self.",
+                ),
+        )
+        .arg(
+            Arg::with_name("rename-remote")
+                .long("rename-remote")
+                .value_names(&["/path/to/file", "remote_name"])
+                .number_of_values(2)
+                .help("Rename the Drive copy of a synced file, keeping the local name.")
+        )
+        .arg(
+            Arg::with_name("unsync")
+                .long("unsync")
+                .value_name("/path/to/file")
+                .takes_value(true)
+                .help("Manually remove any syncs for given path.")
+        )
+        .arg(
+            Arg::with_name("audit-export")
+                .long("audit-export")
+                .value_names(&["from", "to"])
+                .number_of_values(2)
+                .help("Dump the operation journal between two dates (YYYY-MM-DD, inclusive).")
+                .long_help(
+                    "Dump every operation the daemon recorded in its journal (time, user, operation, file, direction, bytes and result) \
+                    between two dates, as csv or json depending on --audit-format. Works without a running daemon.",
+                )
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .help("Summarize transfers from the journal: bytes per day, top files, errors and sync latency.")
+                .long_help(
+                    "Summarize what the daemon transferred over the period given by --since (default 7d): bytes up and \
+                    down per day, the files that moved the most, failed operations by kind (auth, rate limit, quota, \
+                    permission, not found, server, network, other) and the average time from a local change being seen \
+                    to its upload completing. Works without a running daemon.",
+                )
+        )
+        .arg(
+            Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .value_name("period")
+                .requires("report")
+                .help("Period --report covers, back from now: e.g. 30m, 12h, 7d or 2w (default 7d).")
+        )
+        .arg(
+            Arg::with_name("migrate-export")
+                .long("migrate-export")
+                .takes_value(true)
+                .value_name("bundle")
+                .help("Save the config and tracked files to an encrypted bundle, to move rgdrive to another machine.")
+                .long_help(
+                    "Save this profile's config, tracked files and Drive folder state to a single bundle, encrypted with a \
+                    passphrase ($RGDRIVE_PASSPHRASE, or asked for). Restore it on the new machine with --migrate-import. \
+                    Queued retries, changes held for review and the journal aren't included.",
+                )
+        )
+        .arg(
+            Arg::with_name("with-tokens")
+                .long("with-tokens")
+                .takes_value(false)
+                .requires("migrate-export")
+                .help("With --migrate-export, include $GOOGLE_CLIENT_ID, $GOOGLE_CLIENT_SECRET and $GOOGLE_REFRESH_TOKEN in the bundle.")
+        )
+        .arg(
+            Arg::with_name("migrate-import")
+                .long("migrate-import")
+                .takes_value(true)
+                .value_name("bundle")
+                .help("Restore a bundle from --migrate-export and start syncing its files.")
+                .long_help(
+                    "Restore a bundle made by --migrate-export into this profile. Tracked files under the old machine's \
+                    $HOME are moved under this one, files that don't exist here are left out (with the command to pull \
+                    them again), and the config is checked. Credentials in the bundle are saved for the daemon. The \
+                    daemon is then (re)started, watching the imported files. Won't replace an existing config or \
+                    tracked files without --force.",
+                )
+        )
+        .arg(
+            Arg::with_name("audit-format")
+                .long("audit-format")
+                .takes_value(true)
+                .possible_values(&["csv", "json"])
+                .default_value("csv")
+                .help("Output format for --audit-export.")
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Inspect the rgdrive config file.")
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about("Validate the config and report every problem found, with line numbers.")
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .help("Config file to check, defaults to ~/.config/cameron-williams/rgdrive.toml."),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("daemons")
+                .about("List the running daemons of every profile (profile, account, version, socket)."),
+        )
+        .subcommand(
+            SubCommand::with_name("pending")
+                .about("List changes held for review (see [review] in rgdrive.toml)."),
+        )
+        .subcommand(
+            SubCommand::with_name("approve")
+                .about("Upload changes held for review.")
+                .arg(
+                    Arg::with_name("path")
+                        .value_name("PATH")
+                        .required_unless("all")
+                        .help("File (or directory of files) whose pending changes to upload."),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .conflicts_with("path")
+                        .help("Upload every pending change."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("env")
+                .about("Show the network environment in use (see [environments] in rgdrive.toml), or switch to another.")
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Switch to an environment, it's kept across restarts.")
+                        .arg(
+                            Arg::with_name("name")
+                                .value_name("NAME")
+                                .required(true)
+                                .help("Environment to switch to, e.g. hotspot."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("clear")
+                        .about("Use no environment, lifting its limits."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pin")
+                .about("Keep a synced file on disk and current with Drive.")
+                .long_about(
+                    "Keep a synced file on disk and current with Drive: --evict skips it, the remote poller pulls its Drive \
+                    changes and puts it back if it's deleted or made a placeholder. A placeholder is downloaded right away. \
+                    --health fails while a pinned file can't be refreshed.",
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .value_name("PATH")
+                        .required(true)
+                        .help("Synced file, placeholder or Drive url to pin."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unpin")
+                .about("Stop keeping a pinned file on disk, it stays synced.")
+                .arg(
+                    Arg::with_name("path")
+                        .value_name("PATH")
+                        .required(true)
+                        .help("Pinned file or Drive url."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("hydrate")
+                .about("Download the Drive files behind placeholders (see --placeholders), and sync them.")
+                .arg(
+                    Arg::with_name("path")
+                        .value_name("PATH")
+                        .required(true)
+                        .help("Placeholder, or directory whose placeholders to download."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("prompt-status")
+                .about("Print a glyph for the sync state of a directory, for shell prompts.")
+                .long_about(
+                    "Print the sync state of path (the current directory by default) as a single glyph: ✓ synced, ↓ export, \
+                    ◐ a directory with synced files in it, ✗ something in it failed to sync, … a change waiting to go up, ↑ uploading, nothing if it isn't synced. \
+                    Answered from the daemon's memory, never from Drive, and prints nothing if the daemon doesn't reply within \
+                    a few milliseconds.",
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .value_name("PATH")
+                        .help("Path to describe, defaults to the current directory."),
+                )
+                .arg(
+                    Arg::with_name("plain")
+                        .long("plain")
+                        .help("Print the state as a word (synced, export, partial, error, untracked) instead of a glyph."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("install")
+                .about("Install rgdrive, rgdrived and the man page.")
+                .long_about(
+                    "Copy rgdrive and rgdrived into ~/.local/bin (or --dir), create the config directory and install the man page. \
+                    With --systemd, also install and enable a systemd user unit that runs the daemon.",
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .value_name("/path/to/bin")
+                        .help("Install the binaries here instead of ~/.local/bin."),
+                )
+                .arg(
+                    Arg::with_name("systemd")
+                        .long("systemd")
+                        .help("Install and enable the rgdrived systemd user unit."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("uninstall")
+                .about("Stop the daemon and remove what `rgdrive install` put in place.")
+                .arg(
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .value_name("/path/to/bin")
+                        .help("Directory the binaries were installed to, if not ~/.local/bin."),
+                )
+                .arg(
+                    Arg::with_name("purge")
+                        .long("purge")
+                        .help("Also remove tracked files, config and the operation journal."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("self-update")
+                .about("Update rgdrive and rgdrived to the latest GitHub release.")
+                .long_about(
+                    "Download the latest release binaries for this platform, verify them against the release's SHA256SUMS \
+                    and swap them in place of the installed ones. If the daemon is running, offers to restart it on the new version.",
+                )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("Only check whether an update is available."),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .short("y")
+                        .help("Restart the daemon without asking."),
+                ),
+        )
+        .arg(
+            Arg::with_name("man")
+                .long("man")
+                .hidden(true)
+                .help("Print the rgdrive man page."),
+        )
+}
@@ -0,0 +1,51 @@
+use std::env;
+use std::io::{ErrorKind, Write};
+use std::process::{Command, ExitStatus, Stdio};
+
+// Clipboard tools tried in order when no command is configured.
+fn candidates() -> Vec<Vec<&'static str>> {
+    let mut c = Vec::new();
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        c.push(vec!["wl-copy"]);
+    }
+    c.push(vec!["xclip", "-selection", "clipboard"]);
+    c.push(vec!["xsel", "--clipboard", "--input"]);
+    c
+}
+
+// Put text on the clipboard by piping it to command, or to the first clipboard tool that's installed if command is
+// empty.
+pub fn copy(command: &[String], text: &str) -> Result<(), String> {
+    if !command.is_empty() {
+        let args: Vec<&str> = command.iter().map(|s| s.as_str()).collect();
+        return check(&args, run(&args, text));
+    }
+    for args in candidates() {
+        match run(&args, text) {
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            r => return check(&args, r),
+        }
+    }
+    Err(String::from(
+        "No clipboard tool found, install wl-copy, xclip or xsel or set screenshots.clipboard.",
+    ))
+}
+
+fn check(args: &[&str], r: std::io::Result<ExitStatus>) -> Result<(), String> {
+    match r {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} exited with {}", args[0], status)),
+        Err(e) => Err(format!("{}: {}", args[0], e)),
+    }
+}
+
+fn run(args: &[&str], text: &str) -> std::io::Result<ExitStatus> {
+    let mut child = Command::new(args[0])
+        .args(&args[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(text.as_bytes())?;
+    child.wait()
+}
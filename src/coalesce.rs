@@ -0,0 +1,123 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::prelude::*;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::Coalesce;
+
+const BLOCK: usize = 4096;
+
+// Hash of each block of a file, enough to tell roughly how many bytes changed between two versions without keeping
+// a copy of either.
+#[derive(Debug, PartialEq)]
+pub struct Fingerprint {
+    len: u64,
+    blocks: Vec<u64>,
+}
+
+impl Fingerprint {
+    pub fn of(p: &Path) -> Result<Fingerprint, Error> {
+        let mut f = File::open(p)?;
+        let mut buf = vec![0u8; BLOCK];
+        let (mut len, mut blocks) = (0, Vec::new());
+        loop {
+            // Fill the whole block, so a short read doesn't shift every block after it.
+            let mut n = 0;
+            while n < BLOCK {
+                match f.read(&mut buf[n..])? {
+                    0 => break,
+                    r => n += r,
+                }
+            }
+            if n == 0 {
+                break;
+            }
+            let mut h = DefaultHasher::new();
+            h.write(&buf[..n]);
+            blocks.push(h.finish());
+            len += n as u64;
+        }
+        Ok(Fingerprint { len, blocks })
+    }
+
+    // Bytes in the blocks that differ between the two versions, growth and truncation included.
+    pub fn churn(&self, other: &Fingerprint) -> u64 {
+        let n = self.blocks.len().max(other.blocks.len());
+        let changed = (0..n)
+            .filter(|&i| self.blocks.get(i) != other.blocks.get(i))
+            .count() as u64;
+        (changed * BLOCK as u64).min(self.len.max(other.len))
+    }
+}
+
+// Keeps Drive to at most one new revision per file per [coalesce] period. Small changes to a file uploaded within the
+// period are deferred, and go up together once the period is over.
+pub struct Coalescer {
+    period: Duration,
+    min_churn: u64,
+    last: HashMap<PathBuf, (Instant, Fingerprint)>,
+    deferred: Vec<PathBuf>,
+}
+
+impl Coalescer {
+    pub fn new(coalesce: &Coalesce) -> Coalescer {
+        Coalescer {
+            period: Duration::from_secs(coalesce.period_secs),
+            min_churn: coalesce.min_churn_bytes,
+            last: HashMap::new(),
+            deferred: Vec::new(),
+        }
+    }
+
+    // Whether a change to path should wait for the period to end. Returns the churn since the last upload if so.
+    pub fn defer(&mut self, path: &Path) -> Option<u64> {
+        let (at, last) = self.last.get(path)?;
+        if at.elapsed() >= self.period {
+            return None;
+        }
+        let churn = Fingerprint::of(path).ok()?.churn(last);
+        if churn >= self.min_churn {
+            return None;
+        }
+        if !self.deferred.iter().any(|p| p == path) {
+            self.deferred.push(path.to_path_buf());
+        }
+        Some(churn)
+    }
+
+    // Note that path was just uploaded, starting its period.
+    pub fn uploaded(&mut self, path: &Path) {
+        self.deferred.retain(|p| p != path);
+        if self.period == Duration::from_secs(0) {
+            return;
+        }
+        match Fingerprint::of(path) {
+            Ok(f) => {
+                self.last.insert(path.to_path_buf(), (Instant::now(), f));
+            }
+            Err(_) => {
+                self.last.remove(path);
+            }
+        }
+    }
+
+    // Every deferred file, period over or not, e.g. when the daemon stops.
+    pub fn drain(&mut self) -> Vec<PathBuf> {
+        self.deferred.drain(..).collect()
+    }
+
+    // Deferred files whose period is over, to be uploaded now.
+    pub fn due(&mut self) -> Vec<PathBuf> {
+        let (last, period) = (&self.last, self.period);
+        let (due, waiting) = self.deferred.drain(..).partition(|p| match last.get(p) {
+            Some((at, _)) => at.elapsed() >= period,
+            None => true,
+        });
+        self.deferred = waiting;
+        due
+    }
+}
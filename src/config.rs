@@ -0,0 +1,1526 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::guard;
+use crate::paths::{DRIVE_PREFIX, PATHS, ROOT_ID};
+use crate::remote::{drive_id, Remote};
+use crate::transfer;
+use crate::{profile, settings_path, valid_profile, WatchEvent, DEFAULT_PROFILE};
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub policy: Policy,
+    pub limits: Limits,
+    pub health: Thresholds,
+    pub poll: Poll,
+    pub watch: Vec<Watch>,
+    pub screenshots: Screenshots,
+    // Named drive urls, usable as @name (or @name/path/inside/it) anywhere a drive url is accepted.
+    pub aliases: HashMap<String, String>,
+    pub computer: Computer,
+    pub versions: Versions,
+    pub planner: Planner,
+    pub excludes: Excludes,
+    pub hooks: Hooks,
+    pub review: Review,
+    pub batching: Batching,
+    pub debounce: Debounce,
+    pub coalesce: Coalesce,
+    pub vanished: Vanished,
+    pub reconcile: Reconcile,
+    pub events: Events,
+    pub deletes: Deletes,
+    pub xattrs: Xattrs,
+    pub shortcuts: Shortcuts,
+    pub transfers: Transfers,
+    pub replica: Replica,
+    pub cache: Cache,
+    pub media: Media,
+    // Network environments by name, see environment.
+    pub environments: BTreeMap<String, Environment>,
+}
+
+impl Config {
+    // Load the config from settings_path(). A missing file is not an error, it just means everything is default.
+    pub fn load() -> Result<Config, String> {
+        Config::from_path(settings_path())
+    }
+
+    pub fn from_path<P: AsRef<Path>>(p: P) -> Result<Config, String> {
+        let p = p.as_ref();
+        if !p.exists() {
+            return Ok(Config::default());
+        }
+        let contents = fs::read_to_string(p).map_err(|e| format!("{:?}: {}", p, e))?;
+        toml::from_str(&contents).map_err(|e| format!("{:?}: {}", p, e))
+    }
+
+    // Whether target needs resolve() to become a drive url.
+    pub fn is_symbolic(target: &str) -> bool {
+        target.starts_with('@') || target.starts_with(DRIVE_PREFIX)
+    }
+
+    // Resolve an @alias or drive:/path target to a drive url, anything else is returned as is. A path after an alias is
+    // looked up beneath the aliased folder, a drive: path beneath the root of My Drive.
+    pub fn resolve<R: Remote + ?Sized>(
+        &self,
+        remote: &mut R,
+        target: &str,
+    ) -> Result<String, String> {
+        let (folder, path) = if let Some(path) = target.strip_prefix(DRIVE_PREFIX) {
+            (ROOT_ID, path)
+        } else if let Some(alias) = target.strip_prefix('@') {
+            let mut parts = alias.splitn(2, '/');
+            let name = parts.next().unwrap_or("");
+            let url = self
+                .aliases
+                .get(name)
+                .ok_or_else(|| format!("Unknown alias @{}.", name))?;
+            match parts.next().filter(|p| !p.is_empty()) {
+                Some(path) => (
+                    drive_id(url).ok_or_else(|| format!("Alias @{} is not a drive url.", name))?,
+                    path,
+                ),
+                None => return Ok(url.clone()),
+            }
+        } else {
+            return Ok(target.to_string());
+        };
+        if path.trim_matches('/').is_empty() {
+            return Err(format!("{} doesn't name a file.", target));
+        }
+        let id = PATHS
+            .walk(remote, folder, path)
+            .map_err(|e| format!("Error resolving {}: {}", target, e))?;
+        Ok(format!("https://drive.google.com/open?id={}", id))
+    }
+}
+
+// Policy mode. When allowed_folder is set every push/update must land somewhere beneath that Drive folder.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub allowed_folder: Option<String>,
+}
+
+impl Policy {
+    // Folder id all operations are pinned to, None if policy mode is off.
+    pub fn folder_id(&self) -> Option<&str> {
+        self.allowed_folder.as_ref().and_then(|f| drive_id(f))
+    }
+
+    // Check that drive_url sits beneath the allowed folder. Anything that can't be verified is rejected.
+    pub fn permits<R: Remote + ?Sized>(
+        &self,
+        remote: &mut R,
+        drive_url: &str,
+    ) -> Result<(), String> {
+        let folder = match &self.allowed_folder {
+            Some(f) => match drive_id(f) {
+                Some(id) => id,
+                None => {
+                    return Err(format!(
+                        "Policy: allowed_folder {:?} is not a valid drive url.",
+                        f
+                    ))
+                }
+            },
+            None => return Ok(()),
+        };
+        let id = match drive_id(drive_url) {
+            Some(id) => id,
+            None => return Err(format!("Policy: {:?} is not a valid drive url.", drive_url)),
+        };
+        match remote.ancestors(id) {
+            Ok(ancestors) => {
+                if ancestors.iter().any(|a| a == folder) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "Policy: {:?} is outside of the allowed folder {:?}.",
+                        drive_url, folder
+                    ))
+                }
+            }
+            Err(e) => Err(format!(
+                "Policy: unable to verify {:?} is inside the allowed folder: {}",
+                drive_url, e
+            )),
+        }
+    }
+}
+
+// Resource caps for the daemon, so it behaves on small machines.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    // Largest command the daemon will buffer from a single client.
+    pub max_buffer_bytes: u64,
+    // Command buffers the workers may hold between them. Each connection being handled claims max_buffer_bytes of it,
+    // connections that don't fit are turned away.
+    pub max_memory_bytes: u64,
+    // Soft RLIMIT_NOFILE applied at startup. None keeps whatever the daemon inherited.
+    pub max_open_files: Option<u64>,
+    // Number of threads handling client connections.
+    pub worker_threads: usize,
+    // Connections allowed to wait for a free worker, anything past this is turned away.
+    pub max_queued: usize,
+    // Time a client gets to send its whole command before the connection is dropped.
+    pub request_timeout_secs: u64,
+    // Open connections allowed from a single client process.
+    pub max_connections_per_client: usize,
+    // Responses larger than this are sent zstd compressed to clients that can read them. 0 never compresses.
+    pub compress_above_bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_buffer_bytes: 1024 * 1024,
+            max_memory_bytes: 16 * 1024 * 1024,
+            max_open_files: None,
+            worker_threads: 4,
+            max_queued: 16,
+            request_timeout_secs: 10,
+            max_connections_per_client: 4,
+            compress_above_bytes: 64 * 1024,
+        }
+    }
+}
+
+// When the daemon should consider itself unhealthy, and who to tell about it.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Thresholds {
+    // Fraction (0-1) of failed operations within failure_window_secs that marks the daemon unhealthy.
+    pub max_failure_rate: f64,
+    pub failure_window_secs: u64,
+    // Don't judge the failure rate on fewer operations than this.
+    pub min_operations: u64,
+    // Longest a connection may wait for a free worker.
+    pub max_queue_age_secs: u64,
+    // Sync latency objective: the 90th percentile of the time from a local change to its upload completing, over
+    // the uploads within failure_window_secs. 0 doesn't check it.
+    pub max_sync_latency_ms: u64,
+    pub check_interval_secs: u64,
+    // Under a systemd watchdog (WatchdogSec=), stop answering it once the inotify watcher, the worker queue or the
+    // socket listener has been stuck this long, so systemd restarts the daemon.
+    pub stall_secs: u64,
+    // Url to POST a json alert to when health changes.
+    pub webhook: Option<String>,
+    // Also raise a desktop notification (notify-send) when health changes.
+    pub notify: bool,
+}
+
+impl Default for Thresholds {
+    fn default() -> Thresholds {
+        Thresholds {
+            max_failure_rate: 0.5,
+            failure_window_secs: 900,
+            min_operations: 4,
+            max_queue_age_secs: 30,
+            max_sync_latency_ms: 0,
+            check_interval_secs: 30,
+            stall_secs: 900,
+            webhook: None,
+            notify: false,
+        }
+    }
+}
+
+// Remote polling. Off unless interval_secs is set.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Poll {
+    pub interval_secs: Option<u64>,
+    // Drive changes listed per poll, each poll carrying on where the last one stopped. None lists them all every time.
+    pub page_size: Option<usize>,
+    // Changed tracked files re-exported or reconciled at once.
+    pub concurrency: usize,
+}
+
+impl Default for Poll {
+    fn default() -> Poll {
+        Poll {
+            interval_secs: None,
+            page_size: None,
+            concurrency: 1,
+        }
+    }
+}
+
+// A Drive folder whose new files are downloaded into dest as they appear. One way, nothing is uploaded back.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Watch {
+    pub folder: String,
+    pub dest: PathBuf,
+}
+
+// Screenshot mode: new images in dir are uploaded, shared by link, and the link is put on the clipboard. Off unless
+// dir is set.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Screenshots {
+    pub dir: Option<PathBuf>,
+    // Drive folder to upload into. Defaults to the policy folder, or the Drive root.
+    pub folder: Option<String>,
+    // Command the link is piped to. Empty tries wl-copy, xclip and xsel.
+    pub clipboard: Vec<String>,
+}
+
+// Group this machine's uploads in a Drive folder named after it. Drive's own "Computers" section can only be written
+// by Google's desktop client, so this is a regular folder.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Computer {
+    pub enabled: bool,
+    // Folder name, the hostname by default.
+    pub name: Option<String>,
+    // Drive folder the machine's folder is created in. Defaults to the policy folder, or the Drive root.
+    pub folder: Option<String>,
+}
+
+impl Computer {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(hostname)
+    }
+}
+
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ok = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } == 0;
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    match String::from_utf8_lossy(&buf[..len]).into_owned() {
+        h if ok && !h.is_empty() => h,
+        _ => String::from("rgdrive"),
+    }
+}
+
+// Local copies kept before a pull overwrites a file (see --versions/--rollback).
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Versions {
+    // Versions kept per file, 0 turns them off.
+    pub keep: usize,
+}
+
+impl Default for Versions {
+    fn default() -> Versions {
+        Versions { keep: 5 }
+    }
+}
+
+// When a directory push is big enough to be confirmed first. Pushes that won't fit in the Drive quota always are.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Planner {
+    pub warn_files: u64,
+    pub warn_bytes: u64,
+}
+
+impl Default for Planner {
+    fn default() -> Planner {
+        Planner {
+            warn_files: 1000,
+            warn_bytes: 10 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+// Whether the built-in excludes (see exclude::DEFAULT_EXCLUDES) are skipped by the screenshot and watched folder
+// watchers. Directory pushes skip them unless given --no-default-excludes. ignore holds gitignore-style patterns left
+// out of directory pushes, tracked directories and the watchers on top of each directory's .rgdriveignore (see
+// exclude::Ignores), --no-default-excludes or not.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Excludes {
+    pub defaults: bool,
+    pub ignore: Vec<String>,
+}
+
+impl Default for Excludes {
+    fn default() -> Excludes {
+        Excludes {
+            defaults: true,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+// Collect changes to tracked files for window_secs and upload each changed file once, instead of once per save. The
+// window closes early once max_files files are waiting (0 for no limit). A window_secs of 0 uploads every save.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Batching {
+    pub window_secs: u64,
+    pub max_files: usize,
+}
+
+// Hold a changed file until it's gone quiet_ms without changing again, so a burst of writes is one upload (0, the
+// default, uploads as soon as the change is read).
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Debounce {
+    pub quiet_ms: u64,
+}
+
+// Keep Drive to at most one new revision of a file per period_secs (0, the default, uploads every change). Changes
+// touching fewer than min_churn_bytes bytes since the file's last upload wait for the period to end, bigger ones go up
+// right away.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Coalesce {
+    pub period_secs: u64,
+    pub min_churn_bytes: u64,
+}
+
+impl Default for Coalesce {
+    fn default() -> Coalesce {
+        Coalesce {
+            period_secs: 0,
+            min_churn_bytes: 64 * 1024,
+        }
+    }
+}
+
+// Files deleted between a change and its upload are skipped, reported once and not retried. With untrack they're also
+// no longer synced.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Vanished {
+    pub untrack: bool,
+}
+
+// What happens when the poller finds a tracked file changed on Drive. By default it's only logged.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Reconcile {
+    // Whichever of the local file and its Drive copy was modified last replaces the other. The losing side is saved to
+    // the file's versions first.
+    pub newest_wins: bool,
+}
+
+// Inotify events synced files are watched for, instead of WatchEvent::DEFAULT. A file synced with --events keeps its
+// own.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Events {
+    // For every synced file.
+    pub default: Vec<WatchEvent>,
+    // For the files at or beneath a path, the longest matching path wins.
+    pub paths: BTreeMap<PathBuf, Vec<WatchEvent>>,
+}
+
+impl Events {
+    pub fn for_path(&self, path: &Path) -> Vec<WatchEvent> {
+        let longest = self
+            .paths
+            .iter()
+            .filter(|(p, _)| path.starts_with(p))
+            .max_by_key(|(p, _)| p.components().count());
+        match longest {
+            Some((_, events)) => events.clone(),
+            None if !self.default.is_empty() => self.default.clone(),
+            None => WatchEvent::DEFAULT.to_vec(),
+        }
+    }
+}
+
+// What happens to the Drive copy of a synced file deleted locally (seen through delete_self, see [events]). By default
+// it's kept, and the file is no longer synced. Files moved away are never deleted from Drive.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Deletes {
+    // For every synced file.
+    pub default: DeletePolicy,
+    // For the files at or beneath a path (a synced file or directory), the longest matching path wins.
+    pub paths: BTreeMap<PathBuf, DeletePolicy>,
+}
+
+impl Default for Deletes {
+    fn default() -> Deletes {
+        Deletes {
+            default: DeletePolicy::Keep,
+            paths: BTreeMap::new(),
+        }
+    }
+}
+
+impl Deletes {
+    pub fn for_path(&self, path: &Path) -> DeletePolicy {
+        self.paths
+            .iter()
+            .filter(|(p, _)| path.starts_with(p))
+            .max_by_key(|(p, _)| p.components().count())
+            .map(|(_, policy)| *policy)
+            .unwrap_or(self.default)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletePolicy {
+    Keep,
+    // Move it to Drive's trash.
+    Trash,
+    // Delete it for good.
+    Delete,
+}
+
+impl DeletePolicy {
+    pub const ALL: [DeletePolicy; 3] = [
+        DeletePolicy::Keep,
+        DeletePolicy::Trash,
+        DeletePolicy::Delete,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DeletePolicy::Keep => "keep",
+            DeletePolicy::Trash => "trash",
+            DeletePolicy::Delete => "delete",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DeletePolicy> {
+        DeletePolicy::ALL.iter().copied().find(|p| p.name() == name)
+    }
+}
+
+// Stamping synced files with their Drive id and last synced md5 in extended attributes, see stamp.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Xattrs {
+    // Off for filesystems without user extended attributes, where every stamp would fail.
+    pub enabled: bool,
+}
+
+impl Default for Xattrs {
+    fn default() -> Xattrs {
+        Xattrs { enabled: true }
+    }
+}
+
+// How Drive shortcuts in a --pull-dir come down. Either way a shortcut never comes down as the small file it is.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Shortcuts {
+    // Link a shortcut to the local copy of its target when there is one, instead of pulling the target again.
+    pub symlinks: bool,
+}
+
+// Transfers run side by side, each on its own connection to Drive (see pool).
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Transfers {
+    // Most uploads and downloads in flight at once. 1 does them one after another.
+    pub concurrency: usize,
+}
+
+impl Default for Transfers {
+    fn default() -> Transfers {
+        Transfers { concurrency: 4 }
+    }
+}
+
+// A second remote every synced file is also uploaded to, see replica. Off unless profile is set.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Replica {
+    // Profile whose saved OAuth client (`rgdrive --profile <name> --auth`) signs in to the second account.
+    pub profile: Option<String>,
+    // How often the replica is brought up to date with Drive.
+    pub interval_secs: u64,
+}
+
+impl Default for Replica {
+    fn default() -> Replica {
+        Replica {
+            profile: None,
+            interval_secs: 30,
+        }
+    }
+}
+
+// Settings for one network environment (home, hotspot, office...), in effect while it's set with `rgdrive env set`.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Environment {
+    // Average upload rate to keep under, in KiB/s. 0 doesn't limit it.
+    pub max_upload_kbps: u64,
+    // Replaces [poll] interval_secs.
+    pub poll_interval_secs: Option<u64>,
+    // Hold every upload until another environment is set.
+    pub pause_uploads: bool,
+    // Hold uploads of files bigger than this until another environment is set. 0 holds none.
+    pub pause_above_bytes: u64,
+}
+
+// Synced files beneath paths are a cache of their Drive copies, kept to max_bytes by evicting the least recently used
+// (see cache). 0, the default, never evicts.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cache {
+    pub max_bytes: u64,
+    pub paths: Vec<PathBuf>,
+    // How often the cache is checked, besides after every hydrate.
+    pub interval_secs: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache {
+            max_bytes: 0,
+            paths: Vec::new(),
+            interval_secs: 60,
+        }
+    }
+}
+
+// Where the mount table and filesystem UUIDs are read from, to tell which synced files are on removable media (see
+// media). Only worth changing when /proc or /dev/disk aren't where they usually are, e.g. in a container.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Media {
+    pub mountinfo: PathBuf,
+    // Directory of links named after each filesystem's UUID, pointing at its device.
+    pub by_uuid: PathBuf,
+}
+
+impl Default for Media {
+    fn default() -> Media {
+        Media {
+            mountinfo: PathBuf::from("/proc/self/mountinfo"),
+            by_uuid: PathBuf::from("/dev/disk/by-uuid"),
+        }
+    }
+}
+
+// Hold changes to tracked files until they're approved with `rgdrive approve`, instead of uploading them as they're saved.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Review {
+    pub enabled: bool,
+}
+
+// Commands run around transfers.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    // Run with the file's path as the last argument before every upload (pushes, updates, screenshots). A non-zero exit
+    // blocks the upload, e.g. for a malware or DLP scan.
+    pub pre_upload: Vec<String>,
+}
+
+// A problem found by `rgdrive config check`. line is None when it isn't tied to a key in the file.
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(l) => write!(f, "line {}: {}", l, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// Validate the config at p, collecting every problem instead of stopping at the first one like Config::load does.
+pub fn check<P: AsRef<Path>>(p: P) -> Vec<Issue> {
+    let p = p.as_ref();
+    let mut issues = Vec::new();
+    if p.exists() {
+        match fs::read_to_string(p) {
+            Ok(contents) => issues = check_str(&contents),
+            Err(e) => issues.push(Issue {
+                line: None,
+                message: format!("{:?}: {}", p, e),
+            }),
+        }
+    }
+    // Same check the daemon makes at startup.
+    if let Err(e) = transfer::credentials() {
+        issues.push(Issue {
+            line: None,
+            message: format!("{} The daemon won't be able to reach Drive.", e),
+        });
+    }
+    issues
+}
+
+pub fn check_str(contents: &str) -> Vec<Issue> {
+    let root = match contents.parse::<toml::Value>() {
+        Ok(v) => v,
+        // A syntax error means nothing past it can be trusted, so this is the one case that reports a single issue.
+        Err(e) => {
+            return vec![Issue {
+                line: e.line_col().map(|(l, _)| l + 1),
+                message: e.to_string(),
+            }]
+        }
+    };
+    let mut c = Checker {
+        src: contents,
+        issues: Vec::new(),
+    };
+    for (section, v) in root.as_table().unwrap() {
+        if section == "watch" {
+            c.watches(v);
+            continue;
+        }
+        let table = match v.as_table() {
+            Some(t) => t,
+            None => {
+                c.issue(
+                    "",
+                    section,
+                    format!("`{}` must be a [{}] section.", section, section),
+                );
+                continue;
+            }
+        };
+        match section.as_str() {
+            "policy" => c.policy(table),
+            "limits" => c.limits(table),
+            "health" => c.health(table),
+            "poll" => c.poll(table),
+            "screenshots" => c.screenshots(table),
+            "aliases" => c.aliases(table),
+            "computer" => c.computer(table),
+            "versions" => c.versions(table),
+            "planner" => c.planner(table),
+            "excludes" => c.excludes(table),
+            "hooks" => c.hooks(table),
+            "review" => c.review(table),
+            "batching" => c.batching(table),
+            "debounce" => c.debounce(table),
+            "coalesce" => c.coalesce(table),
+            "vanished" => c.vanished(table),
+            "reconcile" => c.reconcile(table),
+            "events" => c.events(table),
+            "deletes" => c.deletes(table),
+            "xattrs" => c.xattrs(table),
+            "shortcuts" => c.shortcuts(table),
+            "transfers" => c.transfers(table),
+            "replica" => c.replica(table),
+            "cache" => c.cache(table),
+            "media" => c.media(table),
+            "environments" => c.environments(table),
+            _ => c.issue("", section, format!("Unknown section [{}].", section)),
+        }
+    }
+    // Anything the checks above don't know about yet still gets caught by the real parser.
+    if c.issues.is_empty() {
+        if let Err(e) = toml::from_str::<Config>(contents) {
+            c.issues.push(Issue {
+                line: e.line_col().map(|(l, _)| l + 1),
+                message: e.to_string(),
+            });
+        }
+    }
+    c.issues.sort_by_key(|i| i.line);
+    c.issues
+}
+
+struct Checker<'a> {
+    src: &'a str,
+    issues: Vec<Issue>,
+}
+
+impl<'a> Checker<'a> {
+    // Line (1 based) that key is set on within [section]. toml::Value doesn't keep spans so this rescans the source.
+    // Entries of an array of tables ([[watch]]) are addressed as watch[0], watch[1], ...
+    fn line_of(&self, section: &str, key: &str) -> Option<usize> {
+        let mut current = String::new();
+        let mut arrays: HashMap<&str, usize> = HashMap::new();
+        for (i, line) in self.src.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with("[[") {
+                let name = line.trim_matches(|c| c == '[' || c == ']').trim();
+                let n = arrays.entry(name).or_insert(0);
+                current = format!("{}[{}]", name, n);
+                *n += 1;
+                if section.is_empty() && name == key {
+                    return Some(i + 1);
+                }
+            } else if line.starts_with('[') {
+                current = line
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .trim()
+                    .to_string();
+                if section.is_empty() && current == key {
+                    return Some(i + 1);
+                }
+            } else if current == section {
+                let k = line
+                    .split('=')
+                    .next()
+                    .unwrap_or("")
+                    .trim()
+                    .trim_matches('"');
+                if k == key {
+                    return Some(i + 1);
+                }
+            }
+        }
+        None
+    }
+
+    fn issue(&mut self, section: &str, key: &str, message: String) {
+        let line = self.line_of(section, key);
+        self.issues.push(Issue { line, message });
+    }
+
+    // Check that section.key is an integer >= min.
+    fn integer(&mut self, section: &str, key: &str, v: &toml::Value, min: i64) {
+        match v.as_integer() {
+            Some(n) if n >= min => {}
+            Some(n) => self.issue(
+                section,
+                key,
+                format!("{}.{} must be at least {}, got {}.", section, key, min, n),
+            ),
+            None => self.issue(
+                section,
+                key,
+                format!(
+                    "{}.{} must be an integer, got {}.",
+                    section,
+                    key,
+                    v.type_str()
+                ),
+            ),
+        }
+    }
+
+    fn policy(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "allowed_folder" => match v.as_str() {
+                    Some(f) if drive_id(f).is_some() => {}
+                    Some(f) => self.issue(
+                        "policy",
+                        key,
+                        format!("policy.allowed_folder {:?} is not a valid drive url.", f),
+                    ),
+                    None => self.issue(
+                        "policy",
+                        key,
+                        format!(
+                            "policy.allowed_folder must be a string, got {}.",
+                            v.type_str()
+                        ),
+                    ),
+                },
+                _ => self.issue("policy", key, format!("Unknown key policy.{}.", key)),
+            }
+        }
+    }
+
+    fn limits(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "max_buffer_bytes"
+                | "max_memory_bytes"
+                | "max_open_files"
+                | "worker_threads"
+                | "request_timeout_secs"
+                | "max_connections_per_client" => self.integer("limits", key, v, 1),
+                "max_queued" | "compress_above_bytes" => self.integer("limits", key, v, 0),
+                _ => self.issue("limits", key, format!("Unknown key limits.{}.", key)),
+            }
+        }
+        // A budget smaller than one command buffer would turn every connection away.
+        let defaults = Limits::default();
+        let get = |key: &str, default: u64| {
+            table
+                .get(key)
+                .and_then(|v| v.as_integer())
+                .map_or(default as i64, |n| n)
+        };
+        let (buffer, memory) = (
+            get("max_buffer_bytes", defaults.max_buffer_bytes),
+            get("max_memory_bytes", defaults.max_memory_bytes),
+        );
+        if memory < buffer {
+            self.issue(
+                "limits",
+                "max_memory_bytes",
+                format!(
+                    "limits.max_memory_bytes ({}) must be at least limits.max_buffer_bytes ({}).",
+                    memory, buffer
+                ),
+            );
+        }
+    }
+
+    fn watches(&mut self, v: &toml::Value) {
+        let entries = match v.as_array() {
+            Some(a) => a,
+            None => {
+                self.issue(
+                    "",
+                    "watch",
+                    String::from("`watch` must be a list of [[watch]] sections."),
+                );
+                return;
+            }
+        };
+        let polled = toml::from_str::<Config>(self.src)
+            .map(|c| c.poll.interval_secs.is_some())
+            .unwrap_or(true);
+        if !polled && !entries.is_empty() {
+            self.issue(
+                "",
+                "watch",
+                String::from("[[watch]] folders are only checked when poll.interval_secs is set."),
+            );
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            let section = format!("watch[{}]", i);
+            let table = match entry.as_table() {
+                Some(t) => t,
+                None => {
+                    self.issue(
+                        "",
+                        "watch",
+                        format!("{} must be a [[watch]] section.", section),
+                    );
+                    continue;
+                }
+            };
+            for key in &["folder", "dest"] {
+                if !table.contains_key(*key) {
+                    self.issue("", "watch", format!("{} is missing `{}`.", section, key));
+                }
+            }
+            for (key, v) in table {
+                match key.as_str() {
+                    "folder" => match v.as_str() {
+                        Some(f) if drive_id(f).is_some() => {}
+                        _ => self.issue(
+                            &section,
+                            key,
+                            format!("{}.folder must be a drive folder url, got {}.", section, v),
+                        ),
+                    },
+                    "dest" => match v.as_str().map(Path::new) {
+                        Some(d) if d.is_absolute() && d.is_dir() => {
+                            if let Some(why) = guard::own_state(d) {
+                                self.issue(
+                                    &section,
+                                    key,
+                                    format!(
+                                        "{}.dest can't be rgdrive's own files: {}.",
+                                        section, why
+                                    ),
+                                );
+                            }
+                        }
+                        Some(d) if d.is_absolute() => self.issue(
+                            &section,
+                            key,
+                            format!("{}.dest {:?} is not an existing directory.", section, d),
+                        ),
+                        _ => self.issue(
+                            &section,
+                            key,
+                            format!("{}.dest must be an absolute path, got {}.", section, v),
+                        ),
+                    },
+                    _ => self.issue(&section, key, format!("Unknown key {}.{}.", section, key)),
+                }
+            }
+        }
+    }
+
+    fn poll(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "interval_secs" | "page_size" | "concurrency" => self.integer("poll", key, v, 1),
+                _ => self.issue("poll", key, format!("Unknown key poll.{}.", key)),
+            }
+        }
+    }
+
+    fn aliases(&mut self, table: &toml::value::Table) {
+        for (name, v) in table {
+            if name.is_empty() || name.contains('/') || name.starts_with('@') {
+                self.issue(
+                    "aliases",
+                    name,
+                    format!(
+                        "Alias name {:?} can't be empty, start with @ or contain /.",
+                        name
+                    ),
+                );
+            }
+            match v.as_str() {
+                Some(u) if drive_id(u).is_some() => {}
+                _ => self.issue(
+                    "aliases",
+                    name,
+                    format!("aliases.{} must be a drive url, got {}.", name, v),
+                ),
+            }
+        }
+    }
+
+    fn planner(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "warn_files" | "warn_bytes" => self.integer("planner", key, v, 1),
+                _ => self.issue("planner", key, format!("Unknown key planner.{}.", key)),
+            }
+        }
+    }
+
+    fn batching(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "window_secs" | "max_files" => self.integer("batching", key, v, 0),
+                _ => self.issue("batching", key, format!("Unknown key batching.{}.", key)),
+            }
+        }
+    }
+
+    fn debounce(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "quiet_ms" => self.integer("debounce", key, v, 0),
+                _ => self.issue("debounce", key, format!("Unknown key debounce.{}.", key)),
+            }
+        }
+    }
+
+    fn coalesce(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "period_secs" | "min_churn_bytes" => self.integer("coalesce", key, v, 0),
+                _ => self.issue("coalesce", key, format!("Unknown key coalesce.{}.", key)),
+            }
+        }
+    }
+
+    fn vanished(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "untrack" => {
+                    if !v.is_bool() {
+                        self.issue(
+                            "vanished",
+                            key,
+                            format!(
+                                "vanished.untrack must be true or false, got {}.",
+                                v.type_str()
+                            ),
+                        )
+                    }
+                }
+                _ => self.issue("vanished", key, format!("Unknown key vanished.{}.", key)),
+            }
+        }
+    }
+
+    fn reconcile(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "newest_wins" => {
+                    if !v.is_bool() {
+                        self.issue(
+                            "reconcile",
+                            key,
+                            format!(
+                                "reconcile.newest_wins must be true or false, got {}.",
+                                v.type_str()
+                            ),
+                        )
+                    }
+                }
+                _ => self.issue("reconcile", key, format!("Unknown key reconcile.{}.", key)),
+            }
+        }
+    }
+
+    fn events(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "default" => self.event_list("events", key, "events.default", v),
+                "paths" => match v.as_table() {
+                    Some(paths) => {
+                        for (path, v) in paths {
+                            if !Path::new(path).is_absolute() {
+                                self.issue(
+                                    "events.paths",
+                                    path,
+                                    format!(
+                                        "events.paths keys must be absolute paths, got {:?}.",
+                                        path
+                                    ),
+                                );
+                            }
+                            let name = format!("events.paths.{:?}", path);
+                            self.event_list("events.paths", path, &name, v);
+                        }
+                    }
+                    None => self.issue(
+                        "events",
+                        key,
+                        String::from("events.paths must be a table of paths to lists of events."),
+                    ),
+                },
+                _ => self.issue("events", key, format!("Unknown key events.{}.", key)),
+            }
+        }
+    }
+
+    fn deletes(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "default" => self.delete_policy("deletes", key, "deletes.default", v),
+                "paths" => match v.as_table() {
+                    Some(paths) => {
+                        for (path, v) in paths {
+                            if !Path::new(path).is_absolute() {
+                                self.issue(
+                                    "deletes.paths",
+                                    path,
+                                    format!(
+                                        "deletes.paths keys must be absolute paths, got {:?}.",
+                                        path
+                                    ),
+                                );
+                            }
+                            let name = format!("deletes.paths.{:?}", path);
+                            self.delete_policy("deletes.paths", path, &name, v);
+                        }
+                    }
+                    None => self.issue(
+                        "deletes",
+                        key,
+                        String::from(
+                            "deletes.paths must be a table of paths to keep, trash or delete.",
+                        ),
+                    ),
+                },
+                _ => self.issue("deletes", key, format!("Unknown key deletes.{}.", key)),
+            }
+        }
+    }
+
+    fn delete_policy(&mut self, section: &str, key: &str, name: &str, v: &toml::Value) {
+        if v.as_str().and_then(DeletePolicy::from_name).is_none() {
+            self.issue(
+                section,
+                key,
+                format!("{} must be one of keep, trash or delete, got {}.", name, v),
+            );
+        }
+    }
+
+    fn xattrs(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "enabled" => {
+                    if !v.is_bool() {
+                        self.issue(
+                            "xattrs",
+                            key,
+                            format!(
+                                "xattrs.enabled must be true or false, got {}.",
+                                v.type_str()
+                            ),
+                        )
+                    }
+                }
+                _ => self.issue("xattrs", key, format!("Unknown key xattrs.{}.", key)),
+            }
+        }
+    }
+
+    fn shortcuts(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "symlinks" => {
+                    if !v.is_bool() {
+                        self.issue(
+                            "shortcuts",
+                            key,
+                            format!(
+                                "shortcuts.symlinks must be true or false, got {}.",
+                                v.type_str()
+                            ),
+                        )
+                    }
+                }
+                _ => self.issue("shortcuts", key, format!("Unknown key shortcuts.{}.", key)),
+            }
+        }
+    }
+
+    fn transfers(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "concurrency" => self.integer("transfers", key, v, 1),
+                _ => self.issue("transfers", key, format!("Unknown key transfers.{}.", key)),
+            }
+        }
+    }
+
+    fn replica(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "profile" => match v.as_str() {
+                    Some(p) if valid_profile(p) && profile().as_deref().unwrap_or(DEFAULT_PROFILE) != p => {}
+                    Some(p) if valid_profile(p) => self.issue(
+                        "replica",
+                        key,
+                        format!("replica.profile {:?} is this profile, a replica needs another account.", p),
+                    ),
+                    _ => self.issue(
+                        "replica",
+                        key,
+                        format!("replica.profile must be a profile name, got {}.", v),
+                    ),
+                },
+                "interval_secs" => self.integer("replica", key, v, 1),
+                _ => self.issue("replica", key, format!("Unknown key replica.{}.", key)),
+            }
+        }
+    }
+
+    fn environments(&mut self, table: &toml::value::Table) {
+        for (name, v) in table {
+            let env = match v.as_table() {
+                Some(t) if valid_profile(name) => t,
+                Some(_) => {
+                    self.issue(
+                        "environments",
+                        name,
+                        format!(
+                            "Environment names may only use letters, digits, - and _, got {:?}.",
+                            name
+                        ),
+                    );
+                    continue;
+                }
+                None => {
+                    self.issue(
+                        "environments",
+                        name,
+                        format!(
+                            "environments.{} must be an [environments.{}] section.",
+                            name, name
+                        ),
+                    );
+                    continue;
+                }
+            };
+            let section = format!("environments.{}", name);
+            for (key, v) in env {
+                match key.as_str() {
+                    "max_upload_kbps" | "pause_above_bytes" => self.integer(&section, key, v, 0),
+                    "poll_interval_secs" => self.integer(&section, key, v, 1),
+                    "pause_uploads" => {
+                        if !v.is_bool() {
+                            self.issue(
+                                &section,
+                                key,
+                                format!(
+                                    "{}.pause_uploads must be true or false, got {}.",
+                                    section,
+                                    v.type_str()
+                                ),
+                            )
+                        }
+                    }
+                    _ => self.issue(&section, key, format!("Unknown key {}.{}.", section, key)),
+                }
+            }
+        }
+    }
+
+    fn cache(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "max_bytes" => self.integer("cache", key, v, 0),
+                "interval_secs" => self.integer("cache", key, v, 1),
+                "paths" => {
+                    let absolute = v.as_array().map(|a| {
+                        a.iter()
+                            .all(|p| p.as_str().map(|p| p.starts_with('/')) == Some(true))
+                    });
+                    if absolute != Some(true) {
+                        self.issue(
+                            "cache",
+                            key,
+                            format!("cache.paths must be a list of absolute paths, got {}.", v),
+                        )
+                    }
+                }
+                _ => self.issue("cache", key, format!("Unknown key cache.{}.", key)),
+            }
+        }
+        let limited = table
+            .get("max_bytes")
+            .and_then(|v| v.as_integer())
+            .unwrap_or(0)
+            > 0;
+        if limited && !table.contains_key("paths") {
+            self.issue(
+                "cache",
+                "max_bytes",
+                String::from("cache.max_bytes needs cache.paths, the directories whose files can be evicted."),
+            )
+        }
+    }
+
+    fn media(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "mountinfo" | "by_uuid" => {
+                    if !v.as_str().map(|p| p.starts_with('/')).unwrap_or(false) {
+                        self.issue(
+                            "media",
+                            key,
+                            format!("media.{} must be an absolute path, got {}.", key, v),
+                        )
+                    }
+                }
+                _ => self.issue("media", key, format!("Unknown key media.{}.", key)),
+            }
+        }
+    }
+
+    // A list of WatchEvent names that asks for at least one event.
+    fn event_list(&mut self, section: &str, key: &str, name: &str, v: &toml::Value) {
+        let names: Option<Vec<&str>> = v
+            .as_array()
+            .and_then(|a| a.iter().map(|n| n.as_str()).collect());
+        let result = match names {
+            Some(names) => WatchEvent::parse_list(&names.join(",")),
+            None => Err(String::from("Use a list of event names.")),
+        };
+        if let Err(e) = result {
+            self.issue(section, key, format!("{} is invalid: {}", name, e));
+        }
+    }
+
+    fn review(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "enabled" => {
+                    if !v.is_bool() {
+                        self.issue(
+                            "review",
+                            key,
+                            format!(
+                                "review.enabled must be true or false, got {}.",
+                                v.type_str()
+                            ),
+                        )
+                    }
+                }
+                _ => self.issue("review", key, format!("Unknown key review.{}.", key)),
+            }
+        }
+    }
+
+    fn hooks(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "pre_upload" => match v.as_array() {
+                    Some(a) if !a.is_empty() && a.iter().all(|s| s.is_str()) => {}
+                    _ => self.issue(
+                        "hooks",
+                        key,
+                        format!(
+                            "hooks.pre_upload must be a non-empty list of strings (the command and its arguments), got {}.",
+                            v
+                        ),
+                    ),
+                },
+                _ => self.issue("hooks", key, format!("Unknown key hooks.{}.", key)),
+            }
+        }
+    }
+
+    fn excludes(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "defaults" => {
+                    if !v.is_bool() {
+                        self.issue(
+                            "excludes",
+                            key,
+                            format!(
+                                "excludes.defaults must be true or false, got {}.",
+                                v.type_str()
+                            ),
+                        )
+                    }
+                }
+                "ignore" => match v.as_array() {
+                    Some(a) if a.iter().all(|s| s.is_str()) => {}
+                    _ => self.issue(
+                        "excludes",
+                        key,
+                        format!(
+                            "excludes.ignore must be a list of gitignore-style patterns, got {}.",
+                            v
+                        ),
+                    ),
+                },
+                _ => self.issue("excludes", key, format!("Unknown key excludes.{}.", key)),
+            }
+        }
+    }
+
+    fn versions(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "keep" => self.integer("versions", key, v, 0),
+                _ => self.issue("versions", key, format!("Unknown key versions.{}.", key)),
+            }
+        }
+    }
+
+    fn computer(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "enabled" => {
+                    if !v.is_bool() {
+                        self.issue(
+                            "computer",
+                            key,
+                            format!(
+                                "computer.enabled must be true or false, got {}.",
+                                v.type_str()
+                            ),
+                        )
+                    }
+                }
+                "name" => match v.as_str() {
+                    Some(n) if !n.trim().is_empty() && !n.contains('/') => {}
+                    _ => self.issue(
+                        "computer",
+                        key,
+                        format!("computer.name must be a folder name, got {}.", v),
+                    ),
+                },
+                "folder" => match v.as_str() {
+                    Some(f) if drive_id(f).is_some() => {}
+                    _ => self.issue(
+                        "computer",
+                        key,
+                        format!("computer.folder must be a drive folder url, got {}.", v),
+                    ),
+                },
+                _ => self.issue("computer", key, format!("Unknown key computer.{}.", key)),
+            }
+        }
+    }
+
+    fn screenshots(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "dir" => match v.as_str().map(Path::new) {
+                    Some(d) if d.is_absolute() && d.is_dir() => {
+                        if let Some(why) = guard::own_state(d) {
+                            self.issue(
+                                "screenshots",
+                                key,
+                                format!("screenshots.dir can't be rgdrive's own files: {}.", why),
+                            );
+                        }
+                    }
+                    Some(d) if d.is_absolute() => self.issue(
+                        "screenshots",
+                        key,
+                        format!("screenshots.dir {:?} is not an existing directory.", d),
+                    ),
+                    _ => self.issue(
+                        "screenshots",
+                        key,
+                        format!("screenshots.dir must be an absolute path, got {}.", v),
+                    ),
+                },
+                "folder" => match v.as_str() {
+                    Some(f) if drive_id(f).is_some() => {}
+                    _ => self.issue(
+                        "screenshots",
+                        key,
+                        format!("screenshots.folder must be a drive folder url, got {}.", v),
+                    ),
+                },
+                "clipboard" => match v.as_array() {
+                    Some(a) if !a.is_empty() && a.iter().all(|s| s.is_str()) => {}
+                    _ => self.issue(
+                        "screenshots",
+                        key,
+                        format!(
+                            "screenshots.clipboard must be a command as a list of strings, got {}.",
+                            v
+                        ),
+                    ),
+                },
+                _ => self.issue(
+                    "screenshots",
+                    key,
+                    format!("Unknown key screenshots.{}.", key),
+                ),
+            }
+        }
+    }
+
+    fn health(&mut self, table: &toml::value::Table) {
+        for (key, v) in table {
+            match key.as_str() {
+                "max_failure_rate" => match v.as_float() {
+                    Some(r) if (0.0..=1.0).contains(&r) => {}
+                    _ => self.issue(
+                        "health",
+                        key,
+                        format!(
+                            "health.max_failure_rate must be a number between 0.0 and 1.0, got {}.",
+                            v
+                        ),
+                    ),
+                },
+                "failure_window_secs" | "check_interval_secs" | "stall_secs" => {
+                    self.integer("health", key, v, 1)
+                }
+                "min_operations" | "max_queue_age_secs" | "max_sync_latency_ms" => {
+                    self.integer("health", key, v, 0)
+                }
+                "webhook" => match v.as_str() {
+                    Some(u) if u.starts_with("http://") || u.starts_with("https://") => {}
+                    _ => self.issue(
+                        "health",
+                        key,
+                        format!("health.webhook must be an http(s) url, got {}.", v),
+                    ),
+                },
+                "notify" => {
+                    if !v.is_bool() {
+                        self.issue(
+                            "health",
+                            key,
+                            format!("health.notify must be true or false, got {}.", v.type_str()),
+                        )
+                    }
+                }
+                _ => self.issue("health", key, format!("Unknown key health.{}.", key)),
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Error;
+use std::panic;
+use std::sync::Mutex;
+use std::thread;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+
+use crate::{crash_path, write_atomic};
+
+// Log lines kept for a crash report.
+const RECENT: usize = 50;
+
+lazy_static! {
+    // The last RECENT log lines, oldest first.
+    static ref EVENTS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::with_capacity(RECENT));
+}
+
+// What the daemon was doing when it panicked, written to crash_path() (beside its log). The next --status points to it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Crash {
+    // Unix timestamp (seconds).
+    pub time: i64,
+    pub version: String,
+    pub thread: String,
+    pub message: String,
+    // file:line of the panic.
+    pub location: Option<String>,
+    pub backtrace: String,
+    // The log lines leading up to it, oldest first.
+    pub events: Vec<String>,
+    // Whether --status has shown it already.
+    #[serde(default)]
+    pub reported: bool,
+}
+
+impl Crash {
+    pub fn load() -> Option<Crash> {
+        fs::read_to_string(crash_path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        write_atomic(
+            &crash_path(),
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )
+    }
+
+    // The last crash, if --status hasn't shown it yet. It's only shown once.
+    pub fn unreported() -> Option<Crash> {
+        let mut crash = Crash::load().filter(|c| !c.reported)?;
+        crash.reported = true;
+        let _ = crash.save();
+        Some(crash)
+    }
+}
+
+// Logs like env_logger (RUST_LOG) and keeps the last RECENT lines for crash reports, info and above even when RUST_LOG
+// doesn't ask for them.
+struct Recorder {
+    inner: env_logger::Logger,
+}
+
+impl Log for Recorder {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let line = format!(
+                "{} {} {}: {}",
+                Utc::now().to_rfc3339(),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            let mut events = EVENTS.lock().unwrap();
+            if events.len() >= RECENT {
+                events.pop_front();
+            }
+            events.push_back(line);
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+// In place of env_logger::init() for the daemon: logging, plus a panic hook writing a crash report. The panic still
+// goes to stderr like it always has.
+pub fn init() {
+    let inner = env_logger::Builder::from_default_env().build();
+    log::set_max_level(inner.filter().max(LevelFilter::Info));
+    if log::set_boxed_logger(Box::new(Recorder { inner })).is_err() {
+        return;
+    }
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default(info);
+        let payload = info.payload();
+        let message = match (
+            payload.downcast_ref::<&str>(),
+            payload.downcast_ref::<String>(),
+        ) {
+            (Some(s), _) => s.to_string(),
+            (_, Some(s)) => s.clone(),
+            _ => String::from("(no message)"),
+        };
+        // The panic may have happened while logging, with the lock held.
+        let events = match EVENTS.try_lock() {
+            Ok(events) => events.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        };
+        let crash = Crash {
+            time: Utc::now().timestamp(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            thread: thread::current().name().unwrap_or("unnamed").to_string(),
+            message,
+            location: info
+                .location()
+                .map(|l| format!("{}:{}", l.file(), l.line())),
+            backtrace: format!("{:?}", backtrace::Backtrace::new()),
+            events,
+            reported: false,
+        };
+        match crash.save() {
+            Ok(_) => eprintln!("Crash report written to {:?}.", crash_path()),
+            Err(e) => eprintln!("Couldn't write crash report to {:?}: {}", crash_path(), e),
+        }
+    }));
+}
@@ -0,0 +1,172 @@
+use std::fs;
+use std::io::Error;
+use std::path::PathBuf;
+use std::process;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{daemons_dir, profile, write_atomic, DSocket, DEFAULT_PROFILE};
+
+// A running daemon, registered in daemons_dir() while it's up so `rgdrive daemons` can find every profile's socket.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Instance {
+    pub pid: u32,
+    pub socket: PathBuf,
+    pub profile: String,
+    // Drive account the daemon syncs with, "-" if the remote can't say.
+    pub account: String,
+    pub version: String,
+}
+
+impl Instance {
+    // This process, about to listen on socket.
+    pub fn current(socket: PathBuf, account: String) -> Instance {
+        Instance {
+            pid: process::id(),
+            socket,
+            profile: profile().unwrap_or_else(|| String::from(DEFAULT_PROFILE)),
+            account,
+            version: String::from(env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    fn file(pid: u32) -> PathBuf {
+        daemons_dir().join(pid.to_string())
+    }
+
+    pub fn register(&self) -> Result<(), Error> {
+        fs::create_dir_all(daemons_dir())?;
+        write_atomic(&Instance::file(self.pid), &serde_json::to_vec(self)?)
+    }
+
+    // One line for `rgdrive daemons`: profile, account, version and socket.
+    pub fn describe(&self) -> String {
+        format!(
+            "{:<12}  {:<24}  {:<8}  {}",
+            self.profile,
+            self.account,
+            self.version,
+            self.socket.display()
+        )
+    }
+}
+
+// Remove this process's registration, on the way out.
+pub fn unregister() {
+    let _ = fs::remove_file(Instance::file(process::id()));
+}
+
+// Registered daemons that are still listening, sorted by profile. Registrations left behind by daemons that died
+// without unregistering are cleaned up along the way.
+pub fn running() -> Vec<Instance> {
+    let entries = match fs::read_dir(daemons_dir()) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+    let mut running = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let instance = fs::read_to_string(entry.path())
+            .ok()
+            .and_then(|s| serde_json::from_str::<Instance>(&s).ok());
+        match instance {
+            Some(i) if DSocket::new(&i.socket).is_active() => running.push(i),
+            _ => {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+    running.sort_by(|a, b| a.profile.cmp(&b.profile));
+    running
+}
+
+// Start of the line rgdrived writes to stderr when it gives up during startup, followed by a StartupError as json.
+pub const STARTUP_ERROR_PREFIX: &str = "rgdrived startup error: ";
+
+// What stopped rgdrived from starting. Each has its own exit code, so scripts (and --start) can tell them apart.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Reason {
+    // Another daemon is listening on the socket, or it couldn't be created.
+    Socket,
+    // The config file doesn't parse, see `rgdrive config check`.
+    Config,
+    // $GOOGLE_CLIENT_ID or $GOOGLE_CLIENT_SECRET is missing or malformed.
+    Credentials,
+    // Drive turned the credentials or token down.
+    Auth,
+    // Anything else, e.g. rgdrived couldn't be run or died without saying why.
+    Other,
+}
+
+impl Reason {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Reason::Socket => 3,
+            Reason::Config => 4,
+            Reason::Credentials => 5,
+            Reason::Auth => 6,
+            Reason::Other => 1,
+        }
+    }
+
+    pub fn from_exit_code(code: Option<i32>) -> Reason {
+        match code {
+            Some(3) => Reason::Socket,
+            Some(4) => Reason::Config,
+            Some(5) => Reason::Credentials,
+            Some(6) => Reason::Auth,
+            _ => Reason::Other,
+        }
+    }
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Reason::Socket => "socket",
+            Reason::Config => "config",
+            Reason::Credentials => "credentials",
+            Reason::Auth => "auth",
+            Reason::Other => "other",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Why rgdrived couldn't start, relayed to the user by `rgdrive --start`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StartupError {
+    pub code: Reason,
+    pub message: String,
+}
+
+impl StartupError {
+    pub fn new<M: Into<String>>(code: Reason, message: M) -> StartupError {
+        StartupError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}{}",
+            STARTUP_ERROR_PREFIX,
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+
+    // The last startup error written to a daemon's log, if any.
+    pub fn from_log(log: &str) -> Option<StartupError> {
+        log.lines()
+            .rev()
+            .filter_map(|l| l.strip_prefix(STARTUP_ERROR_PREFIX))
+            .find_map(|j| serde_json::from_str(j).ok())
+    }
+}
+
+impl std::fmt::Display for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::Debounce;
+
+// Tracked files that changed, each held until it's gone [debounce] quiet_ms without changing again. An editor writing a
+// file in several goes, or saving it a few times in quick succession, makes one upload instead of one per write.
+pub struct Debouncer {
+    quiet: Duration,
+    // When each file last changed.
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl Debouncer {
+    pub fn new(debounce: &Debounce) -> Debouncer {
+        Debouncer {
+            quiet: Duration::from_millis(debounce.quiet_ms),
+            pending: HashMap::new(),
+        }
+    }
+
+    // Whether changes are held at all, a zero quiet period passes every change straight on.
+    pub fn enabled(&self) -> bool {
+        self.quiet > Duration::from_secs(0)
+    }
+
+    // Note a change to path at now, restarting its quiet period. Returns false if path was already waiting.
+    pub fn touch(&mut self, path: &Path, now: Instant) -> bool {
+        self.pending.insert(path.to_path_buf(), now).is_none()
+    }
+
+    // Every file still waiting, e.g. when the daemon stops.
+    pub fn drain(&mut self) -> Vec<PathBuf> {
+        self.pending.drain().map(|(p, _)| p).collect()
+    }
+
+    // Files that have been quiet long enough, to be uploaded now.
+    pub fn due(&mut self, now: Instant) -> Vec<PathBuf> {
+        let quiet = self.quiet;
+        let due: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, at)| now.duration_since(**at) >= quiet)
+            .map(|(p, _)| p.clone())
+            .collect();
+        for p in &due {
+            self.pending.remove(p);
+        }
+        due
+    }
+}
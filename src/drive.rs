@@ -0,0 +1,647 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use chrono::DateTime;
+use serde_json::{json, Value};
+
+use crate::capabilities::Capability;
+use crate::checksum::{save_hashed, Hashing};
+use crate::export::{mime_for, Export};
+use crate::oauth::{self, endpoint, Token};
+use crate::remote::{
+    drive_id, Activity, FileChange, Metadata, Quota, Remote, RemoteError, SharedDrive,
+    ACTIVITY_SCOPE, DRIVE_FILE_SCOPE, DRIVE_SCOPE, FOLDER_MIME, SHORTCUT_MIME,
+};
+
+// Drive's v3 REST api, with an access token from a sign in (see oauth).
+
+const FILES: &str = "https://www.googleapis.com/drive/v3/files";
+const ABOUT: &str = "https://www.googleapis.com/drive/v3/about";
+const DRIVES: &str = "https://www.googleapis.com/drive/v3/drives";
+const CHANGES: &str = "https://www.googleapis.com/drive/v3/changes";
+const UPLOAD: &str = "https://www.googleapis.com/upload/drive/v3/files";
+const ACTIVITY: &str = "https://driveactivity.googleapis.com/v2/activity:query";
+const SHEETS: &str = "https://sheets.googleapis.com/v4/spreadsheets";
+const SHEET_EXPORT: &str = "https://docs.google.com/spreadsheets/d";
+
+// Separates the metadata from the content of a multipart upload.
+const BOUNDARY: &str = "rgdrive-8b0d5f3c7a41e962";
+// Deepest folder nesting ancestors follows, in case parents ever loop.
+const MAX_DEPTH: usize = 64;
+// What Metadata is read from.
+const FIELDS: &str =
+    "id,name,mimeType,size,modifiedTime,md5Checksum,version,shortcutDetails/targetId,parents";
+
+pub struct Drive {
+    token: Token,
+}
+
+impl Drive {
+    // Trade the refresh token a sign in saved for an access token.
+    pub fn connect(id: &str, secret: &str, refresh_token: &str) -> Result<Drive, String> {
+        oauth::refresh(id, secret, refresh_token).map(|token| Drive { token })
+    }
+
+    fn authorized(&self, method: &str, url: &str) -> ureq::Request {
+        let mut req = ureq::request(method, url);
+        req.set("Authorization", &format!("Bearer {}", self.token.access))
+            .set("User-Agent", concat!("rgdrive/", env!("CARGO_PKG_VERSION")))
+            .timeout_connect(30_000)
+            .timeout_read(300_000);
+        req
+    }
+
+    // A Drive api request, which reaches files on shared drives too.
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let mut req = self.authorized(method, url);
+        req.query("supportsAllDrives", "true");
+        req
+    }
+
+    // The given metadata fields of a file id.
+    fn get(&self, id: &str, fields: &str) -> Result<Value, RemoteError> {
+        let resp = self
+            .request("GET", &format!("{}/{}", endpoint(FILES), id))
+            .query("fields", fields)
+            .call();
+        json_of(check(resp)?)
+    }
+
+    // A file's name, as a file name on disk. Falls back to its id.
+    fn name(&self, id: &str) -> Result<String, RemoteError> {
+        let name = self.get(id, "name")?["name"]
+            .as_str()
+            .map(String::from)
+            .unwrap_or_else(|| id.to_string());
+        Ok(Path::new(&name)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| id.to_string()))
+    }
+
+    // The id (gid) of a spreadsheet's sheet (tab) named sheet, the first one's for None.
+    fn sheet_id(&self, id: &str, sheet: Option<&str>) -> Result<i64, RemoteError> {
+        let resp = self
+            .authorized("GET", &format!("{}/{}", endpoint(SHEETS), id))
+            .query("fields", "sheets.properties(sheetId,title)")
+            .call();
+        let found = json_of(check(resp)?)?;
+        let sheets = found["sheets"].as_array().cloned().unwrap_or_default();
+        sheets
+            .iter()
+            .map(|s| &s["properties"])
+            .find(|p| match sheet {
+                Some(sheet) => p["title"] == json!(sheet),
+                None => true,
+            })
+            .and_then(|p| p["sheetId"].as_i64())
+            .ok_or_else(|| RemoteError::Api(format!("No such sheet in {}: {:?}", id, sheet)))
+    }
+
+    // The given fields about the signed in account.
+    fn about(&self, fields: &str) -> Result<Value, RemoteError> {
+        let resp = self
+            .authorized("GET", &endpoint(ABOUT))
+            .query("fields", fields)
+            .call();
+        json_of(check(resp)?)
+    }
+
+    // Every file matching a files.list query, all pages of them.
+    fn list(&self, q: &str) -> Result<Vec<Metadata>, RemoteError> {
+        let mut files = Vec::new();
+        let mut page = String::new();
+        loop {
+            let mut req = self.request("GET", &endpoint(FILES));
+            req.query("q", q)
+                .query("fields", &format!("nextPageToken,files({})", FIELDS))
+                .query("pageSize", "1000")
+                .query("includeItemsFromAllDrives", "true");
+            if !page.is_empty() {
+                req.query("pageToken", &page);
+            }
+            let found = json_of(check(req.call())?)?;
+            if let Some(list) = found["files"].as_array() {
+                files.extend(list.iter().map(metadata_of));
+            }
+            page = match found["nextPageToken"].as_str() {
+                Some(token) => token.to_string(),
+                None => break,
+            };
+        }
+        Ok(files)
+    }
+
+    // Create a file without content (a folder or shortcut) from its metadata, returns its id.
+    fn make(&self, metadata: Value) -> Result<String, RemoteError> {
+        let resp = self
+            .request("POST", &endpoint(FILES))
+            .query("fields", "id")
+            .send_json(metadata);
+        match json_of(check(resp)?)?["id"].as_str() {
+            Some(id) => Ok(id.to_string()),
+            None => Err(RemoteError::Api(String::from(
+                "Drive didn't say what the new file's id is.",
+            ))),
+        }
+    }
+
+    // Upload path as a new file with the given metadata (name, parents), returns its url and the md5 of what was sent.
+    fn create(&self, path: &Path, metadata: Value) -> Result<(String, String), RemoteError> {
+        let file = File::open(path).map_err(|e| RemoteError::Api(format!("{:?}: {}", path, e)))?;
+        let mut file = Hashing::new(file);
+        let head = format!(
+            "--{b}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{}\r\n--{b}\r\n\
+            Content-Type: application/octet-stream\r\n\r\n",
+            metadata,
+            b = BOUNDARY
+        );
+        let tail = format!("\r\n--{}--\r\n", BOUNDARY);
+        let resp = self
+            .request("POST", &endpoint(UPLOAD))
+            .query("uploadType", "multipart")
+            .query("fields", "id")
+            .set(
+                "Content-Type",
+                &format!("multipart/related; boundary={}", BOUNDARY),
+            )
+            .send(head.as_bytes().chain(&mut file).chain(tail.as_bytes()));
+        let created = json_of(check(resp)?)?;
+        match created["id"].as_str() {
+            Some(id) => Ok((
+                format!("https://drive.google.com/open?id={}", id),
+                file.md5(),
+            )),
+            None => Err(RemoteError::Api(format!(
+                "Drive didn't say where {:?} was uploaded to.",
+                path
+            ))),
+        }
+    }
+}
+
+impl Remote for Drive {
+    fn upload(&mut self, path: &Path) -> Result<String, RemoteError> {
+        self.upload_hashed(path, None).map(|(url, _)| url)
+    }
+
+    fn upload_to(&mut self, path: &Path, folder_id: &str) -> Result<String, RemoteError> {
+        self.upload_hashed(path, Some(folder_id))
+            .map(|(url, _)| url)
+    }
+
+    fn download(&mut self, url: &str, path: &Path) -> Result<PathBuf, RemoteError> {
+        self.download_hashed(url, path).map(|(written, _)| written)
+    }
+
+    fn update(&mut self, path: &Path, url: &str) -> Result<(), RemoteError> {
+        self.update_hashed(path, url).map(|_| ())
+    }
+
+    // Transfers are hashed as they stream, the md5 is of exactly what Drive got or sent.
+    fn upload_hashed(
+        &mut self,
+        path: &Path,
+        folder_id: Option<&str>,
+    ) -> Result<(String, Option<String>), RemoteError> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let metadata = match folder_id {
+            Some(folder) => json!({ "name": name, "parents": [folder] }),
+            None => json!({ "name": name }),
+        };
+        self.create(path, metadata)
+            .map(|(url, md5)| (url, Some(md5)))
+    }
+
+    // Like Drive's web ui, downloading into a directory keeps the remote file name.
+    fn download_hashed(
+        &mut self,
+        url: &str,
+        path: &Path,
+    ) -> Result<(PathBuf, Option<String>), RemoteError> {
+        let id = id_of(url)?;
+        let dest = if path.is_dir() {
+            path.join(self.name(id)?)
+        } else {
+            path.to_path_buf()
+        };
+        let resp = self
+            .request("GET", &format!("{}/{}", endpoint(FILES), id))
+            .query("alt", "media")
+            .call();
+        let md5 = save_hashed(&mut check(resp)?.into_reader(), &dest)
+            .map_err(|e| RemoteError::Api(format!("{:?}: {}", dest, e)))?;
+        Ok((dest, Some(md5)))
+    }
+
+    fn update_hashed(&mut self, path: &Path, url: &str) -> Result<Option<String>, RemoteError> {
+        let id = id_of(url)?;
+        let file = File::open(path).map_err(|e| RemoteError::Api(format!("{:?}: {}", path, e)))?;
+        let mut file = Hashing::new(file);
+        let resp = self
+            .request("PATCH", &format!("{}/{}", endpoint(UPLOAD), id))
+            .query("uploadType", "media")
+            .query("fields", "id")
+            .set("Content-Type", "application/octet-stream")
+            .send(&mut file);
+        check(resp)?;
+        Ok(Some(file.md5()))
+    }
+
+    // Files have a single parent on Drive (since 2020), so this follows the first one up to My Drive or a shared drive.
+    fn ancestors(&mut self, id: &str) -> Result<Vec<String>, RemoteError> {
+        let mut ancestors = Vec::new();
+        let mut id = id.to_string();
+        while ancestors.len() < MAX_DEPTH {
+            match self.get(&id, "parents")?["parents"][0].as_str() {
+                Some(parent) => {
+                    id = parent.to_string();
+                    ancestors.push(id.clone());
+                }
+                None => break,
+            }
+        }
+        Ok(ancestors)
+    }
+
+    // v3 files have no etag, their version stands in: it goes up with every change to the file, content or metadata.
+    fn metadata(&mut self, id: &str) -> Result<Metadata, RemoteError> {
+        Ok(metadata_of(&self.get(id, FIELDS)?))
+    }
+
+    fn changes_token(&mut self) -> Result<String, RemoteError> {
+        let resp = self
+            .request("GET", &format!("{}/startPageToken", endpoint(CHANGES)))
+            .call();
+        match json_of(check(resp)?)?["startPageToken"].as_str() {
+            Some(token) => Ok(token.to_string()),
+            None => Err(RemoteError::Api(String::from(
+                "Drive didn't say where its changes start.",
+            ))),
+        }
+    }
+
+    // Without a page size, every page up to the end of the feed (newStartPageToken). Changes to shared drives
+    // themselves (rather than their files) have no fileId and are skipped, trashed files count as removed.
+    fn changes(
+        &mut self,
+        token: &str,
+        page_size: Option<usize>,
+    ) -> Result<(Vec<FileChange>, String), RemoteError> {
+        let mut changes = Vec::new();
+        let mut page = token.to_string();
+        loop {
+            let size = page_size.unwrap_or(1000).clamp(1, 1000);
+            let resp = self
+                .request("GET", &endpoint(CHANGES))
+                .query("pageToken", &page)
+                .query("pageSize", &size.to_string())
+                .query("includeItemsFromAllDrives", "true")
+                .query(
+                    "fields",
+                    &format!(
+                        "nextPageToken,newStartPageToken,changes(fileId,removed,file({},trashed))",
+                        FIELDS
+                    ),
+                )
+                .call();
+            let listed = json_of(check(resp)?)?;
+            for c in listed["changes"].as_array().into_iter().flatten() {
+                let id = match c["fileId"].as_str() {
+                    Some(id) => id.to_string(),
+                    None => continue,
+                };
+                let gone = c["removed"] == json!(true) || c["file"]["trashed"] == json!(true);
+                let file = if gone || !c["file"].is_object() {
+                    None
+                } else {
+                    Some(metadata_of(&c["file"]))
+                };
+                changes.push(FileChange { id, file });
+            }
+            if let Some(end) = listed["newStartPageToken"].as_str() {
+                return Ok((changes, end.to_string()));
+            }
+            page = match listed["nextPageToken"].as_str() {
+                Some(next) => next.to_string(),
+                None => {
+                    return Err(RemoteError::Api(String::from(
+                        "Drive listed changes without saying where the next ones start.",
+                    )))
+                }
+            };
+            if page_size.is_some() {
+                return Ok((changes, page));
+            }
+        }
+    }
+
+    // From the Drive Activity api, which lists newest first.
+    fn activity(&mut self, id: &str) -> Result<Vec<Activity>, RemoteError> {
+        let mut activity = Vec::new();
+        let mut page = None;
+        loop {
+            let mut query = json!({ "itemName": format!("items/{}", id), "pageSize": 100 });
+            if let Some(token) = page {
+                query["pageToken"] = json!(token);
+            }
+            let resp = self
+                .authorized("POST", &endpoint(ACTIVITY))
+                .send_json(query);
+            let found = json_of(check(resp)?)?;
+            if let Some(list) = found["activities"].as_array() {
+                activity.extend(list.iter().map(activity_of));
+            }
+            page = match found["nextPageToken"].as_str() {
+                Some(token) => Some(token.to_string()),
+                None => break,
+            };
+        }
+        activity.reverse();
+        Ok(activity)
+    }
+
+    // Drive exports the first sheet of a spreadsheet, a sheet or range is picked out by the spreadsheet's own export.
+    fn export(&mut self, id: &str, export: &Export, path: &Path) -> Result<PathBuf, RemoteError> {
+        let dest = if path.is_dir() {
+            path.join(format!("{}.{}", self.name(id)?, export.format))
+        } else {
+            path.to_path_buf()
+        };
+        let resp = if export.sheet.is_some() || export.range.is_some() {
+            let gid = self.sheet_id(id, export.sheet.as_deref())?;
+            let mut req =
+                self.authorized("GET", &format!("{}/{}/export", endpoint(SHEET_EXPORT), id));
+            req.query("format", &export.format)
+                .query("gid", &gid.to_string());
+            if let Some(range) = &export.range {
+                req.query("range", range);
+            }
+            req.call()
+        } else {
+            let mime = mime_for(&export.format)
+                .ok_or_else(|| RemoteError::Api(format!("Can't export as {:?}.", export.format)))?;
+            self.authorized("GET", &format!("{}/{}/export", endpoint(FILES), id))
+                .query("mimeType", mime)
+                .call()
+        };
+        save_hashed(&mut check(resp)?.into_reader(), &dest)
+            .map_err(|e| RemoteError::Api(format!("{:?}: {}", dest, e)))?;
+        Ok(dest)
+    }
+
+    fn starred(&mut self) -> Result<Vec<Metadata>, RemoteError> {
+        self.list("starred = true and trashed = false")
+    }
+
+    fn list_folder(&mut self, folder_id: &str) -> Result<Vec<Metadata>, RemoteError> {
+        self.list(&format!("'{}' in parents and trashed = false", folder_id))
+    }
+
+    // Anyone with the link can view.
+    fn share(&mut self, id: &str) -> Result<String, RemoteError> {
+        let resp = self
+            .request("POST", &format!("{}/{}/permissions", endpoint(FILES), id))
+            .send_json(json!({"role": "reader", "type": "anyone"}));
+        check(resp)?;
+        Ok(format!(
+            "https://drive.google.com/file/d/{}/view?usp=sharing",
+            id
+        ))
+    }
+
+    fn create_folder(&mut self, name: &str, parent_id: &str) -> Result<String, RemoteError> {
+        self.make(json!({ "name": name, "mimeType": FOLDER_MIME, "parents": [parent_id] }))
+    }
+
+    fn rename(&mut self, id: &str, name: &str) -> Result<(), RemoteError> {
+        let resp = self
+            .request("PATCH", &format!("{}/{}", endpoint(FILES), id))
+            .query("fields", "id")
+            .send_json(json!({ "name": name }));
+        check(resp).map(|_| ())
+    }
+
+    fn trash(&mut self, id: &str) -> Result<(), RemoteError> {
+        let resp = self
+            .request("PATCH", &format!("{}/{}", endpoint(FILES), id))
+            .query("fields", "id")
+            .send_json(json!({ "trashed": true }));
+        check(resp).map(|_| ())
+    }
+
+    fn delete(&mut self, id: &str) -> Result<(), RemoteError> {
+        let resp = self
+            .request("DELETE", &format!("{}/{}", endpoint(FILES), id))
+            .call();
+        check(resp).map(|_| ())
+    }
+
+    fn create_shortcut(
+        &mut self,
+        target_id: &str,
+        name: &str,
+        parent_id: &str,
+    ) -> Result<String, RemoteError> {
+        self.make(json!({
+            "name": name,
+            "mimeType": SHORTCUT_MIME,
+            "parents": [parent_id],
+            "shortcutDetails": { "targetId": target_id },
+        }))
+    }
+
+    // Drive leaves out the limit for unlimited storage. Numbers are strings, as int64s always are in its json.
+    fn quota(&mut self) -> Result<Quota, RemoteError> {
+        let quota = &self.about("storageQuota(limit,usage)")?["storageQuota"];
+        let bytes = |field: &str| quota[field].as_str().and_then(|n| n.parse().ok());
+        Ok(Quota {
+            limit: bytes("limit"),
+            usage: bytes("usage").unwrap_or(0),
+        })
+    }
+
+    fn account(&mut self) -> Result<String, RemoteError> {
+        match self.about("user(emailAddress)")?["user"]["emailAddress"].as_str() {
+            Some(email) => Ok(email.to_string()),
+            None => Err(RemoteError::Api(String::from(
+                "Drive didn't say who's signed in.",
+            ))),
+        }
+    }
+
+    fn shared_drives(&mut self) -> Result<Vec<SharedDrive>, RemoteError> {
+        let mut drives = Vec::new();
+        let mut page = String::new();
+        loop {
+            let mut req = self.authorized("GET", &endpoint(DRIVES));
+            req.query("fields", "nextPageToken,drives(id,name,capabilities)")
+                .query("pageSize", "100");
+            if !page.is_empty() {
+                req.query("pageToken", &page);
+            }
+            let found = json_of(check(req.call())?)?;
+            if let Some(list) = found["drives"].as_array() {
+                drives.extend(list.iter().map(|d| SharedDrive {
+                    id: d["id"].as_str().unwrap_or_default().to_string(),
+                    name: d["name"].as_str().unwrap_or_default().to_string(),
+                    role: role_of(&d["capabilities"]).to_string(),
+                }));
+            }
+            page = match found["nextPageToken"].as_str() {
+                Some(token) => token.to_string(),
+                None => break,
+            };
+        }
+        Ok(drives)
+    }
+
+    fn scopes(&mut self) -> Result<Vec<String>, RemoteError> {
+        Ok(self.token.scopes.clone())
+    }
+
+    fn token_expires(&mut self) -> Option<i64> {
+        Some(self.token.expires)
+    }
+
+    // Everything works with the drive scope, activity needs its own. drive.file only sees rgdrive's own files, so
+    // listing starred files or shared drives would come back (misleadingly) empty.
+    fn capabilities(&mut self) -> Vec<Capability> {
+        let granted = |scope: &str| self.token.scopes.iter().any(|s| s == scope);
+        let withheld: &[Capability] = if granted(DRIVE_SCOPE) {
+            &[]
+        } else if granted(DRIVE_FILE_SCOPE) {
+            &[Capability::Starred, Capability::Drives]
+        } else {
+            return Vec::new();
+        };
+        Capability::ALL
+            .iter()
+            .copied()
+            .filter(|c| !withheld.contains(c))
+            .filter(|c| *c != Capability::Activity || granted(ACTIVITY_SCOPE))
+            .collect()
+    }
+}
+
+fn metadata_of(file: &Value) -> Metadata {
+    let text = |field: &str| file[field].as_str().unwrap_or_default().to_string();
+    Metadata {
+        id: text("id"),
+        name: text("name"),
+        etag: text("version"),
+        // Docs editors files take no space, and have no size.
+        size: text("size").parse().unwrap_or(0),
+        mime_type: text("mimeType"),
+        modified: DateTime::parse_from_rfc3339(&text("modifiedTime"))
+            .map(|t| t.timestamp())
+            .unwrap_or(0),
+        md5: file["md5Checksum"].as_str().map(String::from),
+        target: file["shortcutDetails"]["targetId"]
+            .as_str()
+            .map(String::from),
+        parents: file["parents"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|p| p.as_str().map(String::from))
+            .collect(),
+    }
+}
+
+// People are named by their id ("people/..."), except the signed in user. The action is the primary one, in snake case
+// (e.g. "permission_change"), with moves to the trash told apart from deletes.
+fn activity_of(a: &Value) -> Activity {
+    let time = a["timestamp"]
+        .as_str()
+        .or_else(|| a["timeRange"]["endTime"].as_str())
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.timestamp())
+        .unwrap_or(0);
+    let actor = &a["actors"][0];
+    let actor = if actor["user"]["knownUser"]["isCurrentUser"] == json!(true) {
+        String::from("you")
+    } else if let Some(person) = actor["user"]["knownUser"]["personName"].as_str() {
+        person.to_string()
+    } else if actor["administrator"].is_object() {
+        String::from("administrator")
+    } else if actor["system"].is_object() {
+        String::from("Drive")
+    } else {
+        String::from("unknown")
+    };
+    let detail = &a["primaryActionDetail"];
+    let action = match detail.as_object().and_then(|d| d.keys().next()) {
+        Some(kind) if kind == "delete" && detail["delete"]["type"] == json!("TRASH") => {
+            String::from("trash")
+        }
+        Some(kind) => kind.chars().fold(String::new(), |mut s, c| {
+            if c.is_ascii_uppercase() {
+                s.push('_');
+            }
+            s.push(c.to_ascii_lowercase());
+            s
+        }),
+        None => String::from("unknown"),
+    };
+    Activity {
+        time,
+        actor,
+        action,
+    }
+}
+
+// Drives list what the account can do on a shared drive rather than its role, so this works the role back out of that.
+fn role_of(capabilities: &Value) -> &'static str {
+    let can = |what: &str| capabilities[what] == json!(true);
+    if can("canManageMembers") {
+        "organizer"
+    } else if can("canTrashChildren") {
+        "fileOrganizer"
+    } else if can("canAddChildren") {
+        "writer"
+    } else if can("canComment") {
+        "commenter"
+    } else {
+        "reader"
+    }
+}
+
+fn id_of(url: &str) -> Result<&str, RemoteError> {
+    drive_id(url).ok_or_else(|| RemoteError::Api(format!("{:?} is not a drive url.", url)))
+}
+
+// The response if Drive answered with success, otherwise its error as "<status> <reason>: <error reason>, <message>",
+// e.g. "404 Not Found: notFound, File not found: abc.".
+fn check(resp: ureq::Response) -> Result<ureq::Response, RemoteError> {
+    if let Some(e) = resp.synthetic_error() {
+        return Err(RemoteError::Api(format!(
+            "Couldn't reach Drive (connection failed): {}",
+            e
+        )));
+    }
+    if resp.ok() {
+        return Ok(resp);
+    }
+    let (status, text) = (resp.status(), resp.status_text().to_string());
+    let body = resp.into_string().unwrap_or_default();
+    let error: Value = serde_json::from_str(&body).unwrap_or_default();
+    let message = match (
+        error["error"]["errors"][0]["reason"].as_str(),
+        error["error"]["message"].as_str(),
+    ) {
+        (Some(reason), Some(message)) => format!("{}, {}", reason, message),
+        (None, Some(message)) => message.to_string(),
+        _ => body,
+    };
+    Err(RemoteError::Api(format!(
+        "{} {}: {}",
+        status, text, message
+    )))
+}
+
+fn json_of(resp: ureq::Response) -> Result<Value, RemoteError> {
+    resp.into_json()
+        .map_err(|e| RemoteError::Api(format!("Unexpected answer from Drive: {}", e)))
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Error;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+use crate::config::Environment;
+use crate::{environment_path, write_atomic};
+
+// Network environments ([environments.<name>]): the settings for home, a hotspot, the office, switched between at
+// runtime with `rgdrive env set <name>` instead of editing the config and restarting. The one set is remembered across
+// restarts. With none set, nothing is limited or held.
+
+pub struct Active {
+    current: Mutex<Option<(String, Environment)>>,
+    // When the next upload may start, to keep under max_upload_kbps on average.
+    next_upload: Mutex<Instant>,
+}
+
+lazy_static! {
+    pub static ref ENVIRONMENT: Active = Active {
+        current: Mutex::new(None),
+        next_upload: Mutex::new(Instant::now()),
+    };
+}
+
+impl Active {
+    // Switch to the environment set before the daemon last stopped, if the config still has it. Returns its name.
+    pub fn restore(&self, environments: &BTreeMap<String, Environment>) -> Option<String> {
+        let name = fs::read_to_string(environment_path()).ok()?;
+        let name = name.trim();
+        match environments.get(name) {
+            Some(env) => {
+                *self.current.lock().unwrap() = Some((name.to_string(), env.clone()));
+                Some(name.to_string())
+            }
+            None => {
+                log::warn!(
+                    "Environment {:?} is no longer in the config, none is set.",
+                    name
+                );
+                None
+            }
+        }
+    }
+
+    // Switch to the environment name (none if None), and remember it.
+    pub fn set(
+        &self,
+        name: Option<&str>,
+        environments: &BTreeMap<String, Environment>,
+    ) -> Result<(), String> {
+        let next = match name {
+            Some(n) => match environments.get(n) {
+                Some(env) => Some((n.to_string(), env.clone())),
+                None => {
+                    return Err(format!(
+                        "No environment {:?} in the config, expected one of: {}.",
+                        n,
+                        environments
+                            .keys()
+                            .cloned()
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ))
+                }
+            },
+            None => None,
+        };
+        save(name).map_err(|e| format!("Couldn't save the environment: {}", e))?;
+        *self.current.lock().unwrap() = next;
+        Ok(())
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(n, _)| n.clone())
+    }
+
+    pub fn settings(&self) -> Option<Environment> {
+        self.current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(_, e)| e.clone())
+    }
+
+    pub fn poll_interval(&self) -> Option<Duration> {
+        self.settings()?.poll_interval_secs.map(Duration::from_secs)
+    }
+
+    // Why an upload of bytes is held back right now, None if it can go.
+    pub fn holds(&self, bytes: u64) -> Option<String> {
+        let (name, env) = self.current.lock().unwrap().clone()?;
+        if env.pause_uploads {
+            Some(format!("uploads are paused on {}", name))
+        } else if env.pause_above_bytes > 0 && bytes > env.pause_above_bytes {
+            Some(format!(
+                "files over {} bytes wait on {}",
+                env.pause_above_bytes, name
+            ))
+        } else {
+            None
+        }
+    }
+
+    // Wait until an upload of bytes fits under max_upload_kbps. Uploads aren't slowed down themselves, they're spaced
+    // out so that on average they stay under it.
+    pub fn pace(&self, bytes: u64) {
+        let kbps = match self.settings() {
+            Some(env) if env.max_upload_kbps > 0 => env.max_upload_kbps,
+            _ => return,
+        };
+        let wait = {
+            let mut next = self.next_upload.lock().unwrap();
+            let now = Instant::now();
+            let start = (*next).max(now);
+            *next = start + Duration::from_millis(bytes * 1000 / (kbps * 1024));
+            start - now
+        };
+        if wait > Duration::from_millis(0) {
+            log::debug!("Waiting {:?} to keep uploads under {} KiB/s.", wait, kbps);
+            thread::sleep(wait);
+        }
+    }
+}
+
+fn save(name: Option<&str>) -> Result<(), Error> {
+    let p = environment_path();
+    match name {
+        Some(n) => {
+            if let Some(parent) = p.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_atomic(&p, n.as_bytes())
+        }
+        None if p.exists() => fs::remove_file(p),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// Build output, VCS metadata and editor leftovers that are almost never meant to be uploaded. Patterns ending in / match
+// a directory anywhere in the path, the rest match file names. * matches any run of characters.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    ".git/",
+    ".hg/",
+    ".svn/",
+    "target/",
+    "node_modules/",
+    "__pycache__/",
+    "*.o",
+    "*.pyc",
+    ".DS_Store",
+];
+
+// Swap, backup, lock and half-written files editors and other tools leave next to the file being worked on. Part of
+// the default excludes, and always dropped by the directory watchers since they're never a finished file.
+pub const EDITOR_TEMP: &[&str] = &[
+    "*.swp",
+    "*.swo",
+    "*.swx",
+    "*~",
+    ".#*",
+    "#*#",
+    "4913",
+    "*.tmp",
+    "*.kate-swp",
+    ".goutputstream-*",
+    ".~lock.*#",
+    "*.part",
+    "*.crdownload",
+];
+
+// Per-directory ignore files, with gitignore-style patterns for the directory they're in and everything beneath it.
+pub const IGNORE_FILE: &str = ".rgdriveignore";
+
+fn names(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(n) => Some(n.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+// Whether path (relative to whatever is being pushed or watched) matches one of the default excludes.
+pub fn is_excluded(path: &Path) -> bool {
+    let names = names(path);
+    let (file, dirs) = match names.split_last() {
+        Some(s) => s,
+        None => return false,
+    };
+    is_editor_temp(file)
+        || DEFAULT_EXCLUDES.iter().any(|p| match p.strip_suffix('/') {
+            Some(dir) => dirs.iter().any(|d| glob_match(dir, d)),
+            None => glob_match(p, file),
+        })
+}
+
+// Whether a file name is an editor's temp file (see EDITOR_TEMP).
+pub fn is_editor_temp(name: &str) -> bool {
+    EDITOR_TEMP.iter().any(|p| glob_match(p, name))
+}
+
+// Match name against pattern, where * matches any (possibly empty) run of characters.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == name,
+        Some(i) => {
+            let (prefix, rest) = (&pattern[..i], &pattern[i + 1..]);
+            if !name.starts_with(prefix) {
+                return false;
+            }
+            let name = &name[prefix.len()..];
+            (0..=name.len())
+                .filter(|&j| name.is_char_boundary(j))
+                .any(|j| glob_match(rest, &name[j..]))
+        }
+    }
+}
+
+// One line of an ignore file or [excludes] ignore, as in gitignore: a pattern with a / in it (other than a trailing one)
+// matches the path from the directory it was written for, any other only the name. ** matches any number of
+// directories, a trailing / only matches directories and a leading ! takes a path back out of what's ignored.
+struct Rule {
+    // Directory the rule applies beneath, relative to the root being pushed or watched.
+    base: PathBuf,
+    pattern: String,
+    negate: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str, base: &Path) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negate, line) = match line.strip_prefix('!') {
+            Some(l) => (true, l),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(l) => (true, l),
+            None => (false, line),
+        };
+        let pattern = line.trim_start_matches('/');
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            base: base.to_path_buf(),
+            pattern: pattern.to_string(),
+            negate,
+            dir_only,
+            anchored: line.contains('/'),
+        })
+    }
+
+    // Whether the rule matches rel, relative to the root.
+    fn matches(&self, rel: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let names = match rel.strip_prefix(&self.base) {
+            Ok(r) => names(r),
+            Err(_) => return false,
+        };
+        if self.anchored {
+            let pattern: Vec<&str> = self.pattern.split('/').collect();
+            segments_match(&pattern, &names)
+        } else {
+            names
+                .last()
+                .map(|n| glob_match(&self.pattern, n))
+                .unwrap_or(false)
+        }
+    }
+}
+
+fn segments_match(pattern: &[&str], names: &[String]) -> bool {
+    match pattern.split_first() {
+        None => names.is_empty(),
+        Some((&"**", rest)) => (0..=names.len()).any(|i| segments_match(rest, &names[i..])),
+        Some((p, rest)) => match names.split_first() {
+            Some((n, names)) => glob_match(p, n) && segments_match(rest, names),
+            None => false,
+        },
+    }
+}
+
+// What's ignored beneath root: the configured patterns, then the ignore file of every directory from root down,
+// later rules overriding earlier ones. Ignore files are read once per Ignores, so a push sees them as they were when
+// it started.
+pub struct Ignores {
+    root: PathBuf,
+    global: Vec<Rule>,
+    files: HashMap<PathBuf, Vec<Rule>>,
+}
+
+impl Ignores {
+    pub fn new(root: &Path, patterns: &[String]) -> Ignores {
+        Ignores {
+            root: root.to_path_buf(),
+            global: patterns
+                .iter()
+                .filter_map(|p| Rule::parse(p, Path::new("")))
+                .collect(),
+            files: HashMap::new(),
+        }
+    }
+
+    // Whether path, beneath root, is ignored or inside an ignored directory. Like git, nothing in an ignored directory
+    // can be taken back out of it with !.
+    pub fn is_ignored(&mut self, path: &Path) -> bool {
+        let rel = path.strip_prefix(&self.root).unwrap_or(path);
+        let names = names(rel);
+        let mut dirs = vec![PathBuf::new()];
+        let mut prefix = PathBuf::new();
+        for (i, name) in names.iter().enumerate() {
+            prefix.push(name);
+            let is_dir = i + 1 < names.len() || self.root.join(&prefix).is_dir();
+            for dir in &dirs {
+                if !self.files.contains_key(dir) {
+                    let rules = fs::read_to_string(self.root.join(dir).join(IGNORE_FILE))
+                        .unwrap_or_default()
+                        .lines()
+                        .filter_map(|l| Rule::parse(l, dir))
+                        .collect();
+                    self.files.insert(dir.clone(), rules);
+                }
+            }
+            let rules = self
+                .global
+                .iter()
+                .chain(dirs.iter().flat_map(|d| &self.files[d]));
+            let ignored = rules.fold(false, |ignored, r| {
+                if r.matches(&prefix, is_dir) {
+                    !r.negate
+                } else {
+                    ignored
+                }
+            });
+            if ignored {
+                return true;
+            }
+            dirs.push(prefix.clone());
+        }
+        false
+    }
+}
+
+// Whether path is ignored beneath root, see Ignores.
+pub fn is_ignored(root: &Path, path: &Path, patterns: &[String]) -> bool {
+    Ignores::new(root, patterns).is_ignored(path)
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+pub const SHEET_MIME: &str = "application/vnd.google-apps.spreadsheet";
+pub const DOC_MIME: &str = "application/vnd.google-apps.document";
+pub const SLIDES_MIME: &str = "application/vnd.google-apps.presentation";
+
+// Formats each Docs editors type can be exported as.
+pub const SHEET_FORMATS: &[&str] = &["csv", "tsv", "xlsx", "ods", "pdf"];
+pub const DOC_FORMATS: &[&str] = &["docx", "odt", "pdf", "txt", "md", "html", "rtf", "epub"];
+pub const SLIDES_FORMATS: &[&str] = &["pptx", "odp", "pdf", "txt"];
+
+// Export formats available for a remote mimeType. None if files of that type can't be exported (they're plain
+// downloads).
+pub fn formats_for(mime_type: &str) -> Option<&'static [&'static str]> {
+    match mime_type {
+        SHEET_MIME => Some(SHEET_FORMATS),
+        DOC_MIME => Some(DOC_FORMATS),
+        SLIDES_MIME => Some(SLIDES_FORMATS),
+        _ => None,
+    }
+}
+
+// mimeType Drive exports a format as.
+pub fn mime_for(format: &str) -> Option<&'static str> {
+    Some(match format {
+        "csv" => "text/csv",
+        "tsv" => "text/tab-separated-values",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ods" => "application/x-vnd.oasis.opendocument.spreadsheet",
+        "pdf" => "application/pdf",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "html" => "text/html",
+        "rtf" => "application/rtf",
+        "epub" => "application/epub+zip",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "odp" => "application/vnd.oasis.opendocument.presentation",
+        _ => return None,
+    })
+}
+
+// How to export a Google Docs editors file on pull.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Export {
+    pub format: String,
+    // Sheet (tab) name, the first sheet if None.
+    pub sheet: Option<String>,
+    // A1 notation, e.g. "A1:D20". The whole sheet if None.
+    pub range: Option<String>,
+}
+
+impl Export {
+    // Check the options make sense for a remote file of mime_type before anything is fetched.
+    pub fn validate(&self, mime_type: &str) -> Result<(), String> {
+        let formats = match formats_for(mime_type) {
+            Some(f) => f,
+            None => {
+                return Err(format!(
+                    "Only Google Docs, Sheets and Slides can be exported, this file is {}.",
+                    mime_type
+                ))
+            }
+        };
+        if !formats.contains(&self.format.as_str()) {
+            return Err(format!(
+                "Can't export a {} as {:?}, expected one of {}.",
+                mime_type,
+                self.format,
+                formats.join(", ")
+            ));
+        }
+        if (self.sheet.is_some() || self.range.is_some())
+            && !(mime_type == SHEET_MIME && (self.format == "csv" || self.format == "tsv"))
+        {
+            return Err(String::from(
+                "A sheet or range can only be exported from a Sheet, as csv or tsv.",
+            ));
+        }
+        Ok(())
+    }
+}
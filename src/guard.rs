@@ -0,0 +1,59 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::exclude::glob_match;
+use crate::versions::versions_dir;
+use crate::{canonical_path, config_root, crash_path, log_path, socket_path};
+
+// rgdrive's own files. Syncing them is a feedback loop: the daemon writes its state, log or socket, notices the change
+// and uploads it, which it records in its state, and so on. Pulling over them corrupts the daemon. rgdrive refuses
+// both unless given --allow-own-state.
+
+// Sockets, logs and downloads in progress of every profile, e.g. /tmp/rgdrive-work.sock or /tmp/rgdrived.err.
+const TEMP_FILES: &str = "rgdrive*";
+
+// What rgdrive keeps where.
+fn locations() -> Vec<(PathBuf, &'static str)> {
+    vec![
+        (config_root(), "rgdrive's config and state"),
+        (versions_dir(), "rgdrive's saved versions"),
+        (socket_path(), "the daemon's socket"),
+        (log_path(), "the daemon's log"),
+        (crash_path(), "the daemon's crash report"),
+    ]
+}
+
+// Why path can't be synced, if it's one of rgdrive's own files or inside one of its directories.
+pub fn own_state(path: &Path) -> Option<String> {
+    let path = canonical_path(path);
+    for (p, what) in locations() {
+        if path.starts_with(canonical_path(&p)) {
+            return Some(format!("{:?} is part of {}", path, what));
+        }
+    }
+    let temp = canonical_path(&env::temp_dir());
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name))
+            if dir == temp && glob_match(TEMP_FILES, &name.to_string_lossy()) =>
+        {
+            Some(format!("{:?} is one of rgdrive's temp files", path))
+        }
+        _ => None,
+    }
+}
+
+// Like own_state, but also refuses a directory with rgdrive's own files somewhere beneath it, for directory pushes.
+pub fn holds_own_state(path: &Path) -> Option<String> {
+    if let Some(why) = own_state(path) {
+        return Some(why);
+    }
+    let path = canonical_path(path);
+    locations().into_iter().find_map(|(p, what)| {
+        let p = canonical_path(&p);
+        if p.starts_with(&path) {
+            Some(format!("{:?} contains {} ({:?})", path, what, p))
+        } else {
+            None
+        }
+    })
+}
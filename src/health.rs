@@ -0,0 +1,192 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+use crate::config::Thresholds;
+use crate::pins::PINS;
+use crate::stats::LATENCY;
+
+pub const REAUTH_REQUIRED: &str = "re-authentication required";
+
+// Cap on remembered outcomes, so a burst of operations can't grow the window without bound.
+const MAX_OUTCOMES: usize = 10_000;
+
+// Rolling view of how the daemon is doing, checked against the [health] thresholds.
+pub struct Health {
+    // (when, succeeded) for recently journaled operations, oldest first.
+    outcomes: Mutex<VecDeque<(Instant, bool)>>,
+    // When each connection still waiting for a worker was queued, oldest first.
+    queued: Mutex<VecDeque<Instant>>,
+    unhealthy: AtomicBool,
+    // Drive's last refusal of the token (e.g. revoked by a password change). Sync is paused while it's set.
+    reauth: Mutex<Option<String>>,
+    // When the inotify watcher last came round its loop.
+    watcher: Mutex<Instant>,
+    // When the socket listener accepted the connection it's still handing to the workers, None while it waits for one.
+    accepting: Mutex<Option<Instant>>,
+}
+
+lazy_static! {
+    pub static ref HEALTH: Health = Health {
+        outcomes: Mutex::new(VecDeque::new()),
+        queued: Mutex::new(VecDeque::new()),
+        unhealthy: AtomicBool::new(false),
+        reauth: Mutex::new(None),
+        watcher: Mutex::new(Instant::now()),
+        accepting: Mutex::new(None),
+    };
+}
+
+impl Health {
+    pub fn record(&self, ok: bool) {
+        let mut outcomes = self.outcomes.lock().unwrap();
+        if outcomes.len() >= MAX_OUTCOMES {
+            outcomes.pop_front();
+        }
+        outcomes.push_back((Instant::now(), ok));
+    }
+
+    // A connection was handed to the worker queue.
+    pub fn enqueued(&self) {
+        self.queued.lock().unwrap().push_back(Instant::now());
+    }
+
+    // The connection just enqueued was turned away instead.
+    pub fn rejected(&self) {
+        self.queued.lock().unwrap().pop_back();
+    }
+
+    // A worker picked up the oldest queued connection.
+    pub fn dequeued(&self) {
+        self.queued.lock().unwrap().pop_front();
+    }
+
+    // Drive refused the token with error. Returns whether sync was running until now.
+    pub fn auth_failed(&self, error: &str) -> bool {
+        self.reauth
+            .lock()
+            .unwrap()
+            .replace(error.to_string())
+            .is_none()
+    }
+
+    // Signed in again with `rgdrive --login`.
+    pub fn reauthenticated(&self) {
+        *self.reauth.lock().unwrap() = None;
+    }
+
+    pub fn reauth_required(&self) -> bool {
+        self.reauth.lock().unwrap().is_some()
+    }
+
+    // Every threshold currently exceeded, empty when healthy.
+    pub fn evaluate(&self, t: &Thresholds) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if let Some(e) = self.reauth.lock().unwrap().as_ref() {
+            reasons.push(format!(
+                "{}, sync is paused until `rgdrive --login` ({})",
+                REAUTH_REQUIRED, e
+            ));
+        }
+        let window = Duration::from_secs(t.failure_window_secs);
+
+        let (ops, failures) = {
+            let mut outcomes = self.outcomes.lock().unwrap();
+            while let Some((when, _)) = outcomes.front() {
+                if when.elapsed() <= window {
+                    break;
+                }
+                outcomes.pop_front();
+            }
+            let failures = outcomes.iter().filter(|(_, ok)| !ok).count();
+            (outcomes.len(), failures)
+        };
+        if ops > 0 && ops as u64 >= t.min_operations {
+            let rate = failures as f64 / ops as f64;
+            if rate > t.max_failure_rate {
+                reasons.push(format!(
+                    "{} of {} operations failed in the last {}s",
+                    failures, ops, t.failure_window_secs
+                ));
+            }
+        }
+
+        if let Some(oldest) = self.queued.lock().unwrap().front() {
+            let age = oldest.elapsed().as_secs();
+            if age > t.max_queue_age_secs {
+                reasons.push(format!(
+                    "oldest queued request has waited {}s for a worker",
+                    age
+                ));
+            }
+        }
+
+        if t.max_sync_latency_ms > 0 {
+            if let Some(p) = LATENCY.percentiles(Some(window)) {
+                if p.count as u64 >= t.min_operations && p.p90 > t.max_sync_latency_ms {
+                    reasons.push(format!(
+                        "p90 sync latency was {}ms over the last {}s, above {}ms",
+                        p.p90, t.failure_window_secs, t.max_sync_latency_ms
+                    ));
+                }
+            }
+        }
+
+        for pin in PINS.lock().unwrap().failing() {
+            reasons.push(format!(
+                "pinned {:?} couldn't be refreshed ({})",
+                pin.path,
+                pin.last_error.as_deref().unwrap_or_default()
+            ));
+        }
+        reasons
+    }
+
+    // The inotify watcher is starting another pass over its events.
+    pub fn watcher_alive(&self) {
+        *self.watcher.lock().unwrap() = Instant::now();
+    }
+
+    // The socket listener accepted a connection (true), or is done with it and waiting for the next one (false).
+    pub fn accepting(&self, busy: bool) {
+        *self.accepting.lock().unwrap() = if busy { Some(Instant::now()) } else { None };
+    }
+
+    // The daemon's loops that have been stuck for longer than stall: the inotify watcher, the worker queue and the
+    // socket listener. Empty when they're all moving. An idle listener or queue isn't stuck, it's waiting for clients.
+    pub fn stalled(&self, stall: Duration) -> Vec<String> {
+        let mut reasons = Vec::new();
+        let watcher = self.watcher.lock().unwrap().elapsed();
+        if watcher > stall {
+            reasons.push(format!(
+                "inotify watcher hasn't come round for {}s",
+                watcher.as_secs()
+            ));
+        }
+        if let Some(oldest) = self.queued.lock().unwrap().front() {
+            if oldest.elapsed() > stall {
+                reasons.push(format!(
+                    "oldest queued request has waited {}s for a worker",
+                    oldest.elapsed().as_secs()
+                ));
+            }
+        }
+        if let Some(since) = *self.accepting.lock().unwrap() {
+            if since.elapsed() > stall {
+                reasons.push(format!(
+                    "socket listener has been stuck on a connection for {}s",
+                    since.elapsed().as_secs()
+                ));
+            }
+        }
+        reasons
+    }
+
+    // Set the unhealthy flag, returns the previous value so callers can act on transitions.
+    pub fn set_unhealthy(&self, unhealthy: bool) -> bool {
+        self.unhealthy.swap(unhealthy, Ordering::SeqCst)
+    }
+}
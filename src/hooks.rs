@@ -0,0 +1,31 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Run the pre_upload hook (see [hooks]) on path. Anything but a zero exit blocks the upload, with the hook's own output
+// as the reason. A hook that can't be run blocks it too, a scan that didn't happen is never taken as a pass.
+pub fn pre_upload(command: &[String], path: &Path) -> Result<(), String> {
+    if command.is_empty() {
+        return Ok(());
+    }
+    let out = Command::new(&command[0])
+        .args(&command[1..])
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| {
+            format!(
+                "Upload blocked, pre_upload hook {} failed to run: {}",
+                command[0], e
+            )
+        })?;
+    if out.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let reason = match (stderr.trim(), stdout.trim()) {
+        ("", "") => format!("{} exited with {}", command[0], out.status),
+        ("", s) | (s, _) => s.to_string(),
+    };
+    Err(format!("Upload blocked by pre_upload hook: {}", reason))
+}
@@ -0,0 +1,192 @@
+// rgdrive install/uninstall: put the binaries, man page and (optionally) a systemd user unit in place, or take them out.
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::prelude::*;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use rgdrive::{
+    config_dir, credentials_path, dirs_path, environment_path, folders_path, journal_path,
+    pending_dir, pins_path, queue_path, replicas_path, settings_path, socket_path, staged_path,
+    state_path, watched_path, DCommand, DResult, DSocket,
+};
+
+const UNIT_NAME: &str = "rgdrived.service";
+
+fn home() -> PathBuf {
+    PathBuf::from(env::var("HOME").expect("$HOME not set"))
+}
+
+// Default install location for both binaries.
+pub fn local_bin_dir() -> PathBuf {
+    home().join(".local/bin")
+}
+
+fn man_path() -> PathBuf {
+    home().join(".local/share/man/man1/rgdrive.1")
+}
+
+fn unit_path() -> PathBuf {
+    home().join(".config/systemd/user").join(UNIT_NAME)
+}
+
+// Credentials for the systemd unit, which doesn't inherit the login shell's environment. Written 0600.
+fn env_file_path() -> PathBuf {
+    config_dir().with_file_name("rgdrived.env")
+}
+
+fn systemctl(args: &[&str]) -> Result<(), String> {
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .map_err(|e| format!("Failed to run systemctl: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("`systemctl --user {}` failed.", args.join(" ")))
+    }
+}
+
+// Copy src to dest unless they're already the same file.
+fn copy_bin(src: &Path, dest: &Path) -> Result<(), String> {
+    if let (Ok(a), Ok(b)) = (src.canonicalize(), dest.canonicalize()) {
+        if a == b {
+            return Ok(());
+        }
+    }
+    // Copy then rename, so a running binary is never overwritten in place.
+    let tmp = dest.with_extension("install");
+    fs::copy(src, &tmp).map_err(|e| format!("Failed to copy {:?}: {}", src, e))?;
+    fs::rename(&tmp, dest).map_err(|e| format!("Failed to install {:?}: {}", dest, e))
+}
+
+fn write_unit(daemon: &Path) -> Result<(), String> {
+    let (id, secret) = match (
+        env::var("GOOGLE_CLIENT_ID"),
+        env::var("GOOGLE_CLIENT_SECRET"),
+    ) {
+        (Ok(id), Ok(secret)) => (id, secret),
+        _ => return Err(String::from(
+            "$GOOGLE_CLIENT_ID and $GOOGLE_CLIENT_SECRET must be set to install the systemd unit.",
+        )),
+    };
+    let mut f = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(env_file_path())
+        .map_err(|e| format!("Failed to write {:?}: {}", env_file_path(), e))?;
+    write!(
+        f,
+        "GOOGLE_CLIENT_ID={}\nGOOGLE_CLIENT_SECRET={}\nRUST_LOG=info\n",
+        id, secret
+    )
+    .map_err(|e| e.to_string())?;
+
+    let unit = format!(
+        "[Unit]\nDescription=rgdrive Google Drive sync daemon\n\n\
+         [Service]\nExecStart={}\nEnvironmentFile={}\nRestart=on-failure\nWatchdogSec=60\n\n\
+         [Install]\nWantedBy=default.target\n",
+        daemon.display(),
+        env_file_path().display()
+    );
+    fs::create_dir_all(unit_path().parent().unwrap()).map_err(|e| e.to_string())?;
+    fs::write(unit_path(), unit)
+        .map_err(|e| format!("Failed to write {:?}: {}", unit_path(), e))?;
+    systemctl(&["daemon-reload"])?;
+    systemctl(&["enable", "--now", UNIT_NAME])
+}
+
+fn do_install(dest: &Path, systemd: bool) -> Result<String, String> {
+    let daemon = crate::get_bin_path()
+        .ok_or("Couldn't find rgdrived next to rgdrive, in ~/.local/bin or on $PATH.")?;
+    let cli = env::current_exe().map_err(|e| e.to_string())?;
+
+    fs::create_dir_all(dest).map_err(|e| format!("Failed to create {:?}: {}", dest, e))?;
+    copy_bin(&cli, &dest.join("rgdrive"))?;
+    copy_bin(&daemon, &dest.join("rgdrived"))?;
+
+    fs::create_dir_all(config_dir().parent().unwrap()).map_err(|e| e.to_string())?;
+    fs::create_dir_all(man_path().parent().unwrap()).map_err(|e| e.to_string())?;
+    fs::write(man_path(), crate::MAN_PAGE).map_err(|e| e.to_string())?;
+
+    let mut msg = format!("Installed rgdrive and rgdrived to {:?}.", dest);
+    if systemd {
+        write_unit(&dest.join("rgdrived"))?;
+        msg.push_str(&format!(" Enabled systemd user unit {}.", UNIT_NAME));
+    }
+    Ok(msg)
+}
+
+fn do_uninstall(dest: &Path, purge: bool) -> Result<String, String> {
+    // Stop the daemon first. Under systemd, disabling the unit stops it too.
+    if unit_path().exists() {
+        systemctl(&["disable", "--now", UNIT_NAME])?;
+        fs::remove_file(unit_path()).map_err(|e| e.to_string())?;
+        let _ = fs::remove_file(env_file_path());
+        let _ = systemctl(&["daemon-reload"]);
+    }
+    let socket = DSocket::new(socket_path());
+    if socket.is_active() {
+        socket
+            .send_command_timeout(DCommand::Quit, crate::STOP_TIMEOUT)
+            .map_err(|e| format!("Failed to stop daemon: {}", e))?;
+        thread::sleep(Duration::from_millis(200));
+    }
+
+    for p in &[dest.join("rgdrive"), dest.join("rgdrived"), man_path()] {
+        if p.exists() {
+            fs::remove_file(p).map_err(|e| format!("Failed to remove {:?}: {}", p, e))?;
+        }
+    }
+
+    let mut msg = format!("Uninstalled rgdrive from {:?}.", dest);
+    if purge {
+        for p in &[
+            config_dir(),
+            settings_path(),
+            journal_path(),
+            watched_path(),
+            replicas_path(),
+            state_path(),
+            environment_path(),
+            pins_path(),
+            env_file_path(),
+            credentials_path(),
+            queue_path(),
+            staged_path(),
+            folders_path(),
+            dirs_path(),
+        ] {
+            if p.exists() {
+                fs::remove_file(p).map_err(|e| format!("Failed to remove {:?}: {}", p, e))?;
+            }
+        }
+        if pending_dir().exists() {
+            fs::remove_dir_all(pending_dir()).map_err(|e| e.to_string())?;
+        }
+        msg.push_str(" Removed tracked files, config and journal.");
+    }
+    Ok(msg)
+}
+
+pub fn install(dest: Option<&str>, systemd: bool) -> DResult {
+    let dest = dest.map(PathBuf::from).unwrap_or_else(local_bin_dir);
+    match do_install(&dest, systemd) {
+        Ok(msg) => DResult::ok(msg),
+        Err(e) => DResult::error(format!("install failed: {}", e)),
+    }
+}
+
+pub fn uninstall(dest: Option<&str>, purge: bool) -> DResult {
+    let dest = dest.map(PathBuf::from).unwrap_or_else(local_bin_dir);
+    match do_uninstall(&dest, purge) {
+        Ok(msg) => DResult::ok(msg),
+        Err(e) => DResult::error(format!("uninstall failed: {}", e)),
+    }
+}
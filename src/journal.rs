@@ -0,0 +1,204 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{BufReader, Error};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::journal_path;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+    // Local bookkeeping only, nothing was transferred.
+    None,
+}
+
+// A single operation performed by the daemon. Stored one json object per line in the journal file.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Entry {
+    // Unix timestamp (seconds).
+    pub time: i64,
+    pub user: String,
+    pub op: String,
+    #[serde(with = "crate::rawpath")]
+    pub path: PathBuf,
+    pub drive_url: String,
+    pub direction: Direction,
+    pub bytes: u64,
+    // "ok" or the error message.
+    pub result: String,
+    // Uploads of a local change: milliseconds from when the change was seen to when its upload completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+}
+
+impl Entry {
+    pub fn new<O: Into<String>, U: Into<String>>(
+        op: O,
+        path: &Path,
+        drive_url: U,
+        direction: Direction,
+        result: Result<(), String>,
+    ) -> Entry {
+        Entry {
+            time: Utc::now().timestamp(),
+            user: current_user(),
+            op: op.into(),
+            path: path.to_path_buf(),
+            drive_url: drive_url.into(),
+            direction,
+            bytes: match direction {
+                Direction::None => 0,
+                _ => fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            },
+            result: match result {
+                Ok(_) => String::from("ok"),
+                Err(e) => e,
+            },
+            latency_ms: None,
+        }
+    }
+
+    pub fn with_latency(mut self, latency: Option<Duration>) -> Entry {
+        self.latency_ms = latency.map(|d| d.as_millis() as u64);
+        self
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.result == "ok"
+    }
+
+    // Header row matching to_csv().
+    pub fn csv_header() -> &'static str {
+        "time,user,op,path,drive_url,direction,bytes,result"
+    }
+
+    pub fn to_csv(&self) -> String {
+        let fields = [
+            Utc.timestamp_opt(self.time, 0)
+                .single()
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_else(|| self.time.to_string()),
+            self.user.clone(),
+            self.op.clone(),
+            crate::rawpath::escape(&self.path),
+            self.drive_url.clone(),
+            format!("{:?}", self.direction),
+            self.bytes.to_string(),
+            self.result.clone(),
+        ];
+        fields
+            .iter()
+            .map(|f| csv_escape(f))
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+// Quote a csv field if it contains anything that would break the row.
+fn csv_escape(f: &str) -> String {
+    if f.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", f.replace('"', "\"\""))
+    } else {
+        f.to_string()
+    }
+}
+
+// Name of the user the process is running as, falls back to the uid if there is no passwd entry.
+fn current_user() -> String {
+    unsafe {
+        let uid = libc::getuid();
+        let pw = libc::getpwuid(uid);
+        if pw.is_null() || (*pw).pw_name.is_null() {
+            return uid.to_string();
+        }
+        CStr::from_ptr((*pw).pw_name).to_string_lossy().into_owned()
+    }
+}
+
+// Append entry to the journal file.
+pub fn record(entry: &Entry) -> Result<(), Error> {
+    let path = journal_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(&path)?;
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    f.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+// All journal entries with from <= time <= to. Lines that fail to parse are skipped.
+pub fn entries_between(from: i64, to: i64) -> Result<Vec<Entry>, Error> {
+    let path = journal_path();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let reader = BufReader::new(File::open(&path)?);
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        match serde_json::from_str::<Entry>(&line?) {
+            Ok(e) => {
+                if e.time >= from && e.time <= to {
+                    entries.push(e);
+                }
+            }
+            Err(e) => log::warn!("Skipping malformed journal line: {:?}", e),
+        }
+    }
+    Ok(entries)
+}
+
+// Repeated failures of one op on one path, collapsed into a single line for --status.
+#[derive(Debug, PartialEq)]
+pub struct FailureGroup {
+    pub op: String,
+    pub path: PathBuf,
+    pub count: usize,
+    // Most recent error message of the group.
+    pub last_error: String,
+}
+
+// Group the failed entries by (op, path), most frequent first.
+pub fn summarize_failures(entries: &[Entry]) -> Vec<FailureGroup> {
+    let mut groups: Vec<FailureGroup> = Vec::new();
+    let mut index: HashMap<(&str, &Path), usize> = HashMap::new();
+    for e in entries.iter().filter(|e| !e.is_ok()) {
+        match index.get(&(e.op.as_str(), e.path.as_path())) {
+            Some(&i) => {
+                groups[i].count += 1;
+                groups[i].last_error = e.result.clone();
+            }
+            None => {
+                index.insert((e.op.as_str(), e.path.as_path()), groups.len());
+                groups.push(FailureGroup {
+                    op: e.op.clone(),
+                    path: e.path.clone(),
+                    count: 1,
+                    last_error: e.result.clone(),
+                });
+            }
+        }
+    }
+    groups.sort_by_key(|g| Reverse(g.count));
+    groups
+}
+
+// Files whose most recent operation failed, i.e. the ones that are currently out of sync.
+pub fn last_errors(entries: &[Entry]) -> Vec<&Entry> {
+    let mut last: HashMap<&Path, &Entry> = HashMap::new();
+    for e in entries {
+        last.insert(e.path.as_path(), e);
+    }
+    let mut failed: Vec<&Entry> = last.values().copied().filter(|e| !e.is_ok()).collect();
+    failed.sort_by_key(|e| e.time);
+    failed
+}
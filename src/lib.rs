@@ -1,41 +1,390 @@
 extern crate log;
 
+pub mod batch;
+pub mod cache;
+pub mod capabilities;
+pub mod checksum;
+pub mod clipboard;
+pub mod coalesce;
+pub mod config;
+pub mod crash;
+pub mod daemons;
+pub mod debounce;
+pub mod drive;
+pub mod environment;
+pub mod exclude;
+pub mod export;
+pub mod guard;
+pub mod health;
+pub mod hooks;
+pub mod journal;
+pub mod media;
+pub mod migrate;
+pub mod names;
+pub mod oauth;
+pub mod paths;
+pub mod pins;
+pub mod placeholder;
+pub mod plan;
+pub mod poll;
+pub mod pool;
+pub mod public;
+pub mod queue;
+pub mod rawpath;
+pub mod remote;
+pub mod replica;
+pub mod report;
+pub mod review;
+pub mod session;
+pub mod shutdown;
+pub mod stamp;
+pub mod state;
+pub mod stats;
+pub mod status;
+pub mod transfer;
+pub mod trash;
+pub mod versions;
+pub mod watchdog;
+pub mod window;
+pub mod xattr;
+
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 
 use std::net::Shutdown;
+use std::os::unix::fs::MetadataExt;
 use std::os::unix::net::UnixStream;
 
 use std::time::Duration;
 
+use bincode::Options;
 use inotify::{Inotify, WatchDescriptor, WatchMask};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::config::Events;
+use crate::export::Export;
+use crate::media::Media;
+use crate::state::State;
+use crate::transfer::Overwrite;
+
 pub const SOCKET_PATH: &str = "/tmp/rgdrive.sock";
+pub const STDERR_PATH: &str = "/tmp/rgdrived.err";
+pub const CRASH_PATH: &str = "/tmp/rgdrived.crash";
 pub const CONFIG_PATH: &str = "/.config/cameron-williams/tracked_files";
+pub const SETTINGS_PATH: &str = "/.config/cameron-williams/rgdrive.toml";
+pub const JOURNAL_PATH: &str = "/.config/cameron-williams/journal";
+pub const PENDING_PATH: &str = "/.config/cameron-williams/pending";
+pub const WATCHED_PATH: &str = "/.config/cameron-williams/watched";
+pub const STAGED_PATH: &str = "/.config/cameron-williams/staged";
+pub const QUEUE_PATH: &str = "/.config/cameron-williams/queue";
+pub const FOLDERS_PATH: &str = "/.config/cameron-williams/folders";
+pub const DAEMONS_PATH: &str = "/.config/cameron-williams/daemons";
+pub const CREDENTIALS_PATH: &str = "/.config/cameron-williams/credentials";
+pub const DIRS_PATH: &str = "/.config/cameron-williams/dirs";
+pub const REPLICAS_PATH: &str = "/.config/cameron-williams/replicas";
+pub const STATE_PATH: &str = "/.config/cameron-williams/state.db";
+pub const ENVIRONMENT_PATH: &str = "/.config/cameron-williams/environment";
+pub const PINS_PATH: &str = "/.config/cameron-williams/pins";
 
-pub fn config_dir() -> PathBuf {
+// Everything above lives here. A named profile keeps its own copy in profiles/<name> beneath it.
+const CONFIG_ROOT: &str = "/.config/cameron-williams";
+pub const DEFAULT_PROFILE: &str = "default";
+
+// Largest frame either side of the socket will send or accept.
+pub const MAX_FRAME_BYTES: u64 = 16 * 1024 * 1024;
+
+// A client can start its request with HELLO and a u32 of ACCEPT_ flags, saying what it can read back. Daemons from
+// before it turn the request away as malformed, and the client sends it again without.
+pub const HELLO: &[u8; 4] = b"RGD\x01";
+// Responses may come as a zstd frame, see encode_response.
+pub const ACCEPT_ZSTD: u32 = 1;
+// Every zstd frame starts with this, a bincode DResult never does (its first byte is the variant, 0 to 2).
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+fn home_path(p: &str) -> PathBuf {
+    profile_path(p, profile().as_deref())
+}
+
+// home_path of the given profile, None being the default one.
+fn profile_path(p: &str, profile: Option<&str>) -> PathBuf {
+    match profile.filter(|name| *name != DEFAULT_PROFILE) {
+        Some(name) => shared_path(&p.replacen(
+            CONFIG_ROOT,
+            &format!("{}/profiles/{}", CONFIG_ROOT, name),
+            1,
+        )),
+        None => shared_path(p),
+    }
+}
+
+// Like home_path, but the same for every profile.
+fn shared_path(p: &str) -> PathBuf {
     let mut dir = env::var("HOME").expect("$HOME not set");
-    dir.push_str(CONFIG_PATH);
+    dir.push_str(p);
     PathBuf::from(dir)
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+// Profile this process runs as, set by --profile through $RGDRIVE_PROFILE. None is the default profile.
+pub fn profile() -> Option<String> {
+    match env::var("RGDRIVE_PROFILE") {
+        Ok(p) if !p.is_empty() && p != DEFAULT_PROFILE => Some(p),
+        _ => None,
+    }
+}
+
+// Profile names end up in file names, so keep them to letters, digits, - and _.
+pub fn valid_profile(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// Socket the daemon listens on, one per profile. $RGDRIVE_SOCKET overrides the default, mostly so tests can run their
+// own daemon.
+pub fn socket_path() -> PathBuf {
+    match (env::var("RGDRIVE_SOCKET"), profile()) {
+        (Ok(p), _) => PathBuf::from(p),
+        (Err(_), Some(name)) => PathBuf::from(format!("/tmp/rgdrive-{}.sock", name)),
+        (Err(_), None) => PathBuf::from(SOCKET_PATH),
+    }
+}
+
+// The daemon's stderr, one per profile (or beside an $RGDRIVE_SOCKET).
+pub fn log_path() -> PathBuf {
+    if let Ok(socket) = env::var("RGDRIVE_SOCKET") {
+        return PathBuf::from(format!("{}.err", socket));
+    }
+    match profile() {
+        Some(name) => PathBuf::from(format!("/tmp/rgdrived-{}.err", name)),
+        None => PathBuf::from(STDERR_PATH),
+    }
+}
+
+// The daemon's last crash report (see crash::Crash), beside its log.
+pub fn crash_path() -> PathBuf {
+    if let Ok(socket) = env::var("RGDRIVE_SOCKET") {
+        return PathBuf::from(format!("{}.crash", socket));
+    }
+    match profile() {
+        Some(name) => PathBuf::from(format!("/tmp/rgdrived-{}.crash", name)),
+        None => PathBuf::from(CRASH_PATH),
+    }
+}
+
+// Everything rgdrive keeps in $HOME, for every profile.
+pub fn config_root() -> PathBuf {
+    shared_path(CONFIG_ROOT)
+}
+
+// Where running daemons of every profile register themselves, see daemons::Instance.
+pub fn daemons_dir() -> PathBuf {
+    shared_path(DAEMONS_PATH)
+}
+
+// The bincode tracked files list the state database replaced, see state::migrate.
+pub fn config_dir() -> PathBuf {
+    home_path(CONFIG_PATH)
+}
+
+// The state database, see state::State.
+pub fn state_path() -> PathBuf {
+    home_path(STATE_PATH)
+}
+
+pub fn settings_path() -> PathBuf {
+    home_path(SETTINGS_PATH)
+}
+
+pub fn journal_path() -> PathBuf {
+    home_path(JOURNAL_PATH)
+}
+
+// Directory holding the write-ahead records of in progress batch pushes.
+pub fn pending_dir() -> PathBuf {
+    home_path(PENDING_PATH)
+}
+
+// Remote files already downloaded from watched folders.
+pub fn watched_path() -> PathBuf {
+    home_path(WATCHED_PATH)
+}
+
+// Changes held back for review, see review::Staged.
+pub fn staged_path() -> PathBuf {
+    home_path(STAGED_PATH)
+}
+
+// Failed operations waiting to be retried, see queue::Queue.
+pub fn queue_path() -> PathBuf {
+    home_path(QUEUE_PATH)
+}
+
+// Drive folder ids already looked up, see paths::PathCache.
+pub fn folders_path() -> PathBuf {
+    home_path(FOLDERS_PATH)
+}
+
+// Client credentials imported with `rgdrive --migrate-import`, used when they aren't in the environment.
+pub fn credentials_path() -> PathBuf {
+    home_path(CREDENTIALS_PATH)
+}
+
+// credentials_path() of another profile, e.g. the [replica] one.
+pub fn credentials_path_of(profile: &str) -> PathBuf {
+    profile_path(CREDENTIALS_PATH, Some(profile))
+}
+
+// Where each synced file stands on the [replica] remote, see replica::Replicas.
+pub fn replicas_path() -> PathBuf {
+    home_path(REPLICAS_PATH)
+}
+
+// Name of the network environment set with `rgdrive env set`, see environment.
+pub fn environment_path() -> PathBuf {
+    home_path(ENVIRONMENT_PATH)
+}
+
+// Files kept on disk and current with Drive, see pins.
+pub fn pins_path() -> PathBuf {
+    home_path(PINS_PATH)
+}
+
+// Directories whose new files are pushed and tracked as they appear, see TrackedDir.
+pub fn dirs_path() -> PathBuf {
+    home_path(DIRS_PATH)
+}
+
+// Replace the file at p with contents, via a temp file and rename so readers never see a partial write.
+pub fn write_atomic(p: &PathBuf, contents: &[u8]) -> Result<(), Error> {
+    let tmp = p.with_extension("tmp");
+    let mut f = File::create(&tmp)?;
+    f.write_all(contents)?;
+    f.sync_all()?;
+    fs::rename(&tmp, p)
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(Error),
+    // Frame was larger than the given limit.
+    TooLarge(u64),
+    Encode(String),
+    Decode(String),
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProtocolError::Io(e) => write!(f, "{}", e),
+            ProtocolError::TooLarge(limit) => write!(f, "frame larger than {} bytes", limit),
+            ProtocolError::Encode(e) => write!(f, "failed to encode frame: {}", e),
+            ProtocolError::Decode(e) => write!(f, "failed to decode frame: {}", e),
+        }
+    }
+}
+
+impl From<Error> for ProtocolError {
+    fn from(e: Error) -> ProtocolError {
+        ProtocolError::Io(e)
+    }
+}
+
+// Same fixed int layout as bincode::serialize, but bounded so a bad length prefix can't make us allocate gigabytes.
+fn wire_options(limit: u64) -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(limit)
+}
+
+// Serialize msg into a frame of at most limit bytes.
+pub fn encode<T: Serialize>(msg: &T, limit: u64) -> Result<Vec<u8>, ProtocolError> {
+    wire_options(limit).serialize(msg).map_err(|e| match *e {
+        bincode::ErrorKind::SizeLimit => ProtocolError::TooLarge(limit),
+        e => ProtocolError::Encode(e.to_string()),
+    })
+}
+
+// Deserialize a frame. Never panics, any malformed, truncated or oversized input is an Err.
+pub fn decode<T: DeserializeOwned>(buf: &[u8], limit: u64) -> Result<T, ProtocolError> {
+    if buf.len() as u64 > limit {
+        return Err(ProtocolError::TooLarge(limit));
+    }
+    wire_options(limit).deserialize(buf).map_err(|e| match *e {
+        bincode::ErrorKind::SizeLimit => ProtocolError::TooLarge(limit),
+        e => ProtocolError::Decode(e.to_string()),
+    })
+}
+
+// Read a whole frame (everything up to EOF), failing as soon as more than limit bytes arrive.
+pub fn read_frame<R: Read>(r: R, limit: u64) -> Result<Vec<u8>, ProtocolError> {
+    let mut buf: Vec<u8> = Vec::new();
+    r.take(limit + 1).read_to_end(&mut buf)?;
+    if buf.len() as u64 > limit {
+        return Err(ProtocolError::TooLarge(limit));
+    }
+    Ok(buf)
+}
+
+// Encode a response, zstd compressed if it's larger than compress_above bytes (and compressing makes it smaller). None
+// for clients that didn't say they can read that.
+pub fn encode_response(
+    r: &DResult,
+    limit: u64,
+    compress_above: Option<u64>,
+) -> Result<Vec<u8>, ProtocolError> {
+    let buf = encode(r, limit)?;
+    match compress_above {
+        Some(above) if buf.len() as u64 > above => {
+            let packed = zstd::encode_all(&buf[..], 3)?;
+            Ok(if packed.len() < buf.len() {
+                packed
+            } else {
+                buf
+            })
+        }
+        _ => Ok(buf),
+    }
+}
+
+// Decode a response, compressed or not. A compressed one may not unpack to more than limit bytes either.
+pub fn decode_response(buf: &[u8], limit: u64) -> Result<DResult, ProtocolError> {
+    if !buf.starts_with(&ZSTD_MAGIC) {
+        return decode(buf, limit);
+    }
+    let unpacked = read_frame(zstd::Decoder::new(buf)?, limit)?;
+    decode(&unpacked, limit)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub enum DResult {
     Ok(String),
     Err(String),
+    // Succeeded, with named fields (e.g. path and drive_url) for scripts alongside the message, see --format json.
+    Fields(String, Vec<(String, String)>),
 }
 
 impl DResult {
     // Send result on stream.
-    pub fn send(&self, mut s: &UnixStream) -> Result<(), Error> {
+    pub fn send(&self, s: &UnixStream) -> Result<(), ProtocolError> {
+        self.send_compressed(s, None)
+    }
+
+    // Send result on stream, compressed if it's larger than compress_above bytes, see encode_response.
+    pub fn send_compressed(
+        &self,
+        mut s: &UnixStream,
+        compress_above: Option<u64>,
+    ) -> Result<(), ProtocolError> {
         // Set write timeout just in case the client isn't listening/ready for a response for some reason.
         s.set_write_timeout(Some(Duration::from_secs(15)))?;
-        s.write_all(&bincode::serialize(&self).unwrap())?;
+        s.write_all(&encode_response(self, MAX_FRAME_BYTES, compress_above)?)?;
         Ok(())
     }
 
@@ -46,18 +395,114 @@ impl DResult {
     pub fn ok<M: Into<String>>(m: M) -> DResult {
         DResult::Ok(m.into())
     }
+
+    pub fn fields<M: Into<String>>(m: M, fields: &[(&str, String)]) -> DResult {
+        DResult::Fields(
+            m.into(),
+            fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+        )
+    }
+
+    pub fn is_ok(&self) -> bool {
+        !matches!(self, DResult::Err(_))
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            DResult::Ok(m) | DResult::Err(m) | DResult::Fields(m, _) => m,
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub enum DCommand {
-    // Args are as followed: drive_url, path_to_download_to, overwrite
-    Pull(String, PathBuf, bool),
-    // path_to_file_to_push
-    Push(PathBuf),
-    // path_to_local_file, drive_url
-    FSync(PathBuf, String),
+    // Args are as followed: drive_url, path_to_download_to, overwrite mode, relink
+    Pull(String, #[serde(with = "rawpath")] PathBuf, Overwrite, bool),
+    // path_to_file_to_push, skip_default_excludes
+    Push(#[serde(with = "rawpath")] PathBuf, bool),
+    // path_to_file_to_push, drive_folder_url_to_push_into, skip_default_excludes
+    PushTo(#[serde(with = "rawpath")] PathBuf, String, bool),
+    // directory_to_push, drive_folder_url_to_push_into, skip_default_excludes. Files created in the directory later are
+    // pushed and tracked too, see TrackedDir.
+    TrackDir(#[serde(with = "rawpath")] PathBuf, Option<String>, bool),
+    // path_to_file_or_directory_to_push, drive_folder_url_to_push_into, name_on_drive, skip_default_excludes. Answered
+    // once the push has definitively succeeded or failed, temporary Drive failures are retried first (--wait).
+    PushWait(
+        #[serde(with = "rawpath")] PathBuf,
+        Option<String>,
+        Option<String>,
+        bool,
+    ),
+    // path_to_file_to_push, name_on_drive, drive_folder_url_to_push_into
+    PushAs(#[serde(with = "rawpath")] PathBuf, String, Option<String>),
+    // path_to_tracked_file, new_name_on_drive
+    RenameRemote(#[serde(with = "rawpath")] PathBuf, String),
+    // Sync state of each path, answered one status per line in the same order (see status::PathStatus). Never talks
+    // to Drive, for file manager emblems and shell prompts.
+    PathStatusBatch(#[serde(with = "rawpath::vec")] Vec<PathBuf>),
+    // Changes held back while [review] is enabled.
+    Pending,
+    // Upload the pending changes to path (or beneath it), all of them if None.
+    Approve(#[serde(with = "rawpath::option")] Option<PathBuf>),
+    // Failed operations waiting to be retried.
+    Queue,
+    // id_of_queued_op
+    QueueDrop(u64),
+    QueueRetry(u64),
+    // directory_to_plan_a_push_of, skip_default_excludes
+    Plan(#[serde(with = "rawpath")] PathBuf, bool),
+    // path_to_local_file, drive_url, events to watch it for (see WatchEvent, the configured ones if None)
+    FSync(
+        #[serde(with = "rawpath")] PathBuf,
+        String,
+        Option<Vec<WatchEvent>>,
+    ),
     // path_to_local_file
-    FUnSync(PathBuf),
+    FUnSync(#[serde(with = "rawpath")] PathBuf),
+    Stats,
+    Health,
+    // path_or_drive_url, resolved to a drive url by the client
+    Activity(String),
+    // directory_to_sync_starred_files_into
+    PullStarred(#[serde(with = "rawpath")] PathBuf),
+    // directory_to_recover_stamped_files_from
+    RebuildState(#[serde(with = "rawpath")] PathBuf),
+    // drive_folder_url, directory_to_recreate_it_in, placeholders_instead_of_downloads
+    PullDir(String, #[serde(with = "rawpath")] PathBuf, bool),
+    // placeholder_or_directory_of_placeholders
+    Hydrate(#[serde(with = "rawpath")] PathBuf),
+    // drive_url, path_to_export_to, overwrite, export options
+    Export(String, #[serde(with = "rawpath")] PathBuf, bool, Export),
+    // path_or_drive_url, resolved to a drive url by the client
+    Share(String),
+    // Answered once the daemon has finished starting up, see `rgdrive --start`.
+    Ready,
+    // Connect to Drive again after `rgdrive --login`, resuming sync if Drive had refused the token.
+    Login,
+    // Which optional Drive features the account and scope allow, see capabilities::Capability.
+    Capabilities,
+    // Shared drives the account can access, one tab separated id, name and role per line.
+    Drives,
+    // Account the daemon is signed in as, its token's scopes and when the token expires, as fields.
+    Account,
+    // Network environment in use and the ones configured, see [environments].
+    Env,
+    // environment_name, None to use none
+    SetEnv(Option<String>),
+    // path_or_drive_url (resolved to a drive url by the client), drive_folder_url_to_put_the_shortcut_in
+    Shortcut(String, Option<String>),
+    // Files in a Drive folder (the Drive root if None), one tab separated id, name, kind, size, modified time and
+    // shortcut target per line, see Metadata::kind.
+    Ls(Option<String>),
+    // drive_url_of_a_synced_file, path_of_a_placeholder_for_it. Kept on disk and current with Drive, see pins.
+    Pin(String, #[serde(with = "rawpath::option")] Option<PathBuf>),
+    // pinned_path_or_drive_url
+    Unpin(String),
+    // Pinned files, one per line.
+    Pins,
 
     None,
     Message(String),
@@ -66,15 +511,27 @@ pub enum DCommand {
 }
 
 impl DCommand {
-    // Initialize a DCommand from a &UnixStream. A reference since the stream will be used later to possibly send a response.
-    pub fn from_stream(mut s: &UnixStream) -> DCommand {
-        let mut buf: Vec<u8> = Vec::new();
-        s.read_to_end(&mut buf).unwrap();
-        if buf.len() > 0 {
-            bincode::deserialize(&buf).unwrap()
+    // Read a DCommand from a stream (usually a &UnixStream, so it can be used afterwards to send a response).
+    // An empty stream is DCommand::None, anything larger than limit bytes is rejected.
+    pub fn from_stream<R: Read>(s: R, limit: u64) -> Result<DCommand, ProtocolError> {
+        DCommand::from_request(s, limit).map(|(cmd, _)| cmd)
+    }
+
+    // Like from_stream, also returning the ACCEPT_ flags of the client's HELLO (0 without one).
+    pub fn from_request<R: Read>(s: R, limit: u64) -> Result<(DCommand, u32), ProtocolError> {
+        let buf = read_frame(s, limit)?;
+        let start = HELLO.len() + 4;
+        let (flags, buf) = if buf.len() >= start && buf.starts_with(HELLO) {
+            let mut flags = [0; 4];
+            flags.copy_from_slice(&buf[HELLO.len()..start]);
+            (u32::from_le_bytes(flags), &buf[start..])
         } else {
-            DCommand::None
+            (0, &buf[..])
+        };
+        if buf.is_empty() {
+            return Ok((DCommand::None, flags));
         }
+        Ok((decode(buf, limit)?, flags))
     }
 }
 
@@ -88,126 +545,425 @@ impl DSocket {
     }
 
     pub fn is_active(&self) -> bool {
-        if let Err(_) = UnixStream::connect(&self.path) {
-            false
-        } else {
-            true
-        }
+        UnixStream::connect(&self.path).is_ok()
     }
 
     // Send given command to the daemon. Expects and will wait timeout duration for a response.
-    pub fn send_command(&self, cmd: DCommand) -> Result<DResult, Error> {
+    pub fn send_command(&self, cmd: DCommand) -> Result<DResult, ProtocolError> {
+        self.send_command_timeout(cmd, Duration::from_secs(15))
+    }
+
+    // Like send_command, but gives up on the response after timeout.
+    pub fn send_command_timeout(
+        &self,
+        cmd: DCommand,
+        timeout: Duration,
+    ) -> Result<DResult, ProtocolError> {
+        let frame = encode(&cmd, MAX_FRAME_BYTES)?;
+        let mut hello = Vec::with_capacity(HELLO.len() + 4 + frame.len());
+        hello.extend_from_slice(HELLO);
+        hello.extend_from_slice(&ACCEPT_ZSTD.to_le_bytes());
+        hello.extend_from_slice(&frame);
+        match self.request(&hello, timeout)? {
+            // A daemon older than HELLO, it never ran the command.
+            DResult::Err(e) if e.starts_with("Malformed command") => self.request(&frame, timeout),
+            r => Ok(r),
+        }
+    }
+
+    fn request(&self, frame: &[u8], timeout: Duration) -> Result<DResult, ProtocolError> {
         // Connect to stream.
         let mut stream = UnixStream::connect(&self.path)?;
 
         // Write command to stream.
-        stream.write_all(&bincode::serialize(&cmd).unwrap())?;
+        stream.write_all(frame)?;
 
         // Shutdown write half of stream and set read timeout for response.
         stream.shutdown(Shutdown::Write)?;
-        stream.set_read_timeout(Some(Duration::from_secs(15)))?;
+        stream.set_read_timeout(Some(timeout))?;
 
-        let mut buf: Vec<u8> = Vec::new();
-        stream.read_to_end(&mut buf)?;
-        let result: DResult = bincode::deserialize(&buf).unwrap();
-        Ok(result)
+        let buf = read_frame(&stream, MAX_FRAME_BYTES)?;
+        decode_response(&buf, MAX_FRAME_BYTES)
     }
 
     // Send given command to the daemon. Does not expect a response.
-    pub fn send_command_no_response(&self, cmd: DCommand) -> Result<(), Error> {
+    pub fn send_command_no_response(&self, cmd: DCommand) -> Result<(), ProtocolError> {
         let mut stream = UnixStream::connect(&self.path)?;
-        stream.write_all(&bincode::serialize(&cmd).unwrap())?;
+        stream.write_all(&encode(&cmd, MAX_FRAME_BYTES)?)?;
         Ok(())
     }
 }
 
+// Returns a list of all subpaths in given path. Recursive. Directories that can't be read are skipped with a warning.
+pub fn get_subpaths(p: &PathBuf) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let entries = match fs::read_dir(p) {
+        Ok(e) => e,
+        Err(e) => {
+            log::warn!("Skipping unreadable directory {:?}: {}", p, e);
+            return paths;
+        }
+    };
+    for entry in entries {
+        let path = match entry {
+            Ok(e) => e.path(),
+            Err(e) => {
+                log::warn!("Skipping unreadable entry in {:?}: {}", p, e);
+                continue;
+            }
+        };
+        if path.is_dir() {
+            paths.extend(get_subpaths(&path));
+        } else if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+// Device and inode of the file at path, if it has other hard links. Hard links are one file, they're synced with one
+// Drive file.
+pub fn shared_inode(path: &Path) -> Option<(u64, u64)> {
+    let m = fs::metadata(path).ok()?;
+    if m.is_file() && m.nlink() > 1 {
+        Some((m.dev(), m.ino()))
+    } else {
+        None
+    }
+}
+
+// Absolute path with symlinks and ./.. resolved, so every spelling of a file maps to one tracker entry. A path that
+// doesn't exist (any more) is resolved through its parent, and kept as is if that doesn't exist either.
+pub fn canonical_path(p: &Path) -> PathBuf {
+    if let Ok(c) = p.canonicalize() {
+        return c;
+    }
+    match (p.parent(), p.file_name()) {
+        (Some(parent), Some(name)) => match parent.canonicalize() {
+            Ok(c) => c.join(name),
+            Err(_) => p.to_path_buf(),
+        },
+        _ => p.to_path_buf(),
+    }
+}
+
+// Tracked files are kept by canonical path (see canonical_path).
 pub struct Tracker {
     pub inotify: Inotify,
     pub tracked_files: Vec<TrackedFile>,
-    tracked_files_path: PathBuf,
+    pub tracked_dirs: Vec<TrackedDir>,
+    // Events files without their own list are watched for, see config::Events.
+    pub events: Events,
+    // Whether synced files are stamped with their Drive id, see stamp.
+    pub stamp: bool,
+    // [excludes] ignore, for the tracked directories' watches.
+    pub ignore: Vec<String>,
+    // ctime (seconds, nanoseconds) of each file right after it was stamped. Stamping is an attrib event of its own.
+    stamped: HashMap<PathBuf, (i64, i64)>,
+    // Watches on tracked directories and every subdirectory beneath them, with the directory each one is on.
+    dir_watches: Vec<(WatchDescriptor, PathBuf)>,
+    // Watches on the other directories holding tracked files, for files moved out of and into them (see move_path).
+    parent_watches: Vec<(WatchDescriptor, PathBuf)>,
+    state: State,
 }
 
+// Watched on tracked directories: new files once they're written (or moved in), new subdirectories, and files moved
+// out (like PARENT_MASK, one directory only has one watch).
+const DIR_MASK: WatchMask = WatchMask::from_bits_truncate(
+    WatchMask::CREATE.bits()
+        | WatchMask::CLOSE_WRITE.bits()
+        | WatchMask::MOVED_TO.bits()
+        | WatchMask::MOVED_FROM.bits(),
+);
+
+// Watched on the directories of tracked files: a move is a moved_from and a moved_to with the same cookie.
+const PARENT_MASK: WatchMask =
+    WatchMask::from_bits_truncate(WatchMask::MOVED_FROM.bits() | WatchMask::MOVED_TO.bits());
+
 impl Tracker {
-    // Initialize Tracker.
-    pub fn init() -> Tracker {
-        let mut tracker = Tracker {
+    // Initialize Tracker, watching every tracked file before returning.
+    pub fn init() -> Result<Tracker, String> {
+        let mut tracker = Tracker::load()?;
+        tracker.watch_dirs();
+        tracker.watch_pending(usize::MAX);
+        Ok(tracker)
+    }
+
+    // Load the tracked files without watching any of them yet, see watch_pending. Cheap even for huge sync sets, so the
+    // daemon can start answering before every watch is in place. A tracked_files list left by an older version is
+    // moved into the state database first. Fails rather than starting out with no tracked files if either can't be read.
+    pub fn load() -> Result<Tracker, String> {
+        let mut state = State::open(&state_path())?;
+        let legacy = config_dir();
+        if legacy.exists() {
+            let n = state::migrate(&mut state, &legacy)?;
+            log::info!(
+                "Moved {} tracked files from {:?} to {:?}.",
+                n,
+                legacy,
+                state_path()
+            );
+        }
+        let tracked_files = state.load().map_err(|e| {
+            format!(
+                "Couldn't read the tracked files in {:?}: {}",
+                state_path(),
+                e
+            )
+        })?;
+        Ok(Tracker {
             inotify: Inotify::init().unwrap(),
-            tracked_files: Vec::new(),
-            tracked_files_path: config_dir(),
-        };
+            tracked_files,
+            tracked_dirs: TrackedDir::load()?,
+            events: Events::default(),
+            stamp: true,
+            ignore: Vec::new(),
+            stamped: HashMap::new(),
+            dir_watches: Vec::new(),
+            parent_watches: Vec::new(),
+            state,
+        })
+    }
 
-        // If we have an existing list of tracked files, open it and attempt to read it's contents.
-        if tracker.tracked_files_path.exists() {
-            // On a failed file read, just return tracker with empty tracked_files vec.
-            let mut f = match File::open(&tracker.tracked_files_path) {
-                Ok(f) => f,
+    // Add watches (see TrackedFile::events) to up to max tracked files that aren't watched yet, so a big sync set can be
+    // watched a chunk at a time without holding the tracker throughout. Files that can't be watched are dropped, unless
+    // their media isn't mounted right now (they're marked absent, see reattach). Returns how many are still waiting for
+    // a watch.
+    pub fn watch_pending(&mut self, max: usize) -> usize {
+        let mut failed: HashSet<PathBuf> = HashSet::new();
+        let mut parents: Vec<PathBuf> = Vec::new();
+        let mut watched = 0;
+        let mut remaining = 0;
+        for tf in self.tracked_files.iter_mut() {
+            // Exports only change from the remote side, there's nothing to watch.
+            if tf.wd.is_some() || tf.is_export() {
+                continue;
+            }
+            if watched == max {
+                remaining += 1;
+                continue;
+            }
+            let events = tf.events(&self.events);
+            match self.inotify.add_watch(&tf.path, WatchEvent::mask(&events)) {
+                Ok(wd) => {
+                    log::debug!("adding {:?} to watch", tf);
+                    tf.wd = Some(wd);
+                    parents.push(tf.path.clone());
+                    watched += 1;
+                }
+                Err(_) if tf.media.is_some() && !tf.path.exists() => {
+                    log::info!(
+                        "{:?} is on media that isn't mounted, syncing it again once it's back.",
+                        tf.path
+                    );
+                    tf.absent = true;
+                }
                 Err(e) => {
-                    log::error!("Error opening tracked files config file: {:?}", e);
-                    return tracker;
+                    log::error!("Failed to add {:?} to Inotify watch: {:?}", tf, e);
+                    failed.insert(tf.path.clone());
                 }
-            };
+            }
+        }
+        if !failed.is_empty() {
+            self.tracked_files.retain(|tf| !failed.contains(&tf.path));
+            for p in &failed {
+                if let Err(e) = self.state.delete(p) {
+                    log::error!("Failed to forget {:?}: {:?}", p, e);
+                }
+            }
+        }
+        for p in parents {
+            self.watch_parent(&p);
+        }
+        remaining
+    }
 
-            // Read existing files to buf, and overwrite empty vec with any existing files.
-            let mut buf: Vec<u8> = Vec::new();
-            f.read_to_end(&mut buf).unwrap();
-            // Deserialize file to Vec<Trackedfile>
-            let tracked_files: Vec<TrackedFile> = match bincode::deserialize(&buf) {
-                Ok(v) => v,
-                Err(e) => {
-                    log::warn!("Error deserializing from file: {:?}.. Continuing anyways with a blank tracker.", e);
-                    return tracker;
+    // Watch every tracked directory for new files. Directories that can't be watched are dropped.
+    pub fn watch_dirs(&mut self) {
+        let dirs = self.tracked_dirs.clone();
+        let mut failed: Vec<PathBuf> = Vec::new();
+        for d in &dirs {
+            if let Err(e) = self.watch_tree(&d.path, d) {
+                log::error!("Failed to watch tracked directory {:?}: {:?}", d.path, e);
+                failed.push(d.path.clone());
+            }
+        }
+        if !failed.is_empty() {
+            self.tracked_dirs.retain(|d| !failed.contains(&d.path));
+            if let Err(e) = TrackedDir::save(&self.tracked_dirs) {
+                log::error!("Failed to save tracked directories: {:?}", e);
+            }
+        }
+    }
+
+    // Watch dir and every subdirectory beneath it for new files, leaving out the default excluded ones if root skips
+    // them. Directories already watched are left as they are.
+    pub fn watch_tree(&mut self, dir: &Path, root: &TrackedDir) -> Result<(), Error> {
+        if self.dir_watches.iter().any(|(_, d)| d == dir) {
+            return Ok(());
+        }
+        let wd = self.inotify.add_watch(dir, DIR_MASK)?;
+        log::debug!("watching {:?} for new files", dir);
+        // Watching it again kept the watch it had for moves, which now covers both.
+        self.parent_watches.retain(|(_, d)| d != dir);
+        self.dir_watches.push((wd, dir.to_path_buf()));
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() && !root.skips(&path, &self.ignore) {
+                self.watch_tree(&path, root)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Push new files in dir to Drive and track them from now on, see TrackedDir. A directory already tracked takes on
+    // dir's Drive folder and excludes, for the files created in it from now on.
+    pub fn add_dir(&mut self, mut dir: TrackedDir) -> Result<(), Error> {
+        dir.path = canonical_path(&dir.path);
+        if let Some(d) = self.tracked_dirs.iter_mut().find(|d| d.path == dir.path) {
+            if *d == dir {
+                return Ok(());
+            }
+            *d = dir;
+            return TrackedDir::save(&self.tracked_dirs);
+        }
+        self.watch_tree(&dir.path.clone(), &dir)?;
+        self.tracked_dirs.push(dir);
+        TrackedDir::save(&self.tracked_dirs)
+    }
+
+    // Stop picking up new files in the tracked directory at path. Files already tracked stay tracked. Returns false if
+    // path isn't a tracked directory.
+    pub fn remove_dir(&mut self, path: &Path) -> Result<bool, Error> {
+        let path = canonical_path(path);
+        if !self.tracked_dirs.iter().any(|d| d.path == path) {
+            return Ok(false);
+        }
+        self.tracked_dirs.retain(|d| d.path != path);
+        // A subdirectory can still belong to another tracked directory above it.
+        let still: Vec<PathBuf> = self.tracked_dirs.iter().map(|d| d.path.clone()).collect();
+        let (dropped, kept): (Vec<_>, Vec<_>) = self
+            .dir_watches
+            .drain(..)
+            .partition(|(_, d)| d.starts_with(&path) && !still.iter().any(|s| d.starts_with(s)));
+        self.dir_watches = kept;
+        for (wd, d) in dropped {
+            // Still the directory of tracked files, it's watched for moves only from now on.
+            if self
+                .tracked_files
+                .iter()
+                .any(|tf| tf.path.parent() == Some(&d))
+            {
+                if let Ok(wd) = self.inotify.add_watch(&d, PARENT_MASK) {
+                    self.parent_watches.push((wd, d));
                 }
-            };
-            // Iterate any trackedfiles that were deseralized from file. Add watches for MODIFY, DELETE_SELF, and MOVE_SELF.
-            // Update the TrackedFile resource to include the WatchDescriptor and add it back to the tracker tracked files list.
-            for tf in tracked_files {
-                let wd = match tracker.inotify.add_watch(
-                    &tf.path,
-                    WatchMask::MODIFY | WatchMask::DELETE_SELF | WatchMask::MOVE_SELF,
-                ) {
-                    Ok(wd) => wd,
-                    Err(e) => {
-                        log::error!("Failed to add {:?} to Inotify watch: {:?}", tf, e);
-                        continue;
-                    }
-                };
-                log::info!("adding {:?} to watch", tf);
-                tracker
-                    .tracked_files
-                    .push(TrackedFile { wd: Some(wd), ..tf });
+            } else {
+                let _ = self.inotify.rm_watch(wd);
             }
         }
-        tracker
+        TrackedDir::save(&self.tracked_dirs)?;
+        Ok(true)
+    }
+
+    // The watched directory an inotify event's watch descriptor belongs to.
+    pub fn find_dir_by_wd(&self, wd: &WatchDescriptor) -> Option<&Path> {
+        self.dir_watches
+            .iter()
+            .find(|(w, _)| w == wd)
+            .map(|(_, d)| d.as_path())
+    }
+
+    // The directory of tracked files (tracked directory or not) an inotify event's watch descriptor belongs to.
+    pub fn find_parent_by_wd(&self, wd: &WatchDescriptor) -> Option<&Path> {
+        self.parent_watches
+            .iter()
+            .chain(&self.dir_watches)
+            .find(|(w, _)| w == wd)
+            .map(|(_, d)| d.as_path())
+    }
+
+    // Forget a directory watch the kernel dropped (the directory was deleted or moved away).
+    pub fn forget_dir_watch(&mut self, wd: &WatchDescriptor) {
+        self.dir_watches.retain(|(w, _)| w != wd);
+        self.parent_watches.retain(|(w, _)| w != wd);
+    }
+
+    // Watch the directory of the tracked file at path for moves, unless it already is. Without it a moved file would
+    // only look deleted.
+    fn watch_parent(&mut self, path: &Path) {
+        let dir = match path.parent() {
+            Some(d) => d,
+            None => return,
+        };
+        if self
+            .dir_watches
+            .iter()
+            .chain(&self.parent_watches)
+            .any(|(_, d)| d == dir)
+        {
+            return;
+        }
+        match self.inotify.add_watch(dir, PARENT_MASK) {
+            Ok(wd) => self.parent_watches.push((wd, dir.to_path_buf())),
+            Err(e) => log::debug!("Couldn't watch {:?} for moves: {:?}", dir, e),
+        }
+    }
+
+    // Stop watching dir for moves once no tracked file is left in it.
+    fn unwatch_parent(&mut self, dir: &Path) {
+        if self
+            .tracked_files
+            .iter()
+            .any(|tf| tf.path.parent() == Some(dir))
+        {
+            return;
+        }
+        let (dropped, kept): (Vec<_>, Vec<_>) =
+            self.parent_watches.drain(..).partition(|(_, d)| d == dir);
+        self.parent_watches = kept;
+        for (wd, _) in dropped {
+            let _ = self.inotify.rm_watch(wd);
+        }
     }
 
-    // Saves current Inotify config/tracked paths to file, as Inotify saved paths are not persistent between sessions.
-    fn save(&self) -> Result<(), Error> {
-        // Open tracked files path. Create new so that it erases any existing paths, since they could have been changed or removed since the last time we accessed the file.
-        let mut f = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(&self.tracked_files_path)?;
-        // Serialize the tracked files vec and write it to the file.
-        f.write_all(&bincode::serialize(&self.tracked_files).unwrap())?;
+    // The tracked directory path is beneath (the innermost one, if they're nested).
+    pub fn tracked_dir_of(&self, path: &Path) -> Option<&TrackedDir> {
+        self.tracked_dirs
+            .iter()
+            .filter(|d| path.starts_with(&d.path))
+            .max_by_key(|d| d.path.components().count())
+    }
+
+    // Saves the tracked files at path to the state database, as Inotify watches are not persistent between sessions.
+    // Every mutator saves the rows it changed this way (or forgets them, see forget), nothing else is written.
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        for tf in self.tracked_files.iter().filter(|tf| tf.path == path) {
+            self.state
+                .upsert(tf)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        }
         Ok(())
     }
 
-    // Adds given path to the inotify watchlist for MODIFY/DELETE_SELF/MOVE_SELF events.
+    // Removes the row for path from the state database.
+    fn forget(&self, path: &Path) -> Result<(), Error> {
+        self.state
+            .delete(path)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    // Adds given path to the inotify watchlist, for the events configured for it (see config::Events).
     pub fn add_path<P: Into<PathBuf>, U: Into<String>>(&mut self, p: P, u: U) -> Result<(), Error> {
-        let (url, path) = (u.into(), p.into());
+        let (url, path) = (u.into(), canonical_path(&p.into()));
 
         // Check if path is already added to the watchlist. Skip path if it is.
-        for tf in &self.tracked_files {
-            if *tf.path == path {
-                return Ok(());
-            }
+        if self.find_by_path(&path).is_some() {
+            return Ok(());
         }
 
-        // Add path to inotify watchlist for specific WatchMasks.
-        let wd = match self.inotify.add_watch(
-            &path,
-            WatchMask::MODIFY | WatchMask::DELETE_SELF | WatchMask::MOVE_SELF,
-        ) {
+        // Add path to inotify watchlist for the events it's configured for.
+        let mask = WatchEvent::mask(&self.events.for_path(&path));
+        let wd = match self.inotify.add_watch(&path, mask) {
             Ok(wd) => {
                 log::debug!("added {:?} to the watchlist", wd);
                 wd
@@ -222,50 +978,668 @@ impl Tracker {
                 return Err(e);
             }
         };
+        self.watch_parent(&path);
         // Add a trackedfile entry with the newly created WatchDescriptor.
         self.tracked_files.push(TrackedFile {
-            drive_url: url,
-            path: path,
             wd: Some(wd),
+            media: media::media_of(&path),
+            ..TrackedFile::new(path.clone(), url)
         });
         // Save and write to file so new config will persist through sessions.
-        self.save()?;
+        self.save(&path)?;
+        Ok(())
+    }
+
+    // Adds every file in batch to the watchlist with a single save. If any of them fails the tracker is rolled back to how
+    // it was before the call, so a batch is never left half tracked.
+    pub fn add_paths(&mut self, batch: Vec<TrackedFile>) -> Result<(), Error> {
+        let before = self.tracked_files.len();
+        let mut result = Ok(());
+        let mounts = media::mountinfo();
+        for mut tf in batch {
+            tf.path = canonical_path(&tf.path);
+            tf.media = media::media_in(&mounts, &tf.path);
+            if self.find_by_path(&tf.path).is_some() {
+                continue;
+            }
+            match self
+                .inotify
+                .add_watch(&tf.path, WatchEvent::mask(&tf.events(&self.events)))
+            {
+                Ok(wd) => self.tracked_files.push(TrackedFile { wd: Some(wd), ..tf }),
+                Err(e) => {
+                    log::error!(
+                        "Failed to add {:?} to the inotify watchlist: {:?}",
+                        tf.path,
+                        e
+                    );
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if result.is_ok() {
+            result = self
+                .state
+                .upsert_all(&self.tracked_files[before..])
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e));
+        }
+        if result.is_err() {
+            for tf in self.tracked_files.drain(before..) {
+                if let Some(wd) = tf.wd {
+                    let _ = self.inotify.rm_watch(wd);
+                }
+            }
+        }
+        let added: Vec<PathBuf> = self.tracked_files[before..]
+            .iter()
+            .map(|tf| tf.path.clone())
+            .collect();
+        for p in added {
+            self.watch_parent(&p);
+        }
+        result
+    }
+
+    // Record the md5 of the tracked file at path as of now, when it's known to match its Drive copy (just pulled or
+    // uploaded). Pulls with --overwrite-mode local-unmodified compare against it.
+    pub fn mark_synced(&mut self, path: &Path) -> Result<(), Error> {
+        let path = canonical_path(path);
+        let md5 = checksum::md5_file(&path).ok();
+        self.mark_synced_as(&path, md5)
+    }
+
+    // Like mark_synced, with the md5 of what was transferred, hashed as it streamed (or before it started). A save
+    // landing while an upload was under way then still shows as a change.
+    pub fn mark_synced_as(&mut self, path: &Path, md5: Option<String>) -> Result<(), Error> {
+        let path = canonical_path(path);
+        let now = chrono::Utc::now().timestamp();
+        let mtime = fs::metadata(&path).ok().map(|m| m.mtime());
+        match self.tracked_files.iter_mut().find(|tf| tf.path == path) {
+            Some(tf) => {
+                // Exports aren't stamped, they'd be uploaded if the stamp were ever used to recover them.
+                if self.stamp && tf.export.is_none() {
+                    match stamp::write(&path, &tf.drive_url, md5.as_deref())
+                        .and_then(|_| fs::metadata(&path))
+                    {
+                        Ok(m) => {
+                            self.stamped
+                                .insert(path.clone(), (m.ctime(), m.ctime_nsec()));
+                        }
+                        Err(e) => log::debug!("Couldn't stamp {:?}: {}", path, e),
+                    }
+                }
+                tf.md5 = md5.clone();
+                tf.synced_at = Some(now);
+                tf.mtime = mtime;
+                tf.last_error = None;
+            }
+            None => return Ok(()),
+        }
+        // Hard links share a watch, and their content.
+        let wd = self.find_by_path(&path).and_then(|tf| tf.wd.clone());
+        let mut changed = vec![path];
+        if wd.is_some() {
+            for tf in self.tracked_files.iter_mut().filter(|tf| tf.wd == wd) {
+                tf.md5 = md5.clone();
+                tf.synced_at = Some(now);
+                tf.mtime = mtime;
+                tf.last_error = None;
+                changed.push(tf.path.clone());
+            }
+        }
+        changed.sort();
+        changed.dedup();
+        for p in &changed {
+            self.save(p)?;
+        }
         Ok(())
     }
 
+    // Record the Drive etag the poller saw for url, on every file synced with it.
+    pub fn set_revision(&mut self, url: &str, etag: &str) -> Result<(), Error> {
+        let mut changed = Vec::new();
+        for tf in self
+            .tracked_files
+            .iter_mut()
+            .filter(|tf| tf.drive_url == url)
+        {
+            tf.revision = Some(etag.to_string());
+            changed.push(tf.path.clone());
+        }
+        for p in &changed {
+            self.save(p)?;
+        }
+        Ok(())
+    }
+
+    // Record that syncing the tracked file at path failed with error, for --list. Cleared once it syncs again.
+    pub fn sync_failed(&mut self, path: &Path, error: &str) -> Result<(), Error> {
+        let path = canonical_path(path);
+        match self.tracked_files.iter_mut().find(|tf| tf.path == path) {
+            Some(tf) => tf.last_error = Some(error.to_string()),
+            None => return Ok(()),
+        }
+        self.save(&path)
+    }
+
+    // Track path again as synced with what its stamp says, after the tracked files were lost. Its md5 is the one it was
+    // last synced at, so changes made since are still seen as changes.
+    pub fn recover(&mut self, path: &Path, s: &stamp::Stamp) -> Result<(), Error> {
+        let path = canonical_path(path);
+        self.add_path(&path, s.drive_url())?;
+        if let Some(tf) = self.tracked_files.iter_mut().find(|tf| tf.path == path) {
+            tf.md5 = s.md5.clone();
+        }
+        self.save(&path)
+    }
+
+    // Watch the tracked file at path for events from now on, or the configured ones again if None. Returns false if path
+    // isn't tracked.
+    pub fn set_events(
+        &mut self,
+        path: &Path,
+        events: Option<Vec<WatchEvent>>,
+    ) -> Result<bool, Error> {
+        let path = canonical_path(path);
+        let defaults = &self.events;
+        let tf = match self.tracked_files.iter_mut().find(|tf| tf.path == path) {
+            Some(tf) => tf,
+            None => return Ok(false),
+        };
+        tf.events = events;
+        // Watching the same file again replaces its mask, and keeps its watch descriptor.
+        if tf.wd.is_some() {
+            tf.wd = Some(
+                self.inotify
+                    .add_watch(&tf.path, WatchEvent::mask(&tf.events(defaults)))?,
+            );
+        }
+        self.save(&path)?;
+        Ok(true)
+    }
+
+    // The filesystem of the tracked file watched by wd was unmounted, taking the watch with it. The file is marked
+    // absent until it's back, see reattach. Returns its path.
+    pub fn unmounted(&mut self, wd: &WatchDescriptor) -> Option<PathBuf> {
+        let tf = self
+            .tracked_files
+            .iter_mut()
+            .find(|tf| tf.wd.as_ref() == Some(wd))?;
+        tf.wd = None;
+        tf.absent = true;
+        Some(tf.path.clone())
+    }
+
+    // The file watched by wd was deleted or moved away, taking the watch with it. Editors often save that way, writing a
+    // new file and renaming it over the old one. The files lose their watch until rewatch finds something at their
+    // path again. Returns their paths, more than one for hard links.
+    pub fn unwatch(&mut self, wd: &WatchDescriptor) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for tf in self
+            .tracked_files
+            .iter_mut()
+            .filter(|tf| tf.wd.as_ref() == Some(wd))
+        {
+            tf.wd = None;
+            paths.push(tf.path.clone());
+        }
+        if !paths.is_empty() {
+            // A file moved away is still there, and still watched, until the watch is removed.
+            let _ = self.inotify.rm_watch(wd.clone());
+        }
+        paths
+    }
+
+    // Watch the file at path again after unwatch, once there's a file there. Returns the tracked file if it's watched
+    // again, None while there's nothing at path.
+    pub fn rewatch(&mut self, path: &Path) -> Result<Option<TrackedFile>, Error> {
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let defaults = &self.events;
+        let tf = match self
+            .tracked_files
+            .iter_mut()
+            .find(|tf| tf.path == path && tf.wd.is_none())
+        {
+            Some(tf) => tf,
+            None => return Ok(None),
+        };
+        let events = tf.events(defaults);
+        tf.wd = Some(self.inotify.add_watch(path, WatchEvent::mask(&events))?);
+        Ok(Some(tf.clone()))
+    }
+
+    // The tracked file at from was moved to to, within or between watched directories (see watch_parent). It goes on
+    // syncing with the same Drive file from to, watched there like it was at from. Returns it, None if from isn't tracked
+    // or to already is.
+    pub fn move_path(&mut self, from: &Path, to: &Path) -> Result<Option<TrackedFile>, Error> {
+        let to = canonical_path(to);
+        if self.find_by_path(&to).is_some() {
+            return Ok(None);
+        }
+        let defaults = &self.events;
+        let tf = match self.tracked_files.iter_mut().find(|tf| tf.path == from) {
+            Some(tf) => tf,
+            None => return Ok(None),
+        };
+        // Still the same file, a watch it kept follows it there.
+        tf.wd = Some(
+            self.inotify
+                .add_watch(&to, WatchEvent::mask(&tf.events(defaults)))?,
+        );
+        tf.path = to.clone();
+        tf.media = media::media_of(&to);
+        let moved = tf.clone();
+        if let Some(stamped) = self.stamped.remove(from) {
+            self.stamped.insert(to.clone(), stamped);
+        }
+        self.forget(from)?;
+        self.save(&to)?;
+        self.watch_parent(&to);
+        if let Some(dir) = from.parent() {
+            self.unwatch_parent(dir);
+        }
+        Ok(Some(moved))
+    }
+
+    pub fn has_absent(&self) -> bool {
+        self.tracked_files.iter().any(|tf| tf.absent)
+    }
+
+    // Watch the absent files whose media is mounted again. Media mounted somewhere else than before is found by its
+    // UUID, and its files' paths are moved to the new mount point. Returns the files watched again.
+    pub fn reattach(&mut self) -> Vec<TrackedFile> {
+        let mut back = Vec::new();
+        let mut moved: Vec<(PathBuf, PathBuf)> = Vec::new();
+        let defaults = &self.events;
+        for tf in self.tracked_files.iter_mut().filter(|tf| tf.absent) {
+            let media = match &mut tf.media {
+                Some(m) => m,
+                None => continue,
+            };
+            let point = match media::mounted_at(media) {
+                Some(p) => p,
+                None => continue,
+            };
+            if point != media.mount_point {
+                if let Ok(rest) = tf.path.strip_prefix(&media.mount_point) {
+                    let path = point.join(rest);
+                    log::info!(
+                        "{:?} is mounted at {:?} now, {:?} moves to {:?}.",
+                        media.mount_point,
+                        point,
+                        tf.path,
+                        path
+                    );
+                    moved.push((tf.path.clone(), path.clone()));
+                    tf.path = path;
+                    media.mount_point = point;
+                }
+            }
+            let events = tf.events(defaults);
+            if let Ok(wd) = self.inotify.add_watch(&tf.path, WatchEvent::mask(&events)) {
+                tf.wd = Some(wd);
+                tf.absent = false;
+                back.push(tf.clone());
+            }
+        }
+        for (from, to) in &moved {
+            if let Err(e) = self.forget(from).and_then(|_| self.save(to)) {
+                log::error!("Failed to save moved tracked file {:?}: {:?}", to, e);
+            }
+        }
+        for tf in &back {
+            self.watch_parent(&tf.path);
+        }
+        back
+    }
+
+    // Track an export. Exports aren't watched, so it's only recorded (replacing anything already tracked at its path).
+    pub fn add_export(&mut self, mut tf: TrackedFile) -> Result<(), Error> {
+        tf.path = canonical_path(&tf.path);
+        let path = tf.path.clone();
+        self.remove_path(path.clone())?;
+        self.tracked_files.push(tf);
+        self.save(&path)
+    }
+
+    // Set (or clear) the Drive name recorded for the tracked file at path. Returns false if path isn't tracked.
+    pub fn set_remote_name(&mut self, path: &Path, name: Option<String>) -> Result<bool, Error> {
+        let path = canonical_path(path);
+        match self.tracked_files.iter_mut().find(|tf| tf.path == path) {
+            Some(tf) => tf.remote_name = name,
+            None => return Ok(false),
+        }
+        self.save(&path)?;
+        Ok(true)
+    }
+
+    // The tracked file at path, however it's spelled.
+    pub fn find_by_path(&self, path: &Path) -> Option<&TrackedFile> {
+        let path = canonical_path(path);
+        self.tracked_files.iter().find(|tf| tf.path == path)
+    }
+
+    // A tracked file that's a hard link to path, see shared_inode.
+    pub fn hard_link_of(&self, path: &Path) -> Option<&TrackedFile> {
+        let path = canonical_path(path);
+        let inode = shared_inode(&path)?;
+        self.tracked_files
+            .iter()
+            .find(|tf| tf.path != path && shared_inode(&tf.path) == Some(inode))
+    }
+
+    // The tracked file an inotify event's watch descriptor belongs to. Hard links share one, and their Drive file.
+    pub fn find_by_wd(&self, wd: &WatchDescriptor) -> Option<&TrackedFile> {
+        self.tracked_files
+            .iter()
+            .find(|tf| tf.wd.as_ref() == Some(wd))
+    }
+
+    // Whether nothing happened to the file watched by wd since it was stamped, so an attrib event on it was the stamp.
+    pub fn only_stamped(&self, wd: &WatchDescriptor) -> bool {
+        let path = match self.find_by_wd(wd) {
+            Some(tf) => &tf.path,
+            None => return false,
+        };
+        match (self.stamped.get(path), fs::metadata(path)) {
+            (Some(stamped), Ok(m)) => *stamped == (m.ctime(), m.ctime_nsec()),
+            _ => false,
+        }
+    }
+
     pub fn remove_path<P: Into<PathBuf>>(&mut self, p: P) -> Result<(), Error> {
-        let path = p.into();
+        let path = canonical_path(&p.into());
         // Temp vec to hold drained TrackedFiles.
         let mut _tf: Vec<TrackedFile> = Vec::new();
         // Iterate all tracked files, if their patch matches remove them from the Inotify watchlist.
+        let mut removed: Vec<TrackedFile> = Vec::new();
         for tf in self.tracked_files.drain(..) {
             if tf.path == path {
-                if let Some(wd) = tf.wd {
-                    self.inotify.rm_watch(wd)?;
-                }
+                removed.push(tf);
             } else {
                 _tf.push(tf);
             }
         }
         self.tracked_files = _tf;
-        self.save()?;
+        for tf in removed {
+            // Hard links to it that are still synced keep the watch they share with it.
+            let shared = tf.wd.is_some() && self.tracked_files.iter().any(|o| o.wd == tf.wd);
+            // The kernel already dropped the watch of a deleted file, so failing to remove it isn't an error.
+            if let (Some(wd), false) = (tf.wd, shared) {
+                if let Err(e) = self.inotify.rm_watch(wd) {
+                    log::debug!("Watch on {:?} already gone: {:?}", tf.path, e);
+                }
+            }
+        }
+        // A hard link that's still synced shares the stamp.
+        if self.stamp && path.exists() && self.hard_link_of(&path).is_none() {
+            if let Err(e) = stamp::clear(&path) {
+                log::debug!("Couldn't unstamp {:?}: {}", path, e);
+            }
+        }
+        if let Some(dir) = path.parent() {
+            self.unwatch_parent(dir);
+        }
+        self.forget(&path)?;
         Ok(())
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+// Inotify events a tracked file can be watched for. Modify, close_write and attrib upload the file, delete_self and
+// move_self are how a file replaced by an editor's save is noticed.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchEvent {
+    Modify,
+    DeleteSelf,
+    MoveSelf,
+    CloseWrite,
+    // Permissions, ownership and timestamps changed.
+    Attrib,
+}
+
+impl WatchEvent {
+    pub const ALL: [WatchEvent; 5] = [
+        WatchEvent::Modify,
+        WatchEvent::DeleteSelf,
+        WatchEvent::MoveSelf,
+        WatchEvent::CloseWrite,
+        WatchEvent::Attrib,
+    ];
+    // What files are watched for unless configured otherwise.
+    pub const DEFAULT: [WatchEvent; 3] = [
+        WatchEvent::Modify,
+        WatchEvent::DeleteSelf,
+        WatchEvent::MoveSelf,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WatchEvent::Modify => "modify",
+            WatchEvent::DeleteSelf => "delete_self",
+            WatchEvent::MoveSelf => "move_self",
+            WatchEvent::CloseWrite => "close_write",
+            WatchEvent::Attrib => "attrib",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WatchEvent> {
+        WatchEvent::ALL.iter().copied().find(|e| e.name() == name)
+    }
+
+    // A comma separated list of names, e.g. from --events.
+    pub fn parse_list(list: &str) -> Result<Vec<WatchEvent>, String> {
+        let events = list
+            .split(',')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(|n| {
+                WatchEvent::from_name(n).ok_or_else(|| {
+                    let names: Vec<&str> = WatchEvent::ALL.iter().map(|e| e.name()).collect();
+                    format!("Unknown event {:?}, use {}.", n, names.join(", "))
+                })
+            })
+            .collect::<Result<Vec<WatchEvent>, String>>()?;
+        if events.is_empty() {
+            return Err(String::from(
+                "No events given, there'd be nothing to watch.",
+            ));
+        }
+        Ok(events)
+    }
+
+    // The inotify mask for events.
+    pub fn mask(events: &[WatchEvent]) -> WatchMask {
+        events.iter().fold(WatchMask::empty(), |mask, e| {
+            mask | match e {
+                WatchEvent::Modify => WatchMask::MODIFY,
+                WatchEvent::DeleteSelf => WatchMask::DELETE_SELF,
+                WatchEvent::MoveSelf => WatchMask::MOVE_SELF,
+                WatchEvent::CloseWrite => WatchMask::CLOSE_WRITE,
+                WatchEvent::Attrib => WatchMask::ATTRIB,
+            }
+        })
+    }
+}
+
+// A directory synced as a whole: files created in it (or any subdirectory) are pushed into the same Drive folder as
+// the directory's own push, and tracked like any other pushed file.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TrackedDir {
+    #[serde(with = "rawpath")]
+    pub path: PathBuf,
+    // Drive folder url new files are pushed into, the usual upload folder if None (see transfer::upload_folder).
+    pub dest: Option<String>,
+    // Whether the default excludes (see exclude::DEFAULT_EXCLUDES) are left out.
+    pub excludes: bool,
+}
+
+impl TrackedDir {
+    // The tracked directories, none if the file doesn't exist yet. A file that can't be read or parsed is an error
+    // rather than an empty list, saving over it would lose every directory.
+    pub fn load() -> Result<Vec<TrackedDir>, String> {
+        let p = dirs_path();
+        let unreadable = |e: &dyn std::fmt::Display| {
+            format!(
+                "Couldn't read the tracked directories in {:?} ({}). Move it aside to start without them.",
+                p, e
+            )
+        };
+        match fs::read_to_string(&p) {
+            Ok(s) => serde_json::from_str(&s).map_err(|e| unreadable(&e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(unreadable(&e)),
+        }
+    }
+
+    pub fn save(dirs: &[TrackedDir]) -> Result<(), Error> {
+        let p = dirs_path();
+        if let Some(parent) = p.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&p, serde_json::to_string(dirs)?.as_bytes())
+    }
+
+    // Whether path, beneath the directory, is left alone because of the default excludes or ignore rules (see
+    // exclude::Ignores, ignore being the configured patterns).
+    pub fn skips(&self, path: &Path, ignore: &[String]) -> bool {
+        (self.excludes && exclude::is_excluded(path.strip_prefix(&self.path).unwrap_or(path)))
+            || exclude::is_ignored(&self.path, path, ignore)
+    }
+}
+
+// Prefix of the versioned tracked files format, kept for write-ahead batches (see batch) and the tracked_files list the
+// state database replaced (see state). Files without it are the original bare Vec<(drive_url, path)>, whose leading u64
+// length could never spell this out.
+const TRACKED_MAGIC: &[u8; 4] = b"RGDT";
+// Every path in a versioned list is canonical.
+const TRACKED_VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
 pub struct TrackedFile {
     pub drive_url: String,
+    #[serde(with = "rawpath")]
     pub path: PathBuf,
+    // Set when the local file is an export of a Docs editors file. Exports are pull only: they're never watched or
+    // uploaded, and are re-exported whenever the remote file changes.
+    pub export: Option<Export>,
+    // mimeType of the remote file, when known.
+    pub mime_type: Option<String>,
+    // Name of the Drive copy, when it's different from the local file name (--as, --rename-remote).
+    pub remote_name: Option<String>,
+    // md5 of the local file when it last matched its Drive copy (after a pull or upload), None if unknown.
+    pub md5: Option<String>,
+    // Events the file is watched for, given to --sync with --events. None for the configured ones (see config::Events).
+    pub events: Option<Vec<WatchEvent>>,
+    // The removable (or at least not root) filesystem the file is on, see media.
+    pub media: Option<Media>,
+    // Unix timestamp (seconds) of the last upload or pull, None if it hasn't synced since it was tracked.
+    pub synced_at: Option<i64>,
+    // Why the last upload or pull failed, None if it succeeded.
+    pub last_error: Option<String>,
+    // Drive's etag for the file when the poller last saw it change, None until it has.
+    pub revision: Option<String>,
+    // mtime (seconds) of the local file as of its last sync.
+    pub mtime: Option<i64>,
 
     #[serde(skip)]
     pub wd: Option<WatchDescriptor>,
+    // Its media is unmounted, it's synced again once it's back.
+    #[serde(skip)]
+    pub absent: bool,
+}
+
+// The original tracked files format (no version prefix), a drive url and path per file.
+#[derive(Deserialize)]
+struct LegacyTrackedFile {
+    drive_url: String,
+    #[serde(with = "rawpath")]
+    path: PathBuf,
 }
 
 impl TrackedFile {
+    pub fn new<P: Into<PathBuf>, U: Into<String>>(p: P, u: U) -> TrackedFile {
+        TrackedFile {
+            drive_url: u.into(),
+            path: p.into(),
+            ..TrackedFile::default()
+        }
+    }
+
+    pub fn is_export(&self) -> bool {
+        self.export.is_some()
+    }
+
+    // Whether the local file changed since it was last synced, None if that can't be told (never synced, or missing).
+    // Only files modified since then are hashed.
+    pub fn changed_locally(&self) -> Option<bool> {
+        let (synced_at, md5) = (self.synced_at?, self.md5.as_ref()?);
+        let modified = fs::metadata(&self.path).ok()?.mtime();
+        if modified < synced_at {
+            return Some(false);
+        }
+        Some(checksum::md5_file(&self.path).ok()?.as_str() != md5)
+    }
+
+    // What the file is watched for: its own events, or the configured ones.
+    pub fn events(&self, defaults: &Events) -> Vec<WatchEvent> {
+        match &self.events {
+            Some(events) => events.clone(),
+            None => defaults.for_path(&self.path),
+        }
+    }
+
+    // Serialize a list of tracked files, with the version prefix.
+    pub fn encode_all(files: &[TrackedFile]) -> Vec<u8> {
+        let mut buf = TRACKED_MAGIC.to_vec();
+        buf.extend_from_slice(&TRACKED_VERSION.to_le_bytes());
+        buf.extend(bincode::serialize(files).unwrap());
+        buf
+    }
+
+    // Format version of an encoded list of tracked files, 0 for the original unversioned format.
+    pub fn version(buf: &[u8]) -> u32 {
+        if buf.len() >= 8 && &buf[..4] == TRACKED_MAGIC {
+            let mut version = [0; 4];
+            version.copy_from_slice(&buf[4..8]);
+            return u32::from_le_bytes(version);
+        }
+        0
+    }
+
+    // Deserialize the current or the original (unversioned) format.
+    pub fn decode_all(buf: &[u8]) -> Result<Vec<TrackedFile>, bincode::Error> {
+        if buf.len() >= 8 && &buf[..4] == TRACKED_MAGIC {
+            let version = TrackedFile::version(buf);
+            if version != TRACKED_VERSION {
+                return Err(Box::new(bincode::ErrorKind::Custom(format!(
+                    "unknown tracked files format version {}",
+                    version
+                ))));
+            }
+            return bincode::deserialize(&buf[8..]);
+        }
+        let legacy: Vec<LegacyTrackedFile> = bincode::deserialize(buf)?;
+        Ok(legacy
+            .into_iter()
+            .map(|tf| TrackedFile::new(tf.path, tf.drive_url))
+            .collect())
+    }
+
+    // The tracked files in the state database (or bincode list, from before it) at p. Empty if it can't be read.
     pub fn from_path<P: Into<PathBuf>>(p: P) -> Vec<TrackedFile> {
+        let p = p.into();
+        if state::is_database(&p) {
+            return state::read(&p).unwrap_or_else(|e| {
+                log::error!("Error reading tracked files from {:?}: {}", p, e);
+                Vec::new()
+            });
+        }
         // On a failed file read, just return an empty vec.
-        let mut f = match File::open(p.into()) {
+        let mut f = match File::open(&p) {
             Ok(f) => f,
             Err(e) => {
                 log::error!("Error opening tracked files config file: {:?}", e);
@@ -277,14 +1651,26 @@ impl TrackedFile {
         let mut buf: Vec<u8> = Vec::new();
         f.read_to_end(&mut buf).unwrap();
         // Deserialize file to Vec<Trackedfile>
-        match bincode::deserialize(&buf) {
-            Ok(v) => return v,
+        match TrackedFile::decode_all(&buf) {
+            Ok(v) => v,
             Err(e) => {
                 log::warn!("Error deserializing from file: {:?}.. Continuing anyways with a blank tracker.", e);
-                return Vec::new();
+                Vec::new()
             }
-        };
+        }
     }
-}
 
-fn main() {}
+    // This profile's tracked files, as the daemon last saved them. From the tracked_files list if the daemon hasn't
+    // run since the state database replaced it.
+    pub fn load_all() -> Vec<TrackedFile> {
+        let state = state_path();
+        if state.exists() {
+            return TrackedFile::from_path(state);
+        }
+        let legacy = config_dir();
+        if legacy.exists() {
+            return TrackedFile::from_path(legacy);
+        }
+        Vec::new()
+    }
+}
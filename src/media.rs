@@ -0,0 +1,131 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+// Files on filesystems that come and go (USB drives, SD cards). When one is unmounted its files are marked absent
+// instead of failing, and they're watched again (and reconciled) when it's mounted again, wherever it's mounted.
+
+// The filesystem a tracked file lives on, when that isn't the root filesystem.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Media {
+    // Filesystem UUID, how the media is found again if it comes back at another mount point.
+    pub uuid: Option<String>,
+    // Where it was mounted when last seen.
+    #[serde(with = "crate::rawpath")]
+    pub mount_point: PathBuf,
+}
+
+struct Mount {
+    point: PathBuf,
+    // Device name, e.g. sdb1.
+    device: Option<String>,
+}
+
+lazy_static! {
+    // Where mount information is read from, [media] in the config.
+    static ref SOURCES: RwLock<config::Media> = RwLock::new(config::Media::default());
+}
+
+// Read mount information from where [media] says, instead of /proc/self/mountinfo and /dev/disk/by-uuid.
+pub fn configure(sources: &config::Media) {
+    *SOURCES.write().unwrap() = sources.clone();
+}
+
+// The mount table as it is now. Compared between calls to notice mounts and unmounts cheaply.
+pub fn mountinfo() -> String {
+    let path = SOURCES.read().unwrap().mountinfo.clone();
+    fs::read_to_string(path).unwrap_or_default()
+}
+
+// Mount points in mountinfo are escaped, spaces as \040 and so on.
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        match u8::from_str_radix(rest.get(i + 1..i + 4).unwrap_or(""), 8) {
+            Ok(b) => {
+                out.push(b as char);
+                rest = &rest[i + 4..];
+            }
+            Err(_) => {
+                out.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn mounts(info: &str) -> Vec<Mount> {
+    info.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let point = PathBuf::from(unescape(fields.get(4)?));
+            // The source comes after the optional fields, two past the "-" separator.
+            let sep = fields.iter().position(|f| *f == "-")?;
+            let device = fields
+                .get(sep + 2)
+                .filter(|s| s.starts_with("/dev/"))
+                .and_then(|s| Path::new(s).file_name())
+                .map(|n| n.to_string_lossy().into_owned());
+            Some(Mount { point, device })
+        })
+        .collect()
+}
+
+// UUID of the filesystem on device, from the /dev/disk/by-uuid links (e.g. ABCD-1234 -> ../../sdb1).
+fn uuid_of(device: &str) -> Option<String> {
+    let dir = SOURCES.read().unwrap().by_uuid.clone();
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .find(|e| {
+            fs::read_link(e.path())
+                .map(|target| target.file_name().map(|n| n == device).unwrap_or(false))
+                .unwrap_or(false)
+        })
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+}
+
+// The media path is on, None if it's on the root filesystem (which never goes away).
+pub fn media_of(path: &Path) -> Option<Media> {
+    media_in(&mountinfo(), path)
+}
+
+// Like media_of, with the mount table already read (see mountinfo), for many paths at once.
+pub fn media_in(info: &str, path: &Path) -> Option<Media> {
+    let mount = mounts(info)
+        .into_iter()
+        .filter(|m| path.starts_with(&m.point))
+        .max_by_key(|m| m.point.components().count())?;
+    if mount.point == Path::new("/") {
+        return None;
+    }
+    Some(Media {
+        uuid: mount.device.as_deref().and_then(uuid_of),
+        mount_point: mount.point,
+    })
+}
+
+// Where media is mounted right now: wherever its UUID is, or its old mount point if it has no UUID. None if it isn't
+// mounted.
+pub fn mounted_at(media: &Media) -> Option<PathBuf> {
+    let mounts = mounts(&mountinfo());
+    match &media.uuid {
+        Some(uuid) => mounts
+            .into_iter()
+            .find(|m| m.device.as_deref().and_then(uuid_of).as_ref() == Some(uuid))
+            .map(|m| m.point),
+        None => mounts
+            .into_iter()
+            .find(|m| m.point == media.mount_point)
+            .map(|m| m.point),
+    }
+}
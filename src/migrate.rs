@@ -0,0 +1,235 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chrono::Utc;
+use hmac::Hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::{self, Issue};
+use crate::state::State;
+use crate::{
+    config_dir, folders_path, settings_path, state_path, transfer, watched_path, write_atomic,
+    TrackedFile,
+};
+
+// Moving rgdrive to another machine: the config, the tracked files and (optionally) the Drive sign in, in
+// one file encrypted with a passphrase. Written by --migrate-export, restored by --migrate-import.
+
+const BUNDLE_MAGIC: &[u8; 4] = b"RGDB";
+const BUNDLE_VERSION: u32 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+// PBKDF2-HMAC-SHA256 rounds turning the passphrase into the key.
+const KDF_ROUNDS: u32 = 100_000;
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Bundle {
+    // rgdrive version, host and time the bundle was made on.
+    pub version: String,
+    pub host: String,
+    pub created: String,
+    // $HOME where the bundle was made. Paths beneath it are moved beneath $HOME on import.
+    pub home: PathBuf,
+    pub settings: Option<String>,
+    pub tracked: Vec<TrackedFile>,
+    pub folders: Option<String>,
+    pub watched: Option<String>,
+    // $GOOGLE_CLIENT_ID and $GOOGLE_CLIENT_SECRET, only with --with-tokens.
+    pub credentials: Option<(String, String)>,
+    // The refresh token from signing in, with --with-tokens when signed in.
+    pub refresh_token: Option<String>,
+}
+
+// What --migrate-import did.
+#[derive(Debug, Default)]
+pub struct Restored {
+    pub tracked: usize,
+    // Tracked files moved from the old $HOME to this one.
+    pub rebased: usize,
+    // Tracked files that don't exist here, left out.
+    pub missing: Vec<TrackedFile>,
+    // Problems with the imported config, see config::check_str.
+    pub issues: Vec<Issue>,
+    pub credentials: bool,
+}
+
+impl Bundle {
+    // This profile's state. Queued retries, changes held for review and the journal stay behind, they only make sense
+    // on this machine.
+    pub fn collect(with_credentials: bool) -> Result<Bundle, String> {
+        let (credentials, refresh_token) = if with_credentials {
+            let credentials =
+                transfer::credentials().map_err(|e| format!("--with-tokens: {}", e))?;
+            (Some(credentials), transfer::refresh_token().ok())
+        } else {
+            (None, None)
+        };
+        let tracked = TrackedFile::load_all();
+        Ok(Bundle {
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            host: config::hostname(),
+            created: Utc::now().to_rfc3339(),
+            home: home(),
+            settings: fs::read_to_string(settings_path()).ok(),
+            tracked,
+            folders: fs::read_to_string(folders_path()).ok(),
+            watched: fs::read_to_string(watched_path()).ok(),
+            credentials,
+            refresh_token,
+        })
+    }
+
+    // Encrypt the bundle with a key derived from passphrase. The header (magic, version, salt and nonce) is
+    // authenticated along with the contents.
+    pub fn seal(&self, passphrase: &str) -> Result<Vec<u8>, String> {
+        if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+            return Err(format!(
+                "Use a passphrase of at least {} characters.",
+                MIN_PASSPHRASE_LEN
+            ));
+        }
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut salt)
+            .and_then(|_| getrandom::getrandom(&mut nonce))
+            .map_err(|e| format!("Couldn't generate a key: {}", e))?;
+        let mut buf = BUNDLE_MAGIC.to_vec();
+        buf.extend_from_slice(&BUNDLE_VERSION.to_le_bytes());
+        buf.extend_from_slice(&salt);
+        buf.extend_from_slice(&nonce);
+        let contents = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+        let sealed = cipher(passphrase, &salt)
+            .encrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: &contents,
+                    aad: &buf,
+                },
+            )
+            .map_err(|_| String::from("Couldn't encrypt the bundle."))?;
+        buf.extend(sealed);
+        Ok(buf)
+    }
+
+    pub fn open(buf: &[u8], passphrase: &str) -> Result<Bundle, String> {
+        let header = 8 + SALT_LEN + NONCE_LEN;
+        if buf.len() < header || &buf[..4] != BUNDLE_MAGIC {
+            return Err(String::from(
+                "Not an rgdrive bundle, make one with --migrate-export.",
+            ));
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&buf[4..8]);
+        let version = u32::from_le_bytes(version);
+        if version != BUNDLE_VERSION {
+            return Err(format!(
+                "The bundle is format version {}, this rgdrive only reads version {}. Update rgdrive first.",
+                version, BUNDLE_VERSION
+            ));
+        }
+        let salt = &buf[8..8 + SALT_LEN];
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&buf[8 + SALT_LEN..header]);
+        let contents = cipher(passphrase, salt)
+            .decrypt(
+                &Nonce::from(nonce),
+                Payload {
+                    msg: &buf[header..],
+                    aad: &buf[..header],
+                },
+            )
+            .map_err(|_| String::from("Wrong passphrase, or the bundle is damaged."))?;
+        serde_json::from_slice(&contents).map_err(|e| format!("Unreadable bundle: {}", e))
+    }
+
+    // Write the bundle's state into this profile. Tracked files are moved to this $HOME if they were in the old one,
+    // and left out if they don't exist here. Unless force is set, nothing is replaced if this profile already has a
+    // config or tracked files. The daemon mustn't be running, it would save over the tracked files.
+    pub fn restore(self, force: bool) -> Result<Restored, String> {
+        let existing = TrackedFile::load_all().len();
+        if !force && (existing > 0 || settings_path().exists()) {
+            return Err(format!(
+                "This profile already has a config or tracked files ({} tracked), pass --force to replace them.",
+                existing
+            ));
+        }
+
+        let home = home();
+        let mut restored = Restored::default();
+        let mut kept = Vec::with_capacity(self.tracked.len());
+        for mut tf in self.tracked {
+            if let Some(p) = rebase(&tf.path, &self.home, &home) {
+                tf.path = p;
+                restored.rebased += 1;
+            }
+            if tf.path.exists() {
+                kept.push(tf);
+            } else {
+                restored.missing.push(tf);
+            }
+        }
+        restored.tracked = kept.len();
+        let err = |e: std::io::Error| format!("Couldn't write the imported state: {}", e);
+        // Saving adds to what's tracked, the files tracked here before --force are dropped first.
+        if state_path().exists() {
+            fs::remove_file(state_path()).map_err(err)?;
+        }
+        let mut state = State::open(&state_path())
+            .map_err(|e| format!("Couldn't write the imported state: {}", e))?;
+        state
+            .upsert_all(&kept)
+            .map_err(|e| format!("Couldn't write the imported state: {}", e))?;
+        // An older tracked_files list would be moved over them when the daemon starts.
+        if config_dir().exists() {
+            fs::remove_file(config_dir()).map_err(err)?;
+        }
+
+        if let Some(settings) = self.settings {
+            let settings = if self.home != home {
+                settings.replace(
+                    &format!("{}/", self.home.display()),
+                    &format!("{}/", home.display()),
+                )
+            } else {
+                settings
+            };
+            restored.issues = config::check_str(&settings);
+            write_atomic(&settings_path(), settings.as_bytes()).map_err(err)?;
+        }
+        if let Some(folders) = self.folders {
+            write_atomic(&folders_path(), folders.as_bytes()).map_err(err)?;
+        }
+        if let Some(watched) = self.watched {
+            write_atomic(&watched_path(), watched.as_bytes()).map_err(err)?;
+        }
+        if let Some((id, secret)) = self.credentials {
+            transfer::save_credentials(&id, &secret, self.refresh_token.as_deref()).map_err(err)?;
+            restored.credentials = true;
+        }
+        Ok(restored)
+    }
+}
+
+// p beneath home, if it was beneath old and the two differ.
+fn rebase(p: &Path, old: &Path, home: &Path) -> Option<PathBuf> {
+    if old == home {
+        return None;
+    }
+    p.strip_prefix(old).ok().map(|rest| home.join(rest))
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, KDF_ROUNDS, &mut key);
+    ChaCha20Poly1305::new(&Key::from(key))
+}
+
+fn home() -> PathBuf {
+    PathBuf::from(env::var("HOME").expect("$HOME not set"))
+}
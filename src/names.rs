@@ -0,0 +1,86 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{stamp, xattr};
+
+// Drive keeps names in NFC, Linux filesystems keep whatever bytes were written, which for names that came from a Mac is
+// often NFD. "café" can be two different byte strings, so local entries are matched to Drive's by their NFC form.
+
+pub fn nfc(name: &str) -> String {
+    name.nfc().collect()
+}
+
+// Whether two names are the same once normalized.
+pub fn same_name(a: &str, b: &str) -> bool {
+    a == b || nfc(a) == nfc(b)
+}
+
+// The entry in dir for the Drive name, however it's normalized locally. An exact match wins, None if there's none.
+pub fn find_in(dir: &Path, name: &str) -> Option<PathBuf> {
+    let exact = dir.join(name);
+    if exact.exists() {
+        return Some(exact);
+    }
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .find(|e| {
+            e.file_name()
+                .to_str()
+                .map(|n| same_name(n, name))
+                .unwrap_or(false)
+        })
+        .map(|e| e.path())
+}
+
+// The entry in dir whose name differs from name only in case, if there is one. On a case-insensitive mount (FAT, SMB)
+// it's the same file, so writing name would overwrite it.
+pub fn case_collision(dir: &Path, name: &str) -> Option<PathBuf> {
+    let folded = nfc(name).to_lowercase();
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .find(|e| {
+            e.file_name()
+                .to_str()
+                .map(|n| !same_name(n, name) && nfc(n).to_lowercase() == folded)
+                .unwrap_or(false)
+        })
+        .map(|e| e.path())
+}
+
+// Where the Drive file id called name is pulled to in dir: what's already there by that name (see find_in), or a new
+// entry. A new name that only differs in case from one already there is numbered instead, "name (2).ext", "name (3).ext"
+// and so on, the first that's free (or already holds the same Drive file, see stamp), so it never overwrites the other
+// file and pulling again finds the same one.
+pub fn dest_in(dir: &Path, name: &str, id: &str) -> PathBuf {
+    if let Some(existing) = find_in(dir, name) {
+        return existing;
+    }
+    let other = match case_collision(dir, name) {
+        Some(p) => p,
+        None => return dir.join(name),
+    };
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => name.split_at(i),
+        _ => (name, ""),
+    };
+    let dest = (2..)
+        .map(|n| format!("{} ({}){}", stem, n, ext))
+        .find_map(|n| match find_in(dir, &n) {
+            Some(p) if xattr::get(&p, stamp::ID_ATTR).as_deref() == Some(id) => Some(p),
+            Some(_) => None,
+            None if case_collision(dir, &n).is_none() => Some(dir.join(n)),
+            None => None,
+        })
+        .unwrap();
+    log::warn!(
+        "{:?} only differs in case from {:?}, pulling it as {:?}.",
+        name,
+        other,
+        dest
+    );
+    dest
+}
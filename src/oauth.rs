@@ -0,0 +1,240 @@
+use std::env;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::remote::{ACTIVITY_SCOPE, DRIVE_SCOPE};
+
+// Signing in to Drive, OAuth 2.0 for desktop apps: the browser asks the user to let rgdrive in, then hands a code back
+// to a listener on 127.0.0.1 which is traded for a refresh token. That's saved with the client credentials (see
+// transfer::save_credentials) and traded for an access token whenever a connection needs one.
+
+const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+// Longest a sign in waits for the browser to come back.
+const SIGN_IN_TIMEOUT: Duration = Duration::from_secs(300);
+
+// An access token and what it's good for.
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub access: String,
+    // Unix timestamp (seconds).
+    pub expires: i64,
+    pub scopes: Vec<String>,
+    // Only handed out by a sign in.
+    pub refresh: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+    #[serde(default)]
+    scope: String,
+    refresh_token: Option<String>,
+}
+
+impl TokenResponse {
+    fn token(self) -> Token {
+        Token {
+            access: self.access_token,
+            expires: Utc::now().timestamp() + self.expires_in,
+            scopes: self.scope.split_whitespace().map(String::from).collect(),
+            refresh: self.refresh_token,
+        }
+    }
+}
+
+// url of one of Google's apis. With $RGDRIVE_GOOGLE_API set, the same path on that server instead, e.g. a proxy.
+pub fn endpoint(url: &str) -> String {
+    match env::var("RGDRIVE_GOOGLE_API") {
+        Ok(base) => {
+            let path = url.splitn(4, '/').nth(3).unwrap_or("");
+            format!("{}/{}", base.trim_end_matches('/'), path)
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+// Sign in with the browser: print (and try to open) Google's consent page, and wait for it to redirect back with a
+// code. The token that's traded for includes a refresh token.
+pub fn sign_in(id: &str, secret: &str) -> Result<Token, String> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.set_nonblocking(true).map(|_| l))
+        .map_err(|e| format!("Couldn't listen for the sign in: {}", e))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("Couldn't listen for the sign in: {}", e))?
+        .port();
+    let redirect = format!("http://127.0.0.1:{}", port);
+    let state = random_hex(16)?;
+    // PKCE, so a code intercepted on its way back is no use without the verifier.
+    let verifier = random_hex(32)?;
+    let challenge =
+        base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+    let scopes = format!("{} {}", DRIVE_SCOPE, ACTIVITY_SCOPE);
+    let url = Url::parse_with_params(
+        &endpoint(AUTH_URL),
+        &[
+            ("client_id", id),
+            ("redirect_uri", redirect.as_str()),
+            ("response_type", "code"),
+            ("scope", scopes.as_str()),
+            ("access_type", "offline"),
+            ("prompt", "consent"),
+            ("state", state.as_str()),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|e| format!("Bad sign in url: {}", e))?;
+    eprintln!("Sign in to Drive in your browser: {}", url);
+    open_browser(url.as_str());
+    let code = wait_for_code(&listener, &state)?;
+    let resp = ureq::post(&endpoint(TOKEN_URL))
+        .timeout(Duration::from_secs(30))
+        .send_form(&[
+            ("code", code.as_str()),
+            ("client_id", id),
+            ("client_secret", secret),
+            ("redirect_uri", redirect.as_str()),
+            ("grant_type", "authorization_code"),
+            ("code_verifier", verifier.as_str()),
+        ]);
+    let token = parse_token(resp)?;
+    if token.refresh.is_none() {
+        return Err(String::from(
+            "Drive signed in without a refresh token. Remove rgdrive's access at \
+            https://myaccount.google.com/permissions and sign in again.",
+        ));
+    }
+    Ok(token)
+}
+
+// Trade a refresh token from a sign in for an access token.
+pub fn refresh(id: &str, secret: &str, refresh_token: &str) -> Result<Token, String> {
+    let resp = ureq::post(&endpoint(TOKEN_URL))
+        .timeout(Duration::from_secs(30))
+        .send_form(&[
+            ("client_id", id),
+            ("client_secret", secret),
+            ("refresh_token", refresh_token),
+            ("grant_type", "refresh_token"),
+        ]);
+    parse_token(resp)
+}
+
+// The token endpoint answers errors as {"error": "invalid_grant", "error_description": "..."}.
+fn parse_token(resp: ureq::Response) -> Result<Token, String> {
+    if let Some(e) = resp.synthetic_error() {
+        return Err(format!(
+            "Couldn't reach Google's sign in (connection failed): {}",
+            e
+        ));
+    }
+    let (status, text) = (resp.status(), resp.status_text().to_string());
+    let body = resp.into_string().unwrap_or_default();
+    if !(200..300).contains(&status) {
+        let v: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
+        let error = v["error"].as_str().unwrap_or("");
+        let description = v["error_description"].as_str().unwrap_or("");
+        return Err(format!("{} {}: {}, {}", status, text, error, description));
+    }
+    serde_json::from_str::<TokenResponse>(&body)
+        .map(TokenResponse::token)
+        .map_err(|e| format!("Unexpected answer from Google's sign in: {}", e))
+}
+
+// $BROWSER, or xdg-open. Nothing's lost if neither works, the url is printed to be opened by hand.
+fn open_browser(url: &str) {
+    let browser = env::var("BROWSER").unwrap_or_else(|_| String::from("xdg-open"));
+    let _ = Command::new(browser)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+}
+
+// Serve the browser's requests until the redirect with the code (or an error) comes back.
+fn wait_for_code(listener: &TcpListener, state: &str) -> Result<String, String> {
+    let deadline = Instant::now() + SIGN_IN_TIMEOUT;
+    loop {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(String::from("Timed out waiting for the sign in."));
+                }
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Err(e) => return Err(format!("Sign in failed: {}", e)),
+        };
+        if let Some(result) = answer(stream, state) {
+            return result;
+        }
+    }
+}
+
+// The code (or error) in one request to the listener, None for anything else the browser asks for (e.g. a favicon).
+fn answer(mut stream: TcpStream, state: &str) -> Option<Result<String, String>> {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+    let mut reader = BufReader::new(stream.try_clone().ok()?);
+    let mut request = String::new();
+    reader.read_line(&mut request).ok()?;
+    // The headers aren't needed, but reading them keeps the browser from seeing the connection reset.
+    let mut line = String::new();
+    while reader.read_line(&mut line).map(|n| n > 2).unwrap_or(false) {
+        line.clear();
+    }
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    let url = Url::parse(&format!("http://127.0.0.1{}", target)).ok()?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    let result = if let Some(error) = param("error") {
+        Err(format!("Sign in was refused: {}", error))
+    } else if let Some(code) = param("code") {
+        if param("state").as_deref() == Some(state) {
+            Ok(code)
+        } else {
+            Err(String::from(
+                "Sign in came back for another request, try again.",
+            ))
+        }
+    } else {
+        let _ = stream
+            .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        return None;
+    };
+    let page = match &result {
+        Ok(_) => "Signed in to Drive, you can close this tab.",
+        Err(_) => "Signing in to Drive failed, see rgdrive for why.",
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        page.len(),
+        page
+    );
+    Some(result)
+}
+
+fn random_hex(bytes: usize) -> Result<String, String> {
+    let mut buf = vec![0u8; bytes];
+    getrandom::getrandom(&mut buf)
+        .map_err(|e| format!("Couldn't generate a sign in secret: {}", e))?;
+    Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+}
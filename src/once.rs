@@ -0,0 +1,256 @@
+// rgdrive --push/--pull --once: do the transfer right here in the cli, without the daemon. Nothing is tracked or watched
+// afterwards, which suits containers, CI jobs and scripts.
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use rgdrive::config::Config;
+use rgdrive::export::Export;
+use rgdrive::hooks;
+use rgdrive::journal::{self, Direction, Entry};
+use rgdrive::remote::{drive_id, Remote};
+use rgdrive::transfer::{
+    self, check_overwrite, preserve_before_overwrite, push_paths, restore_trashed, upload,
+    upload_folder, vanished, Overwrite,
+};
+use rgdrive::{canonical_path, DResult, TrackedFile};
+
+// Config and a connected remote, the part of daemon startup a one-shot transfer needs.
+fn setup() -> Result<(Config, Box<dyn Remote>), String> {
+    let config = Config::load()?;
+    let remote = transfer::connect().map_err(|e| e.to_string())?;
+    Ok((config, remote))
+}
+
+// Resolve an [aliases] name or drive:/path to a url, anything else is used as is.
+fn resolve(config: &Config, remote: &mut dyn Remote, target: &str) -> Result<String, String> {
+    if Config::is_symbolic(target) {
+        config.resolve(remote, target)
+    } else {
+        Ok(target.to_string())
+    }
+}
+
+// One-shot transfers are journaled like the daemon's. The journal is best effort, the transfer already happened.
+fn journal(op: &str, path: &Path, url: &str, direction: Direction, result: Result<(), String>) {
+    if let Err(e) = journal::record(&Entry::new(op, path, url, direction, result)) {
+        eprintln!("Failed to write journal entry for {:?}: {}", path, e);
+    }
+}
+
+pub fn push(path: &Path, dest: Option<&str>, name: Option<&str>, excludes: bool) -> DResult {
+    match try_push(path, dest, name, excludes) {
+        Ok(m) => DResult::ok(m),
+        Err(e) => DResult::error(e),
+    }
+}
+
+fn try_push(
+    path: &Path,
+    dest: Option<&str>,
+    name: Option<&str>,
+    excludes: bool,
+) -> Result<String, String> {
+    if !path.exists() {
+        return Err(format!("Cannot push path: {:?} does not exist.", path));
+    }
+    if name.is_some() && path.is_dir() {
+        return Err(format!(
+            "Cannot push {:?} under another name: only single files can be renamed on Drive.",
+            path
+        ));
+    }
+    let (config, mut remote) = setup()?;
+    let dest = match dest {
+        Some(d) => Some(resolve(&config, &mut *remote, d)?),
+        None => None,
+    };
+    let folder = upload_folder(&mut *remote, dest.as_deref(), &config)?;
+
+    if !path.is_dir() {
+        let result = hooks::pre_upload(&config.hooks.pre_upload, path)
+            .and_then(|_| upload(&mut *remote, path, folder.as_deref()).map_err(|e| e.to_string()));
+        journal(
+            "push",
+            path,
+            result.as_deref().unwrap_or(""),
+            Direction::Up,
+            result.as_ref().map(|_| ()).map_err(|e| e.clone()),
+        );
+        let url = result.map_err(|e| format!("Failed to upload {:?}: {}", path, e))?;
+        if let Some(name) = name {
+            let id =
+                drive_id(&url).ok_or_else(|| format!("Drive returned a bad url: {:?}", url))?;
+            let renamed = remote.rename(id, name).map_err(|e| e.to_string());
+            journal("rename", path, &url, Direction::None, renamed.clone());
+            renamed.map_err(|e| {
+                format!(
+                    "Pushed {:?} to {}, but renaming it on Drive failed: {}",
+                    path, url, e
+                )
+            })?;
+        }
+        return Ok(url);
+    }
+
+    let (paths, skipped) = push_paths(path, excludes, &config.excludes.ignore);
+    let (mut failed, mut deleted) = (0, 0);
+    for p in &paths {
+        let uploaded = hooks::pre_upload(&config.hooks.pre_upload, p)
+            .and_then(|_| upload(&mut *remote, p, folder.as_deref()).map_err(|e| e.to_string()));
+        match uploaded {
+            Ok(url) => {
+                println!("{:?} -> {}", p, url);
+                journal("push", p, &url, Direction::Up, Ok(()));
+            }
+            Err(_) if vanished(p) => {
+                println!(
+                    "{:?} was deleted before it could be uploaded, skipped it.",
+                    p
+                );
+                journal("vanished", p, "", Direction::None, Ok(()));
+                deleted += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to upload {:?}: {}", p, e);
+                journal("push", p, "", Direction::Up, Err(e));
+                failed += 1;
+            }
+        }
+    }
+    let mut msg = format!(
+        "Directory upload status: {} successes, {} fails, {} skipped by the excludes.",
+        paths.len() - failed - deleted,
+        failed,
+        skipped
+    );
+    if deleted > 0 {
+        msg.push_str(&format!(
+            " {} deleted before they could be uploaded.",
+            deleted
+        ));
+    }
+    if failed > 0 {
+        Err(msg)
+    } else {
+        Ok(msg)
+    }
+}
+
+pub fn pull(url: &str, path: &Path, overwrite: Overwrite) -> DResult {
+    match try_pull(url, path, overwrite) {
+        Ok(m) => DResult::ok(m),
+        Err(e) => DResult::error(e),
+    }
+}
+
+// A pull goes into an existing directory, or to a new file in one.
+fn check_dest(path: &Path) -> Result<(), String> {
+    if !path.is_dir()
+        && !path
+            .parent()
+            .map(|p| p.as_os_str().is_empty() || p.is_dir())
+            .unwrap_or(false)
+    {
+        return Err(format!("Destination {:?} doesn't exist.", path));
+    }
+    Ok(())
+}
+
+fn try_pull(url: &str, path: &Path, overwrite: Overwrite) -> Result<String, String> {
+    check_dest(path)?;
+    let (config, mut remote) = setup()?;
+    let url = resolve(&config, &mut *remote, url)?;
+    config.policy.permits(&mut *remote, &url)?;
+    // The daemon's record of the file, if it's tracked.
+    let synced = TrackedFile::load_all()
+        .into_iter()
+        .find(|tf| tf.path == canonical_path(path))
+        .and_then(|tf| tf.md5);
+    check_overwrite(overwrite, path, &url, synced.as_deref(), &mut *remote)?;
+
+    let trashed = preserve_before_overwrite(path, &config)?;
+    match remote.download(&url, path) {
+        Ok(written) => {
+            journal("pull", &written, &url, Direction::Down, Ok(()));
+            Ok(format!("Pulled {} to {:?}.", url, written))
+        }
+        Err(e) => {
+            restore_trashed(trashed, path);
+            journal("pull", path, &url, Direction::Down, Err(e.to_string()));
+            Err(format!("Error downloading {}: {}", url, e))
+        }
+    }
+}
+
+// --pull --anonymous: a file shared with anyone with the link, without credentials, config or a daemon, e.g. on a fresh
+// machine. There's no account to sync it with, so it's only downloaded. Docs editors files need an export format.
+pub fn pull_anonymous(
+    url: &str,
+    path: &Path,
+    overwrite: Overwrite,
+    export: Option<Export>,
+) -> DResult {
+    match try_pull_anonymous(url, path, overwrite, export) {
+        Ok(m) => DResult::ok(m),
+        Err(e) => DResult::error(e),
+    }
+}
+
+fn try_pull_anonymous(
+    url: &str,
+    path: &Path,
+    overwrite: Overwrite,
+    export: Option<Export>,
+) -> Result<String, String> {
+    check_dest(path)?;
+    if Config::is_symbolic(url) {
+        return Err(format!(
+            "{} needs Drive access to resolve, pull the file's link with --anonymous instead.",
+            url
+        ));
+    }
+    let id = drive_id(url).ok_or_else(|| format!("{:?} is not a drive url.", url))?;
+    let config = Config::load()?;
+    let mut remote = transfer::connect_anonymous();
+
+    // The file name only comes with the file, so it's staged beside where it goes until the overwrite check.
+    let dir = match path.parent() {
+        Some(p) if !path.is_dir() => p,
+        _ => path,
+    };
+    let staging = dir.join(format!(".rgdrive-anonymous.{}", process::id()));
+    fs::create_dir_all(&staging).map_err(|e| format!("{:?}: {}", staging, e))?;
+    let result = match &export {
+        Some(e) => remote.export(id, e, &staging),
+        None => remote.download(url, &staging),
+    }
+    .map_err(|e| e.to_string())
+    .and_then(|staged| {
+        let dest = match staged.file_name() {
+            Some(name) if path.is_dir() => path.join(name),
+            _ => path.to_path_buf(),
+        };
+        check_overwrite(overwrite, &dest, url, None, &mut *remote)?;
+        let trashed = preserve_before_overwrite(&dest, &config)?;
+        fs::rename(&staged, &dest).map_err(|e| {
+            restore_trashed(trashed, &dest);
+            format!("{:?}: {}", dest, e)
+        })?;
+        Ok(dest)
+    });
+    let _ = fs::remove_dir_all(&staging);
+    match result {
+        Ok(written) => {
+            journal("pull", &written, url, Direction::Down, Ok(()));
+            Ok(format!(
+                "Pulled {} to {:?} anonymously, it isn't synced.",
+                url, written
+            ))
+        }
+        Err(e) => {
+            journal("pull", path, url, Direction::Down, Err(e.clone()));
+            Err(format!("Error pulling {}: {}", url, e))
+        }
+    }
+}
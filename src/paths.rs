@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Error;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::names::same_name;
+use crate::remote::{FileChange, Remote, RemoteError, FOLDER_MIME};
+use crate::{folders_path, write_atomic};
+
+// Prefix for addressing Drive files by path, e.g. drive:/Work/Specs/plan.md.
+pub const DRIVE_PREFIX: &str = "drive:";
+// Folder id Drive accepts for the root of My Drive.
+pub const ROOT_ID: &str = "root";
+
+// How long (seconds) a cached folder is trusted before it's looked up again. The poller drops folders that change on
+// Drive long before that, this only bounds how stale the cache gets with polling off.
+const CACHE_TTL_SECS: i64 = 24 * 3600;
+
+// A folder found by name beneath another.
+#[derive(Serialize, Deserialize, Clone)]
+struct Cached {
+    id: String,
+    // Unix timestamp (seconds) of the lookup.
+    time: i64,
+}
+
+// What's kept on disk: parent id -> name -> folder.
+#[derive(Default, Serialize, Deserialize)]
+struct Folders {
+    children: HashMap<String, HashMap<String, Cached>>,
+}
+
+impl Folders {
+    fn load() -> Folders {
+        fs::read_to_string(folders_path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    // Losing the cache only costs lookups, so a failed save is logged and otherwise ignored.
+    fn save(&self) {
+        if let Err(e) = self.write() {
+            log::warn!("Failed to save the Drive folder cache: {:?}", e);
+        }
+    }
+
+    fn write(&self) -> Result<(), Error> {
+        let p = folders_path();
+        if let Some(parent) = p.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&p, serde_json::to_string(self)?.as_bytes())
+    }
+
+    fn get(&self, parent: &str, name: &str) -> Option<&str> {
+        self.children
+            .get(parent)?
+            .get(name)
+            .filter(|c| Utc::now().timestamp() - c.time < CACHE_TTL_SECS)
+            .map(|c| c.id.as_str())
+    }
+
+    fn insert(&mut self, parent: &str, name: &str, id: &str) {
+        self.children.entry(parent.to_string()).or_default().insert(
+            name.to_string(),
+            Cached {
+                id: id.to_string(),
+                time: Utc::now().timestamp(),
+            },
+        );
+        self.save();
+    }
+
+    // Forget the folder id, and everything found beneath it.
+    fn invalidate(&mut self, id: &str) {
+        self.children.remove(id);
+        for names in self.children.values_mut() {
+            names.retain(|_, c| c.id != id);
+        }
+        self.children.retain(|_, names| !names.is_empty());
+    }
+}
+
+// Cache of (folder id, name) -> id lookups of the folders along a path and the folders uploads land in, so pushes and
+// path lookups don't list every folder along the way each time. Kept on disk across restarts, and the poller drops
+// any folder that's renamed, moved or deleted on Drive (see forget_changed). The last component of a path is always
+// looked up fresh, that's the one most likely to have changed.
+pub struct PathCache {
+    folders: Mutex<Folders>,
+}
+
+lazy_static! {
+    pub static ref PATHS: PathCache = PathCache {
+        folders: Mutex::new(Folders::load()),
+    };
+}
+
+impl PathCache {
+    // Id of the file at path ("a/b/c.txt") beneath folder_id. If a walk that used the cache fails, it's retried
+    // without it in case something along the path was moved or renamed since.
+    pub fn walk<R: Remote + ?Sized>(
+        &self,
+        remote: &mut R,
+        folder_id: &str,
+        path: &str,
+    ) -> Result<String, String> {
+        match self.walk_from(remote, folder_id, path, true) {
+            Err((_, true)) => {
+                let mut folders = self.folders.lock().unwrap();
+                folders.children.clear();
+                folders.save();
+                drop(folders);
+                self.walk_from(remote, folder_id, path, false)
+                    .map_err(|(e, _)| e)
+            }
+            r => r.map_err(|(e, _)| e),
+        }
+    }
+
+    // Errors carry whether any cached component was used.
+    fn walk_from<R: Remote + ?Sized>(
+        &self,
+        remote: &mut R,
+        folder_id: &str,
+        path: &str,
+        use_cache: bool,
+    ) -> Result<String, (String, bool)> {
+        let mut id = folder_id.to_string();
+        let mut cached = false;
+        let names: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        for (i, &name) in names.iter().enumerate() {
+            if use_cache && i + 1 < names.len() {
+                if let Some(child) = self.folders.lock().unwrap().get(&id, name) {
+                    id = child.to_string();
+                    cached = true;
+                    continue;
+                }
+            }
+            let mut found = remote
+                .list_folder(&id)
+                .map_err(|e| (e.to_string(), cached))?
+                .into_iter()
+                .filter(|m| same_name(&m.name, name));
+            let child = match (found.next(), found.next()) {
+                (Some(m), None) => m.id,
+                // Drive allows several files with the same name in a folder, guessing would be worse than failing.
+                (Some(_), Some(_)) => {
+                    return Err((format!("{:?} matches more than one file.", name), cached))
+                }
+                (None, _) => return Err((format!("{:?} not found.", name), cached)),
+            };
+            // Only folders are worth keeping, the last component is never read from the cache.
+            if i + 1 < names.len() {
+                self.folders.lock().unwrap().insert(&id, name, &child);
+            }
+            id = child;
+        }
+        Ok(id)
+    }
+
+    // Id of the folder called name directly beneath parent, None if there isn't one.
+    pub fn folder<R: Remote + ?Sized>(
+        &self,
+        remote: &mut R,
+        parent: &str,
+        name: &str,
+    ) -> Result<Option<String>, RemoteError> {
+        if let Some(id) = self.folders.lock().unwrap().get(parent, name) {
+            return Ok(Some(id.to_string()));
+        }
+        let found = remote
+            .list_folder(parent)?
+            .into_iter()
+            .find(|m| same_name(&m.name, name) && m.mime_type == FOLDER_MIME);
+        if let Some(m) = &found {
+            self.remember(parent, name, &m.id);
+        }
+        Ok(found.map(|m| m.id))
+    }
+
+    // Cache a folder just created (or otherwise known) beneath parent.
+    pub fn remember(&self, parent: &str, name: &str, id: &str) {
+        self.folders.lock().unwrap().insert(parent, name, id);
+    }
+
+    // Drop the cached folders among changes (renamed, moved, trashed or removed on Drive), they're looked up again the
+    // next time they're needed. Returns how many were dropped.
+    pub fn forget_changed(&self, changes: &[FileChange]) -> usize {
+        let mut folders = self.folders.lock().unwrap();
+        let cached: HashSet<&str> = folders
+            .children
+            .values()
+            .flat_map(|names| names.values().map(|c| c.id.as_str()))
+            .collect();
+        let changed: HashSet<String> = changes
+            .iter()
+            .filter(|c| cached.contains(c.id.as_str()))
+            .map(|c| c.id.clone())
+            .collect();
+        if changed.is_empty() {
+            return 0;
+        }
+        for id in &changed {
+            folders.invalidate(id);
+        }
+        folders.save();
+        changed.len()
+    }
+}
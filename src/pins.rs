@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::queue::age;
+use crate::{pins_path, write_atomic};
+
+// Files pinned with `rgdrive pin`, which must always be on disk and current with Drive. The poller pulls a pinned file
+// again whenever its Drive copy changes, or the local copy is gone or only a placeholder; the cache never evicts it;
+// and the daemon is unhealthy while one can't be refreshed.
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pin {
+    #[serde(with = "crate::rawpath")]
+    pub path: PathBuf,
+    pub drive_url: String,
+    // Unix timestamp (seconds) of the last refresh, or of pinning if it was current then.
+    pub refreshed_at: Option<i64>,
+    // Why the last refresh failed, None if it succeeded.
+    pub last_error: Option<String>,
+}
+
+impl Pin {
+    // One line for --pins: path, Drive url, and when it was last refreshed or why it couldn't be.
+    pub fn describe(&self, now: i64) -> String {
+        let state = match (&self.last_error, self.refreshed_at) {
+            (Some(e), _) => format!("refresh failed: {}", e),
+            (None, Some(t)) => format!("refreshed {} ago", age(now - t)),
+            (None, None) => String::from("not refreshed yet"),
+        };
+        format!("{}  {}  {}", self.path.display(), self.drive_url, state)
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Pins {
+    pins: Vec<Pin>,
+}
+
+lazy_static! {
+    pub static ref PINS: Mutex<Pins> = Mutex::new(Pins::load());
+}
+
+impl Pins {
+    pub fn load() -> Pins {
+        fs::read_to_string(pins_path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let p = pins_path();
+        if let Some(parent) = p.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&p, serde_json::to_string(self)?.as_bytes())
+    }
+
+    pub fn all(&self) -> &[Pin] {
+        &self.pins
+    }
+
+    pub fn is_pinned(&self, path: &Path) -> bool {
+        self.pins.iter().any(|p| p.path == path)
+    }
+
+    // Pin path, synced with drive_url. Returns false if it already was.
+    pub fn add(&mut self, path: &Path, drive_url: &str) -> Result<bool, Error> {
+        if self.is_pinned(path) {
+            return Ok(false);
+        }
+        self.pins.push(Pin {
+            path: path.to_path_buf(),
+            drive_url: drive_url.to_string(),
+            refreshed_at: None,
+            last_error: None,
+        });
+        self.save()?;
+        Ok(true)
+    }
+
+    // Unpin the file at pinned, or every file synced with it if it's a Drive url. Returns what was unpinned.
+    pub fn remove(&mut self, pinned: &str) -> Result<Vec<Pin>, Error> {
+        let (removed, kept): (Vec<Pin>, Vec<Pin>) = self
+            .pins
+            .drain(..)
+            .partition(|p| p.drive_url == pinned || p.path == Path::new(pinned));
+        self.pins = kept;
+        if !removed.is_empty() {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    // Unpin the file at path, e.g. once it's no longer synced.
+    pub fn remove_path(&mut self, path: &Path) -> Result<(), Error> {
+        if !self.is_pinned(path) {
+            return Ok(());
+        }
+        self.pins.retain(|p| p.path != path);
+        self.save()
+    }
+
+    // The pinned file at from was moved to to, it stays pinned there.
+    pub fn moved(&mut self, from: &Path, to: &Path) -> Result<(), Error> {
+        if !self.is_pinned(from) {
+            return Ok(());
+        }
+        for pin in self.pins.iter_mut().filter(|p| p.path == from) {
+            pin.path = to.to_path_buf();
+        }
+        self.save()
+    }
+
+    // Record how refreshing the pinned file at path went.
+    pub fn refreshed(&mut self, path: &Path, result: Result<(), String>) -> Result<(), Error> {
+        let pin = match self.pins.iter_mut().find(|p| p.path == path) {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        match result {
+            Ok(()) => {
+                pin.refreshed_at = Some(Utc::now().timestamp());
+                pin.last_error = None;
+            }
+            Err(e) => pin.last_error = Some(e),
+        }
+        self.save()
+    }
+
+    // Pins whose last refresh failed.
+    pub fn failing(&self) -> Vec<&Pin> {
+        self.pins
+            .iter()
+            .filter(|p| p.last_error.is_some())
+            .collect()
+    }
+}
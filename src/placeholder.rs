@@ -0,0 +1,57 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::stamp;
+use crate::xattr;
+
+// Placeholders stand in for Drive files that haven't been downloaded (--pull-dir --placeholders): an empty file whose
+// extended attributes say which Drive file it is. `rgdrive hydrate` downloads the real contents and syncs them.
+
+// Drive id of the file the placeholder stands for, the same attribute synced files are stamped with.
+const ID_ATTR: &str = stamp::ID_ATTR;
+// Size of the Drive file, so ls -l isn't the only way to tell how big hydrating will be.
+const SIZE_ATTR: &str = "user.rgdrive.size";
+
+// Create an empty placeholder at path for the Drive file id. The filesystem has to support user extended attributes
+// (most do; tmpfs only since Linux 6.6).
+pub fn create(path: &Path, id: &str, size: u64) -> io::Result<()> {
+    fs::File::create(path)?;
+    let attrs =
+        xattr::set(path, ID_ATTR, id).and_then(|_| xattr::set(path, SIZE_ATTR, &size.to_string()));
+    if attrs.is_err() {
+        // An empty file that isn't a placeholder would look like an empty Drive file.
+        let _ = fs::remove_file(path);
+    }
+    attrs
+}
+
+// Drive id of the file path stands in for, None if it isn't a placeholder. A placeholder that was written to is real
+// content now and no longer counts, and neither does an empty file that was synced (see stamp), which shares the id.
+pub fn drive_id_of(path: &Path) -> Option<String> {
+    match fs::metadata(path) {
+        Ok(m) if m.is_file() && m.len() == 0 && !stamp::is_stamped(path) => {
+            xattr::get(path, ID_ATTR)
+        }
+        _ => None,
+    }
+}
+
+// Size of the Drive file behind a placeholder, as it was when the placeholder was made.
+pub fn size_of(path: &Path) -> Option<u64> {
+    xattr::get(path, SIZE_ATTR).and_then(|s| s.parse().ok())
+}
+
+// Every placeholder at or beneath path.
+pub fn find(path: &Path) -> Vec<PathBuf> {
+    if path.is_dir() {
+        crate::get_subpaths(&path.to_path_buf())
+            .into_iter()
+            .filter(|p| drive_id_of(p).is_some())
+            .collect()
+    } else if drive_id_of(path).is_some() {
+        vec![path.to_path_buf()]
+    } else {
+        Vec::new()
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::{Local, TimeZone};
+
+use crate::config::Planner;
+use crate::remote::Quota;
+
+// Drive caps uploads at 750 GiB per user per day.
+pub const DAILY_UPLOAD_LIMIT: u64 = 750 * 1024 * 1024 * 1024;
+// Sustained writes per second Drive allows a single user before it starts returning rate limit errors.
+pub const WRITES_PER_SEC: u64 = 3;
+
+// What a directory push is about to do, worked out before anything is uploaded.
+#[derive(Debug)]
+pub struct Plan {
+    pub files: usize,
+    pub bytes: u64,
+    // Files left out by the default excludes or ignore rules.
+    pub skipped: usize,
+    // None if the remote couldn't report it.
+    pub quota: Option<Quota>,
+    // Anything that makes the push worth confirming first. Empty if it's fine to go ahead.
+    pub warnings: Vec<String>,
+}
+
+impl Plan {
+    pub fn new(paths: &[PathBuf], skipped: usize, quota: Option<Quota>, limits: &Planner) -> Plan {
+        let bytes = paths
+            .iter()
+            .filter_map(|p| fs::metadata(p).ok())
+            .map(|m| m.len())
+            .sum();
+        let mut plan = Plan {
+            files: paths.len(),
+            bytes,
+            skipped,
+            quota,
+            warnings: Vec::new(),
+        };
+        if let Some(free) = plan.quota.as_ref().and_then(|q| q.free()) {
+            if plan.bytes > free {
+                plan.warnings.push(format!(
+                    "{} won't fit in the {} left of your Drive storage quota.",
+                    human_bytes(plan.bytes),
+                    human_bytes(free)
+                ));
+            }
+        }
+        if plan.bytes > DAILY_UPLOAD_LIMIT {
+            plan.warnings.push(format!(
+                "{} is more than Drive's daily upload limit of {}, the push will fail part way through.",
+                human_bytes(plan.bytes),
+                human_bytes(DAILY_UPLOAD_LIMIT)
+            ));
+        }
+        if plan.files as u64 > limits.warn_files {
+            plan.warnings.push(format!(
+                "{} files is more than planner.warn_files ({}).",
+                plan.files, limits.warn_files
+            ));
+        }
+        if plan.bytes > limits.warn_bytes {
+            plan.warnings.push(format!(
+                "{} is more than planner.warn_bytes ({}).",
+                human_bytes(plan.bytes),
+                human_bytes(limits.warn_bytes)
+            ));
+        }
+        plan
+    }
+
+    // Lower bound on how long the uploads take, from Drive's write rate limit alone.
+    pub fn min_secs(&self) -> u64 {
+        (self.files as u64).div_ceil(WRITES_PER_SEC)
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Push plan: {} files, {}.",
+            self.files,
+            human_bytes(self.bytes)
+        )?;
+        if self.skipped > 0 {
+            write!(f, " {} more skipped by the excludes.", self.skipped)?;
+        }
+        writeln!(f)?;
+        match &self.quota {
+            Some(q) => match q.limit {
+                Some(limit) => writeln!(
+                    f,
+                    "Drive storage: {} of {} used.",
+                    human_bytes(q.usage),
+                    human_bytes(limit)
+                )?,
+                None => writeln!(f, "Drive storage: {} used, no limit.", human_bytes(q.usage))?,
+            },
+            None => writeln!(f, "Drive storage: unknown.")?,
+        }
+        write!(
+            f,
+            "At Drive's rate limit of {} writes/s this takes at least {}s.",
+            WRITES_PER_SEC,
+            self.min_secs()
+        )?;
+        for w in &self.warnings {
+            write!(f, "\nWarning: {}", w)?;
+        }
+        Ok(())
+    }
+}
+
+// A unix timestamp (seconds) in local time to the minute, e.g. 2020-06-01 09:30. One too far out for chrono is shown as
+// the number it is.
+pub fn human_time(t: i64) -> String {
+    match Local.timestamp_opt(t, 0).single() {
+        Some(t) => t.format("%Y-%m-%d %H:%M").to_string(),
+        None => format!("@{}", t),
+    }
+}
+
+pub fn human_bytes(b: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut n = b as f64;
+    let mut unit = 0;
+    while n >= 1024.0 && unit < UNITS.len() - 1 {
+        n /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", b)
+    } else {
+        format!("{:.1} {}", n, UNITS[unit])
+    }
+}
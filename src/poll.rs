@@ -0,0 +1,213 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::remote::{drive_id, FileChange, Metadata, Remote, RemoteError};
+use crate::state::State;
+use crate::stats::{Stats, STATS};
+use crate::{watched_path, write_atomic, TrackedFile};
+
+// Names of the cursors kept in the state database: the poller's, and each watched folder's.
+const CHANGES_CURSOR: &str = "changes";
+
+fn folder_cursor(folder_id: &str) -> String {
+    format!("folder {}", folder_id)
+}
+
+// A tracked file whose Drive copy changed between polls.
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub drive_url: String,
+    pub metadata: Metadata,
+}
+
+// Polls Drive's changes feed (changes.list) from where the last poll left off, so a poll costs one request, or one
+// per page of changes, however many files are tracked. The page token is kept in the state database, so after a
+// restart polling carries on where it left off, and files changed on Drive while the daemon was stopped are noticed
+// by its first poll. So is each watched folder's cursor, the token as of which everything in it has been seen: a folder
+// in step with the feed finds its new files among the changes, only one that isn't is listed in full.
+pub struct Poller {
+    state: State,
+    // Where the next poll lists changes from, None before the first poll.
+    token: Option<String>,
+    // Where the latest poll listed them from.
+    from: Option<String>,
+    // Watched folder cursors set since the last save. They're saved with the token, a folder's cursor must never get
+    // ahead of the feed.
+    folders: HashMap<String, Option<String>>,
+    // Whether the token moved since the last save.
+    dirty: bool,
+}
+
+impl Poller {
+    // Carry on from the token saved in the state database at path, if there is one.
+    pub fn open(path: &Path) -> Result<Poller, String> {
+        let state = State::open(path)?;
+        let token = state
+            .cursor(CHANGES_CURSOR)
+            .map_err(|e| format!("{:?}: {}", path, e))?;
+        Ok(Poller {
+            state,
+            from: token.clone(),
+            token,
+            folders: HashMap::new(),
+            dirty: false,
+        })
+    }
+
+    // Save the token and folder cursors if they moved since the last save, all at once.
+    pub fn save(&mut self) -> rusqlite::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut cursors: Vec<(String, Option<String>)> = self.folders.drain().collect();
+        cursors.push((String::from(CHANGES_CURSOR), self.token.clone()));
+        self.state.set_cursors(&cursors)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    // Everything that changed on Drive since the last poll, at most page_size changes (the rest the poll after). The
+    // first poll only notes where the feed ends. Save once the changes are dealt with, so a restart part way through
+    // sees them again. Unsupported is passed up so callers can stop polling a remote that can't answer.
+    pub fn poll<R: Remote + ?Sized>(
+        &mut self,
+        remote: &mut R,
+        page_size: Option<usize>,
+    ) -> Result<Vec<FileChange>, RemoteError> {
+        Stats::incr(&STATS.remote_polls);
+        self.from = self.token.clone();
+        let (changes, next) = match &self.token {
+            Some(token) => remote.changes(token, page_size)?,
+            None => (Vec::new(), remote.changes_token()?),
+        };
+        if self.token.as_ref() != Some(&next) {
+            self.token = Some(next);
+            self.dirty = true;
+        }
+        Ok(changes)
+    }
+
+    // Whether the latest poll's changes are everything new in watched folder_id, i.e. it was in step with the feed
+    // where the poll started.
+    pub fn in_step(&self, folder_id: &str) -> bool {
+        let name = folder_cursor(folder_id);
+        let cursor = match self.folders.get(&name) {
+            Some(unsaved) => unsaved.clone(),
+            None => self.state.cursor(&name).ok().flatten(),
+        };
+        cursor.is_some() && cursor == self.from
+    }
+
+    // Note folder_id as in step with the feed as of the latest poll, or with in_step false, as having missed something
+    // so it's listed in full next time. Kept until the next save.
+    pub fn set_in_step(&mut self, folder_id: &str, in_step: bool) {
+        let token = self.token.clone().filter(|_| in_step);
+        self.folders.insert(folder_cursor(folder_id), token);
+        self.dirty = true;
+    }
+}
+
+// The tracked files among changes, with their newest metadata. A change already dealt with (its version is the tracked
+// file's revision, see Tracker::set_revision) isn't one, nor is a Drive copy that was removed.
+pub fn tracked_changes(changes: &[FileChange], tracked: &[TrackedFile]) -> Vec<Change> {
+    let urls: HashMap<&str, &TrackedFile> = tracked
+        .iter()
+        .filter_map(|tf| drive_id(&tf.drive_url).map(|id| (id, tf)))
+        .collect();
+    let mut found: Vec<Change> = Vec::new();
+    // Index in found of each file id, a file changed twice since the last poll can be listed twice.
+    let mut listed: HashMap<&str, usize> = HashMap::new();
+    for c in changes {
+        let (tf, m) = match (urls.get(c.id.as_str()), &c.file) {
+            (Some(tf), Some(m)) => (tf, m),
+            _ => continue,
+        };
+        if tf.revision.as_deref() == Some(m.etag.as_str()) {
+            continue;
+        }
+        let change = Change {
+            drive_url: tf.drive_url.clone(),
+            metadata: m.clone(),
+        };
+        match listed.get(c.id.as_str()) {
+            Some(&i) => found[i] = change,
+            None => {
+                Stats::incr(&STATS.remote_changes);
+                listed.insert(&c.id, found.len());
+                found.push(change);
+            }
+        }
+    }
+    found
+}
+
+// Remote files already brought down from each watched folder, persisted so a restart doesn't fetch them all again.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Inbound {
+    seen: HashMap<String, HashSet<String>>,
+}
+
+impl Inbound {
+    pub fn load() -> Inbound {
+        fs::read_to_string(watched_path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let p = watched_path();
+        if let Some(parent) = p.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&p, serde_json::to_string(self)?.as_bytes())
+    }
+
+    // Files among changes that are in folder_id and haven't been brought down yet.
+    pub fn new_in(&self, changes: &[FileChange], folder_id: &str) -> Vec<Metadata> {
+        let seen = self.seen.get(folder_id);
+        let mut files: Vec<Metadata> = Vec::new();
+        // Index in files of each file id, a file changed twice since the last poll can be listed twice.
+        let mut listed: HashMap<&str, usize> = HashMap::new();
+        for m in changes.iter().filter_map(|c| c.file.as_ref()) {
+            if !m.parents.iter().any(|p| p == folder_id)
+                || seen.and_then(|s| s.get(&m.id)).is_some()
+            {
+                continue;
+            }
+            match listed.get(m.id.as_str()) {
+                Some(&i) => files[i] = m.clone(),
+                None => {
+                    listed.insert(&m.id, files.len());
+                    files.push(m.clone());
+                }
+            }
+        }
+        files
+    }
+
+    // Files in folder_id that haven't been brought down yet, listing all of it.
+    pub fn new_files<R: Remote + ?Sized>(
+        &self,
+        remote: &mut R,
+        folder_id: &str,
+    ) -> Result<Vec<Metadata>, RemoteError> {
+        let seen = self.seen.get(folder_id);
+        Ok(remote
+            .list_folder(folder_id)?
+            .into_iter()
+            .filter(|m| seen.and_then(|s| s.get(&m.id)).is_none())
+            .collect())
+    }
+
+    pub fn mark(&mut self, folder_id: &str, id: &str) {
+        self.seen
+            .entry(folder_id.to_string())
+            .or_default()
+            .insert(id.to_string());
+    }
+}
@@ -0,0 +1,151 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, TryLockError};
+use std::thread;
+
+use log::warn;
+
+use crate::remote::Remote;
+use crate::session::Open;
+
+// Connections to Drive for the daemon's transfers, so a slow upload only holds up the connection it's using instead of
+// everything else the daemon does. Connections are opened as they're needed, up to the [transfers] concurrency.
+pub struct Pool {
+    conns: Vec<Mutex<Option<Box<dyn Remote>>>>,
+    open: Option<Open>,
+    // Cleared once opening a connection fails, the pool makes do with the connections it has from then on.
+    growing: AtomicBool,
+    // Held while looking for a free connection, so one being returned can't slip by unnoticed.
+    waiting: Mutex<()>,
+    returned: Condvar,
+}
+
+// A connection borrowed from the pool, returned to it when dropped.
+pub struct Conn<'a> {
+    guard: Option<MutexGuard<'a, Option<Box<dyn Remote>>>>,
+    pool: &'a Pool,
+}
+
+impl Pool {
+    // A pool starting out with first, opening up to size - 1 more connections with open when they're all busy.
+    pub fn new(first: Box<dyn Remote>, open: Open, size: usize) -> Pool {
+        let mut conns = vec![Mutex::new(Some(first))];
+        conns.extend((1..size.max(1)).map(|_| Mutex::new(None)));
+        Pool {
+            conns,
+            open: Some(open),
+            growing: AtomicBool::new(true),
+            waiting: Mutex::new(()),
+            returned: Condvar::new(),
+        }
+    }
+
+    // A pool of just remote, whose calls all take turns.
+    pub fn single(remote: Box<dyn Remote>) -> Pool {
+        Pool {
+            conns: vec![Mutex::new(Some(remote))],
+            open: None,
+            growing: AtomicBool::new(false),
+            waiting: Mutex::new(()),
+            returned: Condvar::new(),
+        }
+    }
+
+    // Borrow a free connection, opening another one if they're all busy and there's room, or waiting for one otherwise.
+    pub fn lock(&self) -> Conn<'_> {
+        let mut waiting = self.waiting.lock().unwrap();
+        loop {
+            for conn in &self.conns {
+                let mut guard = match conn.try_lock() {
+                    Ok(g) => g,
+                    // A transfer that panicked doesn't make its connection unusable.
+                    Err(TryLockError::Poisoned(p)) => p.into_inner(),
+                    Err(TryLockError::WouldBlock) => continue,
+                };
+                if guard.is_none() {
+                    if !self.growing.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    match self.open.map(|open| open()) {
+                        Some(Ok(remote)) => *guard = Some(remote),
+                        Some(Err(e)) => {
+                            warn!("Couldn't open another connection to Drive: {}", e);
+                            self.growing.store(false, Ordering::Relaxed);
+                            continue;
+                        }
+                        None => continue,
+                    }
+                }
+                return Conn {
+                    guard: Some(guard),
+                    pool: self,
+                };
+            }
+            waiting = self.returned.wait(waiting).unwrap();
+        }
+    }
+
+    // Most transfers that can be in flight at once.
+    pub fn size(&self) -> usize {
+        self.conns.len()
+    }
+}
+
+impl Deref for Conn<'_> {
+    type Target = Box<dyn Remote>;
+
+    fn deref(&self) -> &Box<dyn Remote> {
+        self.guard.as_ref().and_then(|g| g.as_ref()).unwrap()
+    }
+}
+
+impl DerefMut for Conn<'_> {
+    fn deref_mut(&mut self) -> &mut Box<dyn Remote> {
+        self.guard.as_mut().and_then(|g| g.as_mut()).unwrap()
+    }
+}
+
+impl Drop for Conn<'_> {
+    fn drop(&mut self) {
+        // Unlock before telling the waiters, or the one woken up could find it still taken and go back to sleep.
+        self.guard.take();
+        let _waiting = self.pool.waiting.lock().unwrap();
+        self.pool.returned.notify_one();
+    }
+}
+
+// Run f on every item, at most jobs at a time, and return the results in the order of items. For transfers that don't
+// depend on each other, e.g. the files of a directory push.
+pub fn each<T, R, F>(items: Vec<T>, jobs: usize, f: F) -> Vec<R>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let n = items.len();
+    let items = Arc::new(Mutex::new(items.into_iter().enumerate()));
+    let f = Arc::new(f);
+    let (tx, rx) = mpsc::channel();
+    for _ in 0..jobs.max(1).min(n) {
+        let (items, f, tx) = (Arc::clone(&items), Arc::clone(&f), tx.clone());
+        thread::spawn(move || loop {
+            // Only hold the items lock while taking the next one.
+            let next = items.lock().unwrap().next();
+            match next {
+                Some((i, item)) => {
+                    let _ = tx.send((i, f(item)));
+                }
+                None => return,
+            }
+        });
+    }
+    drop(tx);
+    let mut results: Vec<Option<R>> = (0..n).map(|_| None).collect();
+    for (i, r) in rx {
+        results[i] = Some(r);
+    }
+    results
+        .into_iter()
+        .map(|r| r.expect("transfer job panicked"))
+        .collect()
+}
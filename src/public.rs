@@ -0,0 +1,186 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::checksum::save_hashed;
+use crate::export::{Export, DOC_FORMATS, SHEET_FORMATS, SLIDES_FORMATS};
+use crate::oauth::endpoint;
+use crate::remote::{drive_id, Remote, RemoteError};
+
+// Drive files shared with anyone with the link, fetched without signing in (rgdrive --pull --anonymous). Only downloads
+// and exports work this way, and only of what's shared publicly: anything else gets Drive's sign in page instead.
+
+const DRIVE: &str = "https://drive.google.com";
+const DOCS: &str = "https://docs.google.com";
+
+// Docs editors types, by their path on docs.google.com, and what each exports as.
+const KINDS: [(&str, &[&str]); 3] = [
+    ("document", DOC_FORMATS),
+    ("spreadsheets", SHEET_FORMATS),
+    ("presentation", SLIDES_FORMATS),
+];
+
+pub struct Public;
+
+impl Remote for Public {
+    fn upload(&mut self, _path: &Path) -> Result<String, RemoteError> {
+        Err(RemoteError::Unsupported("upload"))
+    }
+
+    fn update(&mut self, _path: &Path, _url: &str) -> Result<(), RemoteError> {
+        Err(RemoteError::Unsupported("update"))
+    }
+
+    fn download(&mut self, url: &str, path: &Path) -> Result<PathBuf, RemoteError> {
+        self.download_hashed(url, path).map(|(dest, _)| dest)
+    }
+
+    fn download_hashed(
+        &mut self,
+        url: &str,
+        path: &Path,
+    ) -> Result<(PathBuf, Option<String>), RemoteError> {
+        let id = drive_id(url)
+            .ok_or_else(|| RemoteError::Api(format!("{:?} is not a drive url.", url)))?;
+        let link = endpoint(&format!("{}/uc?export=download&id={}", DRIVE, id));
+        let mut resp = get(&link)?;
+        // Files too big for Drive's virus scan get a page asking to confirm the download first.
+        if !is_file(&resp) {
+            let page = resp.into_string().unwrap_or_default();
+            let token = confirm_token(&page).ok_or_else(|| not_public(id))?;
+            resp = get(&format!("{}&confirm={}", link, token))?;
+            if !is_file(&resp) {
+                return Err(not_public(id));
+            }
+        }
+        save(resp, path, id).map(|(dest, md5)| (dest, Some(md5)))
+    }
+
+    // Without metadata there's no telling a Doc from a Sheet, so each kind with the format is tried in turn.
+    fn export(&mut self, id: &str, export: &Export, path: &Path) -> Result<PathBuf, RemoteError> {
+        if export.sheet.is_some() || export.range.is_some() {
+            return Err(RemoteError::Unsupported(
+                "exporting a sheet or range anonymously",
+            ));
+        }
+        for (kind, formats) in KINDS.iter() {
+            if !formats.contains(&export.format.as_str()) {
+                continue;
+            }
+            let link = endpoint(&format!(
+                "{}/{}/d/{}/export?format={}",
+                DOCS, kind, id, export.format
+            ));
+            match get(&link) {
+                Ok(resp) if is_file(&resp) => {
+                    return save(resp, path, &format!("{}.{}", id, export.format))
+                        .map(|(dest, _)| dest);
+                }
+                _ => continue,
+            }
+        }
+        Err(not_public(id))
+    }
+}
+
+fn get(url: &str) -> Result<ureq::Response, RemoteError> {
+    let resp = ureq::get(url)
+        .set("User-Agent", concat!("rgdrive/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(300))
+        .call();
+    if let Some(e) = resp.synthetic_error() {
+        return Err(RemoteError::Api(format!("{}: {}", url, e)));
+    }
+    if !resp.ok() {
+        return Err(RemoteError::Api(format!("{}: {}", url, resp.status_line())));
+    }
+    Ok(resp)
+}
+
+// Downloads come as attachments, Drive's own pages (sign in, virus scan warning) don't.
+fn is_file(resp: &ureq::Response) -> bool {
+    resp.header("Content-Disposition")
+        .map(|d| d.contains("attachment"))
+        .unwrap_or(false)
+}
+
+fn not_public(id: &str) -> RemoteError {
+    RemoteError::Api(format!(
+        "404 Not Found: {} isn't shared with anyone with the link. Sign in (rgdrive --auth) to pull it.",
+        id
+    ))
+}
+
+// The confirm=<token> of the virus scan warning's download link, "t" if the page only has a form to confirm with.
+fn confirm_token(page: &str) -> Option<String> {
+    if let Some(i) = page.find("confirm=") {
+        let token: String = page[i + "confirm=".len()..]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .collect();
+        if !token.is_empty() {
+            return Some(token);
+        }
+    }
+    if page.contains("name=\"confirm\"") {
+        return Some(String::from("t"));
+    }
+    None
+}
+
+// File name from a Content-Disposition header, preferring the utf-8 filename* over the plain one. Only the last
+// component is kept, so a name can't point outside the destination directory.
+fn disposition_name(header: &str) -> Option<String> {
+    let mut name = None;
+    for part in header.split(';').map(str::trim) {
+        if let Some(v) = part.strip_prefix("filename*=") {
+            name = v.splitn(3, '\'').nth(2).and_then(percent_decode);
+            break;
+        } else if let Some(v) = part.strip_prefix("filename=") {
+            name = Some(v.trim_matches('"').to_string());
+        }
+    }
+    let name = Path::new(&name?)
+        .file_name()?
+        .to_string_lossy()
+        .into_owned();
+    Some(name)
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+// Write the body of resp to path, or into it under the name Drive gives (fallback without one), see save_hashed.
+// Returns where it went and the md5 of what was written.
+fn save(
+    resp: ureq::Response,
+    path: &Path,
+    fallback: &str,
+) -> Result<(PathBuf, String), RemoteError> {
+    let dest = if path.is_dir() {
+        let name = resp
+            .header("Content-Disposition")
+            .and_then(disposition_name)
+            .unwrap_or_else(|| fallback.to_string());
+        path.join(name)
+    } else {
+        path.to_path_buf()
+    };
+    match save_hashed(&mut resp.into_reader(), &dest) {
+        Ok(md5) => Ok((dest, md5)),
+        Err(e) => Err(RemoteError::Api(format!("{:?}: {}", dest, e))),
+    }
+}
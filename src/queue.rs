@@ -0,0 +1,179 @@
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::{queue_path, write_atomic};
+
+// First retry of a failed operation is this long after it failed, doubling with each attempt up to MAX_BACKOFF_SECS.
+const BACKOFF_SECS: i64 = 30;
+const MAX_BACKOFF_SECS: i64 = 3600;
+
+// An operation that failed against Drive (offline, rate limited, server errors) and is waiting to be retried.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Op {
+    pub id: u64,
+    pub op: String,
+    #[serde(with = "crate::rawpath")]
+    pub path: PathBuf,
+    pub drive_url: String,
+    // Unix timestamps (seconds) of the first failure, and of the next retry.
+    pub queued: i64,
+    pub next_try: i64,
+    pub attempts: u32,
+    pub last_error: String,
+}
+
+impl Op {
+    // One line for --queue: id, op, path, age, attempts and last error.
+    pub fn describe(&self, now: i64) -> String {
+        format!(
+            "{:>4}  {:<6}  {}  {} old, {} attempt(s), last error: {}",
+            self.id,
+            self.op,
+            self.path.display(),
+            age(now - self.queued),
+            self.attempts,
+            self.last_error
+        )
+    }
+}
+
+// Rough age, e.g. "45s", "12m", "3h", "2d".
+pub fn age(secs: i64) -> String {
+    let secs = secs.max(0);
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 86400 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    }
+}
+
+// Failed operations, retried with backoff until they succeed or are dropped with --queue-drop. Persisted, so nothing
+// queued is forgotten across restarts.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Queue {
+    next_id: u64,
+    ops: Vec<Op>,
+}
+
+lazy_static! {
+    pub static ref QUEUE: Mutex<Queue> = Mutex::new(Queue::load());
+}
+
+impl Queue {
+    pub fn load() -> Queue {
+        fs::read_to_string(queue_path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let p = queue_path();
+        if let Some(parent) = p.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&p, serde_json::to_string(self)?.as_bytes())
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Op> {
+        self.ops.iter().find(|o| o.id == id)
+    }
+
+    // Record a failed op on path. An op already queued for it counts another attempt and backs off further.
+    pub fn failed(
+        &mut self,
+        op: &str,
+        path: &Path,
+        drive_url: &str,
+        error: &str,
+    ) -> Result<(), Error> {
+        let now = Utc::now().timestamp();
+        match self.ops.iter_mut().find(|o| o.op == op && o.path == path) {
+            Some(o) => {
+                o.attempts += 1;
+                o.last_error = error.to_string();
+                o.next_try = now + backoff(o.attempts);
+            }
+            None => {
+                self.next_id += 1;
+                self.ops.push(Op {
+                    id: self.next_id,
+                    op: op.to_string(),
+                    path: path.to_path_buf(),
+                    drive_url: drive_url.to_string(),
+                    queued: now,
+                    next_try: now + backoff(1),
+                    attempts: 1,
+                    last_error: error.to_string(),
+                });
+            }
+        }
+        self.save()
+    }
+
+    // Forget a queued op on path once it has gone through.
+    pub fn done(&mut self, op: &str, path: &Path) -> Result<(), Error> {
+        let before = self.ops.len();
+        self.ops.retain(|o| !(o.op == op && o.path == path));
+        if self.ops.len() == before {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    // The file at from was moved to to, ops queued for it go on from there.
+    pub fn moved(&mut self, from: &Path, to: &Path) -> Result<(), Error> {
+        if !self.ops.iter().any(|o| o.path == from) {
+            return Ok(());
+        }
+        for o in self.ops.iter_mut().filter(|o| o.path == from) {
+            o.path = to.to_path_buf();
+        }
+        self.save()
+    }
+
+    pub fn remove(&mut self, id: u64) -> Result<Option<Op>, Error> {
+        let i = match self.ops.iter().position(|o| o.id == id) {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        let op = self.ops.remove(i);
+        self.save()?;
+        Ok(Some(op))
+    }
+
+    // Make every queued op due now, e.g. once Drive accepts us again. Returns how many there are.
+    pub fn retry_all(&mut self, now: i64) -> Result<usize, Error> {
+        for o in self.ops.iter_mut() {
+            o.next_try = now;
+        }
+        self.save()?;
+        Ok(self.ops.len())
+    }
+
+    // Ops whose next retry is due.
+    pub fn due(&self, now: i64) -> Vec<Op> {
+        self.ops
+            .iter()
+            .filter(|o| o.next_try <= now)
+            .cloned()
+            .collect()
+    }
+}
+
+fn backoff(attempts: u32) -> i64 {
+    BACKOFF_SECS
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF_SECS)
+}
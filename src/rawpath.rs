@@ -0,0 +1,132 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+// File names are bytes, not necessarily UTF-8, but serde refuses to (de)serialize paths that aren't valid UTF-8. Path
+// fields use #[serde(with = "crate::rawpath")] instead: UTF-8 paths are written as strings exactly as before, others as
+// their raw bytes (the same encoding in bincode, an array of numbers in JSON). Paths are only escaped for display.
+
+pub fn serialize<S: Serializer>(path: &Path, s: S) -> Result<S::Ok, S::Error> {
+    match path.to_str() {
+        Some(utf8) => s.serialize_str(utf8),
+        None => s.serialize_bytes(path.as_os_str().as_bytes()),
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<PathBuf, D::Error> {
+    // bincode can't tell a string from bytes without being told which to expect.
+    if d.is_human_readable() {
+        d.deserialize_any(PathVisitor)
+    } else {
+        d.deserialize_byte_buf(PathVisitor)
+    }
+}
+
+struct PathVisitor;
+
+impl<'de> Visitor<'de> for PathVisitor {
+    type Value = PathBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a path as a string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<PathBuf, E> {
+        Ok(PathBuf::from(v))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<PathBuf, E> {
+        Ok(PathBuf::from(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<PathBuf, E> {
+        Ok(PathBuf::from(OsString::from_vec(v.to_vec())))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<PathBuf, E> {
+        Ok(PathBuf::from(OsString::from_vec(v)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<PathBuf, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Ok(PathBuf::from(OsString::from_vec(bytes)))
+    }
+}
+
+struct Raw<'a>(&'a Path);
+
+impl Serialize for Raw<'_> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        serialize(self.0, s)
+    }
+}
+
+struct RawBuf(PathBuf);
+
+impl<'de> Deserialize<'de> for RawBuf {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<RawBuf, D::Error> {
+        deserialize(d).map(RawBuf)
+    }
+}
+
+// For Option<PathBuf> fields.
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(path: &Option<PathBuf>, s: S) -> Result<S::Ok, S::Error> {
+        path.as_deref().map(Raw).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<PathBuf>, D::Error> {
+        Ok(Option::<RawBuf>::deserialize(d)?.map(|p| p.0))
+    }
+}
+
+// For Vec<PathBuf> fields.
+pub mod vec {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(paths: &[PathBuf], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(paths.iter().map(|p| Raw(p)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<PathBuf>, D::Error> {
+        Ok(Vec::<RawBuf>::deserialize(d)?
+            .into_iter()
+            .map(|p| p.0)
+            .collect())
+    }
+}
+
+// path as text for output meant for other programs (--format json, CSV), with bytes that aren't UTF-8 escaped as \xNN
+// and backslashes doubled so the escaping can be undone. Messages meant for people use {:?}, which escapes them too.
+pub fn escape(path: &Path) -> String {
+    let mut out = String::new();
+    let mut bytes = path.as_os_str().as_bytes();
+    while !bytes.is_empty() {
+        let (valid, rest) = match std::str::from_utf8(bytes) {
+            Ok(s) => (s, &bytes[bytes.len()..]),
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                // valid_up_to is always on a char boundary.
+                (std::str::from_utf8(valid).unwrap(), rest)
+            }
+        };
+        out.push_str(&valid.replace('\\', "\\\\"));
+        bytes = match rest.split_first() {
+            Some((b, rest)) => {
+                out.push_str(&format!("\\x{:02x}", b));
+                rest
+            }
+            None => rest,
+        };
+    }
+    out
+}
@@ -0,0 +1,314 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::capabilities::Capability;
+use crate::checksum::md5_file;
+use crate::export::Export;
+use crate::pool::Pool;
+
+#[derive(Debug)]
+pub enum RemoteError {
+    // The operation isn't available through the current Drive client.
+    Unsupported(&'static str),
+    // Any error returned by the Drive api itself.
+    Api(String),
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RemoteError::Unsupported(op) => {
+                write!(f, "{} is not supported by the Drive client", op)
+            }
+            RemoteError::Api(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// Pulls the file/folder id out of the different drive url formats (open?id=, /file/d/<id>, /folders/<id>). A bare id is returned as is.
+pub fn drive_id(url: &str) -> Option<&str> {
+    let id = if let Some(i) = url.find("id=") {
+        url[i + 3..].split('&').next()
+    } else if let Some(i) = url.find("/d/") {
+        url[i + 3..].split('/').next()
+    } else if let Some(i) = url.find("/folders/") {
+        url[i + 9..].split(['/', '?']).next()
+    } else if !url.contains('/') {
+        Some(url)
+    } else {
+        None
+    };
+    id.filter(|id| !id.is_empty())
+}
+
+// A shared drive the account is a member of (drives.list).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedDrive {
+    pub id: String,
+    pub name: String,
+    // The account's role on it: organizer, fileOrganizer, writer, commenter or reader.
+    pub role: String,
+}
+
+impl SharedDrive {
+    // Tab separated id, name and role for --drives. Tabs and newlines in the name are replaced so it stays one line.
+    pub fn to_line(&self) -> String {
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| if c == '\t' || c == '\n' { ' ' } else { c })
+            .collect();
+        format!("{}\t{}\t{}", self.id, name, self.role)
+    }
+}
+
+// Drive storage quota (about.get storageQuota).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quota {
+    // None for unlimited storage.
+    pub limit: Option<u64>,
+    pub usage: u64,
+}
+
+impl Quota {
+    // Bytes left, None if unlimited.
+    pub fn free(&self) -> Option<u64> {
+        self.limit.map(|l| l.saturating_sub(self.usage))
+    }
+}
+
+pub const FOLDER_MIME: &str = "application/vnd.google-apps.folder";
+// A shortcut is a small Drive file standing in for another file or folder, see Metadata::target.
+pub const SHORTCUT_MIME: &str = "application/vnd.google-apps.shortcut";
+// Full access to the user's Drive files, what rgdrive signs in with.
+pub const DRIVE_SCOPE: &str = "https://www.googleapis.com/auth/drive";
+// Access to only the files rgdrive created or was handed, which some Workspace admins restrict apps to.
+pub const DRIVE_FILE_SCOPE: &str = "https://www.googleapis.com/auth/drive.file";
+// Reading who did what to a file, for --activity.
+pub const ACTIVITY_SCOPE: &str = "https://www.googleapis.com/auth/drive.activity.readonly";
+
+// Remote metadata for a single file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub id: String,
+    pub name: String,
+    // Opaque version tag, changes whenever the file's content or metadata does.
+    pub etag: String,
+    pub size: u64,
+    pub mime_type: String,
+    // modifiedTime, as a unix timestamp (seconds).
+    pub modified: i64,
+    // md5Checksum, None for Docs editors files.
+    pub md5: Option<String>,
+    // shortcutDetails.targetId, the id a shortcut points to. None for anything but shortcuts.
+    pub target: Option<String>,
+    // Ids of the folders the file is in.
+    pub parents: Vec<String>,
+}
+
+impl Metadata {
+    pub fn is_shortcut(&self) -> bool {
+        self.mime_type == SHORTCUT_MIME
+    }
+
+    // folder, doc (Docs editors files, no content of their own), shortcut or file.
+    pub fn kind(&self) -> &'static str {
+        if self.mime_type == FOLDER_MIME {
+            "folder"
+        } else if self.is_shortcut() {
+            "shortcut"
+        } else if self.mime_type.starts_with("application/vnd.google-apps.") {
+            "doc"
+        } else {
+            "file"
+        }
+    }
+}
+
+// One entry of a file's Drive Activity history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Activity {
+    // Unix timestamp (seconds).
+    pub time: i64,
+    // Who did it, as reported by Drive (usually an email address).
+    pub actor: String,
+    // e.g. "create", "edit", "rename", "permission_change".
+    pub action: String,
+}
+
+// An entry of Drive's changes feed (changes.list).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileChange {
+    pub id: String,
+    // The file as it is now, None if it was removed: trashed, deleted for good, or no longer shared with the account.
+    pub file: Option<Metadata>,
+}
+
+// The daemon's remote, shared between the socket workers and the inotify thread. Each call borrows one of the pool's
+// connections, see Pool::lock.
+pub type SharedRemote = Arc<Pool>;
+
+// Everything the daemon needs from the remote side. What a client can't do falls back to Unsupported.
+pub trait Remote: Send {
+    // Upload path to the Drive root, returns the url of the new file.
+    fn upload(&mut self, path: &Path) -> Result<String, RemoteError>;
+
+    // Upload path into the given folder id, returns the url of the new file.
+    fn upload_to(&mut self, _path: &Path, _folder_id: &str) -> Result<String, RemoteError> {
+        Err(RemoteError::Unsupported("upload_to"))
+    }
+
+    // Download url to path, returns the path the file was written to.
+    fn download(&mut self, url: &str, path: &Path) -> Result<PathBuf, RemoteError>;
+
+    // Replace the contents of url with the file at path.
+    fn update(&mut self, path: &Path, url: &str) -> Result<(), RemoteError>;
+
+    // The *_hashed transfers also return the md5 of what was sent or written. Clients that can hash it as it streams
+    // override them, these read the file again (before sending, so a save landing during the transfer still shows as a
+    // change).
+    fn upload_hashed(
+        &mut self,
+        path: &Path,
+        folder_id: Option<&str>,
+    ) -> Result<(String, Option<String>), RemoteError> {
+        let md5 = md5_file(path).ok();
+        let url = match folder_id {
+            Some(folder) => self.upload_to(path, folder)?,
+            None => self.upload(path)?,
+        };
+        Ok((url, md5))
+    }
+
+    fn download_hashed(
+        &mut self,
+        url: &str,
+        path: &Path,
+    ) -> Result<(PathBuf, Option<String>), RemoteError> {
+        let written = self.download(url, path)?;
+        let md5 = md5_file(&written).ok();
+        Ok((written, md5))
+    }
+
+    fn update_hashed(&mut self, path: &Path, url: &str) -> Result<Option<String>, RemoteError> {
+        let md5 = md5_file(path).ok();
+        self.update(path, url)?;
+        Ok(md5)
+    }
+
+    // Ids of every folder above the given file id, closest parent first.
+    fn ancestors(&mut self, _id: &str) -> Result<Vec<String>, RemoteError> {
+        Err(RemoteError::Unsupported("ancestors"))
+    }
+
+    // Metadata for a file id.
+    fn metadata(&mut self, _id: &str) -> Result<Metadata, RemoteError> {
+        Err(RemoteError::Unsupported("metadata"))
+    }
+
+    // Where Drive's changes feed ends now. Listing changes from it (see changes) finds only what changes after this.
+    fn changes_token(&mut self) -> Result<String, RemoteError> {
+        Err(RemoteError::Unsupported("changes"))
+    }
+
+    // Files changed since token, oldest first, and the token to list the next changes from. With page_size, at most
+    // that many, the rest are listed from the token returned.
+    fn changes(
+        &mut self,
+        _token: &str,
+        _page_size: Option<usize>,
+    ) -> Result<(Vec<FileChange>, String), RemoteError> {
+        Err(RemoteError::Unsupported("changes"))
+    }
+
+    // Activity on a file id, oldest first.
+    fn activity(&mut self, _id: &str) -> Result<Vec<Activity>, RemoteError> {
+        Err(RemoteError::Unsupported("activity"))
+    }
+
+    // Export a Google Docs editors file (e.g. a Sheet) to path in the given format, returns the path written to.
+    fn export(
+        &mut self,
+        _id: &str,
+        _export: &Export,
+        _path: &Path,
+    ) -> Result<PathBuf, RemoteError> {
+        Err(RemoteError::Unsupported("export"))
+    }
+
+    // Every file the user has starred.
+    fn starred(&mut self) -> Result<Vec<Metadata>, RemoteError> {
+        Err(RemoteError::Unsupported("starred"))
+    }
+
+    // Files directly inside a folder id.
+    fn list_folder(&mut self, _folder_id: &str) -> Result<Vec<Metadata>, RemoteError> {
+        Err(RemoteError::Unsupported("list_folder"))
+    }
+
+    // Give anyone with the link read access to a file id, returns the link.
+    fn share(&mut self, _id: &str) -> Result<String, RemoteError> {
+        Err(RemoteError::Unsupported("share"))
+    }
+
+    // Create a folder named name in parent_id, returns the new folder's id.
+    fn create_folder(&mut self, _name: &str, _parent_id: &str) -> Result<String, RemoteError> {
+        Err(RemoteError::Unsupported("create_folder"))
+    }
+
+    // Rename a file id on Drive.
+    fn rename(&mut self, _id: &str, _name: &str) -> Result<(), RemoteError> {
+        Err(RemoteError::Unsupported("rename"))
+    }
+
+    // Create a shortcut named name in parent_id pointing to target_id, returns the new shortcut's id.
+    fn create_shortcut(
+        &mut self,
+        _target_id: &str,
+        _name: &str,
+        _parent_id: &str,
+    ) -> Result<String, RemoteError> {
+        Err(RemoteError::Unsupported("create_shortcut"))
+    }
+
+    // Move a file id to Drive's trash, where it can be restored from.
+    fn trash(&mut self, _id: &str) -> Result<(), RemoteError> {
+        Err(RemoteError::Unsupported("trash"))
+    }
+
+    // Delete a file id for good, without going through the trash.
+    fn delete(&mut self, _id: &str) -> Result<(), RemoteError> {
+        Err(RemoteError::Unsupported("delete"))
+    }
+
+    // Storage used and available.
+    fn quota(&mut self) -> Result<Quota, RemoteError> {
+        Err(RemoteError::Unsupported("quota"))
+    }
+
+    // Shared drives the account can access.
+    fn shared_drives(&mut self) -> Result<Vec<SharedDrive>, RemoteError> {
+        Err(RemoteError::Unsupported("shared_drives"))
+    }
+
+    // Account (usually an email address) the remote is signed in as.
+    fn account(&mut self) -> Result<String, RemoteError> {
+        Err(RemoteError::Unsupported("account"))
+    }
+
+    // OAuth scopes the signed in token was granted.
+    fn scopes(&mut self) -> Result<Vec<String>, RemoteError> {
+        Err(RemoteError::Unsupported("scopes"))
+    }
+
+    // When the current access token expires (Unix timestamp, seconds), None if that isn't known.
+    fn token_expires(&mut self) -> Option<i64> {
+        None
+    }
+
+    // Optional features the client, account and scope allow. May ask Drive, so it's only called through
+    // capabilities::CAPABILITIES.probe.
+    fn capabilities(&mut self) -> Vec<Capability> {
+        Vec::new()
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Error;
+
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::md5_file;
+use crate::remote::Remote;
+use crate::{replicas_path, write_atomic, TrackedFile};
+
+// Synced files copied to a second remote as well ([replica]), for redundancy across accounts. The replica follows
+// each file's syncs with Drive, whichever way they went (push, uploaded change, pull), and fails on its own without
+// holding Drive up. Files are matched by their Drive url, so hard links and renames don't make extra copies.
+
+// Where a tracked file stands on the replica.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Replicated {
+    // Url of the Drive copy this is a replica of.
+    pub drive_url: String,
+    // The replica copy, None until an upload to it succeeds.
+    pub replica_url: Option<String>,
+    // md5 of what was last uploaded to it.
+    pub md5: Option<String>,
+    // Unix timestamp (seconds) of the last upload to it.
+    pub synced_at: Option<i64>,
+    // Why the last upload to it failed, None if it succeeded.
+    pub last_error: Option<String>,
+}
+
+// Per file state of a replica, for --list and --status.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplicaState {
+    Synced,
+    // Behind Drive, it's uploaded on the next pass.
+    Pending,
+    Error,
+}
+
+impl ReplicaState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReplicaState::Synced => "synced",
+            ReplicaState::Pending => "pending",
+            ReplicaState::Error => "error",
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Replicas {
+    files: Vec<Replicated>,
+}
+
+impl Replicas {
+    pub fn load() -> Replicas {
+        fs::read_to_string(replicas_path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let p = replicas_path();
+        if let Some(parent) = p.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&p, serde_json::to_string(self)?.as_bytes())
+    }
+
+    pub fn get(&self, drive_url: &str) -> Option<&Replicated> {
+        self.files.iter().find(|r| r.drive_url == drive_url)
+    }
+
+    // How tf stands on the replica. None for files that aren't replicated (exports only come down from Drive).
+    pub fn state_of(&self, tf: &TrackedFile) -> Option<ReplicaState> {
+        if tf.is_export() {
+            return None;
+        }
+        Some(match self.get(&tf.drive_url) {
+            Some(r) if r.last_error.is_some() => ReplicaState::Error,
+            Some(r) if r.replica_url.is_some() && r.md5 == tf.md5 => ReplicaState::Synced,
+            _ => ReplicaState::Pending,
+        })
+    }
+
+    // Upload every tracked file the replica is behind on. A file changed locally since it last synced with Drive waits
+    // until it has, so the replica never gets ahead of Drive (or of [review] and the pre_upload hook). Returns how many
+    // were uploaded, and the errors. Saved whenever anything changed.
+    pub fn sync(
+        &mut self,
+        remote: &mut dyn Remote,
+        tracked: &[TrackedFile],
+    ) -> (usize, Vec<String>) {
+        let (mut uploaded, mut errors) = (0, Vec::new());
+        let mut seen = HashSet::new();
+        for tf in tracked {
+            if tf.is_export() || tf.absent || !seen.insert(tf.drive_url.clone()) {
+                continue;
+            }
+            if self.state_of(tf) == Some(ReplicaState::Synced) || tf.md5.is_none() {
+                continue;
+            }
+            let md5 = md5_file(&tf.path).ok();
+            if md5 != tf.md5 {
+                continue;
+            }
+            let i = match self.files.iter().position(|r| r.drive_url == tf.drive_url) {
+                Some(i) => i,
+                None => {
+                    self.files.push(Replicated {
+                        drive_url: tf.drive_url.clone(),
+                        replica_url: None,
+                        md5: None,
+                        synced_at: None,
+                        last_error: None,
+                    });
+                    self.files.len() - 1
+                }
+            };
+            let r = &mut self.files[i];
+            let result = match &r.replica_url {
+                Some(url) => remote.update(&tf.path, url).map(|_| url.clone()),
+                None => remote.upload(&tf.path),
+            };
+            match result {
+                Ok(url) => {
+                    info!("Replicated {:?}: {}", tf.path, url);
+                    r.replica_url = Some(url);
+                    r.md5 = md5;
+                    r.synced_at = Some(Utc::now().timestamp());
+                    r.last_error = None;
+                    uploaded += 1;
+                }
+                Err(e) => {
+                    warn!("Error replicating {:?}: {}", tf.path, e);
+                    r.last_error = Some(e.to_string());
+                    errors.push(e.to_string());
+                }
+            }
+        }
+        // Files no longer synced are forgotten, their replica copies stay where they are (like their Drive copies).
+        let urls: HashSet<&str> = tracked.iter().map(|tf| tf.drive_url.as_str()).collect();
+        let before = self.files.len();
+        self.files.retain(|r| urls.contains(r.drive_url.as_str()));
+        if uploaded > 0 || !errors.is_empty() || self.files.len() != before {
+            if let Err(e) = self.save() {
+                error!("Error saving replica state: {:?}", e);
+            }
+        }
+        (uploaded, errors)
+    }
+}
@@ -0,0 +1,116 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use chrono::{Local, TimeZone};
+
+use crate::journal::{Direction, Entry};
+use crate::transfer;
+
+// --report: what the daemon transferred over a period, summarized from the journal.
+
+// How many top files by volume are listed.
+const TOP_FILES: usize = 5;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    // Bytes (up, down) per local day (YYYY-MM-DD), oldest first. Days without transfers are left out.
+    pub days: Vec<(String, u64, u64)>,
+    // Files that moved the most bytes either way, and how many.
+    pub top: Vec<(PathBuf, u64)>,
+    // Failed operations per category (see category), most frequent first.
+    pub errors: Vec<(&'static str, usize)>,
+    // Average and slowest time from a local change being seen to its upload completing, over uploads that
+    // recorded it.
+    pub latency_ms: Option<(u64, u64)>,
+    pub uploads: usize,
+    pub downloads: usize,
+}
+
+impl Report {
+    pub fn new(entries: &[Entry]) -> Report {
+        let mut r = Report::default();
+        let mut days: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+        let mut files: HashMap<&PathBuf, u64> = HashMap::new();
+        let mut errors: HashMap<&'static str, usize> = HashMap::new();
+        let mut latencies = Vec::new();
+        for e in entries {
+            if !e.is_ok() {
+                *errors.entry(category(&e.result)).or_insert(0) += 1;
+                continue;
+            }
+            let day = match Local.timestamp_opt(e.time, 0).single() {
+                Some(t) => t.format("%Y-%m-%d").to_string(),
+                None => format!("@{}", e.time),
+            };
+            match e.direction {
+                Direction::Up => {
+                    days.entry(day).or_default().0 += e.bytes;
+                    r.uploads += 1;
+                }
+                Direction::Down => {
+                    days.entry(day).or_default().1 += e.bytes;
+                    r.downloads += 1;
+                }
+                Direction::None => continue,
+            }
+            *files.entry(&e.path).or_insert(0) += e.bytes;
+            latencies.extend(e.latency_ms);
+        }
+        r.days = days
+            .into_iter()
+            .map(|(d, (up, down))| (d, up, down))
+            .collect();
+        let mut top: Vec<(PathBuf, u64)> = files.into_iter().map(|(p, b)| (p.clone(), b)).collect();
+        top.sort_by_key(|(p, b)| (Reverse(*b), p.clone()));
+        top.truncate(TOP_FILES);
+        r.top = top;
+        r.errors = errors.into_iter().collect();
+        r.errors.sort_by_key(|(c, n)| (Reverse(*n), *c));
+        if !latencies.is_empty() {
+            let avg = latencies.iter().sum::<u64>() / latencies.len() as u64;
+            r.latency_ms = Some((avg, latencies.iter().copied().max().unwrap_or(0)));
+        }
+        r
+    }
+}
+
+// Rough kind of a journaled error, to tell at a glance whether failures are Drive's, the network's or something else.
+pub fn category(e: &str) -> &'static str {
+    let lower = e.to_lowercase();
+    let has = |needles: &[&str]| needles.iter().any(|n| lower.contains(n));
+    if transfer::token_revoked(e) || has(&["401", "unauthorized", "rgdrive --login"]) {
+        "auth"
+    } else if has(&["429", "ratelimitexceeded", "userratelimitexceeded"]) {
+        "rate limit"
+    } else if has(&["storagequotaexceeded", "quota"]) {
+        "quota"
+    } else if has(&["403", "forbidden", "policy"]) {
+        "permission"
+    } else if has(&["404", "not found"]) {
+        "not found"
+    } else if has(&["500", "502", "503", "504", "backenderror"]) {
+        "server"
+    } else if has(&["timed out", "timeout", "connection", "dns"]) {
+        "network"
+    } else {
+        "other"
+    }
+}
+
+// A period like 7d, 12h, 30m or 2w, in seconds.
+pub fn parse_since(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let unit = match s.chars().last()? {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 7 * 86400,
+        _ => return None,
+    };
+    let n: i64 = s[..s.len() - 1].parse().ok()?;
+    if n < 0 {
+        return None;
+    }
+    n.checked_mul(unit)
+}
@@ -0,0 +1,76 @@
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::{staged_path, write_atomic};
+
+// A local change to a tracked file, held back from Drive until approved.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    #[serde(with = "crate::rawpath")]
+    pub path: PathBuf,
+    pub drive_url: String,
+    // Unix timestamp (seconds) of the latest change.
+    pub time: i64,
+}
+
+// Changes waiting for `rgdrive approve` while [review] is enabled. Persisted, so a restart doesn't lose or upload them.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Staged {
+    changes: Vec<Change>,
+}
+
+lazy_static! {
+    pub static ref STAGED: Mutex<Staged> = Mutex::new(Staged::load());
+}
+
+impl Staged {
+    pub fn load() -> Staged {
+        fs::read_to_string(staged_path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let p = staged_path();
+        if let Some(parent) = p.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&p, serde_json::to_string(self)?.as_bytes())
+    }
+
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    // Hold back a change to path. A file already waiting only has its time bumped, it's uploaded once either way.
+    pub fn stage(&mut self, path: &Path, drive_url: &str) -> Result<(), Error> {
+        let time = Utc::now().timestamp();
+        match self.changes.iter_mut().find(|c| c.path == path) {
+            Some(c) => c.time = time,
+            None => self.changes.push(Change {
+                path: path.to_path_buf(),
+                drive_url: drive_url.to_string(),
+                time,
+            }),
+        }
+        self.save()
+    }
+
+    // Remove and return the changes to path (or anything beneath it, for a directory), or every change if path is None.
+    pub fn take(&mut self, path: Option<&Path>) -> Result<Vec<Change>, Error> {
+        let (taken, kept) = self.changes.drain(..).partition(|c| match path {
+            Some(p) => c.path.starts_with(p),
+            None => true,
+        });
+        self.changes = kept;
+        self.save()?;
+        Ok(taken)
+    }
+}
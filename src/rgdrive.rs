@@ -1,46 +1,112 @@
 extern crate clap;
-use clap::{App, Arg};
 
-mod lib;
-use lib::{config_dir, DCommand, DResult, DSocket, TrackedFile, SOCKET_PATH};
+mod cli;
+mod install;
+mod once;
+mod update;
+
+use rgdrive::config;
+use rgdrive::crash::Crash;
+use rgdrive::daemons::{self, Reason, StartupError};
+use rgdrive::export::Export;
+use rgdrive::guard;
+use rgdrive::journal::{self, Entry, FailureGroup};
+use rgdrive::migrate::Bundle;
+use rgdrive::oauth;
+use rgdrive::placeholder;
+use rgdrive::plan::{human_bytes, human_time};
+use rgdrive::rawpath;
+use rgdrive::replica::{ReplicaState, Replicas};
+use rgdrive::report::{self, Report};
+use rgdrive::shutdown;
+use rgdrive::status::PathStatus;
+use rgdrive::transfer::{self, Overwrite};
+use rgdrive::versions;
+use rgdrive::{
+    crash_path, credentials_path, log_path, profile, settings_path, socket_path, valid_profile,
+    DCommand, DResult, DSocket, TrackedDir, TrackedFile, WatchEvent,
+};
 
 use std::env;
+use std::ffi::OsStr;
 
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::net::UnixStream;
 use std::os::unix::process::CommandExt;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io::Error;
 
+use chrono::{NaiveDate, Utc};
+use clap::ArgMatches;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use serde_json::json;
+
 const ANSI_GREEN: &str = "\x1B[32m";
 const ANSI_RED: &str = "\x1B[31m";
 const ANSI_BLUE: &str = "\x1B[34m";
 const ANSI_RESET: &str = "\x1B[0m";
-const STDERR_PATH: &str = "/tmp/rgdrived.err";
 
-// Gets the bin path of the daemon binary. (assumes it's in the same path as this bin).
-fn get_bin_path() -> String {
-    let bin_dir = env::current_exe().unwrap();
-    let parent = bin_dir.parent().unwrap();
-    let mut pb = parent.to_path_buf();
-    pb.push("rgdrived");
-    String::from(pb.to_str().unwrap())
+// How long --start waits for the daemon to report ready, and for each readiness check.
+const START_TIMEOUT: Duration = Duration::from_secs(15);
+const READY_TIMEOUT: Duration = Duration::from_secs(1);
+// How long --stop waits for the daemon to finish what it's doing, see shutdown::GRACE.
+const STOP_TIMEOUT: Duration = Duration::from_secs(shutdown::GRACE.as_secs() + 5);
+
+// --format json: results, errors, --list and --status are printed as json objects, one per line, instead of colored
+// text.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+fn json_output() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+// Man page rendered from the clap definitions by build.rs.
+const MAN_PAGE: &str = include_str!(concat!(env!("OUT_DIR"), "/rgdrive.1"));
+
+// Gets the bin path of the daemon binary. Looks next to this bin first, then the install dir, then $PATH.
+fn get_bin_path() -> Option<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Some(parent) = env::current_exe().ok().as_ref().and_then(|p| p.parent()) {
+        dirs.push(parent.to_path_buf());
+    }
+    dirs.push(install::local_bin_dir());
+    if let Some(path) = env::var_os("PATH") {
+        dirs.extend(env::split_paths(&path));
+    }
+    dirs.into_iter()
+        .map(|d| d.join("rgdrived"))
+        .find(|p| p.is_file())
+}
+
+// A global option (--profile, --format), which clap leaves on whichever (sub)command it was given to.
+fn global_arg<'a>(matches: &'a ArgMatches<'a>, name: &str) -> Option<&'a str> {
+    matches
+        .value_of(name)
+        .or_else(|| matches.subcommand().1.and_then(|m| global_arg(m, name)))
 }
 
 // Check if the daemon is active and listening. (any unixstream err is assumed not active)
 fn daemon_is_active() -> bool {
-    if let Err(_) = UnixStream::connect(SOCKET_PATH) {
-        false
-    } else {
-        true
-    }
+    UnixStream::connect(socket_path()).is_ok()
 }
 
 // Quick fmt function for errors. Pass an identifier (e.g "push_err" for push function) and the err msg and it will auto color and format.
 fn fmt_err<I: AsRef<str>, M: AsRef<str>>(identifier: I, message: M) {
+    if json_output() {
+        eprintln!(
+            "{}",
+            json!({"ok": false, "kind": identifier.as_ref(), "error": message.as_ref()})
+        );
+        return;
+    }
     eprintln!(
         "{}",
         format!(
@@ -54,171 +120,1188 @@ fn fmt_err<I: AsRef<str>, M: AsRef<str>>(identifier: I, message: M) {
     );
 }
 
+// --porcelain: the Drive url alone on stdout (nothing for a directory), errors alone on stderr, so scripts can use
+// URL=$(rgdrive --push file --wait --porcelain).
+fn print_porcelain(result: DResult) {
+    match result {
+        DResult::Fields(_, fields) => {
+            if let Some((_, url)) = fields.iter().find(|(k, _)| k == "drive_url") {
+                println!("{}", url);
+            }
+        }
+        DResult::Ok(_) => {}
+        DResult::Err(e) => eprintln!("{}", e),
+    }
+}
+
 // Maybe add as a method to DResult instead of a separate function? dresult.format()
 fn fmt_result(r: DResult) {
-    match r {
-        DResult::Ok(s) => {
-            println!("{}OK:{} {}", ANSI_GREEN, ANSI_RESET, s);
+    if json_output() {
+        match r {
+            DResult::Err(e) => eprintln!("{}", json!({"ok": false, "error": e})),
+            r => {
+                let mut out = json!({"ok": true, "message": r.message()});
+                if let DResult::Fields(_, fields) = &r {
+                    for (k, v) in fields {
+                        out[k.as_str()] = json!(v);
+                    }
+                }
+                println!("{}", out);
+            }
         }
+        return;
+    }
+    match r {
         DResult::Err(e) => {
             eprintln!("{}ERR:{} {}", ANSI_RED, ANSI_RESET, e);
         }
+        r => {
+            println!("{}OK:{} {}", ANSI_GREEN, ANSI_RESET, r.message());
+        }
     }
 }
 
-/// Starts the daemon process with proper settings.
-fn start_daemon() {
-    
-    // Ensure client id and secret are set in $ENV.
-    let (client_id, secret) = match (
-        env::var("GOOGLE_CLIENT_ID"),
-        env::var("GOOGLE_CLIENT_SECRET"),
-    ) {
-        (Ok(id), Ok(secret)) => (id, secret),
-        (Ok(_), _) => {
-            fmt_err("start_error", "$GOOGLE_CLIENT_SECRET is not set");
+// Drive url for a local synced path, or arg itself if it isn't a local path. Prints an error and returns None if arg is
+// a local file that isn't synced.
+fn synced_url(arg: &str, identifier: &str) -> Option<String> {
+    match PathBuf::from(arg).canonicalize() {
+        Ok(path) => match TrackedFile::load_all()
+            .into_iter()
+            .find(|tf| tf.path == path)
+        {
+            Some(tf) => Some(tf.drive_url),
+            None => {
+                fmt_err(identifier, format!("{:?} is not synced.", path));
+                None
+            }
+        },
+        Err(_) => Some(arg.to_string()),
+    }
+}
+
+// Print a DCommand::Ls listing: modified time, size, name and id per file, or the fields as json.
+fn print_listing(listing: &str) {
+    let files: Vec<Vec<&str>> = listing
+        .lines()
+        .map(|l| l.split('\t').collect::<Vec<&str>>())
+        .filter(|f| f.len() == 6)
+        .collect();
+    if json_output() {
+        let files: Vec<serde_json::Value> = files
+            .iter()
+            .map(|f| {
+                json!({
+                    "id": f[0],
+                    "name": f[1],
+                    "kind": f[2],
+                    "size": f[3].parse::<u64>().unwrap_or(0),
+                    "modified": f[4].parse::<i64>().unwrap_or(0),
+                    "target": if f[5].is_empty() { None } else { Some(f[5]) },
+                })
+            })
+            .collect();
+        println!("{}", json!({ "files": files }));
+        return;
+    }
+    for f in files {
+        let modified = human_time(f[4].parse().unwrap_or(0));
+        let size = match f[2] {
+            "folder" | "doc" => String::from("-"),
+            _ => human_bytes(f[3].parse().unwrap_or(0)),
+        };
+        let name = match f[2] {
+            "folder" => format!("{}/", f[1]),
+            "shortcut" => format!("{} -> {}", f[1], f[5]),
+            _ => f[1].to_string(),
+        };
+        println!("{}  {:>10}  {}  {}", modified, size, name, f[0]);
+    }
+}
+
+// Print text as a QR code made of half blocks. Colors are inverted so it scans on a dark terminal background.
+fn print_qr(text: &str) -> Result<(), String> {
+    let code = QrCode::new(text.as_bytes()).map_err(|e| e.to_string())?;
+    let image = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+    println!("{}", image);
+    Ok(())
+}
+
+fn list_versions(path: &Path) {
+    let names = match versions::list(path) {
+        Ok(n) => n,
+        Err(e) => {
+            fmt_err("versions_error", format!("{:?}: {}", path, e));
+            return;
+        }
+    };
+    if names.is_empty() {
+        println!("No versions of {:?} kept.", path);
+        return;
+    }
+    println!("Versions of {:?}:", path);
+    for name in names {
+        let size = versions::version_path(path, &name)
+            .and_then(std::fs::metadata)
+            .map(|m| m.len())
+            .unwrap_or(0);
+        println!("  {}  {} bytes", name, size);
+    }
+}
+
+// Write a saved version back over path. Writing in place (rather than renaming) keeps the inode the daemon is
+// watching, so a synced file is uploaded like any other edit.
+fn rollback(path: &Path, version: &str) -> DResult {
+    let keep = config::Config::load()
+        .unwrap_or_default()
+        .versions
+        .keep
+        .max(1);
+    match versions::rollback(path, version, keep) {
+        Ok(()) => DResult::ok(format!("Restored {:?} to version {}.", path, version)),
+        Err(e) => DResult::error(format!("Error restoring {:?}: {}", path, e)),
+    }
+}
+
+// Parses a YYYY-MM-DD date into a unix timestamp at the start of that day (UTC).
+fn parse_date(d: &str) -> Option<i64> {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    NaiveDate::parse_from_str(d, "%Y-%m-%d")
+        .ok()
+        .map(|d| (d - epoch).num_days() * 86400)
+}
+
+// Print every journal entry between from and to (inclusive dates) as csv or json.
+fn audit_export(from: &str, to: &str, format: &str) {
+    let (from, to) = match (parse_date(from), parse_date(to)) {
+        (Some(f), Some(t)) => (f, t + 86399),
+        _ => {
+            fmt_err("audit_error", "Dates must be formatted as YYYY-MM-DD");
             return;
         }
-        (_, Ok(_)) => {
-            fmt_err("start_error", "$GOOGLE_CLIENT_ID is not set");
+    };
+    let entries = match journal::entries_between(from, to) {
+        Ok(e) => e,
+        Err(e) => {
+            fmt_err("audit_error", format!("Failed to read journal: {}", e));
             return;
         }
-        (_, _) => {
+    };
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+    } else {
+        println!("{}", Entry::csv_header());
+        for e in &entries {
+            println!("{}", e.to_csv());
+        }
+    }
+}
+
+// Print a --report of the journal entries from the last since (e.g. 7d).
+fn report(since: &str) {
+    let secs = match report::parse_since(since) {
+        Some(s) => s,
+        None => {
             fmt_err(
-                "start_error",
-                "$GOOGLE_CLIENT_ID and $GOOGLE_CLIENT_SECRET are not set",
+                "report_error",
+                format!("Invalid --since {:?}, use e.g. 30m, 12h, 7d or 2w.", since),
             );
+            process::exit(1);
+        }
+    };
+    let now = Utc::now().timestamp();
+    let from = now.saturating_sub(secs);
+    let r = match journal::entries_between(from, now) {
+        Ok(e) => Report::new(&e),
+        Err(e) => {
+            fmt_err("report_error", format!("Failed to read journal: {}", e));
             return;
         }
     };
+    if json_output() {
+        let days: Vec<_> = r
+            .days
+            .iter()
+            .map(|(d, up, down)| json!({"day": d, "up": up, "down": down}))
+            .collect();
+        let top: Vec<_> = r
+            .top
+            .iter()
+            .map(|(p, b)| json!({"path": rawpath::escape(p), "bytes": b}))
+            .collect();
+        let errors: serde_json::Map<String, serde_json::Value> = r
+            .errors
+            .iter()
+            .map(|(c, n)| (c.to_string(), json!(n)))
+            .collect();
+        println!(
+            "{}",
+            json!({
+                "since": from,
+                "uploads": r.uploads,
+                "downloads": r.downloads,
+                "days": days,
+                "top": top,
+                "errors": errors,
+                "latency_ms": r.latency_ms.map(|(avg, max)| json!({"average": avg, "max": max})),
+            })
+        );
+        return;
+    }
+    println!(
+        "Since {} ({}): {} uploads, {} downloads",
+        human_time(from),
+        since,
+        r.uploads,
+        r.downloads
+    );
+    for (day, up, down) in &r.days {
+        println!(
+            "  {}  up {:>10}  down {:>10}",
+            day,
+            human_bytes(*up),
+            human_bytes(*down)
+        );
+    }
+    if !r.top.is_empty() {
+        println!("Top files:");
+        for (p, b) in &r.top {
+            println!("  {:>10}  {}", human_bytes(*b), tilde(p));
+        }
+    }
+    if !r.errors.is_empty() {
+        println!("Errors:");
+        for (c, n) in &r.errors {
+            println!("  {:>5}  {}", n, c);
+        }
+    }
+    if let Some((avg, max)) = r.latency_ms {
+        println!(
+            "Sync latency: {:.1}s on average, {:.1}s at most",
+            avg as f64 / 1000.0,
+            max as f64 / 1000.0
+        );
+    }
+}
 
-    if !daemon_is_active() {
-        unsafe {
-            Command::new(get_bin_path())
-                .env_clear()
-                .env("RUST_LOG", "debug")
-                .env("HOME", env::var("HOME").unwrap())
-                .env("GOOGLE_CLIENT_ID", client_id)
-                .env("GOOGLE_CLIENT_SECRET", secret)
-                .pre_exec(|| {
-                    let pid_t = libc::setsid();
-                    if pid_t < 0 {
-                        return Err(Error::from_raw_os_error(pid_t));
-                    }
-                    libc::umask(0);
-                    Ok(())
-                })
-                .current_dir("/")
-                .stdin(Stdio::null())
-                .stdout(Stdio::null())
-                .stderr(File::create(STDERR_PATH).unwrap())
-                .spawn()
-                .expect("failed to init command");
-        }
-        println!("{}OK:{} Daemon started.", ANSI_GREEN, ANSI_RESET);
-    } else {
-        println!("daemon already running");
+// Print every problem with the config at path. Exits 1 if there were any, so it can gate scripts.
+fn config_check(path: &PathBuf) {
+    let issues = config::check(path);
+    if issues.is_empty() {
+        fmt_result(DResult::ok(format!("{:?} is valid.", path)));
+        return;
+    }
+    for issue in &issues {
+        match issue.line {
+            Some(l) => fmt_err(
+                "config_error",
+                format!("{}:{}: {}", path.display(), l, issue.message),
+            ),
+            None => fmt_err("config_error", &issue.message),
+        }
     }
+    process::exit(1);
 }
 
-fn main() {
-    // Todo maybe move app config to yaml file or something?
-    let matches = App::new("rgdrive")
-        .version("1.0")
-        .author("Cameron W. <cam@camwilliams.ca>")
-        .arg(
-            Arg::with_name("start")
-                .long("start")
-                .help("Start the background daemon.")
-                .takes_value(false),
-        )
-        .arg(
-            Arg::with_name("stop")
-                .long("stop")
-                .help("Stop the background daemon.")
-                .takes_value(false),
-        )
-        .arg(
-            Arg::with_name("status")
-                .long("status")
-                .help("Check the current status of the background daemon.")
-        )
-        .arg(
-            Arg::with_name("pull")
-                .long("pull")
-                .value_names(&["gdrive_url", "/path/to/file"])
-                .number_of_values(2)
-                .help("Pull specified drive_url to given path, and sync it's contents.")
-        )
-        .arg(
-            Arg::with_name("push")
-                .long("push")
-                .takes_value(true)
-                .value_name("/path/to/file")
-                .help("Push given file to drive, and sync it's contents.")
-        )
-        .arg(Arg::with_name("msg").long("msg").takes_value(true))
-        .arg(
-            Arg::with_name("overwrite")
-                .long("overwrite")
-                .takes_value(false)
-                .help("Optional flag to overwrite file contents when pulling a file if it already exists.")
-        )
-        .arg(
-            Arg::with_name("log")
-                .long("log")
-                .takes_value(false) // maybe change to take a value to limit log lines? --log 5 -> last 5 log lines
-                .help("Optional flag to display daemon log.")
-        )
-        .arg(
-            Arg::with_name("list")
-                .long("list")
-                .takes_value(false)
-                .help("List all currently synced paths.")
+// Shorten paths under $HOME to ~/... for display.
+fn tilde(p: &Path) -> String {
+    match env::var("HOME")
+        .ok()
+        .and_then(|h| p.strip_prefix(h).ok().map(PathBuf::from))
+    {
+        Some(rel) => format!("~/{}", rel.display()),
+        None => p.display().to_string(),
+    }
+}
+
+// Plural noun for a journal op, e.g. "3 pushes of ~/notes.md failed".
+fn op_noun(op: &str, count: usize) -> String {
+    match (op, count) {
+        (_, 1) => op.to_string(),
+        ("push", _) => String::from("pushes"),
+        _ => format!("{}s", op),
+    }
+}
+
+// Summarize recent failures from the journal for --status, rather than making users dig through the stderr log.
+// Failures in the last hour grouped by file, and files whose last operation in the last 24h failed.
+fn recent_failures() -> Option<(Vec<FailureGroup>, Vec<Entry>)> {
+    let now = Utc::now().timestamp();
+    let entries = match journal::entries_between(now - 24 * 3600, now) {
+        Ok(e) => e,
+        Err(e) => {
+            fmt_err("status_error", format!("Failed to read journal: {}", e));
+            return None;
+        }
+    };
+
+    let hour: Vec<Entry> = entries
+        .iter()
+        .filter(|e| e.time >= now - 3600)
+        .cloned()
+        .collect();
+    let failed = journal::last_errors(&entries)
+        .into_iter()
+        .cloned()
+        .collect();
+    Some((journal::summarize_failures(&hour), failed))
+}
+
+fn print_failures() {
+    let (groups, failed) = match recent_failures() {
+        Some(f) => f,
+        None => return,
+    };
+    if !groups.is_empty() {
+        println!("Failures in the last hour:");
+        for g in &groups {
+            println!(
+                "  {red}{} {} of {} failed{end}: {}",
+                g.count,
+                op_noun(&g.op, g.count),
+                tilde(&g.path),
+                g.last_error,
+                red = ANSI_RED,
+                end = ANSI_RESET
+            );
+        }
+    }
+
+    if !failed.is_empty() {
+        println!("Files whose last operation failed (last 24h):");
+        for e in failed {
+            println!(
+                "  {} ({} at {}): {}",
+                tilde(&e.path),
+                e.op,
+                human_time(e.time),
+                e.result
+            );
+        }
+    }
+}
+
+// --status as a single json object, for --format json.
+// Who the daemon is signed in as, see DCommand::Account.
+struct SignedIn {
+    account: Option<String>,
+    scopes: Vec<String>,
+    // Unix timestamp (seconds).
+    token_expires: Option<i64>,
+}
+
+fn signed_in(socket: &DSocket) -> Option<SignedIn> {
+    let fields = match socket.send_command(DCommand::Account) {
+        Ok(DResult::Fields(_, fields)) => fields,
+        _ => return None,
+    };
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .filter(|v| !v.is_empty())
+    };
+    Some(SignedIn {
+        account: field("account"),
+        scopes: field("scopes")
+            .map(|s| s.split(' ').map(String::from).collect())
+            .unwrap_or_default(),
+        token_expires: field("token_expires").and_then(|t| t.parse().ok()),
+    })
+}
+
+// Tracked files synced, waiting for or failing on the [replica], for --status.
+struct ReplicaStatus {
+    profile: String,
+    synced: usize,
+    pending: usize,
+    failed: usize,
+    last_error: Option<String>,
+}
+
+// The [replica] profile and its state, None without one.
+fn replica() -> Option<(String, Replicas)> {
+    let profile = config::Config::load().ok()?.replica.profile?;
+    Some((profile, Replicas::load()))
+}
+
+fn replica_status() -> Option<ReplicaStatus> {
+    let (profile, replicas) = replica()?;
+    let mut status = ReplicaStatus {
+        profile,
+        synced: 0,
+        pending: 0,
+        failed: 0,
+        last_error: None,
+    };
+    for tf in TrackedFile::load_all() {
+        match replicas.state_of(&tf) {
+            Some(ReplicaState::Synced) => status.synced += 1,
+            Some(ReplicaState::Pending) => status.pending += 1,
+            Some(ReplicaState::Error) => {
+                status.failed += 1;
+                status.last_error = replicas
+                    .get(&tf.drive_url)
+                    .and_then(|r| r.last_error.clone());
+            }
+            None => {}
+        }
+    }
+    Some(status)
+}
+
+fn print_status_json(socket: &DSocket) {
+    let running = socket.is_active();
+    let health = match socket.send_command(DCommand::Health) {
+        Ok(DResult::Err(e)) => Some(e),
+        _ => None,
+    };
+    let capabilities: Vec<String> = match socket.send_command(DCommand::Capabilities) {
+        Ok(DResult::Ok(caps)) => caps.lines().map(String::from).collect(),
+        _ => Vec::new(),
+    };
+    let signed_in = signed_in(socket).map(
+        |s| json!({"account": s.account, "scopes": s.scopes, "token_expires": s.token_expires}),
+    );
+    let crash = Crash::unreported().map(|c| {
+        json!({"time": c.time, "message": c.message, "location": c.location, "report": rawpath::escape(&crash_path())})
+    });
+    let replica = replica_status().map(|r| {
+        json!({"profile": r.profile, "synced": r.synced, "pending": r.pending, "failed": r.failed, "last_error": r.last_error})
+    });
+    let (groups, failed) = recent_failures().unwrap_or_default();
+    let groups: Vec<serde_json::Value> = groups
+        .iter()
+        .map(|g| json!({"op": g.op, "path": rawpath::escape(&g.path), "count": g.count, "last_error": g.last_error}))
+        .collect();
+    let failed: Vec<serde_json::Value> = failed
+        .iter()
+        .map(|e| json!({"path": rawpath::escape(&e.path), "op": e.op, "time": e.time, "error": e.result}))
+        .collect();
+    println!(
+        "{}",
+        json!({
+            "running": running,
+            "health": health,
+            "signed_in": signed_in,
+            "crash": crash,
+            "replica": replica,
+            "capabilities": capabilities,
+            "failures_last_hour": groups,
+            "failed": failed,
+        })
+    );
+}
+
+// The tracked directories, or none (after saying why) if they can't be read.
+fn tracked_dirs() -> Vec<TrackedDir> {
+    TrackedDir::load().unwrap_or_else(|e| {
+        fmt_err("list_error", e);
+        Vec::new()
+    })
+}
+
+// Sync state of each tracked file, as the daemon sees it right now. If it isn't running, as of its last run, and false.
+fn list_states(socket: &DSocket, files: &[TrackedFile]) -> (Vec<PathStatus>, bool) {
+    let paths = files.iter().map(|tf| tf.path.clone()).collect();
+    if let Ok(DResult::Ok(lines)) = socket.send_command(DCommand::PathStatusBatch(paths)) {
+        let states: Vec<PathStatus> = lines.lines().filter_map(|l| l.parse().ok()).collect();
+        if states.len() == files.len() {
+            return (states, true);
+        }
+    }
+    let states = files
+        .iter()
+        .map(|tf| match (&tf.last_error, tf.is_export()) {
+            (Some(_), _) => PathStatus::Error,
+            (None, true) => PathStatus::Export,
+            (None, false) => PathStatus::Synced,
+        })
+        .collect();
+    (states, false)
+}
+
+// Whether tf's local copy matches what was last synced, None for exports (they only come down) or when it can't be told.
+fn in_sync(tf: &TrackedFile) -> Option<bool> {
+    if tf.is_export() {
+        return None;
+    }
+    tf.changed_locally().map(|changed| !changed)
+}
+
+// --list as a single json object, for --format json.
+fn print_list_json(socket: &DSocket) {
+    let tracked = TrackedFile::load_all();
+    let (states, live) = list_states(socket, &tracked);
+    let replicas = replica().map(|(_, r)| r);
+    let files: Vec<serde_json::Value> = tracked
+        .iter()
+        .zip(states)
+        .map(|(tf, state)| {
+            json!({
+                "path": rawpath::escape(&tf.path),
+                "drive_url": tf.drive_url,
+                "remote_name": tf.remote_name,
+                "export": tf.export.as_ref().map(|e| &e.format),
+                "absent": tf.media.is_some() && !tf.path.exists(),
+                "state": state.as_str(),
+                "synced_at": tf.synced_at,
+                "last_error": tf.last_error,
+                "in_sync": in_sync(tf),
+                "replica": replicas.as_ref().and_then(|r| r.state_of(tf)).map(ReplicaState::as_str),
+            })
+        })
+        .collect();
+    let dirs: Vec<serde_json::Value> = tracked_dirs()
+        .iter()
+        .map(|d| json!({"path": rawpath::escape(&d.path), "dest": d.dest}))
+        .collect();
+    println!("{}", json!({"files": files, "dirs": dirs, "live": live}));
+}
+
+/// Starts the daemon process with proper settings, and waits for it to report ready.
+fn start_daemon() -> Result<(), StartupError> {
+    let bin = match get_bin_path() {
+        Some(b) => b,
+        None => {
+            return Err(StartupError::new(
+                Reason::Other,
+                "rgdrived not found. Install it with `rgdrive install`.",
+            ))
+        }
+    };
+
+    if daemon_is_active() {
+        println!("daemon already running");
+        return Ok(());
+    }
+
+    let log = File::create(log_path()).map_err(|e| {
+        StartupError::new(
+            Reason::Other,
+            format!("Couldn't create {:?}: {}", log_path(), e),
         )
-        .arg(
-            Arg::with_name("sync")
-                .long("sync")
-                .value_names(&["/path/to/file", "drive_url"])
-                .number_of_values(2)
-                .help("Manually add a sync between given path and drive url.")
+    })?;
+    let mut cmd = Command::new(bin);
+    cmd.env_clear()
+        .env("RUST_LOG", "debug")
+        .env("HOME", env::var("HOME").unwrap())
+        .env("RGDRIVE_PROFILE", profile().unwrap_or_default());
+    // Credentials are checked by the daemon, which says exactly what's wrong with them. Test setups also point it at
+    // their own socket and Google apis, and may inject failures.
+    for var in &[
+        "GOOGLE_CLIENT_ID",
+        "GOOGLE_CLIENT_SECRET",
+        "GOOGLE_REFRESH_TOKEN",
+        "RGDRIVE_GOOGLE_API",
+        "RGDRIVE_SOCKET",
+        "RGDRIVE_INJECT_FAILURES",
+    ] {
+        if let Ok(v) = env::var(var) {
+            cmd.env(var, v);
+        }
+    }
+    let mut child = unsafe {
+        cmd.pre_exec(|| {
+            let pid_t = libc::setsid();
+            if pid_t < 0 {
+                return Err(Error::from_raw_os_error(pid_t));
+            }
+            libc::umask(0);
+            Ok(())
+        })
+        .current_dir("/")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        .spawn()
+        .map_err(|e| StartupError::new(Reason::Other, format!("Failed to run rgdrived: {}", e)))?
+    };
+
+    // The daemon only answers once it's fully up. If it exits first, its log (or exit code) says why.
+    let socket = DSocket::new(socket_path());
+    let started = Instant::now();
+    while started.elapsed() < START_TIMEOUT {
+        if let Ok(Some(status)) = child.try_wait() {
+            let log = fs::read_to_string(log_path()).unwrap_or_default();
+            return Err(match StartupError::from_log(&log) {
+                Some(e) => e,
+                None => StartupError::new(
+                    Reason::from_exit_code(status.code()),
+                    format!(
+                        "rgdrived exited during startup ({}), see `rgdrive --log`.",
+                        status
+                    ),
+                ),
+            });
+        }
+        if let Ok(DResult::Ok(_)) = socket.send_command_timeout(DCommand::Ready, READY_TIMEOUT) {
+            println!("{}OK:{} Daemon started.", ANSI_GREEN, ANSI_RESET);
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Err(StartupError::new(
+        Reason::Other,
+        format!(
+            "rgdrived didn't report ready within {}s, see `rgdrive --log`.",
+            START_TIMEOUT.as_secs()
+        ),
+    ))
+}
+
+// Ask the daemon to quit and wait for its socket to go away.
+fn stop_daemon(socket: &DSocket) -> Result<(), String> {
+    socket
+        .send_command_timeout(DCommand::Quit, STOP_TIMEOUT)
+        .map_err(|e| format!("Failed to stop daemon: {}", e))?;
+    for _ in 0..50 {
+        if !socket.is_active() {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Err(String::from("Daemon did not stop, restart it manually."))
+}
+
+// Bundle passphrase from $RGDRIVE_PASSPHRASE, or asked for (without echo) on the terminal. confirm asks twice.
+fn read_passphrase(confirm: bool) -> Result<String, String> {
+    if let Ok(p) = env::var("RGDRIVE_PASSPHRASE") {
+        return Ok(p);
+    }
+    let passphrase = read_hidden("Bundle passphrase: ")?;
+    if confirm && read_hidden("Repeat passphrase: ")? != passphrase {
+        return Err(String::from("The passphrases don't match."));
+    }
+    Ok(passphrase)
+}
+
+// --auth: client credentials from the environment or asked for, checked, signed in with, and saved.
+fn auth() -> Result<(), String> {
+    let id = match env::var("GOOGLE_CLIENT_ID") {
+        Ok(id) => id,
+        Err(_) => {
+            eprint!("OAuth client id: ");
+            let mut line = String::new();
+            std::io::stdin()
+                .read_line(&mut line)
+                .map_err(|e| format!("Couldn't read the client id: {}", e))?;
+            line.trim().to_string()
+        }
+    };
+    let secret = match env::var("GOOGLE_CLIENT_SECRET") {
+        Ok(s) => s,
+        Err(_) => read_hidden("OAuth client secret: ")?,
+    };
+    transfer::check_credentials(&id, &secret)?;
+    sign_in(id.trim(), secret.trim())
+}
+
+// Sign in with the browser and save the client credentials along with the refresh token, then connect with what was
+// saved the way the daemon will.
+fn sign_in(id: &str, secret: &str) -> Result<(), String> {
+    let token = oauth::sign_in(id, secret)?;
+    transfer::save_credentials(id, secret, token.refresh.as_deref()).map_err(|e| {
+        format!(
+            "Couldn't save the sign in to {}: {}",
+            tilde(&credentials_path()),
+            e
         )
-        .arg(
-            Arg::with_name("unsync")
-                .long("unsync")
-                .value_name("/path/to/file")
-                .takes_value(true)
-                .help("Manually remove any syncs for given path.")
+    })?;
+    env::remove_var("GOOGLE_CLIENT_ID");
+    env::remove_var("GOOGLE_CLIENT_SECRET");
+    env::remove_var("GOOGLE_REFRESH_TOKEN");
+    transfer::connect()
+        .and_then(|mut r| transfer::check_access(r.as_mut()))
+        .map_err(|e| e.to_string())
+}
+
+fn read_hidden(prompt: &str) -> Result<String, String> {
+    eprint!("{}", prompt);
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    let tty = unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut term) } == 0;
+    if tty {
+        let mut quiet = term;
+        quiet.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &quiet) };
+    }
+    let mut line = String::new();
+    let read = std::io::stdin().read_line(&mut line);
+    if tty {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) };
+        eprintln!();
+    }
+    read.map_err(|e| {
+        format!(
+            "Couldn't read the {}: {}",
+            prompt.trim_end_matches(": ").to_lowercase(),
+            e
         )
-        .get_matches();
+    })?;
+    Ok(line.trim_end_matches(&['\n', '\r'][..]).to_string())
+}
+
+fn migrate_export(path: &Path, with_tokens: bool) -> DResult {
+    let bundle = match Bundle::collect(with_tokens) {
+        Ok(b) => b,
+        Err(e) => return DResult::error(e),
+    };
+    let sealed = match read_passphrase(true).and_then(|p| bundle.seal(&p)) {
+        Ok(s) => s,
+        Err(e) => return DResult::error(e),
+    };
+    // The bundle is encrypted, but there's no reason for anyone else to have it.
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut f| f.write_all(&sealed));
+    match written {
+        Ok(()) => DResult::ok(format!(
+            "Exported the config and {} tracked files{} to {}. Import it with `rgdrive --migrate-import {}`.",
+            bundle.tracked.len(),
+            if with_tokens { " with credentials" } else { "" },
+            path.display(),
+            path.display()
+        )),
+        Err(e) => DResult::error(format!("Couldn't write {}: {}", path.display(), e)),
+    }
+}
+
+// Restore a bundle into this profile, then (re)start the daemon so the imported files are watched.
+fn migrate_import(socket: &DSocket, path: &Path, force: bool) -> Result<(), String> {
+    let buf = fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    let bundle = Bundle::open(&buf, &read_passphrase(false)?)?;
+    let (host, created) = (bundle.host.clone(), bundle.created.clone());
+    // A running daemon would save its own tracked files over the imported ones.
+    if socket.is_active() {
+        stop_daemon(socket)?;
+    }
+    let restored = bundle.restore(force)?;
+
+    println!(
+        "Imported the bundle made on {} at {}: {} tracked files ({} moved to this $HOME).",
+        host, created, restored.tracked, restored.rebased
+    );
+    for tf in &restored.missing {
+        println!(
+            "Not found here, left out: {}. Pull it again with `rgdrive --pull {} {}`.",
+            tf.path.display(),
+            tf.drive_url,
+            tf.path.display()
+        );
+    }
+    for issue in &restored.issues {
+        println!("Config {}", issue);
+    }
+    if restored.credentials {
+        println!(
+            "Drive client credentials saved to {}.",
+            tilde(&credentials_path())
+        );
+    }
+    start_daemon().map_err(|e| format!("Imported, but the daemon didn't start: {}", e))
+}
+
+// --overwrite-mode, or always with --overwrite and never without.
+fn overwrite_mode(matches: &ArgMatches) -> Overwrite {
+    match matches
+        .value_of("overwrite-mode")
+        .and_then(Overwrite::from_name)
+    {
+        Some(mode) => mode,
+        None if matches.is_present("overwrite") => Overwrite::Always,
+        None => Overwrite::Never,
+    }
+}
+
+// Parse an id from --queue, printing an error if it isn't one.
+fn queue_id(id: &str) -> Option<u64> {
+    match id.parse::<u64>() {
+        Ok(id) => Some(id),
+        Err(_) => {
+            fmt_err(
+                "queue_error",
+                format!("{:?} is not a queue id, see --queue.", id),
+            );
+            None
+        }
+    }
+}
+
+// Print the running daemons of every profile.
+fn list_daemons() {
+    let running = daemons::running();
+    if running.is_empty() {
+        println!("No daemons running.");
+        return;
+    }
+    // Lined up with Instance::describe.
+    println!("{:<14}{:<26}{:<10}SOCKET", "PROFILE", "ACCOUNT", "VERSION");
+    for instance in &running {
+        println!("{}", instance.describe());
+    }
+}
+
+// Longest a prompt waits on the daemon before printing nothing.
+const PROMPT_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Sync state of path (or the current directory) for a shell prompt. Prints nothing at all if there's nothing to show or
+// the daemon can't answer quickly, a prompt is never the place for an error.
+fn prompt_status(socket: &DSocket, path: Option<&OsStr>, plain: bool) {
+    // The daemon doesn't share our working directory.
+    let cwd = match env::current_dir() {
+        Ok(d) => d,
+        Err(_) => return,
+    };
+    let path = match path {
+        Some(p) => cwd.join(p),
+        None => cwd,
+    };
+    let status =
+        match socket.send_command_timeout(DCommand::PathStatusBatch(vec![path]), PROMPT_TIMEOUT) {
+            Ok(DResult::Ok(s)) => s,
+            _ => return,
+        };
+    let status = match status.parse::<PathStatus>() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    if plain {
+        println!("{}", status);
+    } else if status != PathStatus::Untracked {
+        println!("{}", status.glyph());
+    }
+}
+
+fn main() {
+    let matches = cli::build_app().get_matches();
+
+    // Sockets and files are all found through the profile, so settle it before anything else.
+    JSON.store(
+        global_arg(&matches, "format") == Some("json"),
+        Ordering::Relaxed,
+    );
+    if let Some(name) = global_arg(&matches, "profile") {
+        env::set_var("RGDRIVE_PROFILE", name);
+    }
+    if let Some(name) = profile() {
+        if !valid_profile(&name) {
+            fmt_err(
+                "profile_error",
+                format!(
+                    "{:?} isn't a valid profile name, use letters, digits, - and _.",
+                    name
+                ),
+            );
+            process::exit(1);
+        }
+    }
+
+    if matches.occurrences_of("man") > 0 {
+        print!("{}", MAN_PAGE);
+        return;
+    }
+
+    let socket = DSocket::new(socket_path());
+
+    if let Some(m) = matches
+        .subcommand_matches("config")
+        .and_then(|m| m.subcommand_matches("check"))
+    {
+        let path = m
+            .value_of("file")
+            .map(PathBuf::from)
+            .unwrap_or_else(settings_path);
+        config_check(&path);
+        return;
+    }
+
+    if let Some(m) = matches.subcommand_matches("prompt-status") {
+        prompt_status(&socket, m.value_of_os("path"), m.is_present("plain"));
+        return;
+    }
+
+    if let Some(m) = matches.subcommand_matches("install") {
+        fmt_result(install::install(m.value_of("dir"), m.is_present("systemd")));
+        return;
+    }
+
+    if let Some(m) = matches.subcommand_matches("uninstall") {
+        fmt_result(install::uninstall(m.value_of("dir"), m.is_present("purge")));
+        return;
+    }
+
+    if let Some(m) = matches.subcommand_matches("self-update") {
+        fmt_result(update::self_update(
+            m.is_present("check"),
+            m.is_present("yes"),
+        ));
+        return;
+    }
 
-    let socket = DSocket::new(SOCKET_PATH);
+    // Every profile's daemon, so it doesn't matter which one (if any) --profile picked.
+    if matches.subcommand_matches("daemons").is_some() {
+        list_daemons();
+        return;
+    }
 
-    // Starts the daemon. Put all fds to null except stderr which gets written to STDERR_PATH.
+    // Starts the daemon. Put all fds to null except stderr which gets written to log_path().
     // Todo:// maybe add a 2nd fork so the forked process isn't it's sesssion leader?
     if matches.occurrences_of("start") > 0 {
-        start_daemon();
+        if let Err(e) = start_daemon() {
+            fmt_err("start_error", e.to_string());
+            process::exit(e.code.exit_code());
+        }
         return;
     }
 
     // Print current daemon status and daemon logs to stdout.
+    if matches.occurrences_of("status") > 0 && json_output() {
+        print_status_json(&socket);
+        return;
+    }
     if matches.occurrences_of("status") > 0 {
         let status = match socket.is_active() {
             true => format!("{}running{}", ANSI_GREEN, ANSI_RESET),
             false => format!("{}stopped{}", ANSI_RED, ANSI_RESET),
         };
         println!("Daemon status: {}", status);
+        // E.g. paused until --login.
+        if let Ok(DResult::Err(e)) = socket.send_command(DCommand::Health) {
+            println!("{}Health:{} {}", ANSI_RED, ANSI_RESET, e);
+        }
+        if let Some(s) = signed_in(&socket) {
+            println!("Account: {}", s.account.as_deref().unwrap_or("unknown"));
+            if !s.scopes.is_empty() {
+                println!("Scopes: {}", s.scopes.join(", "));
+            }
+            if let Some(t) = s.token_expires {
+                println!(
+                    "Access token expires: {} (renewed automatically)",
+                    human_time(t)
+                );
+            }
+        }
+        if let Some(c) = Crash::unreported() {
+            println!(
+                "{}Crashed{} at {}: {}{}. Report in {:?}.",
+                ANSI_RED,
+                ANSI_RESET,
+                human_time(c.time),
+                c.message,
+                c.location.map(|l| format!(" ({})", l)).unwrap_or_default(),
+                crash_path()
+            );
+        }
+        if let Some(r) = replica_status() {
+            println!(
+                "Replica (profile {}): {} synced, {} pending, {} failed{}",
+                r.profile,
+                r.synced,
+                r.pending,
+                r.failed,
+                r.last_error
+                    .map(|e| format!(", last error: {}", e))
+                    .unwrap_or_default()
+            );
+        }
+        if let Ok(DResult::Ok(caps)) = socket.send_command(DCommand::Capabilities) {
+            println!("Drive features:");
+            for line in caps.lines() {
+                println!("  {}", line);
+            }
+        }
+        print_failures();
+        return;
+    }
+
+    // Audit export reads the journal directly, so it works whether or not the daemon is running.
+    if let Some(v) = matches.values_of("audit-export") {
+        let vals: Vec<&str> = v.collect();
+        audit_export(vals[0], vals[1], matches.value_of("audit-format").unwrap());
+        return;
+    }
+
+    if matches.is_present("report") {
+        report(matches.value_of("since").unwrap_or("7d"));
+        return;
+    }
+
+    // Migration bundles are plain files too. Importing stops and restarts the daemon itself.
+    if let Some(p) = matches.value_of_os("migrate-export") {
+        fmt_result(migrate_export(
+            Path::new(p),
+            matches.is_present("with-tokens"),
+        ));
+        return;
+    }
+
+    if let Some(p) = matches.value_of_os("migrate-import") {
+        if let Err(e) = migrate_import(&socket, Path::new(p), matches.is_present("force")) {
+            fmt_err("migrate_error", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Versions are plain files, so they don't need the daemon either.
+    if let Some(p) = matches.value_of_os("versions") {
+        list_versions(Path::new(p));
+        return;
+    }
+
+    if let Some(v) = matches.values_of_os("rollback") {
+        let vals: Vec<&OsStr> = v.collect();
+        fmt_result(rollback(Path::new(vals[0]), &vals[1].to_string_lossy()));
+        return;
+    }
+
+    // rgdrive's own files are never synced by accident, see guard.
+    if !matches.is_present("allow-own-state") {
+        let mut refused = Vec::new();
+        if let Some(p) = matches.value_of_os("push") {
+            refused.extend(guard::holds_own_state(Path::new(p)));
+        }
+        if let Some(p) = matches.values_of_os("pull").and_then(|mut v| v.nth(1)) {
+            refused.extend(guard::own_state(Path::new(p)));
+        }
+        if let Some(p) = matches.value_of_os("pull-starred") {
+            refused.extend(guard::own_state(Path::new(p)));
+        }
+        if let Some(p) = matches.values_of_os("pull-dir").and_then(|mut v| v.nth(1)) {
+            refused.extend(guard::own_state(Path::new(p)));
+        }
+        if let Some(p) = matches.values_of_os("sync").and_then(|mut v| v.next()) {
+            refused.extend(guard::own_state(Path::new(p)));
+        }
+        if let Some(why) = refused.first() {
+            fmt_err(
+                "own_state_err",
+                format!(
+                    "Refusing to sync {}, it would feed back into itself. Pass --allow-own-state to do it anyway.",
+                    why
+                ),
+            );
+            process::exit(1);
+        }
+    }
+
+    // Anonymous pulls need neither the daemon nor credentials.
+    if matches.is_present("anonymous") {
+        let vals: Vec<&OsStr> = matches.values_of_os("pull").unwrap().collect();
+        let export = matches.value_of("export-format").map(|format| Export {
+            format: format.to_string(),
+            sheet: matches.value_of("sheet").map(String::from),
+            range: matches.value_of("range").map(String::from),
+        });
+        let result = once::pull_anonymous(
+            &vals[0].to_string_lossy(),
+            Path::new(vals[1]),
+            overwrite_mode(&matches),
+            export,
+        );
+        let failed = matches!(result, DResult::Err(_));
+        fmt_result(result);
+        if failed {
+            process::exit(1);
+        }
+        return;
+    }
+
+    // One-shot transfers run here instead of in the daemon.
+    if matches.is_present("once") {
+        let result = if let Some(p) = matches.value_of_os("push") {
+            once::push(
+                Path::new(p),
+                matches.value_of("dest"),
+                matches.value_of("as"),
+                !matches.is_present("no-default-excludes"),
+            )
+        } else if let Some(v) = matches.values_of_os("pull") {
+            let vals: Vec<&OsStr> = v.collect();
+            once::pull(
+                &vals[0].to_string_lossy(),
+                Path::new(vals[1]),
+                overwrite_mode(&matches),
+            )
+        } else {
+            DResult::error("--once only applies to --push and --pull.")
+        };
+        let failed = matches!(result, DResult::Err(_));
+        fmt_result(result);
+        if failed {
+            process::exit(1);
+        }
         return;
     }
 
     if matches.occurrences_of("log") > 0 {
-        let mut f: File = File::open(STDERR_PATH).unwrap();
+        let mut f: File = File::open(log_path()).unwrap();
         let mut lines: String = String::new();
         f.read_to_string(&mut lines).unwrap();
         println!("{}", lines);
         return;
     }
 
+    // Health check, exit code reflects the result so it can be scripted.
+    if matches.occurrences_of("health") > 0 {
+        if !socket.is_active() {
+            fmt_err("health", "Daemon is not running.");
+            process::exit(2);
+        }
+        match socket.send_command(DCommand::Health) {
+            Ok(r @ DResult::Ok(_)) => fmt_result(r),
+            Ok(r) => {
+                fmt_result(r);
+                process::exit(1);
+            }
+            Err(e) => {
+                fmt_err("health", format!("Couldn't reach daemon: {}", e));
+                process::exit(2);
+            }
+        }
+        return;
+    }
+
+    if matches.occurrences_of("auth") > 0 {
+        match auth() {
+            Ok(()) if socket.is_active() => {
+                fmt_result(socket.send_command(DCommand::Login).unwrap())
+            }
+            Ok(()) => fmt_result(DResult::ok(format!(
+                "Signed in to Drive. Client credentials and sign in saved to {}, the daemon no longer needs them in its environment.",
+                tilde(&credentials_path())
+            ))),
+            Err(e) => {
+                fmt_err("auth_error", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Sign in here, where a browser can be opened, then have a running daemon pick the token up.
+    if matches.occurrences_of("login") > 0 {
+        let signed_in = transfer::credentials().and_then(|(id, secret)| sign_in(&id, &secret));
+        if let Err(e) = signed_in {
+            fmt_err("login_error", e);
+            process::exit(1);
+        }
+        if socket.is_active() {
+            fmt_result(socket.send_command(DCommand::Login).unwrap());
+        } else {
+            fmt_result(DResult::ok("Signed in to Drive."));
+        }
+        return;
+    }
+
     // Any further functions require an active daemon. Check here and error out if not active.
     if !socket.is_active() {
         fmt_err(
@@ -230,11 +1313,103 @@ fn main() {
 
     // Stops the daemon process.
     if matches.occurrences_of("stop") > 0 {
-        let result = socket.send_command(DCommand::Quit).unwrap();
+        let result = socket
+            .send_command_timeout(DCommand::Quit, STOP_TIMEOUT)
+            .unwrap();
         fmt_result(result);
         return;
     }
 
+    if matches.occurrences_of("stats") > 0 {
+        fmt_result(socket.send_command(DCommand::Stats).unwrap());
+        return;
+    }
+
+    if matches.subcommand_matches("pending").is_some() {
+        fmt_result(socket.send_command(DCommand::Pending).unwrap());
+        return;
+    }
+
+    if let Some(m) = matches.subcommand_matches("approve") {
+        // The daemon doesn't share our working directory.
+        let path = m
+            .value_of_os("path")
+            .map(|p| env::current_dir().unwrap_or_default().join(p));
+        fmt_result(socket.send_command(DCommand::Approve(path)).unwrap());
+        return;
+    }
+
+    if let Some(m) = matches.subcommand_matches("env") {
+        let command = match m.subcommand() {
+            ("set", Some(s)) => DCommand::SetEnv(s.value_of("name").map(String::from)),
+            ("clear", _) => DCommand::SetEnv(None),
+            _ => DCommand::Env,
+        };
+        fmt_result(socket.send_command(command).unwrap());
+        return;
+    }
+
+    if let Some(m) = matches.subcommand_matches("pin") {
+        let arg = m.value_of("path").unwrap();
+        let path = env::current_dir().unwrap_or_default().join(arg);
+        // A placeholder isn't tracked, it's pinned by the Drive file it stands for.
+        let command = match placeholder::drive_id_of(&path) {
+            Some(id) => DCommand::Pin(
+                format!("https://drive.google.com/open?id={}", id),
+                Some(path),
+            ),
+            None => match synced_url(arg, "pin_error") {
+                Some(url) => DCommand::Pin(url, None),
+                None => return,
+            },
+        };
+        fmt_result(socket.send_command(command).unwrap());
+        return;
+    }
+
+    if let Some(m) = matches.subcommand_matches("unpin") {
+        let arg = m.value_of("path").unwrap();
+        // A pinned file may not be synced right now (e.g. it's a placeholder), so it's unpinned by path.
+        let pinned = match PathBuf::from(arg).canonicalize() {
+            Ok(p) => p.to_string_lossy().into_owned(),
+            Err(_) => arg.to_string(),
+        };
+        fmt_result(socket.send_command(DCommand::Unpin(pinned)).unwrap());
+        return;
+    }
+
+    if matches.occurrences_of("pins") > 0 {
+        fmt_result(socket.send_command(DCommand::Pins).unwrap());
+        return;
+    }
+
+    if let Some(m) = matches.subcommand_matches("hydrate") {
+        let path = env::current_dir()
+            .unwrap_or_default()
+            .join(m.value_of_os("path").unwrap());
+        fmt_result(socket.send_command(DCommand::Hydrate(path)).unwrap());
+        return;
+    }
+
+    if matches.occurrences_of("queue") > 0 {
+        fmt_result(socket.send_command(DCommand::Queue).unwrap());
+        return;
+    }
+
+    if let Some(id) = matches.value_of("queue-drop") {
+        if let Some(id) = queue_id(id) {
+            fmt_result(socket.send_command(DCommand::QueueDrop(id)).unwrap());
+        }
+        return;
+    }
+
+    if let Some(id) = matches.value_of("queue-retry") {
+        if let Some(id) = queue_id(id) {
+            fmt_result(socket.send_command(DCommand::QueueRetry(id)).unwrap());
+        }
+        return;
+    }
+
     // Testing function, write a msg to the daemon.
     if let Some(m) = matches.value_of("msg") {
         let msg = m.to_string();
@@ -248,36 +1423,263 @@ fn main() {
     }
 
     // Handles push command.
-    if let Some(p) = matches.value_of("push") {
+    if let Some(p) = matches.value_of_os("push") {
         let path = PathBuf::from(p);
-        fmt_result(socket.send_command(DCommand::Push(path)).unwrap());
+        let excludes = !matches.is_present("no-default-excludes");
+        // Scripts waiting on the push need to know whether it failed.
+        let status = matches.is_present("wait") || matches.is_present("porcelain");
+        // Directory pushes are planned first: shown and stopped on --dry-run, or if the plan has warnings.
+        if path.is_dir() {
+            let plan = socket
+                .send_command(DCommand::Plan(path.clone(), excludes))
+                .unwrap();
+            if matches.is_present("dry-run") {
+                fmt_result(plan);
+                return;
+            }
+            if let DResult::Err(p) = plan {
+                if !matches.is_present("force") {
+                    println!("{}", p);
+                    fmt_err(
+                        "push_err",
+                        "Not pushing, rerun with --force to push anyway.",
+                    );
+                    if status {
+                        process::exit(1);
+                    }
+                    return;
+                }
+            }
+        }
+        let dest = matches.value_of("dest").map(String::from);
+        let cmd = match (matches.value_of("as"), dest) {
+            (None, dest) if matches.is_present("recursive") => {
+                DCommand::TrackDir(path, dest, excludes)
+            }
+            (name, dest) if matches.is_present("wait") => {
+                DCommand::PushWait(path, dest, name.map(String::from), excludes)
+            }
+            (Some(name), dest) => DCommand::PushAs(path, name.to_string(), dest),
+            (None, Some(dest)) => DCommand::PushTo(path, dest, excludes),
+            (None, None) => DCommand::Push(path, excludes),
+        };
+        let result = socket.send_command(cmd).unwrap();
+        let failed = !result.is_ok();
+        if matches.is_present("porcelain") {
+            print_porcelain(result);
+        } else {
+            fmt_result(result);
+        }
+        if failed && status {
+            process::exit(1);
+        }
     }
 
     // Handles pull command.
-    if let Some(v) = matches.values_of("pull") {
+    if let Some(v) = matches.values_of_os("pull") {
+        let vals: Vec<&OsStr> = v.collect();
+        let cmd = match matches.value_of("export-format") {
+            Some(format) => DCommand::Export(
+                vals[0].to_string_lossy().into_owned(),
+                PathBuf::from(vals[1]),
+                matches.occurrences_of("overwrite") == 1,
+                Export {
+                    format: format.to_string(),
+                    sheet: matches.value_of("sheet").map(String::from),
+                    range: matches.value_of("range").map(String::from),
+                },
+            ),
+            None => DCommand::Pull(
+                vals[0].to_string_lossy().into_owned(),
+                PathBuf::from(vals[1]),
+                overwrite_mode(&matches),
+                matches.is_present("relink"),
+            ),
+        };
+        fmt_result(socket.send_command(cmd).unwrap());
+    }
+
+    // Handles pull-starred command.
+    if let Some(d) = matches.value_of_os("pull-starred") {
+        fmt_result(
+            socket
+                .send_command(DCommand::PullStarred(PathBuf::from(d)))
+                .unwrap(),
+        );
+    }
+
+    // Handles rebuild-state command. The daemon doesn't share our working directory.
+    if let Some(d) = matches.value_of_os("rebuild-state") {
+        let dir = env::current_dir().unwrap_or_default().join(d);
+        fmt_result(socket.send_command(DCommand::RebuildState(dir)).unwrap());
+    }
+
+    // Handles pull-dir command.
+    if let Some(mut args) = matches.values_of_os("pull-dir") {
+        let url = args.next().unwrap().to_string_lossy().into_owned();
+        let dir = PathBuf::from(args.next().unwrap());
+        let placeholders = matches.is_present("placeholders");
+        fmt_result(
+            socket
+                .send_command(DCommand::PullDir(url, dir, placeholders))
+                .unwrap(),
+        );
+    }
+
+    // Handles activity command. Local paths are looked up in the tracked files.
+    if let Some(a) = matches.value_of("activity") {
+        let url = match synced_url(a, "activity_error") {
+            Some(u) => u,
+            None => return,
+        };
+        fmt_result(socket.send_command(DCommand::Activity(url)).unwrap());
+    }
+
+    // Handles ls command.
+    if matches.occurrences_of("ls") > 0 {
+        let folder = matches.value_of("ls").map(String::from);
+        match socket.send_command(DCommand::Ls(folder)).unwrap() {
+            DResult::Ok(listing) => print_listing(&listing),
+            r => {
+                fmt_result(r);
+                process::exit(1);
+            }
+        }
+    }
+
+    // Handles drives command. Lines are printed as is so scripts can parse them.
+    if matches.occurrences_of("drives") > 0 {
+        match socket.send_command(DCommand::Drives).unwrap() {
+            DResult::Ok(drives) if json_output() => {
+                let drives: Vec<serde_json::Value> = drives
+                    .lines()
+                    .map(|l| {
+                        let f: Vec<&str> = l.split('\t').collect();
+                        json!({"id": f[0], "name": f.get(1), "role": f.get(2)})
+                    })
+                    .collect();
+                println!("{}", json!({ "drives": drives }));
+            }
+            DResult::Ok(drives) => {
+                if !drives.is_empty() {
+                    println!("{}", drives);
+                }
+            }
+            r => {
+                fmt_result(r);
+                process::exit(1);
+            }
+        }
+    }
+
+    // Handles share command, optionally showing the link as a QR code.
+    if let Some(s) = matches.value_of("share") {
+        let url = match synced_url(s, "share_error") {
+            Some(u) => u,
+            None => return,
+        };
+        match socket.send_command(DCommand::Share(url)).unwrap() {
+            DResult::Ok(link) if json_output() => {
+                println!("{}", json!({"ok": true, "link": link}));
+            }
+            DResult::Ok(link) => {
+                if matches.is_present("qr") {
+                    if let Err(e) = print_qr(&link) {
+                        fmt_err("qr_error", e);
+                    }
+                }
+                println!("{}", link);
+            }
+            r => fmt_result(r),
+        }
+    }
+
+    // Handles shortcut command.
+    if let Some(v) = matches.values_of("shortcut") {
         let vals: Vec<&str> = v.collect();
-        let overwrite = matches.occurrences_of("overwrite") == 1;
+        let url = match synced_url(vals[0], "shortcut_error") {
+            Some(u) => u,
+            None => return,
+        };
+        let folder = vals.get(1).map(|f| f.to_string());
         fmt_result(
             socket
-                .send_command(DCommand::Pull(
-                    vals[0].to_string(),
-                    PathBuf::from(vals[1]),
-                    overwrite,
-                ))
+                .send_command(DCommand::Shortcut(url, folder))
                 .unwrap(),
         );
     }
 
     // Handles list command.
-    if matches.occurrences_of("list") > 0 {
+    if matches.occurrences_of("list") > 0 && json_output() {
+        print_list_json(&socket);
+    } else if matches.occurrences_of("list") > 0 {
         // Iterate all Trackedfiles and prettyprint them.
-        let files = TrackedFile::from_path(config_dir());
-        println!("Synced files:");
-        for tf in &files {
+        let files = TrackedFile::load_all();
+        let (states, live) = list_states(&socket, &files);
+        let replicas = replica().map(|(_, r)| r);
+        if live {
+            println!("Synced files:");
+        } else {
+            println!("Synced files (daemon stopped, as of its last run):");
+        }
+        for (tf, state) in files.iter().zip(states) {
+            // Exports only ever come down from Drive.
+            let export = match &tf.export {
+                Some(e) => format!(" (export, {})", e.format),
+                None => String::new(),
+            };
+            let name = match &tf.remote_name {
+                Some(n) => format!(" (as {:?})", n),
+                None => String::new(),
+            };
+            // On removable media that isn't mounted.
+            let absent = if tf.media.is_some() && !tf.path.exists() {
+                " (media absent)"
+            } else {
+                ""
+            };
+            // E.g. [error, synced 2020-06-01 09:30, changed locally: 503 Service Unavailable].
+            let mut sync = vec![state.as_str().to_string()];
+            sync.push(match tf.synced_at {
+                Some(t) => format!("synced {}", human_time(t)),
+                None => String::from("never synced"),
+            });
+            match in_sync(tf) {
+                Some(true) => sync.push(String::from("in sync")),
+                Some(false) => sync.push(String::from("changed locally")),
+                None => {}
+            }
+            if let Some(state) = replicas.as_ref().and_then(|r| r.state_of(tf)) {
+                sync.push(format!("replica {}", state.as_str()));
+            }
+            let error = match &tf.last_error {
+                Some(e) => format!(": {}", e),
+                None => String::new(),
+            };
             println!(
-                "{green}{:?}{end} {blue}->{end} {green}{:?}{end}",
+                "{green}{:?}{end} {blue}{arrow}{end} {green}{:?}{end}{}{}{} [{}{}]",
                 tf.path,
                 tf.drive_url,
+                name,
+                export,
+                absent,
+                sync.join(", "),
+                error,
+                arrow = if tf.is_export() { "<-" } else { "->" },
+                green = ANSI_GREEN,
+                blue = ANSI_BLUE,
+                end = ANSI_RESET
+            );
+        }
+        let dirs = tracked_dirs();
+        if !dirs.is_empty() {
+            println!("Pushing new files in:");
+        }
+        for d in &dirs {
+            println!(
+                "{green}{:?}{end} {blue}->{end} {green}{}{end}",
+                d.path,
+                d.dest.as_deref().unwrap_or("the upload folder"),
                 green = ANSI_GREEN,
                 blue = ANSI_BLUE,
                 end = ANSI_RESET
@@ -286,17 +1688,42 @@ fn main() {
     }
 
     // Handle sync command.
-    if let Some(v) = matches.values_of("sync") {
-        let vals: Vec<&str> = v.collect();
+    if let Some(v) = matches.values_of_os("sync") {
+        let vals: Vec<&OsStr> = v.collect();
+        let events = match matches.value_of("events").map(WatchEvent::parse_list) {
+            Some(Err(e)) => {
+                fmt_err("sync_err", format!("--events: {}", e));
+                process::exit(1);
+            }
+            Some(Ok(events)) => Some(events),
+            None => None,
+        };
         fmt_result(
             socket
-                .send_command(DCommand::FSync(PathBuf::from(vals[0]), vals[1].to_string()))
+                .send_command(DCommand::FSync(
+                    PathBuf::from(vals[0]),
+                    vals[1].to_string_lossy().into_owned(),
+                    events,
+                ))
+                .unwrap(),
+        )
+    }
+
+    // Handle rename-remote command.
+    if let Some(v) = matches.values_of_os("rename-remote") {
+        let vals: Vec<&OsStr> = v.collect();
+        fmt_result(
+            socket
+                .send_command(DCommand::RenameRemote(
+                    PathBuf::from(vals[0]),
+                    vals[1].to_string_lossy().into_owned(),
+                ))
                 .unwrap(),
         )
     }
 
     // Handle unsync command.
-    if let Some(p) = matches.value_of("unsync") {
+    if let Some(p) = matches.value_of_os("unsync") {
         fmt_result(
             socket
                 .send_command(DCommand::FUnSync(PathBuf::from(p)))
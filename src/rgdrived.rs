@@ -2,57 +2,146 @@
 #[macro_use]
 extern crate log;
 
-mod lib;
-use lib::{DCommand, DResult, Tracker, SOCKET_PATH};
+use rgdrive::batch::{self, Batch};
+use rgdrive::cache;
+use rgdrive::capabilities::{self, Capability, CAPABILITIES};
+use rgdrive::checksum;
+use rgdrive::clipboard;
+use rgdrive::coalesce::Coalescer;
+use rgdrive::config::{Config, DeletePolicy, Limits, Thresholds};
+use rgdrive::crash;
+use rgdrive::daemons::{self, Instance, Reason, StartupError};
+use rgdrive::debounce::Debouncer;
+use rgdrive::environment::ENVIRONMENT;
+use rgdrive::exclude;
+use rgdrive::export::Export;
+use rgdrive::health::{HEALTH, REAUTH_REQUIRED};
+use rgdrive::hooks;
+use rgdrive::journal::{self, Direction, Entry};
+use rgdrive::media;
+use rgdrive::names;
+use rgdrive::paths::{PATHS, ROOT_ID};
+use rgdrive::pins::{Pin, PINS};
+use rgdrive::placeholder;
+use rgdrive::plan::{human_bytes, human_time, Plan};
+use rgdrive::poll::{tracked_changes, Change, Inbound, Poller};
+use rgdrive::pool::{self, Pool};
+use rgdrive::queue::QUEUE;
+use rgdrive::rawpath;
+use rgdrive::remote::{drive_id, FileChange, Metadata, Remote, SharedRemote, FOLDER_MIME};
+use rgdrive::replica::Replicas;
+use rgdrive::review::STAGED;
+use rgdrive::session::Patient;
+use rgdrive::shutdown::{self, SHUTDOWN};
+use rgdrive::stamp;
+use rgdrive::stats::{Stats, LATENCY, STATS};
+use rgdrive::status::{self, CHANGED, FAILURES, UPLOADING};
+use rgdrive::transfer::{
+    self, check_overwrite, preserve_before_overwrite, push_paths, resolve_shortcut,
+    restore_trashed, upload, upload_folder, upload_hashed, vanished, ConnectError, Overwrite,
+};
+use rgdrive::versions;
+use rgdrive::watchdog;
+use rgdrive::window::Window;
+use rgdrive::{
+    canonical_path, daemons_dir, shared_inode, socket_path, state_path, DCommand, DResult, DSocket,
+    ProtocolError, TrackedDir, TrackedFile, Tracker, WatchEvent, ACCEPT_ZSTD,
+};
 
-use std::env;
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Error;
+use std::io::{self, Error, Read};
+use std::mem;
+use std::net::Shutdown;
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::process;
+use std::process::{self, Command};
 
+use std::sync::mpsc::{self, SyncSender, TrySendError};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use google_api::Drive;
-use inotify::EventMask;
+use chrono::Utc;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
 
-// Returns a list of all subpaths in given path. Recursive.
-fn get_subpaths(p: &PathBuf) -> Vec<PathBuf> {
-    let mut paths: Vec<PathBuf> = Vec::new();
-    for entry in fs::read_dir(p).unwrap() {
-        let path = entry.unwrap().path();
-        if path.is_dir() {
-            paths.extend(get_subpaths(&path));
-        } else if path.is_file() {
-            paths.push(path);
-        }
+// Record an operation in the journal. A failed journal write is logged but never fails the operation itself.
+fn journal(
+    op: &str,
+    path: &Path,
+    drive_url: &str,
+    direction: Direction,
+    result: Result<(), String>,
+) {
+    record(Entry::new(op, path, drive_url, direction, result));
+}
+
+// journal() for an entry made by the caller, e.g. with the upload's latency.
+fn record(entry: Entry) {
+    HEALTH.record(entry.is_ok());
+    FAILURES.record(&entry.path, entry.is_ok());
+    if !entry.is_ok() && transfer::token_revoked(&entry.result) && HEALTH.auth_failed(&entry.result)
+    {
+        error!(
+            "Drive refused the token ({}), sync is paused until `rgdrive --login`.",
+            entry.result
+        );
+    }
+    if let Err(e) = journal::record(&entry) {
+        error!("Error writing journal entry for {:?}: {:?}", entry.path, e);
     }
-    paths
 }
 
 fn pull(
     drive_url: String,
     path: PathBuf,
-    overwrite: bool,
+    overwrite: Overwrite,
+    relink: bool,
     tracker: Arc<Mutex<Tracker>>,
-    drive: Arc<Mutex<Drive>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
 ) -> Result<DResult, Error> {
+    // A shortcut is only a pointer, what's pulled and synced is the file it points to.
+    let drive_url = resolve_shortcut(&mut **drive.lock(), &drive_url);
+    // Pulled files get synced back up on modify, so they have to pass policy too.
+    if let Err(e) = config.policy.permits(&mut **drive.lock(), &drive_url) {
+        warn!("{}", e);
+        return Ok(DResult::error(e));
+    }
+
+    // Pulls into a directory keep the remote name. It's worked out first, so the checks below see the file that'd be
+    // replaced, and a name only differing in case from one already there doesn't replace that one (see names::dest_in).
+    let path = if path.is_dir() {
+        pull_dest(&path, &drive_url, &drive).unwrap_or(path)
+    } else {
+        path
+    };
+
     // Check if destination path exists, if it does check if we can overwrite it.
     if path.is_file() {
-        if path.exists() && !overwrite {
-            return Ok(
-                DResult::error(
-                    format!("Destination {:?} exists but no overwrite flag specified. Rerun with --overwrite to force destination path overwrite.", path)
-                )
-            );
+        let synced = tracker
+            .lock()
+            .unwrap()
+            .find_by_path(&canonical_path(&path))
+            .and_then(|tf| tf.md5.clone());
+        let allowed = check_overwrite(
+            overwrite,
+            &path,
+            &drive_url,
+            synced.as_deref(),
+            &mut **drive.lock(),
+        );
+        if let Err(e) = allowed {
+            return Ok(DResult::error(e));
         }
     } else {
-        // Is a dir and doesn't exist, return err.
-        if path.extension() == None && !path.is_dir() {
+        // Is a dir and doesn't exist, return err. A new file without an extension (Makefile, or from --pull-dir) is fine
+        // as long as the directory it goes in is there.
+        let parent_exists = path.parent().map(|p| p.is_dir()).unwrap_or(false);
+        if path.extension().is_none() && !path.is_dir() && !parent_exists {
             return Ok(DResult::error(format!(
                 "Destiation {:?} doesn't exist.",
                 path
@@ -60,15 +149,54 @@ fn pull(
         }
     }
 
-    match drive.lock().unwrap().download_file(&drive_url, path) {
-        Ok(path) => {
+    // A path synced with another Drive file is only re-linked to this one when asked to.
+    if let Some(dest) = pull_dest(&path, &drive_url, &drive) {
+        if let Some(other) = synced_elsewhere(&tracker, &dest, &drive_url) {
+            if !relink {
+                return Ok(DResult::error(format!(
+                    "{:?} is already synced with {}. Rerun with --relink to sync it with {} instead.",
+                    dest, other, drive_url
+                )));
+            }
+            info!("Re-linking {:?} from {} to {}.", dest, other, drive_url);
+        }
+    }
+
+    let trashed = match preserve_before_overwrite(&path, &config) {
+        Ok(t) => t,
+        Err(e) => return Ok(DResult::error(e)),
+    };
+
+    let result = drive.lock().download_hashed(&drive_url, &path);
+    match result {
+        Ok((path, md5)) => {
             info!("Downloaded {} successfully.", drive_url);
-            // Add path to tracker.
-            tracker.lock().unwrap().add_path(path, &drive_url)?;
-            Ok(DResult::ok(format!("Pulled {} successfully.", drive_url)))
+            journal("pull", &path, &drive_url, Direction::Down, Ok(()));
+            // Add path to tracker. A replaced file's watch followed the old copy into the trash, so it's watched again,
+            // and a path synced with another file now holds (and syncs with) this one.
+            let relinked = synced_elsewhere(&tracker, &path, &drive_url).is_some();
+            let mut tracker = tracker.lock().unwrap();
+            if trashed.is_some() || relinked {
+                tracker.remove_path(&path)?;
+            }
+            tracker.add_path(&path, &drive_url)?;
+            tracker.mark_synced_as(&path, md5)?;
+            Ok(DResult::fields(
+                format!("Pulled {} successfully.", drive_url),
+                &[("path", rawpath::escape(&path)), ("drive_url", drive_url)],
+            ))
         }
         Err(e) => {
+            restore_trashed(trashed, &path);
             error!("Error downloading {}: {:?}", drive_url, e);
+            journal(
+                "pull",
+                &path,
+                &drive_url,
+                Direction::Down,
+                Err(e.to_string()),
+            );
+            sync_failed(&tracker, &path, &e.to_string());
             Ok(DResult::error(format!(
                 "Error downloading {}: {:?}. See log for more information,",
                 drive_url, e
@@ -77,11 +205,663 @@ fn pull(
     }
 }
 
-// Push given path to Google Drive, and add it to the Inotify watchlist.
+// The file a pull of drive_url to path writes. Pulls into a directory keep the remote name, None if it can't be looked up.
+fn pull_dest(path: &Path, drive_url: &str, drive: &SharedRemote) -> Option<PathBuf> {
+    if !path.is_dir() {
+        return Some(path.to_path_buf());
+    }
+    let id = drive_id(drive_url)?;
+    match drive.lock().metadata(id) {
+        Ok(m) => Some(names::dest_in(path, &m.name, id)),
+        _ => None,
+    }
+}
+
+// The url path is synced with, if it's a different Drive file than drive_url.
+fn synced_elsewhere(tracker: &Arc<Mutex<Tracker>>, path: &Path, drive_url: &str) -> Option<String> {
+    tracker
+        .lock()
+        .unwrap()
+        .find_by_path(path)
+        .filter(|tf| drive_id(&tf.drive_url) != drive_id(drive_url))
+        .map(|tf| tf.drive_url.clone())
+}
+
+// Export drive_url (a Doc, Sheet or Slides file) to path. Exports are tracked with their format and source mimeType
+// so the poller can re-export them when the remote file changes, but are never uploaded.
+fn export_file(
+    drive_url: String,
+    path: PathBuf,
+    overwrite: bool,
+    export: Export,
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
+) -> Result<DResult, Error> {
+    if path.is_file() && !overwrite {
+        return Ok(DResult::error(format!(
+            "Destination {:?} exists but no overwrite flag specified. Rerun with --overwrite to force destination path overwrite.",
+            path
+        )));
+    }
+    let id = match drive_id(&drive_url) {
+        Some(id) => id,
+        None => {
+            return Ok(DResult::error(format!(
+                "{:?} is not a drive url.",
+                drive_url
+            )))
+        }
+    };
+
+    // The format has to be checked against what the remote file actually is.
+    let mime_type = match drive.lock().metadata(id) {
+        Ok(m) => m.mime_type,
+        Err(e) => {
+            return Ok(DResult::error(format!(
+                "Error exporting {}: {}",
+                drive_url, e
+            )))
+        }
+    };
+    if let Err(e) = export.validate(&mime_type) {
+        return Ok(DResult::error(e));
+    }
+
+    let trashed = match preserve_before_overwrite(&path, &config) {
+        Ok(t) => t,
+        Err(e) => return Ok(DResult::error(e)),
+    };
+
+    let result = drive.lock().export(id, &export, &path);
+    match result {
+        Ok(dest) => {
+            info!("Exported {} to {:?} as {}.", drive_url, dest, export.format);
+            journal("export", &dest, &drive_url, Direction::Down, Ok(()));
+            tracker.lock().unwrap().add_export(TrackedFile {
+                export: Some(export),
+                mime_type: Some(mime_type),
+                ..TrackedFile::new(dest.clone(), drive_url.clone())
+            })?;
+            Ok(DResult::ok(format!(
+                "Exported {} to {:?}.",
+                drive_url, dest
+            )))
+        }
+        Err(e) => {
+            restore_trashed(trashed, &path);
+            error!("Error exporting {}: {}", drive_url, e);
+            journal(
+                "export",
+                &path,
+                &drive_url,
+                Direction::Down,
+                Err(e.to_string()),
+            );
+            Ok(DResult::error(format!(
+                "Error exporting {}: {}",
+                drive_url, e
+            )))
+        }
+    }
+}
+
+// Give anyone with the link read access to drive_url. The response is just the link, so the client can render it.
+fn share(
+    drive_url: String,
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+) -> Result<DResult, Error> {
+    let id = match drive_id(&drive_url) {
+        Some(id) => id,
+        None => {
+            return Ok(DResult::error(format!(
+                "{:?} is not a drive url.",
+                drive_url
+            )))
+        }
+    };
+    let path = tracker
+        .lock()
+        .unwrap()
+        .tracked_files
+        .iter()
+        .find(|tf| tf.drive_url == drive_url)
+        .map(|tf| tf.path.clone())
+        .unwrap_or_default();
+    let result = drive.lock().share(id);
+    journal(
+        "share",
+        &path,
+        &drive_url,
+        Direction::None,
+        result.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+    );
+    match result {
+        Ok(link) => {
+            info!("Shared {} by link.", drive_url);
+            Ok(DResult::ok(link))
+        }
+        Err(e) => {
+            error!("Error sharing {}: {}", drive_url, e);
+            Ok(DResult::error(format!(
+                "Error sharing {}: {}",
+                drive_url, e
+            )))
+        }
+    }
+}
+
+// Put a shortcut to drive_url in folder_url (the usual upload folder if None), named like the file it points to.
+fn shortcut(
+    drive_url: String,
+    folder_url: Option<String>,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Config,
+) -> DResult {
+    let id = match drive_id(&drive_url) {
+        Some(id) => id,
+        None => return DResult::error(format!("{:?} is not a drive url.", drive_url)),
+    };
+    let mut remote = drive.lock();
+    let name = match remote.metadata(id) {
+        Ok(m) => m.name,
+        Err(e) => return DResult::error(format!("Error looking up {}: {}", drive_url, e)),
+    };
+    let folder = match upload_folder(&mut **remote, folder_url.as_deref(), config) {
+        Ok(f) => f.unwrap_or_else(|| ROOT_ID.to_string()),
+        Err(e) => return DResult::error(e),
+    };
+    let result = remote.create_shortcut(id, &name, &folder);
+    drop(remote);
+    let path = tracker
+        .lock()
+        .unwrap()
+        .tracked_files
+        .iter()
+        .find(|tf| tf.drive_url == drive_url)
+        .map(|tf| tf.path.clone())
+        .unwrap_or_default();
+    journal(
+        "shortcut",
+        &path,
+        &drive_url,
+        Direction::None,
+        result.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+    );
+    match result {
+        Ok(shortcut) => {
+            let url = format!("https://drive.google.com/open?id={}", shortcut);
+            info!("Created shortcut {} to {}.", url, drive_url);
+            DResult::fields(
+                format!("Created shortcut {} to {}.", url, drive_url),
+                &[("shortcut_url", url), ("drive_url", drive_url)],
+            )
+        }
+        Err(e) => {
+            error!("Error creating a shortcut to {}: {}", drive_url, e);
+            DResult::error(format!("Error creating a shortcut to {}: {}", drive_url, e))
+        }
+    }
+}
+
+// What's in the Drive folder at folder_url (the Drive root if None), folders first, then by name. Shortcuts are listed
+// under their own name with the size and modified time of what they point to.
+fn ls(folder_url: Option<String>, drive: &SharedRemote, config: &Config) -> DResult {
+    let id = match &folder_url {
+        Some(url) => match drive_id(url) {
+            Some(id) => id,
+            None => return DResult::error(format!("{:?} is not a drive folder url.", url)),
+        },
+        None => ROOT_ID,
+    };
+    if let Some(url) = &folder_url {
+        if let Err(e) = config.policy.permits(&mut **drive.lock(), url) {
+            return DResult::error(e);
+        }
+    }
+    let listed = drive.lock().list_folder(id);
+    let mut files = match listed {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Error listing Drive folder {}: {}", id, e);
+            return DResult::error(format!("Error listing Drive folder {}: {}", id, e));
+        }
+    };
+    files.sort_by_key(|m| {
+        (
+            m.mime_type != FOLDER_MIME,
+            names::nfc(&m.name).to_lowercase(),
+        )
+    });
+    let lines: Vec<String> = files
+        .iter()
+        .map(|m| {
+            let (size, modified) = if m.is_shortcut() {
+                match shortcut_target(m, drive) {
+                    Ok(t) => (t.size, t.modified),
+                    Err(_) => (m.size, m.modified),
+                }
+            } else {
+                (m.size, m.modified)
+            };
+            let name: String = m
+                .name
+                .chars()
+                .map(|c| if c == '\t' || c == '\n' { ' ' } else { c })
+                .collect();
+            format!(
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                m.id,
+                name,
+                m.kind(),
+                size,
+                modified,
+                m.target.as_deref().unwrap_or("")
+            )
+        })
+        .collect();
+    DResult::ok(lines.join("\n"))
+}
+
+// A Drive name as a local file name. Drive allows / (and . or ..) in names, which would land somewhere else.
+fn local_name(name: &str) -> String {
+    match name {
+        "" | "." | ".." => format!("_{}", name),
+        n => n.replace('/', "_"),
+    }
+}
+
+// Recreate the Drive folder at folder_url as dir (created if needed): every subfolder is made locally and every file
+// is pulled and synced, or only gets a placeholder (see placeholder) if placeholders is set. Files already synced or
+// already present are skipped, so it's safe to rerun as the folder grows. Docs editors files have no content to
+// download and are skipped too, see --export-format. Shortcuts are pulled as the file or folder they point to.
+fn pull_dir(
+    folder_url: String,
+    dir: PathBuf,
+    placeholders: bool,
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
+) -> Result<DResult, Error> {
+    let root = match drive_id(&folder_url) {
+        Some(id) => id.to_string(),
+        None => {
+            return Ok(DResult::error(format!(
+                "{:?} is not a drive folder url.",
+                folder_url
+            )))
+        }
+    };
+    if let Err(e) = config.policy.permits(&mut **drive.lock(), &folder_url) {
+        warn!("{}", e);
+        return Ok(DResult::error(e));
+    }
+
+    let (mut pulled, mut folders, mut skipped, mut docs, mut failed) = (0, 0, 0, 0, 0);
+    let (mut held, mut linked) = (0, 0);
+    // Drive files can have several parents, so a folder could turn up twice.
+    let mut seen: HashSet<String> = HashSet::new();
+    // Where each folder pulled so far went, for shortcuts to them.
+    let mut dirs: HashMap<String, PathBuf> = HashMap::new();
+    let mut pending = vec![(root, dir.clone())];
+    while let Some((id, local)) = pending.pop() {
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        fs::create_dir_all(&local)?;
+        dirs.insert(id.clone(), local.clone());
+        let listed = drive.lock().list_folder(&id);
+        let files = match listed {
+            Ok(f) => f,
+            Err(e) => {
+                error!("Error listing Drive folder {}: {}", id, e);
+                failed += 1;
+                continue;
+            }
+        };
+        // Drive name, local path and id of each file to download.
+        let mut downloads: Vec<(String, PathBuf, String)> = Vec::new();
+        for m in files {
+            let name = local_name(&m.name);
+            // Files whose names only differ in case, or not at all, are pulled one after the other so dest_in sees the
+            // earlier one.
+            let folded = names::nfc(&name).to_lowercase();
+            let clash = downloads.iter().any(|(_, p, _)| {
+                names::nfc(&p.file_name().unwrap_or_default().to_string_lossy()).to_lowercase()
+                    == folded
+            });
+            if clash {
+                let (p, f) = pull_all(
+                    mem::take(&mut downloads),
+                    &folder_url,
+                    &tracker,
+                    &drive,
+                    &config,
+                )?;
+                pulled += p;
+                failed += f;
+            }
+            let path = names::dest_in(&local, &name, &m.id);
+            // A shortcut comes down as what it points to, under the shortcut's name. Or as a symlink to the local copy
+            // of it, see [shortcuts].
+            let m = if m.is_shortcut() {
+                let target = match shortcut_target(&m, &drive) {
+                    Ok(t) => t,
+                    Err(e) => {
+                        warn!(
+                            "Failed to look up the target of shortcut {:?}: {}",
+                            m.name, e
+                        );
+                        failed += 1;
+                        continue;
+                    }
+                };
+                let local_copy = if config.shortcuts.symlinks {
+                    local_copy_of(&target, &tracker, &dirs).or_else(|| {
+                        downloads
+                            .iter()
+                            .find(|(_, _, id)| *id == target.id)
+                            .map(|(_, p, _)| p.clone())
+                    })
+                } else {
+                    None
+                };
+                if let Some(to) = local_copy {
+                    if fs::symlink_metadata(&path).is_ok() {
+                        skipped += 1;
+                    } else {
+                        std::os::unix::fs::symlink(&to, &path)?;
+                        debug!("Linked shortcut {:?} to {:?}.", path, to);
+                        linked += 1;
+                    }
+                    continue;
+                }
+                target
+            } else {
+                m
+            };
+            if m.mime_type == FOLDER_MIME {
+                folders += 1;
+                pending.push((m.id, path));
+                continue;
+            }
+            if m.mime_type.starts_with("application/vnd.google-apps.") {
+                debug!("Not pulling Docs editors file {:?}.", m.name);
+                docs += 1;
+                continue;
+            }
+            let synced = tracker
+                .lock()
+                .unwrap()
+                .tracked_files
+                .iter()
+                .any(|tf| drive_id(&tf.drive_url) == Some(m.id.as_str()));
+            if synced || path.exists() {
+                skipped += 1;
+                continue;
+            }
+            if placeholders {
+                match placeholder::create(&path, &m.id, m.size) {
+                    Ok(()) => held += 1,
+                    Err(e) => {
+                        warn!("Failed to create a placeholder for {:?}: {}", path, e);
+                        failed += 1;
+                    }
+                }
+                continue;
+            }
+            downloads.push((m.name, path, m.id));
+        }
+        let (p, f) = pull_all(downloads, &folder_url, &tracker, &drive, &config)?;
+        pulled += p;
+        failed += f;
+    }
+
+    let mut msg = format!(
+        "Folder {:?}: {} pulled, {} already present, {} Docs editors files skipped, {} failed, {} subfolders.",
+        dir, pulled, skipped, docs, failed, folders
+    );
+    if placeholders {
+        msg.push_str(&format!(
+            " {} placeholders created, `rgdrive hydrate` downloads them.",
+            held
+        ));
+    }
+    if linked > 0 {
+        msg.push_str(&format!(" {} shortcuts linked.", linked));
+    }
+    info!("{}", msg);
+    if failed > 0 {
+        Ok(DResult::error(msg))
+    } else {
+        Ok(DResult::ok(msg))
+    }
+}
+
+// The file or folder the shortcut m points to.
+fn shortcut_target(m: &Metadata, drive: &SharedRemote) -> Result<Metadata, String> {
+    let id = m
+        .target
+        .as_deref()
+        .ok_or("Drive didn't say what it points to")?;
+    match drive.lock().metadata(id) {
+        Ok(t) => Ok(t),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Where the Drive file or folder m already is locally: a synced file, or a folder pulled into dirs.
+fn local_copy_of(
+    m: &Metadata,
+    tracker: &Arc<Mutex<Tracker>>,
+    dirs: &HashMap<String, PathBuf>,
+) -> Option<PathBuf> {
+    if m.mime_type == FOLDER_MIME {
+        return dirs.get(&m.id).cloned();
+    }
+    tracker
+        .lock()
+        .unwrap()
+        .tracked_files
+        .iter()
+        .find(|tf| !tf.is_export() && !tf.absent && drive_id(&tf.drive_url) == Some(m.id.as_str()))
+        .map(|tf| tf.path.clone())
+}
+
+// Pull each (Drive name, local path, id) of a --pull-dir, [transfers] concurrency at a time like directory pushes.
+// Returns how many were pulled and how many failed.
+fn pull_all(
+    downloads: Vec<(String, PathBuf, String)>,
+    folder_url: &str,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Arc<Config>,
+) -> Result<(usize, usize), Error> {
+    let files = downloads.iter().map(|(_, p, id)| (p.clone(), id.clone()));
+    let results = {
+        let (tracker, drive, config) = (Arc::clone(tracker), Arc::clone(drive), Arc::clone(config));
+        pool::each(
+            files.collect(),
+            config.transfers.concurrency,
+            move |(path, id)| {
+                pull(
+                    format!("https://drive.google.com/open?id={}", id),
+                    path,
+                    Overwrite::Never,
+                    false,
+                    Arc::clone(&tracker),
+                    Arc::clone(&drive),
+                    Arc::clone(&config),
+                )
+            },
+        )
+    };
+    let (mut pulled, mut failed) = (0, 0);
+    for ((name, _, _), result) in downloads.into_iter().zip(results) {
+        match result? {
+            DResult::Err(e) => {
+                warn!("Failed to pull {:?} from {}: {}", name, folder_url, e);
+                failed += 1;
+            }
+            _ => pulled += 1,
+        }
+    }
+    Ok((pulled, failed))
+}
+
+// Download what the placeholders at or beneath path stand for, and sync them from then on.
+fn hydrate(
+    path: PathBuf,
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
+) -> Result<DResult, Error> {
+    let found = placeholder::find(&path);
+    if found.is_empty() {
+        return Ok(DResult::error(format!(
+            "No placeholders at {:?}, nothing to hydrate.",
+            path
+        )));
+    }
+    let (mut hydrated, mut bytes, mut failed) = (0, 0, Vec::new());
+    let mut downloaded = Vec::new();
+    for p in found {
+        let id = match placeholder::drive_id_of(&p) {
+            Some(id) => id,
+            None => continue,
+        };
+        let size = placeholder::size_of(&p).unwrap_or(0);
+        // The placeholder makes way for the download, and is put back if it fails so it can be retried.
+        fs::remove_file(&p)?;
+        let url = format!("https://drive.google.com/open?id={}", id);
+        let r = pull(
+            url,
+            p.clone(),
+            Overwrite::Never,
+            false,
+            Arc::clone(&tracker),
+            Arc::clone(&drive),
+            Arc::clone(&config),
+        );
+        match r {
+            Ok(DResult::Err(e)) => {
+                placeholder::create(&p, &id, size)?;
+                failed.push(format!("{:?}: {}", p, e));
+            }
+            Ok(_) => {
+                hydrated += 1;
+                bytes += size;
+                downloaded.push(canonical_path(&p));
+            }
+            Err(e) => {
+                placeholder::create(&p, &id, size)?;
+                return Err(e);
+            }
+        }
+    }
+    // Room is made for them right away, out of the files used least recently.
+    let (evicted, cached) = evict(&tracker, &config, &downloaded);
+    let mut msg = format!("Hydrated {} files ({} bytes).", hydrated, bytes);
+    if evicted > 0 {
+        msg.push_str(&format!(" Evicted {} to make room.", evicted));
+    }
+    if cached > config.cache.max_bytes && config.cache.max_bytes > 0 {
+        msg.push_str(&format!(
+            " The cache still takes up {}, more than cache.max_bytes ({}).",
+            human_bytes(cached),
+            human_bytes(config.cache.max_bytes)
+        ));
+    }
+    info!("{}", msg);
+    if failed.is_empty() {
+        Ok(DResult::ok(msg))
+    } else {
+        Ok(DResult::error(format!(
+            "{} Failed: {}",
+            msg,
+            failed.join(", ")
+        )))
+    }
+}
+
+// Pull every starred Drive file into dir. Files already synced, or with something already at their destination, are
+// skipped so this can be rerun whenever the set of starred files changes.
+fn pull_starred(
+    dir: PathBuf,
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
+) -> Result<DResult, Error> {
+    if !dir.is_dir() {
+        return Ok(DResult::error(format!(
+            "Destination {:?} is not a directory.",
+            dir
+        )));
+    }
+    let starred = match drive.lock().starred() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Error listing starred files: {}", e);
+            return Ok(DResult::error(format!(
+                "Error listing starred files: {}",
+                e
+            )));
+        }
+    };
+
+    let (mut pulled, mut skipped, mut failed) = (0, 0, 0);
+    for m in starred {
+        let url = format!("https://drive.google.com/open?id={}", m.id);
+        let synced = tracker
+            .lock()
+            .unwrap()
+            .tracked_files
+            .iter()
+            .any(|tf| drive_id(&tf.drive_url) == Some(m.id.as_str()));
+        if synced || names::find_in(&dir, &m.name).is_some() {
+            skipped += 1;
+            continue;
+        }
+        match pull(
+            url,
+            dir.clone(),
+            Overwrite::Never,
+            false,
+            Arc::clone(&tracker),
+            Arc::clone(&drive),
+            Arc::clone(&config),
+        )? {
+            DResult::Err(e) => {
+                warn!("Failed to pull starred file {:?}: {}", m.name, e);
+                failed += 1;
+            }
+            _ => pulled += 1,
+        }
+    }
+
+    let msg = format!(
+        "Starred files: {} pulled, {} already present, {} failed.",
+        pulled, skipped, failed
+    );
+    if failed > 0 {
+        Ok(DResult::error(msg))
+    } else {
+        Ok(DResult::ok(msg))
+    }
+}
+
+// Push given path to Google Drive (into the dest folder url if given, named name if given), and add it to the Inotify
+// watchlist.
 fn push(
     path: PathBuf,
+    dest: Option<String>,
+    name: Option<String>,
+    excludes: bool,
     tracker: Arc<Mutex<Tracker>>,
-    drive: Arc<Mutex<Drive>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
 ) -> Result<DResult, Error> {
     if !path.exists() {
         return Ok(DResult::error(format!(
@@ -89,117 +869,724 @@ fn push(
             path
         )));
     }
+    if name.is_some() && path.is_dir() {
+        return Ok(DResult::error(format!(
+            "Cannot push {:?} under another name: only single files can be renamed on Drive.",
+            path
+        )));
+    }
+    // Symlinks and other spellings of a synced file are the same file, it isn't uploaded again.
+    if let Some(tf) = tracker.lock().unwrap().find_by_path(&path) {
+        return Ok(DResult::fields(
+            format!("{:?} is already synced with {}.", tf.path, tf.drive_url),
+            &[
+                ("path", rawpath::escape(&tf.path)),
+                ("drive_url", tf.drive_url.clone()),
+            ],
+        ));
+    }
+    // So is a hard link to one.
+    let linked = tracker
+        .lock()
+        .unwrap()
+        .hard_link_of(&path)
+        .map(|tf| (tf.path.clone(), tf.drive_url.clone()));
+    if let Some((other, url)) = linked {
+        return Ok(link_tracked(&path, &other, url, &tracker));
+    }
+    let result = upload_folder(&mut **drive.lock(), dest.as_deref(), &config);
+    let folder = match result {
+        Ok(f) => f,
+        Err(e) => {
+            warn!("{}", e);
+            return Ok(DResult::error(e));
+        }
+    };
 
-    // If given path is a dir, upload everything in it.
+    // If given path is a dir, upload everything in it. Uploads are recorded in a batch and tracked together at the end,
+    // so an error part way through never leaves some of the uploaded files tracked and others not.
     if path.is_dir() {
-        let (mut success, mut error): (u16, u16) = (0, 0);
-        // Get all subpaths of given dir. Attempt to add them all and keep track of # fails/successes.
-        for p in get_subpaths(&path) {
-            match drive.lock().unwrap().upload_file(&p) {
-                Ok(url) => {
+        let mut batch = Batch::begin();
+        let mut error: usize = 0;
+        // Get all subpaths of given dir. Attempt to upload them all and keep track of # fails/successes.
+        let (paths, skipped) = push_paths(&path, excludes, &config.excludes.ignore);
+        if skipped > 0 {
+            info!("Skipping {} excluded files in {:?}", skipped, path);
+        }
+        // Files to upload, and hard links to a file that's uploaded with them. Only the first link of each inode is
+        // uploaded, the others go with the same Drive file.
+        let mut uploads: Vec<PathBuf> = Vec::new();
+        let mut links: Vec<(PathBuf, (u64, u64))> = Vec::new();
+        let mut inodes: HashSet<(u64, u64)> = HashSet::new();
+        for p in paths {
+            if tracker.lock().unwrap().find_by_path(&p).is_some() {
+                debug!("{:?} is already synced, not pushing it again.", p);
+                continue;
+            }
+            let linked = tracker
+                .lock()
+                .unwrap()
+                .hard_link_of(&p)
+                .map(|tf| tf.drive_url.clone());
+            if let Some(url) = linked {
+                info!(
+                    "{:?} is a hard link to a synced file, syncing it with {}.",
+                    p, url
+                );
+                if let Err(e) = batch.record(&p, &url, None) {
+                    error!("Error recording {:?} in pending batch: {:?}", p, e);
+                }
+                continue;
+            }
+            match shared_inode(&p) {
+                Some(i) if !inodes.insert(i) => links.push((p, i)),
+                _ => uploads.push(p),
+            }
+        }
+
+        // Uploaded [transfers] concurrency at a time, each on its own connection.
+        let uploaded = {
+            let (drive, config, folder) = (Arc::clone(&drive), Arc::clone(&config), folder.clone());
+            pool::each(uploads.clone(), config.transfers.concurrency, move |p| {
+                hooks::pre_upload(&config.hooks.pre_upload, &p).and_then(|_| {
+                    upload_hashed(&mut **drive.lock(), &p, folder.as_deref())
+                        .map_err(|e| e.to_string())
+                })
+            })
+        };
+        // Drive files of the hard linked files uploaded, and what was uploaded, by inode.
+        let mut urls: HashMap<(u64, u64), (String, Option<String>)> = HashMap::new();
+        for (p, uploaded) in uploads.into_iter().zip(uploaded) {
+            match uploaded {
+                Ok((url, md5)) => {
                     info!("Uploaded {:?}: {:?}", p, url);
-                    match tracker.lock().unwrap().add_path(&p, &url) {
-                        Ok(_) => {
-                            info!("Added {:?} to tracker", p);
-                            success += 1;
-                        }
-                        Err(e) => {
-                            error!("Error adding {:?} to tracker: {:?}", p, e);
-                            error += 1;
-                        }
+                    journal("push", &p, &url, Direction::Up, Ok(()));
+                    if let Some(i) = shared_inode(&p) {
+                        urls.insert(i, (url.clone(), md5.clone()));
                     }
+                    if let Err(e) = batch.record(&p, &url, md5) {
+                        error!("Error recording {:?} in pending batch: {:?}", p, e);
+                    }
+                }
+                Err(_) if vanished(&p) => {
+                    info!(
+                        "{:?} was deleted before it could be pushed, skipping it.",
+                        p
+                    );
+                    journal("vanished", &p, "", Direction::None, Ok(()));
                 }
                 Err(e) => {
-                    error!("Error pushing {:?}: {:?}", p, e);
+                    error!("Error pushing {:?}: {}", p, e);
+                    journal("push", &p, "", Direction::Up, Err(e));
+                    error += 1;
+                }
+            }
+        }
+        for (p, inode) in links {
+            match urls.get(&inode) {
+                Some((url, md5)) => {
+                    info!(
+                        "{:?} is a hard link to a synced file, syncing it with {}.",
+                        p, url
+                    );
+                    if let Err(e) = batch.record(&p, url, md5.clone()) {
+                        error!("Error recording {:?} in pending batch: {:?}", p, e);
+                    }
+                }
+                None => {
+                    error!("Not pushing {:?}, pushing a hard link to it failed.", p);
                     error += 1;
-                    continue;
                 }
             }
         }
+        let uploaded = batch.len();
+        if let Err(e) = batch.commit(&mut tracker.lock().unwrap()) {
+            error!("Error tracking pushed files from {:?}: {:?}", path, e);
+            return Ok(DResult::error(format!(
+                "Uploaded {} files but couldn't track them ({}). They'll be tracked when the daemon next starts.",
+                uploaded, e
+            )));
+        }
+        info!("Added {} files from {:?} to tracker", uploaded, path);
         let result_msg = format!(
             "Directory upload status: {} successes, {} fails.",
-            success, error
+            uploaded, error
         );
         if error > 0 {
             return Ok(DResult::error(result_msg));
         }
-        return Ok(DResult::ok(result_msg));
+        Ok(DResult::ok(result_msg))
 
     // Single file path, upload it.
     } else {
-        match drive.lock().unwrap().upload_file(&path) {
-            Ok(url) => {
+        let uploaded = hooks::pre_upload(&config.hooks.pre_upload, &path).and_then(|_| {
+            upload_hashed(&mut **drive.lock(), &path, folder.as_deref()).map_err(|e| e.to_string())
+        });
+        match uploaded {
+            Ok((url, md5)) => {
                 info!("Uploaded {:?}: {:?}", path, url);
-                match tracker.lock().unwrap().add_path(&path, &url) {
+                journal("push", &path, &url, Direction::Up, Ok(()));
+                let added = {
+                    let mut tracker = tracker.lock().unwrap();
+                    tracker
+                        .add_path(&path, &url)
+                        .and_then(|_| tracker.mark_synced_as(&path, md5))
+                };
+                match added {
                     Ok(_) => {
                         info!("Added {:?} to tracked files.", path);
-                        return Ok(DResult::ok(format!("Uploaded and synced {:?}.", path)));
+                        if let Some(name) = name {
+                            return Ok(rename_remote(path, name, tracker, drive, &config));
+                        }
+                        Ok(DResult::fields(
+                            format!("Uploaded and synced {:?}.", path),
+                            &[("path", rawpath::escape(&path)), ("drive_url", url)],
+                        ))
                     }
                     Err(e) => {
                         error!("Error adding {:?} to tracked files: {:?}.", path, e);
-                        return Ok(DResult::error(format!(
+                        Ok(DResult::error(format!(
                             "Error uploading and syncing {:?}: {:?}",
                             path, e
-                        )));
+                        )))
                     }
                 }
             }
             Err(e) => {
-                let emsg = format!("Failed to upload {:?}: {:?}", path, e);
+                let emsg = format!("Failed to upload {:?}: {}", path, e);
+                journal("push", &path, "", Direction::Up, Err(e));
                 error!("{}", emsg);
-                return Ok(DResult::error(emsg));
+                Ok(DResult::error(emsg))
             }
         }
     }
 }
 
-// Handle each incoming stream. Deserialize command and perform it.
-fn handle_stream(mut stream: UnixStream, tracker: Arc<Mutex<Tracker>>, drive: Arc<Mutex<Drive>>) {
-    // Deserialize command from stream.
-    let command: DCommand = DCommand::from_stream(&mut stream);
+// Track path, a hard link to the synced file other, with other's Drive file instead of uploading it again.
+fn link_tracked(path: &Path, other: &Path, url: String, tracker: &Arc<Mutex<Tracker>>) -> DResult {
+    let added = {
+        let mut tracker = tracker.lock().unwrap();
+        tracker
+            .add_path(path, url.as_str())
+            .and_then(|_| tracker.mark_synced(path))
+    };
+    match added {
+        Ok(_) => {
+            info!(
+                "{:?} is a hard link to {:?}, synced with {}.",
+                path, other, url
+            );
+            DResult::fields(
+                format!(
+                    "{:?} is a hard link to synced {:?}, synced it with the same Drive file.",
+                    path, other
+                ),
+                &[("path", rawpath::escape(path)), ("drive_url", url)],
+            )
+        }
+        Err(e) => DResult::error(format!("Error syncing {:?}: {:?}", path, e)),
+    }
+}
 
-    // Something with the udsockets causes empty bytes to be sent sometimes, dismiss any empty commands now.
-    if let DCommand::None = command {
-        return;
+// Push everything in the directory at path, then keep pushing files as they're created in it (see TrackedDir). The
+// directory is only tracked once the push succeeded, so a failed one can simply be run again.
+fn track_dir(
+    path: PathBuf,
+    dest: Option<String>,
+    excludes: bool,
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
+) -> Result<DResult, Error> {
+    if !path.is_dir() {
+        return Ok(DResult::error(format!(
+            "Cannot track {:?}: only directories pick up new files.",
+            path
+        )));
+    }
+    let pushed = push(
+        path.clone(),
+        dest.clone(),
+        None,
+        excludes,
+        Arc::clone(&tracker),
+        drive,
+        config,
+    )?;
+    let msg = match pushed {
+        DResult::Err(_) => return Ok(pushed),
+        ok => ok.message().to_string(),
+    };
+    let into = match &dest {
+        Some(url) => format!(" into {}", url),
+        None => String::new(),
+    };
+    let dir = TrackedDir {
+        path: path.clone(),
+        dest,
+        excludes,
+    };
+    match tracker.lock().unwrap().add_dir(dir) {
+        Ok(_) => {
+            info!("Tracking new files in {:?}{}.", path, into);
+            Ok(DResult::ok(format!(
+                "{} New files in {:?} will be pushed{} as they appear.",
+                msg, path, into
+            )))
+        }
+        Err(e) => {
+            error!("Error tracking directory {:?}: {:?}", path, e);
+            Ok(DResult::error(format!(
+                "{} Couldn't watch {:?} for new files: {}",
+                msg, path, e
+            )))
+        }
+    }
+}
+
+// A file or directory that appeared in a tracked directory. Files are pushed into the directory's Drive folder and
+// tracked, new subdirectories are watched too and whatever was already written into them is pushed.
+fn discover(
+    path: &Path,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Arc<Config>,
+) {
+    let root = match tracker.lock().unwrap().tracked_dir_of(path) {
+        Some(d) => d.clone(),
+        None => return,
+    };
+    if root.skips(path, &config.excludes.ignore) {
+        Stats::incr(&STATS.events_filtered);
+        return;
+    }
+    if path.is_dir() {
+        if let Err(e) = tracker.lock().unwrap().watch_tree(path, &root) {
+            error!("Failed to watch new directory {:?}: {:?}", path, e);
+        }
+    } else if !path.is_file()
+        || tracker.lock().unwrap().find_by_path(path).is_some()
+        || placeholder::drive_id_of(path).is_some()
+    {
+        return;
+    }
+    let result = push(
+        path.to_path_buf(),
+        root.dest.clone(),
+        None,
+        root.excludes,
+        Arc::clone(tracker),
+        Arc::clone(drive),
+        Arc::clone(config),
+    );
+    match result {
+        Ok(DResult::Err(e)) => error!("Failed to push new {:?}: {}", path, e),
+        Ok(r) => info!("New in {:?}: {:?}. {}", root.path, path, r.message()),
+        Err(e) => error!("Failed to push new {:?}: {:?}", path, e),
+    }
+}
+
+// Rename the Drive copy of the tracked file at path, and remember the name so it's kept apart from the local one.
+fn rename_remote(
+    path: PathBuf,
+    name: String,
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+    config: &Config,
+) -> DResult {
+    let url = match tracker.lock().unwrap().find_by_path(&path) {
+        Some(tf) => tf.drive_url.clone(),
+        None => return DResult::error(format!("{:?} is not synced.", path)),
+    };
+    if let Err(e) = config.policy.permits(&mut **drive.lock(), &url) {
+        warn!("Not renaming {:?} on Drive: {}", path, e);
+        return DResult::error(e);
+    }
+    let result = match drive_id(&url) {
+        Some(id) => drive.lock().rename(id, &name).map_err(|e| e.to_string()),
+        None => Err(format!("{:?} is not a drive url.", url)),
+    };
+    journal("rename", &path, &url, Direction::None, result.clone());
+    if let Err(e) = result {
+        error!("Error renaming {:?} on Drive: {}", path, e);
+        return DResult::error(format!(
+            "{:?} is synced, but renaming it on Drive failed: {}",
+            path, e
+        ));
+    }
+    match tracker
+        .lock()
+        .unwrap()
+        .set_remote_name(&path, Some(name.clone()))
+    {
+        Ok(_) => {
+            info!("Renamed the Drive copy of {:?} to {:?}.", path, name);
+            DResult::fields(
+                format!("{:?} is synced as {:?} on Drive.", path, name),
+                &[("path", rawpath::escape(&path)), ("drive_url", url)],
+            )
+        }
+        Err(e) => {
+            error!("Error saving the Drive name of {:?}: {:?}", path, e);
+            DResult::error(format!(
+                "Renamed {:?} on Drive but couldn't save it: {:?}",
+                path, e
+            ))
+        }
+    }
+}
+
+// Work out what pushing the directory at path would take. Ok if it's fine to go ahead, Err (with the same plan) if
+// there's anything the user should confirm first.
+fn plan_push(path: &PathBuf, excludes: bool, drive: &SharedRemote, config: &Config) -> DResult {
+    if !path.is_dir() {
+        return DResult::error(format!("{:?} is not a directory.", path));
+    }
+    let quota = match drive.lock().quota() {
+        Ok(q) => Some(q),
+        Err(e) => {
+            debug!("No quota for push plan: {}", e);
+            None
+        }
+    };
+    let (paths, skipped) = push_paths(path, excludes, &config.excludes.ignore);
+    let plan = Plan::new(&paths, skipped, quota, &config.planner);
+    if plan.warnings.is_empty() {
+        DResult::ok(plan.to_string())
+    } else {
+        DResult::error(plan.to_string())
+    }
+}
+
+// A client's connection, and how responses go back on it.
+struct Client {
+    stream: UnixStream,
+    // Responses larger than this are sent compressed, None if the client can't read them (or [limits]
+    // compress_above_bytes is 0).
+    compress_above: Option<u64>,
+}
+
+// Send r back to the client. The client may have already hung up, which is only worth a warning.
+fn respond(client: &Client, r: DResult) {
+    if let Err(e) = r.send_compressed(&client.stream, client.compress_above) {
+        warn!("Failed to send response to client: {}", e);
+    }
+}
+
+// Turn a client away without reading its command. Whatever it already sent is drained (briefly)
+// first, closing with unread data would reset the connection before the client sees the response.
+fn reject(stream: &UnixStream, msg: &str, limit: u64) {
+    if let Err(e) = DResult::error(msg).send(stream) {
+        warn!("Failed to send response to client: {}", e);
+    }
+    let _ = stream.shutdown(Shutdown::Write);
+    if stream
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .is_ok()
+    {
+        let _ = io::copy(&mut stream.take(limit), &mut io::sink());
+    }
+}
+
+// Replace any @alias or drive:/path targets in command with the drive url they stand for.
+fn resolve_aliases(
+    command: DCommand,
+    drive: &SharedRemote,
+    config: &Config,
+) -> Result<DCommand, String> {
+    let resolve = |target: String| -> Result<String, String> {
+        if Config::is_symbolic(&target) {
+            config.resolve(&mut **drive.lock(), &target)
+        } else {
+            Ok(target)
+        }
+    };
+    Ok(match command {
+        DCommand::Pull(url, path, overwrite, relink) => {
+            DCommand::Pull(resolve(url)?, path, overwrite, relink)
+        }
+        DCommand::PushTo(path, dest, excludes) => DCommand::PushTo(path, resolve(dest)?, excludes),
+        DCommand::PushAs(path, name, Some(dest)) => {
+            DCommand::PushAs(path, name, Some(resolve(dest)?))
+        }
+        DCommand::TrackDir(path, Some(dest), excludes) => {
+            DCommand::TrackDir(path, Some(resolve(dest)?), excludes)
+        }
+        DCommand::PushWait(path, Some(dest), name, excludes) => {
+            DCommand::PushWait(path, Some(resolve(dest)?), name, excludes)
+        }
+        DCommand::FSync(path, url, events) => DCommand::FSync(path, resolve(url)?, events),
+        DCommand::Activity(url) => DCommand::Activity(resolve(url)?),
+        DCommand::Export(url, path, overwrite, export) => {
+            DCommand::Export(resolve(url)?, path, overwrite, export)
+        }
+        DCommand::Share(url) => DCommand::Share(resolve(url)?),
+        DCommand::Shortcut(url, folder) => {
+            DCommand::Shortcut(resolve(url)?, folder.map(resolve).transpose()?)
+        }
+        DCommand::Ls(Some(folder)) => DCommand::Ls(Some(resolve(folder)?)),
+        DCommand::Pin(url, path) => DCommand::Pin(resolve(url)?, path),
+        DCommand::Unpin(url) => DCommand::Unpin(resolve(url)?),
+        DCommand::PullDir(url, dir, p) => DCommand::PullDir(resolve(url)?, dir, p),
+        c => c,
+    })
+}
+
+// Handle each incoming stream. Deserialize command and perform it.
+fn handle_stream(
+    stream: UnixStream,
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
+) {
+    let mut stream = Client {
+        stream,
+        compress_above: None,
+    };
+    // Deserialize command from stream. The deadline covers the whole read, so a client trickling bytes can't hold the worker.
+    let reader = DeadlineReader {
+        stream: &stream.stream,
+        deadline: Instant::now() + Duration::from_secs(config.limits.request_timeout_secs),
+    };
+    let command = match DCommand::from_request(reader, config.limits.max_buffer_bytes) {
+        Ok((c, accepts)) => {
+            let above = config.limits.compress_above_bytes;
+            if accepts & ACCEPT_ZSTD != 0 && above > 0 {
+                stream.compress_above = Some(above);
+            }
+            c
+        }
+        Err(ProtocolError::Io(ref e))
+            if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            warn!("Client didn't send a complete command in time, dropping connection.");
+            respond(&stream, DResult::error("Request timed out."));
+            return;
+        }
+        Err(e) => {
+            warn!("Rejecting malformed command: {}", e);
+            respond(&stream, DResult::error(format!("Malformed command: {}", e)));
+            return;
+        }
+    };
+
+    // Something with the udsockets causes empty bytes to be sent sometimes, dismiss any empty commands now.
+    if let DCommand::None = command {
+        return;
     }
 
     debug!("Got command: {:?}", command);
 
+    let command = match resolve_aliases(command, &drive, &config) {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("{}", e);
+            respond(&stream, DResult::error(e));
+            return;
+        }
+    };
+
+    if let Some(cap) = required_capability(&command) {
+        if let Err(e) = CAPABILITIES.require(cap) {
+            warn!("{}", e);
+            respond(&stream, DResult::error(e));
+            return;
+        }
+    }
+
     // Match command to command handler.
     match command {
         // Handle message command.
         DCommand::Message(msg) => {
             info!("Message from client: {:?}", msg);
             if msg.contains("ping") {
-                DResult::Ok(String::from("pong")).send(&mut stream).unwrap();
+                respond(&stream, DResult::ok("pong"));
             }
         }
 
         // Handles the file pull command.
-        DCommand::Pull(drive_url, path, overwrite) => {
-            match pull(drive_url, path, overwrite, tracker, drive) {
-                Ok(r) => r.send(&mut stream).unwrap(),
+        DCommand::Pull(drive_url, path, overwrite, relink) => {
+            match pull(drive_url, path, overwrite, relink, tracker, drive, config) {
+                Ok(r) => respond(&stream, r),
+                Err(e) => {
+                    error!("Unrecoverable pull error: {:?}", e);
+                    respond(&stream, DResult::error(format!("{}", e)));
+                }
+            }
+        }
+
+        DCommand::Export(drive_url, path, overwrite, export) => {
+            match export_file(drive_url, path, overwrite, export, tracker, drive, config) {
+                Ok(r) => respond(&stream, r),
+                Err(e) => {
+                    error!("Unrecoverable export error: {:?}", e);
+                    respond(&stream, DResult::error(format!("{}", e)));
+                }
+            }
+        }
+
+        DCommand::Share(url) => match share(url, tracker, drive) {
+            Ok(r) => respond(&stream, r),
+            Err(e) => {
+                error!("Unrecoverable share error: {:?}", e);
+                respond(&stream, DResult::error(format!("{}", e)));
+            }
+        },
+
+        DCommand::Shortcut(url, folder) => {
+            respond(&stream, shortcut(url, folder, &tracker, &drive, &config))
+        }
+
+        DCommand::Ls(folder) => respond(&stream, ls(folder, &drive, &config)),
+
+        DCommand::Pin(url, path) => respond(&stream, pin(url, path, &tracker, &drive, &config)),
+
+        DCommand::Unpin(pinned) => {
+            let removed = PINS.lock().unwrap().remove(&pinned);
+            match removed {
+                Ok(pins) if pins.is_empty() => respond(
+                    &stream,
+                    DResult::error(format!("Nothing pinned for {}.", pinned)),
+                ),
+                Ok(pins) => {
+                    for p in &pins {
+                        journal("unpin", &p.path, &p.drive_url, Direction::None, Ok(()));
+                    }
+                    let msg = format!("Unpinned {:?}, it stays synced.", pins[0].path);
+                    info!("{}", msg);
+                    respond(&stream, DResult::ok(msg));
+                }
+                Err(e) => respond(
+                    &stream,
+                    DResult::error(format!("Error saving the pinned files: {:?}", e)),
+                ),
+            }
+        }
+
+        DCommand::Pins => {
+            let now = Utc::now().timestamp();
+            let pins = PINS.lock().unwrap();
+            if pins.all().is_empty() {
+                respond(&stream, DResult::ok("Nothing pinned."));
+            } else {
+                let lines: Vec<String> = pins.all().iter().map(|p| p.describe(now)).collect();
+                respond(&stream, DResult::ok(lines.join("\n")));
+            }
+        }
+
+        DCommand::PullDir(url, dir, placeholders) => {
+            match pull_dir(url, dir, placeholders, tracker, drive, config) {
+                Ok(r) => respond(&stream, r),
                 Err(e) => {
                     error!("Unrecoverable pull error: {:?}", e);
-                    DResult::error(format!("{}", e)).send(&mut stream).unwrap();
+                    respond(&stream, DResult::error(format!("{}", e)));
                 }
             }
         }
 
-        DCommand::Push(path) => match push(path, tracker, drive) {
-            Ok(r) => r.send(&mut stream).unwrap(),
+        DCommand::Hydrate(path) => match hydrate(path, tracker, drive, config) {
+            Ok(r) => respond(&stream, r),
+            Err(e) => {
+                error!("Unrecoverable pull error: {:?}", e);
+                respond(&stream, DResult::error(format!("{}", e)));
+            }
+        },
+
+        DCommand::RebuildState(dir) => {
+            respond(&stream, rebuild_state(dir, &tracker, &drive, &config))
+        }
+
+        DCommand::PullStarred(dir) => match pull_starred(dir, tracker, drive, config) {
+            Ok(r) => respond(&stream, r),
             Err(e) => {
-                error!("Unrecoverable push error: {:?}", e);
-                DResult::error(format!("{}", e)).send(&mut stream).unwrap();
+                error!("Unrecoverable pull error: {:?}", e);
+                respond(&stream, DResult::error(format!("{}", e)));
             }
         },
 
-        DCommand::FSync(path, drive_url) => {
-            match tracker.lock().unwrap().add_path(&path, &drive_url) {
+        DCommand::Push(path, excludes) => {
+            match push(path, None, None, excludes, tracker, drive, config) {
+                Ok(r) => respond(&stream, r),
+                Err(e) => {
+                    error!("Unrecoverable push error: {:?}", e);
+                    respond(&stream, DResult::error(format!("{}", e)));
+                }
+            }
+        }
+
+        DCommand::PushTo(path, dest, excludes) => {
+            match push(path, Some(dest), None, excludes, tracker, drive, config) {
+                Ok(r) => respond(&stream, r),
+                Err(e) => {
+                    error!("Unrecoverable push error: {:?}", e);
+                    respond(&stream, DResult::error(format!("{}", e)));
+                }
+            }
+        }
+
+        DCommand::PushAs(path, name, dest) => {
+            match push(path, dest, Some(name), true, tracker, drive, config) {
+                Ok(r) => respond(&stream, r),
+                Err(e) => {
+                    error!("Unrecoverable push error: {:?}", e);
+                    respond(&stream, DResult::error(format!("{}", e)));
+                }
+            }
+        }
+
+        // Temporary failures are retried on a remote of its own, so other requests aren't held up while it waits.
+        DCommand::PushWait(path, dest, name, excludes) => {
+            let patient: SharedRemote = Arc::new(Pool::single(Box::new(Patient::new(
+                Arc::clone(&drive),
+                WAIT_RETRIES,
+            ))));
+            match push(path, dest, name, excludes, tracker, patient, config) {
+                Ok(r) => respond(&stream, r),
+                Err(e) => {
+                    error!("Unrecoverable push error: {:?}", e);
+                    respond(&stream, DResult::error(format!("{}", e)));
+                }
+            }
+        }
+
+        DCommand::TrackDir(path, dest, excludes) => {
+            match track_dir(path, dest, excludes, tracker, drive, config) {
+                Ok(r) => respond(&stream, r),
+                Err(e) => {
+                    error!("Unrecoverable push error: {:?}", e);
+                    respond(&stream, DResult::error(format!("{}", e)));
+                }
+            }
+        }
+
+        DCommand::RenameRemote(path, name) => {
+            respond(&stream, rename_remote(path, name, tracker, drive, &config))
+        }
+
+        DCommand::FSync(path, drive_url, events) => {
+            if let Err(e) = config.policy.permits(&mut **drive.lock(), &drive_url) {
+                warn!("{}", e);
+                respond(&stream, DResult::error(e));
+                return;
+            }
+            let result = {
+                let mut tracker = tracker.lock().unwrap();
+                tracker
+                    .add_path(&path, &drive_url)
+                    .and_then(|_| tracker.set_events(&path, events))
+            };
+            journal(
+                "sync",
+                &path,
+                &drive_url,
+                Direction::None,
+                result.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+            );
+            match result {
                 Ok(_) => {
                     let msg = format!("Manual sync added for {:?} -> {:?}", &path, &drive_url);
                     info!("{}", msg);
-                    DResult::ok(msg).send(&mut stream).unwrap();
+                    respond(&stream, DResult::ok(msg));
                 }
                 Err(e) => {
                     let emsg = format!(
@@ -207,144 +1594,2569 @@ fn handle_stream(mut stream: UnixStream, tracker: Arc<Mutex<Tracker>>, drive: Ar
                         &path, &drive_url, e
                     );
                     error!("{}", emsg);
-                    DResult::error(emsg).send(&mut stream).unwrap();
+                    respond(&stream, DResult::error(emsg));
                 }
             }
         }
 
-        DCommand::FUnSync(path) => match tracker.lock().unwrap().remove_path(&path) {
-            Ok(_) => {
-                let msg = format!("Removed sync for {:?}", &path);
-                info!("{}", msg);
-                DResult::ok(msg).send(&mut stream).unwrap();
+        // A tracked directory stops picking up new files, the files in it stay synced.
+        DCommand::FUnSync(path)
+            if tracker
+                .lock()
+                .unwrap()
+                .tracked_dirs
+                .iter()
+                .any(|d| d.path == canonical_path(&path)) =>
+        {
+            let result = tracker.lock().unwrap().remove_dir(&path);
+            journal(
+                "unsync",
+                &path,
+                "",
+                Direction::None,
+                result.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+            );
+            match result {
+                Ok(_) => {
+                    let msg = format!(
+                        "No longer pushing new files in {:?}, the ones already synced stay synced.",
+                        &path
+                    );
+                    info!("{}", msg);
+                    respond(&stream, DResult::ok(msg));
+                }
+                Err(e) => {
+                    let emsg = format!("Error removing sync for {:?}: {:?}", &path, e);
+                    error!("{}", emsg);
+                    respond(&stream, DResult::error(emsg));
+                }
             }
-            Err(e) => {
-                let emsg = format!("Error removing sync for {:?}: {:?}", &path, e);
-                error!("{}", emsg);
-                DResult::error(emsg).send(&mut stream).unwrap();
+        }
+
+        DCommand::FUnSync(path) => {
+            let result = tracker.lock().unwrap().remove_path(&path);
+            if result.is_ok() {
+                if let Err(e) = PINS.lock().unwrap().remove_path(&canonical_path(&path)) {
+                    error!("Error saving the pinned files: {:?}", e);
+                }
             }
-        },
+            journal(
+                "unsync",
+                &path,
+                "",
+                Direction::None,
+                result.as_ref().map(|_| ()).map_err(|e| e.to_string()),
+            );
+            match result {
+                Ok(_) => {
+                    let msg = format!("Removed sync for {:?}", &path);
+                    info!("{}", msg);
+                    respond(&stream, DResult::ok(msg));
+                }
+                Err(e) => {
+                    let emsg = format!("Error removing sync for {:?}: {:?}", &path, e);
+                    error!("{}", emsg);
+                    respond(&stream, DResult::error(emsg));
+                }
+            }
+        }
+
+        DCommand::Stats => respond(&stream, DResult::ok(STATS.report())),
+
+        // Workers only start once everything else is up, so getting here at all means the daemon is ready.
+        DCommand::Ready => respond(&stream, DResult::ok("ready")),
+
+        DCommand::PathStatusBatch(paths) => {
+            let pending: HashSet<PathBuf> = QUEUE
+                .lock()
+                .unwrap()
+                .ops()
+                .iter()
+                .map(|o| o.path.clone())
+                .chain(
+                    STAGED
+                        .lock()
+                        .unwrap()
+                        .changes()
+                        .iter()
+                        .map(|c| c.path.clone()),
+                )
+                .collect();
+            let statuses =
+                status::statuses(&tracker.lock().unwrap().tracked_files, &paths, &pending);
+            let lines: Vec<&str> = statuses.iter().map(|s| s.as_str()).collect();
+            respond(&stream, DResult::ok(lines.join("\n")));
+        }
+
+        DCommand::Pending => respond(&stream, pending()),
+
+        DCommand::Queue => respond(&stream, queue()),
+
+        DCommand::QueueDrop(id) => {
+            let dropped = QUEUE.lock().unwrap().remove(id);
+            match dropped {
+                Ok(Some(op)) => respond(
+                    &stream,
+                    DResult::ok(format!("Dropped queued {} of {:?}.", op.op, op.path)),
+                ),
+                Ok(None) => respond(
+                    &stream,
+                    DResult::error(format!("Nothing queued with id {}.", id)),
+                ),
+                Err(e) => respond(
+                    &stream,
+                    DResult::error(format!("Error saving the retry queue: {:?}", e)),
+                ),
+            }
+        }
+
+        DCommand::QueueRetry(id) => respond(&stream, queue_retry(id, &tracker, &drive, &config)),
+
+        DCommand::Approve(path) => respond(&stream, approve(path, &tracker, &drive, &config)),
+
+        DCommand::Plan(path, excludes) => {
+            respond(&stream, plan_push(&path, excludes, &drive, &config))
+        }
+
+        DCommand::Health => {
+            let reasons = HEALTH.evaluate(&config.health);
+            if reasons.is_empty() {
+                respond(&stream, DResult::ok("healthy"));
+            } else {
+                respond(
+                    &stream,
+                    DResult::error(format!("unhealthy: {}", reasons.join("; "))),
+                );
+            }
+        }
+
+        DCommand::Activity(url) => {
+            let id = match drive_id(&url) {
+                Some(id) => id,
+                None => {
+                    respond(
+                        &stream,
+                        DResult::error(format!("{:?} is not a drive url.", url)),
+                    );
+                    return;
+                }
+            };
+            let result = drive.lock().activity(id);
+            match result {
+                Ok(activity) if activity.is_empty() => respond(
+                    &stream,
+                    DResult::ok(format!("No activity recorded for {}.", url)),
+                ),
+                Ok(activity) => {
+                    let lines: Vec<String> = activity
+                        .iter()
+                        .map(|a| format!("{}  {}  {}", human_time(a.time), a.actor, a.action))
+                        .collect();
+                    respond(
+                        &stream,
+                        DResult::ok(format!("Activity for {}:\n{}", url, lines.join("\n"))),
+                    );
+                }
+                Err(e) => {
+                    error!("Error fetching activity for {}: {}", url, e);
+                    respond(
+                        &stream,
+                        DResult::error(format!("Error fetching activity for {}: {}", url, e)),
+                    );
+                }
+            }
+        }
+
+        DCommand::Login => respond(&stream, login(&drive)),
+
+        DCommand::Capabilities => respond(&stream, DResult::ok(CAPABILITIES.report())),
+
+        DCommand::Drives => {
+            let drives = drive.lock().shared_drives();
+            match drives {
+                Ok(drives) => {
+                    let lines: Vec<String> = drives.iter().map(|d| d.to_line()).collect();
+                    respond(&stream, DResult::ok(lines.join("\n")));
+                }
+                Err(e) => {
+                    error!("Error listing shared drives: {}", e);
+                    respond(
+                        &stream,
+                        DResult::error(format!("Error listing shared drives: {}", e)),
+                    );
+                }
+            }
+        }
+
+        DCommand::Account => respond(&stream, account(&drive)),
+
+        DCommand::Env => respond(&stream, environment(&config)),
+
+        DCommand::SetEnv(name) => respond(&stream, set_environment(name, &config)),
 
         // Handle quit command.
         DCommand::Quit => {
             info!("Received quit command from client. Quitting..");
-            DResult::Ok(String::from("Daemon stopped."))
-                .send(&mut stream)
-                .unwrap();
+            if !SHUTDOWN.request() {
+                respond(&stream, DResult::error("Daemon is already stopping."));
+                return;
+            }
+            // This command is running work of its own.
+            let unfinished = SHUTDOWN.wait_idle(1, shutdown::GRACE);
+            // Held until exiting, so no change to the tracked files lands after this. Each one was saved as it was made.
+            let _tracker = tracker.lock().unwrap();
+            let msg = if unfinished == 0 {
+                String::from("Daemon stopped.")
+            } else {
+                warn!("Stopping with {} operation(s) still under way.", unfinished);
+                format!(
+                    "Daemon stopped, {} operation(s) didn't finish within {}s.",
+                    unfinished,
+                    shutdown::GRACE.as_secs()
+                )
+            };
+            respond(&stream, DResult::ok(msg));
+            daemons::unregister();
             process::exit(0);
         }
         _ => {}
     }
 }
 
-/// Listens forever for inotify events.
-fn inotify_listen(tracker: Arc<Mutex<Tracker>>, drive: Arc<Mutex<Drive>>) {
-    let mut buffer = [0; 1024];
-    debug!("waiting for events..");
-    loop {
-        let events = tracker
-            .lock()
-            .unwrap()
-            .inotify
-            .read_events(&mut buffer)
-            .expect("Failed to read inotify events");
+// Whether a watcher should leave path (in the watched dir) alone because of the default excludes or ignore rules.
+fn excluded(path: &Path, dir: &Path, config: &Config) -> bool {
+    (config.excludes.defaults && exclude::is_excluded(path.strip_prefix(dir).unwrap_or(path)))
+        || exclude::is_ignored(dir, path, &config.excludes.ignore)
+}
 
-        for event in events {
-            match event.mask {
-                // Handle modify events. Find file associated with wd and update it on drive.
-                EventMask::MODIFY => {
-                    for tf in &tracker.lock().unwrap().tracked_files {
-                        if let Some(wd) = &tf.wd {
-                            if *wd == event.wd {
-                                match drive
-                                    .lock()
-                                    .unwrap()
-                                    .update_file(tf.path.clone(), &tf.drive_url)
-                                {
-                                    Ok(_) => info!("Successfully updated file: {:?}", &tf.path),
-                                    Err(e) => {
-                                        error!("Error updating file {:?} : {:?}", &tf.path, e)
-                                    }
-                                }
-                            }
-                        }
-                    }
+// Download anything new in the watched folders. Files that can't be fetched are retried on the next poll.
+fn check_watches(
+    inbound: &mut Inbound,
+    poller: &mut Poller,
+    feed: &[FileChange],
+    drive: &SharedRemote,
+    config: &Config,
+) {
+    for w in &config.watch {
+        let folder = match drive_id(&w.folder) {
+            Some(f) => f,
+            None => continue,
+        };
+        let files = match poller.in_step(folder) {
+            true => Ok(inbound.new_in(feed, folder)),
+            false => inbound.new_files(&mut **drive.lock(), folder),
+        };
+        let files = match files {
+            Ok(f) => f,
+            Err(e) => {
+                warn!("Failed to list watched folder {}: {}", w.folder, e);
+                continue;
+            }
+        };
+        // A file that didn't come down is only found again by listing the folder.
+        let mut missed = false;
+        for m in files {
+            let url = format!("https://drive.google.com/open?id={}", m.id);
+            let dest = names::dest_in(&w.dest, &m.name, &m.id);
+            if excluded(&dest, &w.dest, config) {
+                debug!(
+                    "Not downloading default excluded {:?} from watched folder.",
+                    m.name
+                );
+                inbound.mark(folder, &m.id);
+                continue;
+            }
+            // Inbound only ever adds files, anything already at the destination is left alone.
+            if dest.exists() {
+                warn!(
+                    "{:?} already exists, not downloading {} from watched folder.",
+                    dest, url
+                );
+                inbound.mark(folder, &m.id);
+                continue;
+            }
+            let mut remote = drive.lock();
+            let result = config
+                .policy
+                .permits(&mut **remote, &url)
+                .and_then(|_| remote.download(&url, &dest).map_err(|e| e.to_string()));
+            drop(remote);
+            journal(
+                "watch",
+                &dest,
+                &url,
+                Direction::Down,
+                result.clone().map(|_| ()),
+            );
+            match result {
+                Ok(p) => {
+                    info!("Downloaded {:?} from watched folder {}", p, w.folder);
+                    inbound.mark(folder, &m.id);
                 }
-                // Handles delete events. For now expected behaviour on a local file delete is just to remove the sync on it, not delete it on drive.
-                EventMask::DELETE => {
-                    for tf in &tracker.lock().unwrap().tracked_files {
-                        if let Some(wd) = &tf.wd {
-                            if *wd == event.wd {
-                                match tracker.lock().unwrap().remove_path(&tf.path) {
-                                    Ok(_) => {
-                                        info!("{:?} was deleted locally, removing sync.", tf.path);
-                                    },
-                                    Err(e) => {
-                                        error!("{:?} was deleted locally, failed to remove sync: {:?}", tf.path, e);
-                                    }
-                                }
-                            }
-                        }
-                    }
+                Err(e) => {
+                    error!("Failed to download {} from watched folder: {}", url, e);
+                    missed = true;
                 }
-                // Skip all other events.
-                _ => {},
             }
         }
-        // debug!("Checking for events...");
-        thread::sleep(Duration::from_millis(500));
+        poller.set_in_step(folder, !missed);
+    }
+    if let Err(e) = inbound.save() {
+        error!("Failed to save watched folder state: {:?}", e);
     }
 }
 
-fn main() {
-    env_logger::init();
-    // Check if socket exists already, if it does delete it.
-    let socket = Path::new(SOCKET_PATH);
-    if socket.exists() {
-        fs::remove_file(&socket).unwrap()
+// Refresh an export after its remote file changed.
+fn reexport(e: &TrackedFile, export: &Export, drive: &SharedRemote) {
+    let id = match drive_id(&e.drive_url) {
+        Some(id) => id,
+        None => return,
+    };
+    let result = drive.lock().export(id, export, &e.path);
+    journal(
+        "export",
+        &e.path,
+        &e.drive_url,
+        Direction::Down,
+        result.as_ref().map(|_| ()).map_err(|err| err.to_string()),
+    );
+    match result {
+        Ok(p) => info!("Re-exported {} to {:?}.", e.drive_url, p),
+        Err(err) => error!(
+            "Failed to re-export {} to {:?}: {}",
+            e.drive_url, e.path, err
+        ),
     }
+}
 
-    // Create unix domain socket listener on SOCKET_PATH.
-    let listener = match UnixListener::bind(&socket) {
-        Ok(s) => s,
-        Err(e) => {
-            error!("Couldn't listen on socket: {:#?}", e);
+// Keep the [replica] remote up to date with Drive, see replica::Replicas. It's connected to (again) as needed, one that
+// can't be reached only falls behind.
+fn replicate(tracker: Arc<Mutex<Tracker>>, profile: String, interval: Duration) {
+    let mut replicas = Replicas::load();
+    let mut remote: Option<Box<dyn Remote>> = None;
+    loop {
+        let running = SHUTDOWN.begin();
+        if SHUTDOWN.requested() {
             return;
         }
-    };
-    info!("Daemon initialized.");
-
-    // Initialize gdrive api client.
-    let drive = match Drive::new(
-        String::from(env::var("GOOGLE_CLIENT_ID").unwrap()),
-        String::from(env::var("GOOGLE_CLIENT_SECRET").unwrap()),
-        None,
-    ) {
-        Ok(d) => Arc::new(Mutex::new(d)),
-        Err(e) => {
-            error!(
-                "Error initializing Drive API client: {:#?}. Unable to continue.",
-                e
-            );
-            process::exit(1);
+        if remote.is_none() {
+            match transfer::connect_replica(&profile) {
+                Ok(r) => {
+                    info!("Replicating synced files to profile {:?}.", profile);
+                    remote = Some(r);
+                }
+                Err(e) => warn!("Couldn't connect to the replica ({:?}): {}", profile, e),
+            }
         }
-    };
-
-    // Tracker hold inotify, and ensures that tracked files exist between sessions.
-    let tracker = Arc::new(Mutex::new(Tracker::init()));
-
-    // Spawn a new thread which listens for and handles Inotify events.
+        if let Some(r) = remote.as_mut() {
+            let tracked = tracker.lock().unwrap().tracked_files.clone();
+            let (uploaded, errors) = replicas.sync(r.as_mut(), &tracked);
+            if uploaded > 0 {
+                debug!("Replicated {} file(s) to {:?}.", uploaded, profile);
+            }
+            // An expired access token is replaced by connecting again.
+            if errors.iter().any(|e| transfer::token_expired(e)) {
+                remote = None;
+            }
+        }
+        drop(running);
+        thread::sleep(interval);
+    }
+}
+
+// Keep the [cache] under max_bytes, see evict.
+fn cache_keeper(tracker: Arc<Mutex<Tracker>>, config: Arc<Config>, interval: Duration) {
+    loop {
+        let running = SHUTDOWN.begin();
+        if SHUTDOWN.requested() {
+            return;
+        }
+        evict(&tracker, &config, &[]);
+        drop(running);
+        thread::sleep(interval);
+    }
+}
+
+// Put a placeholder for the Drive file id in place of the synced file at path, and stop syncing it. The placeholder is
+// made beside it first, so on any error the file is left as it was, still synced.
+fn replace_with_placeholder(
+    tracker: &Arc<Mutex<Tracker>>,
+    path: &Path,
+    id: &str,
+    size: u64,
+) -> Result<(), Error> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let part = path.with_file_name(format!(".{}.part", name));
+    placeholder::create(&part, id, size)?;
+    let mut tracker = tracker.lock().unwrap();
+    let synced = tracker.find_by_path(path).cloned();
+    // Unwatched first, replacing it isn't a change to upload.
+    let result = tracker
+        .remove_path(path)
+        .and_then(|_| match fs::rename(&part, path) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if let Err(e) = tracker.add_paths(synced.into_iter().collect()) {
+                    error!("Couldn't sync {:?} again: {:?}", path, e);
+                }
+                Err(e)
+            }
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&part);
+    }
+    result
+}
+
+// Turn the least recently used files of the [cache] back into placeholders until the rest fit in max_bytes, sparing
+// keep. A file changed since it last synced isn't on Drive yet, it stays until it's uploaded. Returns how many were
+// evicted, and what the cache takes up now.
+fn evict(tracker: &Arc<Mutex<Tracker>>, config: &Config, keep: &[PathBuf]) -> (usize, u64) {
+    let max = config.cache.max_bytes;
+    if max == 0 {
+        return (0, 0);
+    }
+    let files = cache::cached(&tracker.lock().unwrap().tracked_files, &config.cache.paths);
+    let mut total = cache::total(&files);
+    let mut evicted = 0;
+    for c in files {
+        if total <= max {
+            break;
+        }
+        if keep.contains(&c.path)
+            || PINS.lock().unwrap().is_pinned(&c.path)
+            || checksum::md5_file(&c.path).ok() != c.md5
+        {
+            continue;
+        }
+        let id = match drive_id(&c.drive_url) {
+            Some(id) => id,
+            None => continue,
+        };
+        let result =
+            replace_with_placeholder(tracker, &c.path, id, c.size).map_err(|e| e.to_string());
+        journal(
+            "evict",
+            &c.path,
+            &c.drive_url,
+            Direction::None,
+            result.clone(),
+        );
+        match result {
+            Ok(()) => {
+                info!(
+                    "Evicted {:?} ({}) from the cache, `rgdrive hydrate` brings it back.",
+                    c.path,
+                    human_bytes(c.size)
+                );
+                Stats::incr(&STATS.files_evicted);
+                total -= c.size;
+                evicted += 1;
+            }
+            // Still synced as it was, nothing else is likely to do better.
+            Err(e) => {
+                error!("Couldn't evict {:?}: {}", c.path, e);
+                break;
+            }
+        }
+    }
+    if total > max {
+        debug!(
+            "The cache takes up {}, more than cache.max_bytes ({}), and nothing else can be evicted.",
+            human_bytes(total),
+            human_bytes(max)
+        );
+    }
+    (evicted, total)
+}
+
+// Poll Drive every poll.interval_secs: tracked files are checked for remote changes, which aren't pulled down (yet)
+// but are logged and counted in --stats, exports are refreshed, and watched folders are checked for new files.
+fn remote_poll(
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
+    interval: Duration,
+) {
+    let mut poller = match Poller::open(&state_path()) {
+        Ok(p) => p,
+        Err(e) => {
+            error!(
+                "Not polling Drive for changes, can't open the state database: {}",
+                e
+            );
+            return;
+        }
+    };
+    let mut inbound = Inbound::load();
+    loop {
+        // Nothing gets through to Drive until `rgdrive --login`.
+        if HEALTH.reauth_required() {
+            thread::sleep(interval);
+            continue;
+        }
+        let running = SHUTDOWN.begin();
+        if SHUTDOWN.requested() {
+            return;
+        }
+        let feed = poller.poll(&mut **drive.lock(), config.poll.page_size);
+        // Pinned files missing locally are still restored without it, and watched folders listed.
+        let feed = feed.unwrap_or_else(|e| {
+            debug!("Not checking for remote changes: {}", e);
+            Vec::new()
+        });
+        check_watches(&mut inbound, &mut poller, &feed, &drive, &config);
+        let tracked = Arc::new(tracker.lock().unwrap().tracked_files.clone());
+        let changes = tracked_changes(&feed, &tracked);
+        // Drive md5 of each file that changed, for the pinned ones.
+        let changed: HashMap<String, Option<String>> = changes
+            .iter()
+            .map(|c| (c.drive_url.clone(), c.metadata.md5.clone()))
+            .collect();
+        let (t, d, c) = (
+            Arc::clone(&tracker),
+            Arc::clone(&drive),
+            Arc::clone(&config),
+        );
+        pool::each(changes, config.poll.concurrency, move |change| {
+            remote_change(change, &tracked, &t, &d, &c)
+        });
+        keep_pins(&changed, &tracker, &drive, &config);
+        // Folders renamed, moved or deleted on Drive are looked up again next time they're needed.
+        match PATHS.forget_changed(&feed) {
+            0 => {}
+            n => info!(
+                "{} cached Drive folder(s) changed remotely, dropped them.",
+                n
+            ),
+        }
+        // Only once the changes are dealt with, a restart part way through sees them again. The watched folders'
+        // cursors go with it.
+        if let Err(e) = poller.save() {
+            error!("Error saving where polling left off: {:?}", e);
+        }
+        drop(running);
+        thread::sleep(ENVIRONMENT.poll_interval().unwrap_or(interval));
+    }
+}
+
+// Deal with a tracked file changed on Drive: re-export it, or with [reconcile] newest_wins, bring the newer side over.
+fn remote_change(
+    c: Change,
+    tracked: &[TrackedFile],
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Arc<Config>,
+) {
+    info!(
+        "Drive copy of {} ({:?}) changed remotely.",
+        c.drive_url, c.metadata.name
+    );
+    let saved = tracker
+        .lock()
+        .unwrap()
+        .set_revision(&c.drive_url, &c.metadata.etag);
+    if let Err(e) = saved {
+        error!("Error saving the revision of {}: {:?}", c.drive_url, e);
+    }
+    for tf in tracked.iter().filter(|tf| tf.drive_url == c.drive_url) {
+        if tf.absent {
+            debug!("{:?} is absent, reconciling it once it's back.", tf.path);
+        } else if let Some(export) = &tf.export {
+            reexport(tf, export, drive);
+        } else if config.reconcile.newest_wins {
+            newest_wins(tf, &c.metadata, tracker, drive, config);
+        }
+    }
+}
+
+// Refresh the pinned files that need it, changed being the Drive md5 of every file that changed since the last poll.
+fn keep_pins(
+    changed: &HashMap<String, Option<String>>,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Arc<Config>,
+) {
+    let pins = PINS.lock().unwrap().all().to_vec();
+    for pin in pins {
+        // Our own uploads come back as changes too.
+        let stale = match changed.get(&pin.drive_url) {
+            Some(md5) => md5.is_none() || checksum::md5_file(&pin.path).ok() != *md5,
+            None => false,
+        };
+        let result = match refresh_pin(&pin, stale, tracker, drive, config) {
+            Some(r) => r,
+            None => continue,
+        };
+        match &result {
+            Ok(()) => info!("Refreshed pinned {:?}.", pin.path),
+            Err(e) => warn!("Couldn't refresh pinned {:?}: {}", pin.path, e),
+        }
+        if let Err(e) = PINS.lock().unwrap().refreshed(&pin.path, result) {
+            error!("Error saving the pinned files: {:?}", e);
+        }
+    }
+}
+
+// Pull pin again if it needs it: missing locally, only a placeholder, behind its Drive copy (stale) or its last refresh
+// failed. A local change that hasn't gone up yet is never replaced. None if there was nothing to do.
+fn refresh_pin(
+    pin: &Pin,
+    stale: bool,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Arc<Config>,
+) -> Option<Result<(), String>> {
+    let held = placeholder::drive_id_of(&pin.path)
+        .map(|id| (id, placeholder::size_of(&pin.path).unwrap_or(0)));
+    let missing = fs::symlink_metadata(&pin.path).is_err();
+    if !missing && held.is_none() {
+        if !stale && pin.last_error.is_none() {
+            return None;
+        }
+        let synced = tracker
+            .lock()
+            .unwrap()
+            .find_by_path(&pin.path)
+            .and_then(|tf| tf.md5.clone());
+        if synced.is_some() && checksum::md5_file(&pin.path).ok() != synced {
+            debug!(
+                "Not refreshing pinned {:?}, its local change goes up first.",
+                pin.path
+            );
+            return None;
+        }
+    }
+    // Like hydrate, a placeholder makes way for the download and is put back if it fails.
+    if held.is_some() {
+        if let Err(e) = fs::remove_file(&pin.path) {
+            return Some(Err(e.to_string()));
+        }
+    }
+    let overwrite = if missing || held.is_some() {
+        Overwrite::Never
+    } else {
+        Overwrite::LocalUnmodified
+    };
+    let result = match pull(
+        pin.drive_url.clone(),
+        pin.path.clone(),
+        overwrite,
+        false,
+        Arc::clone(tracker),
+        Arc::clone(drive),
+        Arc::clone(config),
+    ) {
+        Ok(DResult::Err(e)) => Err(e),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let (Err(_), Some((id, size))) = (&result, &held) {
+        if let Err(e) = placeholder::create(&pin.path, id, *size) {
+            error!("Error putting back placeholder {:?}: {}", pin.path, e);
+        }
+    }
+    Some(result)
+}
+
+// Pin the synced file drive_url (or the placeholder for it at path): keep it on disk and current with Drive. It's
+// pulled right away if it isn't on disk.
+fn pin(
+    drive_url: String,
+    path: Option<PathBuf>,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Arc<Config>,
+) -> DResult {
+    let path = match path {
+        Some(p) => canonical_path(&p),
+        None => {
+            let tracked = tracker
+                .lock()
+                .unwrap()
+                .tracked_files
+                .iter()
+                .find(|tf| tf.drive_url == drive_url && !tf.is_export())
+                .map(|tf| tf.path.clone());
+            match tracked {
+                Some(p) => p,
+                None => {
+                    return DResult::error(format!(
+                        "{} isn't synced, --pull it first to pin it.",
+                        drive_url
+                    ))
+                }
+            }
+        }
+    };
+    match PINS.lock().unwrap().add(&path, &drive_url) {
+        Ok(true) => {}
+        Ok(false) => return DResult::ok(format!("{:?} is already pinned.", path)),
+        Err(e) => return DResult::error(format!("Error saving the pinned files: {:?}", e)),
+    }
+    journal("pin", &path, &drive_url, Direction::None, Ok(()));
+    let pin = Pin {
+        path: path.clone(),
+        drive_url,
+        refreshed_at: None,
+        last_error: None,
+    };
+    let result = refresh_pin(&pin, false, tracker, drive, config).unwrap_or(Ok(()));
+    let saved = PINS.lock().unwrap().refreshed(&path, result.clone());
+    if let Err(e) = saved {
+        error!("Error saving the pinned files: {:?}", e);
+    }
+    let mut msg = format!(
+        "Pinned {:?}, it's kept on disk and current with Drive.",
+        path
+    );
+    if let Err(e) = result {
+        msg = format!("Pinned {:?}, but couldn't pull it: {}", path, e);
+    }
+    if config.poll.interval_secs.is_none() {
+        msg.push_str(" [poll] is off, so it isn't refreshed until it's on.");
+    }
+    info!("{}", msg);
+    DResult::ok(msg)
+}
+
+// [reconcile] newest_wins: bring tf in line with its changed Drive copy, whichever of the two was modified last replacing
+// the other. The losing side is saved to the file's versions first (pull does that for the local copy).
+fn newest_wins(
+    tf: &TrackedFile,
+    remote: &Metadata,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Arc<Config>,
+) {
+    // Our own uploads come back as remote changes too.
+    let local_md5 = match checksum::md5_file(&tf.path) {
+        Ok(m) => m,
+        Err(_) => return,
+    };
+    if remote.md5.as_deref() == Some(local_md5.as_str()) {
+        return;
+    }
+    let local_modified = fs::metadata(&tf.path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    if remote.modified > local_modified {
+        info!(
+            "Drive copy of {:?} is newer than the local file, pulling it.",
+            tf.path
+        );
+        let pulled = pull(
+            tf.drive_url.clone(),
+            tf.path.clone(),
+            Overwrite::Always,
+            false,
+            Arc::clone(tracker),
+            Arc::clone(drive),
+            Arc::clone(config),
+        );
+        match pulled {
+            Ok(DResult::Err(e)) => {
+                error!("Failed to pull newer Drive copy of {:?}: {}", tf.path, e)
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to pull newer Drive copy of {:?}: {:?}", tf.path, e),
+        }
+        return;
+    }
+
+    info!(
+        "{:?} is newer than its Drive copy, uploading it over the Drive copy.",
+        tf.path
+    );
+    if config.versions.keep > 0 {
+        match save_remote_version(tf, drive, config.versions.keep) {
+            Ok(v) => info!("Saved the Drive copy of {:?} as version {}.", tf.path, v),
+            Err(e) => {
+                error!(
+                    "Not replacing the Drive copy of {:?}, it couldn't be saved first: {}",
+                    tf.path, e
+                );
+                return;
+            }
+        }
+    }
+    let _ = update_tracked(tf, tracker, drive, config);
+}
+
+// Download the Drive copy of tf into the local file's versions.
+fn save_remote_version(
+    tf: &TrackedFile,
+    drive: &SharedRemote,
+    keep: usize,
+) -> Result<String, String> {
+    let id = drive_id(&tf.drive_url).unwrap_or_default();
+    let tmp = std::env::temp_dir().join(format!("rgdrive-{}-{}", process::id(), id));
+    let result = drive
+        .lock()
+        .download(&tf.drive_url, &tmp)
+        .map_err(|e| e.to_string())
+        .and_then(|_| versions::save_from(&tf.path, &tmp, keep).map_err(|e| e.to_string()));
+    let _ = fs::remove_file(&tmp);
+    result
+}
+
+// How long a tracked file that was deleted or moved away has to come back, as a new file an editor saved, before it's
+// taken as gone for good.
+const REPLACE_GRACE: Duration = Duration::from_secs(5);
+
+// How many times `--push --wait` retries a temporary Drive failure, see session::Patient. Waits add up to about a
+// minute.
+const WAIT_RETRIES: u32 = 6;
+
+// Image types picked up from the screenshots directory.
+const SCREENSHOT_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+// Whether path looks like a finished screenshot. Hidden files are usually a tool's temp file, renamed once written.
+fn is_screenshot(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .map(|n| n.to_string_lossy().starts_with('.'))
+        .unwrap_or(true);
+    let image = path
+        .extension()
+        .map(|e| SCREENSHOT_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+        .unwrap_or(false);
+    !hidden && image && path.is_file()
+}
+
+// Whether a directory watcher event is for an editor temp file, which is dropped (and counted) right away.
+fn editor_temp(name: &OsStr) -> bool {
+    let temp = exclude::is_editor_temp(&name.to_string_lossy());
+    if temp {
+        Stats::incr(&STATS.events_filtered);
+    }
+    temp
+}
+
+// Upload a screenshot, share it by link and put the link on the clipboard. Returns the link.
+fn share_screenshot(path: &Path, drive: &SharedRemote, config: &Config) -> Result<String, String> {
+    hooks::pre_upload(&config.hooks.pre_upload, path)?;
+    let mut remote = drive.lock();
+    let folder = upload_folder(&mut **remote, config.screenshots.folder.as_deref(), config)?;
+    let url = upload(&mut **remote, path, folder.as_deref()).map_err(|e| e.to_string())?;
+    let id = drive_id(&url).ok_or_else(|| format!("Drive returned a bad url: {:?}", url))?;
+    let link = remote.share(id).map_err(|e| e.to_string())?;
+    drop(remote);
+    clipboard::copy(&config.screenshots.clipboard, &link)?;
+    Ok(link)
+}
+
+// Start watching the screenshots directory. Done before the daemon accepts commands so nothing written after startup
+// is missed.
+fn watch_screenshots(dir: &Path) -> Result<Inotify, Error> {
+    let mut inotify = Inotify::init()?;
+    inotify.add_watch(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+    Ok(inotify)
+}
+
+// Share every new image in the screenshots directory as soon as it's written (or moved in).
+fn screenshot_listen(mut inotify: Inotify, dir: PathBuf, drive: SharedRemote, config: Arc<Config>) {
+    // Some tools write a screenshot more than once (e.g. after cropping), each one is only shared the first time.
+    let mut shared: HashSet<PathBuf> = HashSet::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let paths: Vec<PathBuf> = match inotify.read_events_blocking(&mut buffer) {
+            Ok(events) => events
+                .filter_map(|e| e.name)
+                .filter(|n| !editor_temp(n))
+                .map(|n| dir.join(n))
+                .collect(),
+            Err(e) => {
+                error!("Failed to read screenshots directory events: {:?}", e);
+                return;
+            }
+        };
+        let _running = SHUTDOWN.begin();
+        if SHUTDOWN.requested() {
+            return;
+        }
+        for path in paths {
+            if shared.contains(&path) || !is_screenshot(&path) || excluded(&path, &dir, &config) {
+                continue;
+            }
+            let result = share_screenshot(&path, &drive, &config);
+            journal(
+                "screenshot",
+                &path,
+                result.as_ref().map(|l| l.as_str()).unwrap_or(""),
+                Direction::Up,
+                result.clone().map(|_| ()),
+            );
+            match result {
+                Ok(link) => {
+                    info!(
+                        "Shared screenshot {:?}, copied {} to the clipboard.",
+                        path, link
+                    );
+                    shared.insert(path);
+                }
+                Err(e) => error!("Failed to share screenshot {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+// Queue changes still held back (by [batching], [debounce] or [coalesce]) when the daemon stops, so they go up once it's
+// back instead of waiting for the files' next save.
+fn queue_held(held: Vec<PathBuf>, tracker: &Arc<Mutex<Tracker>>) {
+    for p in held {
+        let url = match tracker.lock().unwrap().find_by_path(&p) {
+            Some(tf) => tf.drive_url.clone(),
+            None => continue,
+        };
+        info!(
+            "Queued the held back change to {:?}, the daemon is stopping.",
+            p
+        );
+        let queued =
+            QUEUE
+                .lock()
+                .unwrap()
+                .failed("update", &p, &url, "held back when the daemon stopped");
+        if let Err(e) = queued {
+            error!("Error saving the retry queue: {:?}", e);
+        }
+    }
+}
+
+/// Listens forever for inotify events.
+fn inotify_listen(tracker: Arc<Mutex<Tracker>>, drive: SharedRemote, config: Arc<Config>) {
+    let mut buffer = [0; 1024];
+    // Changes still in the window when the daemon stops go up with the file's next save.
+    let mut window = Window::new(&config.batching);
+    let mut coalescer = Coalescer::new(&config.coalesce);
+    let mut debouncer = Debouncer::new(&config.debounce);
+    // Tracked files whose watch went with their old inode, since when, and whether it was deleted (rather than moved
+    // away), see Tracker::unwatch.
+    let mut replaced: Vec<(PathBuf, Instant, bool)> = Vec::new();
+    // Tracked files moved out of their directory, by the cookie pairing them with where they were moved to.
+    let mut moved_out: Vec<(u32, PathBuf, Instant)> = Vec::new();
+    let mut mounts = media::mountinfo();
+    // The watcher's held changes are only queued once it's seen the daemon is stopping, so it counts as running till then.
+    let running = SHUTDOWN.begin();
+    debug!("waiting for events..");
+    loop {
+        HEALTH.watcher_alive();
+        if SHUTDOWN.requested() {
+            let mut held = window.drain();
+            held.extend(debouncer.drain());
+            held.extend(coalescer.drain());
+            queue_held(held, &tracker);
+            drop(running);
+            return;
+        }
+        let events = tracker
+            .lock()
+            .unwrap()
+            .inotify
+            .read_events(&mut buffer)
+            .expect("Failed to read inotify events");
+
+        // Watches that saw a MODIFY during this read. A burst of writes to one file only needs a single upload.
+        let mut modified: Vec<WatchDescriptor> = Vec::new();
+        // Files and directories that appeared in tracked directories.
+        let mut discovered: Vec<PathBuf> = Vec::new();
+        // Tracked files moved (renamed) within or between watched directories, from and to.
+        let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for event in events {
+            Stats::incr(&STATS.events_read);
+            match event.mask {
+                // Stamping a synced file (see stamp) is an attrib event too, it isn't a change to upload.
+                EventMask::ATTRIB
+                    if event.name.is_none() && tracker.lock().unwrap().only_stamped(&event.wd) => {}
+                // attrib and close_write are only asked for by files configured for them, see WatchEvent.
+                EventMask::MODIFY | EventMask::ATTRIB | EventMask::CLOSE_WRITE
+                    if event.name.is_none() =>
+                {
+                    if modified.contains(&event.wd) {
+                        Stats::incr(&STATS.events_coalesced);
+                    } else {
+                        modified.push(event.wd);
+                    }
+                }
+                // Saved by renaming a new file over it (or moving the old one aside first), or deleted. One that doesn't
+                // come back is unsynced, and a deleted one's Drive copy goes the way of [deletes] (see delete_remote).
+                EventMask::DELETE_SELF | EventMask::MOVE_SELF if event.name.is_none() => {
+                    let now = Instant::now();
+                    let deleted = event.mask.contains(EventMask::DELETE_SELF);
+                    let paths = tracker.lock().unwrap().unwatch(&event.wd);
+                    for p in paths {
+                        debug!("{:?} was replaced or removed, watching for it again.", p);
+                        replaced.push((p, now, deleted));
+                    }
+                }
+                EventMask::Q_OVERFLOW => {
+                    Stats::incr(&STATS.event_overflows);
+                    warn!("Inotify event queue overflowed, some events were dropped.");
+                }
+                EventMask::MOVED_FROM => {
+                    let tracker = tracker.lock().unwrap();
+                    let from = match (tracker.find_parent_by_wd(&event.wd), event.name) {
+                        (Some(dir), Some(name)) => dir.join(name),
+                        _ => continue,
+                    };
+                    if tracker.find_by_path(&from).is_some() {
+                        moved_out.push((event.cookie, from, Instant::now()));
+                    }
+                }
+                // Moved to an editor's temp or backup name, it's being saved rather than renamed.
+                EventMask::MOVED_TO
+                    if moved_out.iter().any(|(c, ..)| *c == event.cookie)
+                        && !event.name.map(editor_temp).unwrap_or(true) =>
+                {
+                    let i = moved_out
+                        .iter()
+                        .position(|(c, ..)| *c == event.cookie)
+                        .unwrap();
+                    let (_, from, _) = moved_out.remove(i);
+                    let dir = tracker
+                        .lock()
+                        .unwrap()
+                        .find_parent_by_wd(&event.wd)
+                        .map(Path::to_path_buf);
+                    if let (Some(dir), Some(name)) = (dir, event.name) {
+                        moves.push((from, dir.join(name)));
+                    }
+                }
+                EventMask::IGNORED => tracker.lock().unwrap().forget_dir_watch(&event.wd),
+                EventMask::UNMOUNT => {
+                    if let Some(p) = tracker.lock().unwrap().unmounted(&event.wd) {
+                        info!(
+                            "{:?} is absent, its media was unmounted. Syncing it again once it's back.",
+                            p
+                        );
+                    }
+                }
+                // New files (once written) and subdirectories in tracked directories.
+                mask if mask.intersects(
+                    EventMask::CLOSE_WRITE | EventMask::MOVED_TO | EventMask::CREATE,
+                ) =>
+                {
+                    let name = match event.name {
+                        Some(n) if !editor_temp(n) => n,
+                        _ => continue,
+                    };
+                    // A new file is picked up once it's written, not while it's still empty.
+                    if mask.contains(EventMask::CREATE) && !mask.contains(EventMask::ISDIR) {
+                        continue;
+                    }
+                    if let Some(dir) = tracker.lock().unwrap().find_dir_by_wd(&event.wd) {
+                        let path = dir.join(name);
+                        if !discovered.contains(&path) {
+                            discovered.push(path);
+                        }
+                    }
+                }
+                // Skip all other events.
+                _ => {}
+            }
+        }
+        for path in discovered {
+            discover(&path, &tracker, &drive, &config);
+        }
+        // A moved file's watch went with it like a replaced one's, but it's followed to where it went instead.
+        for (from, to) in moves {
+            replaced.retain(|(p, ..)| *p != from);
+            follow_move(&from, &to, &tracker, &drive, &config);
+        }
+        // One moved somewhere that isn't watched is only gone, see replaced.
+        moved_out.retain(|(_, _, since)| since.elapsed() < REPLACE_GRACE);
+        // Absent files are looked for again whenever something is mounted or unmounted.
+        if tracker.lock().unwrap().has_absent() {
+            let now = media::mountinfo();
+            if now != mounts {
+                mounts = now;
+                let back = tracker.lock().unwrap().reattach();
+                for tf in back {
+                    reconcile_returned(&tf, &tracker, &drive, &config);
+                }
+            }
+        }
+
+        // Find the file associated with each modified wd and sync it, now or when the batching window closes. With
+        // [debounce], only once it's been quiet for a while.
+        let now = Instant::now();
+        let mut saved: Vec<TrackedFile> = modified
+            .iter()
+            .filter_map(|wd| tracker.lock().unwrap().find_by_wd(wd).cloned())
+            .collect();
+        // A replaced file is watched again as soon as the new one is there, and that's a change like any other. One
+        // still missing after REPLACE_GRACE was really deleted or moved away, and a deleted one's Drive copy goes the way
+        // of [deletes].
+        replaced.retain(|(p, since, deleted)| {
+            let rewatched = tracker.lock().unwrap().rewatch(p);
+            match rewatched {
+                Ok(Some(tf)) => {
+                    info!("{:?} was replaced, watching the new file.", p);
+                    saved.push(tf);
+                    false
+                }
+                Ok(None) if since.elapsed() < REPLACE_GRACE => true,
+                Ok(None) => {
+                    let gone = tracker.lock().unwrap().find_by_path(p).cloned();
+                    match tracker.lock().unwrap().remove_path(p) {
+                        Ok(_) => info!("{:?} was deleted or moved away locally, removing sync.", p),
+                        Err(e) => error!("{:?} is gone, failed to remove sync: {:?}", p, e),
+                    }
+                    if let (true, Some(tf)) = (*deleted, gone) {
+                        delete_remote(&tf, &tracker, &drive, &config);
+                    }
+                    false
+                }
+                Err(e) => {
+                    error!("Failed to watch {:?} again: {:?}", p, e);
+                    false
+                }
+            }
+        });
+        let mut changed: Vec<TrackedFile> = Vec::new();
+        for tf in saved {
+            CHANGED.seen(&tf.path);
+            // Modified and replaced in the same read, it's still one upload.
+            if changed.iter().any(|c| c.path == tf.path) {
+                debug!("Collapsing changes to {:?} into one upload.", &tf.path);
+                Stats::incr(&STATS.uploads_collapsed);
+            } else if !debouncer.enabled() {
+                changed.push(tf);
+            } else if !debouncer.touch(&tf.path, now) {
+                Stats::incr(&STATS.saves_debounced);
+            }
+        }
+        for p in debouncer.due(now) {
+            // Files unsynced while they were quieting down are skipped.
+            if let Some(tf) = tracker.lock().unwrap().find_by_path(&p) {
+                changed.push(tf.clone());
+            }
+        }
+        for tf in changed {
+            if !window.enabled() {
+                sync_change(&tf, &tracker, &drive, &config, &mut coalescer);
+            } else if !window.add(&tf.path) {
+                Stats::incr(&STATS.saves_batched);
+            }
+        }
+        if window.due(Instant::now()) {
+            let paths = window.drain();
+            debug!("Batching window closed, syncing {} file(s).", paths.len());
+            for p in paths {
+                // Files unsynced during the window are skipped.
+                let tf = match tracker.lock().unwrap().find_by_path(&p) {
+                    Some(tf) => tf.clone(),
+                    None => continue,
+                };
+                sync_change(&tf, &tracker, &drive, &config, &mut coalescer);
+            }
+        }
+        retry_queued(&tracker, &drive, &config);
+        // Changes held back by coalescing go up once their file's period is over.
+        for p in coalescer.due() {
+            let tf = match tracker.lock().unwrap().find_by_path(&p) {
+                Some(tf) => tf.clone(),
+                None => continue,
+            };
+            if let Ok(true) = update_tracked(&tf, &tracker, &drive, &config) {
+                coalescer.uploaded(&tf.path);
+            }
+        }
+        // debug!("Checking for events...");
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+// Sync the files stamped as synced (see stamp) at or beneath dir again, after the tracked files were lost. Each one is
+// reconciled like a file whose media came back, so changes made on either side meanwhile aren't lost.
+fn rebuild_state(
+    dir: PathBuf,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Arc<Config>,
+) -> DResult {
+    if !config.xattrs.enabled {
+        return DResult::error(
+            "Stamps are turned off ([xattrs] enabled = false), there's nothing to rebuild from.",
+        );
+    }
+    let (mut recovered, mut known, mut failed) = (0, 0, Vec::new());
+    for (path, s) in stamp::find(&dir) {
+        let path = canonical_path(&path);
+        if tracker.lock().unwrap().find_by_path(&path).is_some() {
+            known += 1;
+            continue;
+        }
+        let r = tracker.lock().unwrap().recover(&path, &s);
+        match r {
+            Ok(()) => {
+                recovered += 1;
+                let tf = tracker.lock().unwrap().find_by_path(&path).cloned();
+                if let Some(tf) = tf {
+                    reconcile_returned(&tf, tracker, drive, config);
+                }
+            }
+            Err(e) => failed.push(format!("{:?}: {}", path, e)),
+        }
+    }
+    let msg = format!(
+        "Recovered {} synced files from stamps in {:?}, {} were still tracked.",
+        recovered, dir, known
+    );
+    info!("{}", msg);
+    if failed.is_empty() {
+        DResult::ok(msg)
+    } else {
+        DResult::error(format!("{} Failed: {}", msg, failed.join(", ")))
+    }
+}
+
+// A file whose media was mounted again: whichever side changed while it was away is synced to the other. Changes on
+// both sides go by [reconcile] newest_wins if it's set, otherwise the local copy is uploaded like any other save.
+fn reconcile_returned(
+    tf: &TrackedFile,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Arc<Config>,
+) {
+    let local = checksum::md5_file(&tf.path).ok();
+    let local_changed = local.is_none() || local != tf.md5;
+    let remote = drive_id(&tf.drive_url).and_then(|id| drive.lock().metadata(id).ok());
+    let remote_changed = match (&remote, &tf.md5) {
+        (Some(m), Some(synced)) => m.md5.as_ref() != Some(synced),
+        _ => false,
+    };
+    match (local_changed, remote, remote_changed) {
+        (false, _, false) => info!("{:?} is back, nothing changed while it was away.", tf.path),
+        (true, Some(remote), true) if config.reconcile.newest_wins => {
+            newest_wins(tf, &remote, tracker, drive, config)
+        }
+        (true, _, _) => {
+            info!(
+                "{:?} is back and changed while it was away, uploading it.",
+                tf.path
+            );
+            let _ = update_tracked(tf, tracker, drive, config);
+        }
+        (false, _, true) => {
+            info!(
+                "{:?} is back, its Drive copy changed while it was away. Pulling it.",
+                tf.path
+            );
+            let pulled = pull(
+                tf.drive_url.clone(),
+                tf.path.clone(),
+                Overwrite::Always,
+                false,
+                Arc::clone(tracker),
+                Arc::clone(drive),
+                Arc::clone(config),
+            );
+            match pulled {
+                Ok(DResult::Err(e)) => error!("Failed to pull {:?}: {}", tf.path, e),
+                Ok(_) => {}
+                Err(e) => error!("Failed to pull {:?}: {:?}", tf.path, e),
+            }
+        }
+    }
+}
+
+// Sync a local change to tf: skipped if its content didn't actually change, held for approval in review mode, deferred
+// if it's a small change to a file uploaded within the [coalesce] period, uploaded otherwise.
+fn sync_change(
+    tf: &TrackedFile,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Config,
+    coalescer: &mut Coalescer,
+) {
+    // A touch, a save without changes, or saves queued one after the other (the first one's upload may already have
+    // taken the newest content up). Nothing to upload or review, unless the file is watched for attrib to upload its
+    // chmods too.
+    let attrib = tf.events(&config.events).contains(&WatchEvent::Attrib);
+    if !attrib && unchanged_on_drive(tf, tracker, drive) {
+        debug!(
+            "Collapsing save of {:?}, Drive already has its newest content.",
+            &tf.path
+        );
+        Stats::incr(&STATS.uploads_collapsed);
+        CHANGED.forget(&tf.path);
+        return;
+    }
+    if config.review.enabled {
+        match STAGED.lock().unwrap().stage(&tf.path, &tf.drive_url) {
+            Ok(_) => info!("Holding change to {:?} for review.", &tf.path),
+            Err(e) => error!("Error staging change to {:?}: {:?}", &tf.path, e),
+        }
+        return;
+    }
+    if let Some(churn) = coalescer.defer(&tf.path) {
+        info!(
+            "Deferring update of {:?}, {} changed since its last upload.",
+            &tf.path,
+            human_bytes(churn)
+        );
+        journal("coalesce", &tf.path, &tf.drive_url, Direction::None, Ok(()));
+        return;
+    }
+    if let Ok(true) = update_tracked(tf, tracker, drive, config) {
+        coalescer.uploaded(&tf.path);
+    }
+}
+
+// Whether tf's content hashes the same as what was last uploaded or pulled. Files synced before their md5 was kept are
+// checked against Drive's md5Checksum instead, which is then kept so the next check stays local.
+fn unchanged_on_drive(
+    tf: &TrackedFile,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+) -> bool {
+    let md5 = match checksum::md5_file(&tf.path) {
+        Ok(md5) => md5,
+        Err(_) => return false,
+    };
+    let synced = tracker
+        .lock()
+        .unwrap()
+        .find_by_path(&tf.path)
+        .and_then(|tf| tf.md5.clone());
+    if let Some(synced) = synced {
+        return md5 == synced;
+    }
+    let remote = drive_id(&tf.drive_url).and_then(|id| match drive.lock().metadata(id) {
+        Ok(m) => m.md5,
+        _ => None,
+    });
+    if remote.as_ref() != Some(&md5) {
+        return false;
+    }
+    if let Err(e) = tracker.lock().unwrap().mark_synced_as(&tf.path, Some(md5)) {
+        error!("Error saving the tracked files: {:?}", e);
+    }
+    true
+}
+
+// Upload the local copy of tf over its Drive file, journaling the result. Ok(false) if the file was deleted before it
+// could be uploaded.
+fn update_tracked(
+    tf: &TrackedFile,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Config,
+) -> Result<bool, String> {
+    // Its media was unmounted, it's reconciled once it's back.
+    let absent = tracker
+        .lock()
+        .unwrap()
+        .find_by_path(&tf.path)
+        .map(|tf| tf.absent)
+        .unwrap_or(false);
+    if absent {
+        debug!("Not updating absent {:?}.", &tf.path);
+        return Ok(false);
+    }
+    if vanished(&tf.path) {
+        forget_vanished(tf, tracker, config);
+        return Ok(false);
+    }
+    if let Err(e) = hooks::pre_upload(&config.hooks.pre_upload, &tf.path) {
+        warn!("Skipping update of {:?}: {}", &tf.path, e);
+        journal(
+            "update",
+            &tf.path,
+            &tf.drive_url,
+            Direction::Up,
+            Err(e.clone()),
+        );
+        sync_failed(tracker, &tf.path, &e);
+        return Err(e);
+    }
+    // Drive would only refuse it, keep the change queued for once `rgdrive --login` resumes sync.
+    if HEALTH.reauth_required() {
+        let e = format!("{}, run `rgdrive --login`", REAUTH_REQUIRED);
+        debug!("Not updating {:?}: {}", &tf.path, e);
+        if let Err(e) = QUEUE
+            .lock()
+            .unwrap()
+            .failed("update", &tf.path, &tf.drive_url, &e)
+        {
+            error!("Error saving the retry queue: {:?}", e);
+        }
+        return Err(e);
+    }
+    // Held back by the network environment, queued until `rgdrive env set` switches to one that lets it through.
+    let size = fs::metadata(&tf.path).map(|m| m.len()).unwrap_or(0);
+    if let Some(e) = ENVIRONMENT.holds(size) {
+        debug!("Not updating {:?}: {}", &tf.path, e);
+        if let Err(e) = QUEUE
+            .lock()
+            .unwrap()
+            .failed("update", &tf.path, &tf.drive_url, &e)
+        {
+            error!("Error saving the retry queue: {:?}", e);
+        }
+        return Err(e);
+    }
+    ENVIRONMENT.pace(size);
+    let mut drive = drive.lock();
+    if let Err(e) = config.policy.permits(&mut **drive, &tf.drive_url) {
+        warn!("Skipping update of {:?}: {}", &tf.path, e);
+        return Err(e);
+    }
+    UPLOADING.start(&tf.path);
+    let result = drive.update_hashed(&tf.path, &tf.drive_url);
+    UPLOADING.done(&tf.path);
+    match result {
+        Ok(md5) => {
+            drop(drive);
+            info!("Successfully updated file: {:?}", &tf.path);
+            if let Err(e) = tracker.lock().unwrap().mark_synced_as(&tf.path, md5) {
+                error!("Error saving the tracked files: {:?}", e);
+            }
+            let latency = CHANGED.uploaded(&tf.path);
+            if let Some(l) = latency {
+                LATENCY.record(l);
+            }
+            record(
+                Entry::new("update", &tf.path, &tf.drive_url, Direction::Up, Ok(()))
+                    .with_latency(latency),
+            );
+            if let Err(e) = QUEUE.lock().unwrap().done("update", &tf.path) {
+                error!("Error saving the retry queue: {:?}", e);
+            }
+            Ok(true)
+        }
+        // Deleted while it was being uploaded.
+        Err(_) if vanished(&tf.path) => {
+            drop(drive);
+            forget_vanished(tf, tracker, config);
+            Ok(false)
+        }
+        Err(e) => {
+            error!("Error updating file {:?} : {:?}", &tf.path, e);
+            journal(
+                "update",
+                &tf.path,
+                &tf.drive_url,
+                Direction::Up,
+                Err(e.to_string()),
+            );
+            sync_failed(tracker, &tf.path, &e.to_string());
+            // Queued to be retried, see --queue.
+            if let Err(e) =
+                QUEUE
+                    .lock()
+                    .unwrap()
+                    .failed("update", &tf.path, &tf.drive_url, &e.to_string())
+            {
+                error!("Error saving the retry queue: {:?}", e);
+            }
+            Err(e.to_string())
+        }
+    }
+}
+
+// Note a failed upload or pull of path for --list.
+fn sync_failed(tracker: &Arc<Mutex<Tracker>>, path: &Path, error: &str) {
+    if let Err(e) = tracker.lock().unwrap().sync_failed(path, error) {
+        error!("Error saving the tracked files: {:?}", e);
+    }
+}
+
+// A file deleted between its change and its upload (build artifacts, temp files) isn't an error and isn't retried.
+// It's reported once and skipped, with [vanished] untrack it's no longer synced either.
+fn forget_vanished(tf: &TrackedFile, tracker: &Arc<Mutex<Tracker>>, config: &Config) {
+    info!(
+        "{:?} was deleted before it could be uploaded, skipping it.",
+        &tf.path
+    );
+    journal("vanished", &tf.path, &tf.drive_url, Direction::None, Ok(()));
+    CHANGED.forget(&tf.path);
+    if let Err(e) = QUEUE.lock().unwrap().done("update", &tf.path) {
+        error!("Error saving the retry queue: {:?}", e);
+    }
+    if config.vanished.untrack {
+        match tracker.lock().unwrap().remove_path(&tf.path) {
+            Ok(_) => info!("Removed sync for deleted {:?}.", &tf.path),
+            Err(e) => error!("Error removing sync for deleted {:?}: {:?}", &tf.path, e),
+        }
+    }
+}
+
+// Retry the queued ops that are due. Ops on files no longer tracked have nothing to retry against and are dropped.
+fn retry_queued(tracker: &Arc<Mutex<Tracker>>, drive: &SharedRemote, config: &Config) {
+    if HEALTH.reauth_required() || ENVIRONMENT.holds(0).is_some() {
+        return;
+    }
+    let due = QUEUE.lock().unwrap().due(Utc::now().timestamp());
+    for op in due {
+        // The file of a queued trash or delete is gone already, only its Drive copy is left.
+        if let Some(policy) = DeletePolicy::from_name(&op.op) {
+            info!(
+                "Retrying {} of {:?}, attempt {}.",
+                op.op,
+                op.path,
+                op.attempts + 1
+            );
+            let _ = apply_delete(policy, &op.path, &op.drive_url, drive, config);
+            continue;
+        }
+        let tf = tracker.lock().unwrap().find_by_path(&op.path).cloned();
+        match tf {
+            Some(tf) => {
+                info!(
+                    "Retrying {} of {:?}, attempt {}.",
+                    op.op,
+                    op.path,
+                    op.attempts + 1
+                );
+                if op.op == "rename" {
+                    let _ = rename_moved(&tf, drive, config);
+                } else {
+                    let _ = update_tracked(&tf, tracker, drive, config);
+                }
+            }
+            None => {
+                info!(
+                    "Dropping queued {} of {:?}, it's no longer synced.",
+                    op.op, op.path
+                );
+                let _ = QUEUE.lock().unwrap().remove(op.id);
+            }
+        }
+    }
+}
+
+// Who the daemon is signed in as, for --status. Fields are empty when the remote can't say.
+fn account(drive: &SharedRemote) -> DResult {
+    let mut drive = drive.lock();
+    let account = drive.account().unwrap_or_default();
+    let scopes = drive.scopes().unwrap_or_default();
+    let expires = drive.token_expires();
+    DResult::fields(
+        format!(
+            "Signed in as {}.",
+            if account.is_empty() {
+                "an unknown account"
+            } else {
+                &account
+            }
+        ),
+        &[
+            ("account", account.clone()),
+            ("scopes", scopes.join(" ")),
+            (
+                "token_expires",
+                expires.map(|t| t.to_string()).unwrap_or_default(),
+            ),
+        ],
+    )
+}
+
+// The retry queue, for --queue.
+fn queue() -> DResult {
+    let queue = QUEUE.lock().unwrap();
+    if queue.ops().is_empty() {
+        return DResult::ok("Nothing queued.");
+    }
+    let now = Utc::now().timestamp();
+    let lines: Vec<String> = queue.ops().iter().map(|o| o.describe(now)).collect();
+    DResult::ok(lines.join("\n"))
+}
+
+// The tracked file at from was moved (or renamed) to to. It goes on syncing with the same Drive file from there, and
+// its Drive copy takes on the new name, unless it was pushed under a name of its own (--name).
+fn follow_move(
+    from: &Path,
+    to: &Path,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Config,
+) {
+    let moved = tracker.lock().unwrap().move_path(from, to);
+    let tf = match moved {
+        Ok(Some(tf)) => tf,
+        Ok(None) => {
+            debug!("Not following {:?} to {:?}, it's synced already.", from, to);
+            return;
+        }
+        Err(e) => {
+            error!("Failed to follow {:?} to {:?}: {:?}", from, to, e);
+            return;
+        }
+    };
+    info!(
+        "{:?} was moved to {:?}, syncing it from there.",
+        from, tf.path
+    );
+    journal("move", &tf.path, &tf.drive_url, Direction::None, Ok(()));
+    if let Err(e) = PINS.lock().unwrap().moved(from, &tf.path) {
+        error!("Error saving the pinned files: {:?}", e);
+    }
+    if let Err(e) = QUEUE.lock().unwrap().moved(from, &tf.path) {
+        error!("Error saving the retry queue: {:?}", e);
+    }
+    if tf.remote_name.is_none() && from.file_name() != tf.path.file_name() {
+        let _ = rename_moved(&tf, drive, config);
+    }
+}
+
+// Rename the Drive copy of tf to its file's name, after it was renamed locally. A failure is queued and retried like an
+// upload, one outside the [policy] allowed folder keeps its Drive name.
+fn rename_moved(tf: &TrackedFile, drive: &SharedRemote, config: &Config) -> Result<(), String> {
+    if let Err(e) = CAPABILITIES.require(Capability::Rename) {
+        warn!("{} Keeping the Drive name of {:?}.", e, tf.path);
+        return Err(e.to_string());
+    }
+    if let Err(e) = config.policy.permits(&mut **drive.lock(), &tf.drive_url) {
+        warn!("Keeping the Drive name of {:?}: {}", tf.path, e);
+        journal(
+            "rename",
+            &tf.path,
+            &tf.drive_url,
+            Direction::None,
+            Err(e.clone()),
+        );
+        if let Err(e) = QUEUE.lock().unwrap().done("rename", &tf.path) {
+            error!("Error saving the retry queue: {:?}", e);
+        }
+        return Err(e);
+    }
+    let id =
+        drive_id(&tf.drive_url).ok_or_else(|| format!("{:?} is not a drive url.", tf.drive_url))?;
+    let name = tf
+        .path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let result = drive.lock().rename(id, &name).map_err(|e| e.to_string());
+    journal(
+        "rename",
+        &tf.path,
+        &tf.drive_url,
+        Direction::None,
+        result.clone(),
+    );
+    let queued = match &result {
+        Ok(()) => {
+            info!("Renamed the Drive copy of {:?} to {:?}.", tf.path, name);
+            QUEUE.lock().unwrap().done("rename", &tf.path)
+        }
+        Err(e) => {
+            error!("Failed to rename the Drive copy of {:?}: {}", tf.path, e);
+            QUEUE
+                .lock()
+                .unwrap()
+                .failed("rename", &tf.path, &tf.drive_url, e)
+        }
+    };
+    if let Err(e) = queued {
+        error!("Error saving the retry queue: {:?}", e);
+    }
+    result
+}
+
+// Apply the [deletes] policy to tf, a synced file deleted locally. Its Drive copy is kept while another synced path (a
+// hard link) still uses it, or it's pinned (the pin puts the file back).
+fn delete_remote(
+    tf: &TrackedFile,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Config,
+) {
+    let policy = config.deletes.for_path(&tf.path);
+    if policy == DeletePolicy::Keep || tf.is_export() {
+        return;
+    }
+    let linked = tracker
+        .lock()
+        .unwrap()
+        .tracked_files
+        .iter()
+        .any(|o| o.drive_url == tf.drive_url);
+    if linked || PINS.lock().unwrap().is_pinned(&tf.path) {
+        info!(
+            "Keeping the Drive copy of {:?}, it's still synced elsewhere or pinned.",
+            tf.path
+        );
+        return;
+    }
+    if let Err(e) = CAPABILITIES.require(Capability::Delete) {
+        warn!("{} Keeping the Drive copy of {:?}.", e, tf.path);
+        return;
+    }
+    let _ = apply_delete(policy, &tf.path, &tf.drive_url, drive, config);
+}
+
+// Trash or delete drive_url, the Drive copy of path. A failure is queued and retried like an upload. One outside the
+// [policy] allowed folder is kept, and dropped from the queue.
+fn apply_delete(
+    policy: DeletePolicy,
+    path: &Path,
+    drive_url: &str,
+    drive: &SharedRemote,
+    config: &Config,
+) -> Result<(), String> {
+    let id = drive_id(drive_url).ok_or_else(|| format!("{:?} is not a drive url.", drive_url))?;
+    if policy == DeletePolicy::Keep {
+        return Ok(());
+    }
+    if let Err(e) = config.policy.permits(&mut **drive.lock(), drive_url) {
+        warn!("Keeping the Drive copy of {:?}: {}", path, e);
+        journal(
+            policy.name(),
+            path,
+            drive_url,
+            Direction::None,
+            Err(e.clone()),
+        );
+        if let Err(e) = QUEUE.lock().unwrap().done(policy.name(), path) {
+            error!("Error saving the retry queue: {:?}", e);
+        }
+        return Err(e);
+    }
+    let result = match policy {
+        DeletePolicy::Keep => return Ok(()),
+        DeletePolicy::Trash => drive.lock().trash(id),
+        DeletePolicy::Delete => drive.lock().delete(id),
+    }
+    .map_err(|e| e.to_string());
+    journal(
+        policy.name(),
+        path,
+        drive_url,
+        Direction::None,
+        result.clone(),
+    );
+    let queued = match &result {
+        Ok(()) => {
+            info!(
+                "{:?} was deleted locally, {} its Drive copy {}.",
+                path,
+                if policy == DeletePolicy::Trash {
+                    "trashed"
+                } else {
+                    "deleted"
+                },
+                drive_url
+            );
+            QUEUE.lock().unwrap().done(policy.name(), path)
+        }
+        Err(e) => {
+            error!(
+                "Failed to {} the Drive copy of {:?}: {}",
+                policy.name(),
+                path,
+                e
+            );
+            QUEUE
+                .lock()
+                .unwrap()
+                .failed(policy.name(), path, drive_url, e)
+        }
+    };
+    if let Err(e) = queued {
+        error!("Error saving the retry queue: {:?}", e);
+    }
+    result
+}
+
+// Retry a queued op right away, for --queue-retry.
+fn queue_retry(
+    id: u64,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Config,
+) -> DResult {
+    let op = match QUEUE.lock().unwrap().get(id) {
+        Some(op) => op.clone(),
+        None => return DResult::error(format!("Nothing queued with id {}.", id)),
+    };
+    if let Some(policy) = DeletePolicy::from_name(&op.op) {
+        return match apply_delete(policy, &op.path, &op.drive_url, drive, config) {
+            Ok(()) => DResult::ok(format!(
+                "Retried {} of {:?}, it went through.",
+                op.op, op.path
+            )),
+            Err(e) => DResult::error(format!("Retry of {} of {:?} failed: {}", op.op, op.path, e)),
+        };
+    }
+    let tf = match tracker.lock().unwrap().find_by_path(&op.path) {
+        Some(tf) => tf.clone(),
+        None => {
+            let _ = QUEUE.lock().unwrap().remove(id);
+            return DResult::error(format!(
+                "{:?} is no longer synced, dropped it from the queue.",
+                op.path
+            ));
+        }
+    };
+    if op.op == "rename" {
+        return match rename_moved(&tf, drive, config) {
+            Ok(()) => DResult::ok(format!(
+                "Retried {} of {:?}, it went through.",
+                op.op, op.path
+            )),
+            Err(e) => DResult::error(format!("Retry of {} of {:?} failed: {}", op.op, op.path, e)),
+        };
+    }
+    match update_tracked(&tf, tracker, drive, config) {
+        Ok(true) => DResult::ok(format!(
+            "Retried {} of {:?}, it went through.",
+            op.op, op.path
+        )),
+        Ok(false) => DResult::ok(format!(
+            "{:?} was deleted, dropped it from the queue.",
+            op.path
+        )),
+        Err(e) => DResult::error(format!("Retry of {} of {:?} failed: {}", op.op, op.path, e)),
+    }
+}
+
+// Find out which optional features the remote supports, so commands needing the others fail fast.
+fn probe_capabilities(remote: &mut dyn Remote) {
+    let caps = CAPABILITIES.probe(remote);
+    info!("Remote capabilities: {}", capabilities::names(&caps));
+}
+
+// The capability a command can't do without, if any.
+fn required_capability(command: &DCommand) -> Option<Capability> {
+    match command {
+        DCommand::Activity(_) => Some(Capability::Activity),
+        DCommand::Share(_) => Some(Capability::Share),
+        DCommand::Shortcut(..) => Some(Capability::Shortcuts),
+        DCommand::Ls(_) => Some(Capability::Folders),
+        DCommand::Drives => Some(Capability::Drives),
+        DCommand::PullStarred(_) => Some(Capability::Starred),
+        DCommand::PullDir(..) => Some(Capability::Folders),
+        DCommand::Pull(_, _, Overwrite::RemoteNewer, _) => Some(Capability::Metadata),
+        DCommand::Export(..) => Some(Capability::Export),
+        DCommand::RenameRemote(..) => Some(Capability::Rename),
+        DCommand::PushTo(..)
+        | DCommand::PushAs(_, _, Some(_))
+        | DCommand::PushWait(_, Some(_), _, _)
+        | DCommand::TrackDir(_, Some(_), _) => Some(Capability::Folders),
+        _ => None,
+    }
+}
+
+// Connect again once `rgdrive --login` has signed in anew, and resume sync if it was paused.
+fn login(drive: &SharedRemote) -> DResult {
+    let mut remote = match transfer::connect() {
+        Ok(r) => r,
+        Err(e) => return DResult::error(e.to_string()),
+    };
+    if let Err(e) = transfer::check_access(remote.as_mut()) {
+        return DResult::error(e.to_string());
+    }
+    probe_capabilities(remote.as_mut());
+    *drive.lock() = remote;
+    if !HEALTH.reauth_required() {
+        return DResult::ok("Signed in to Drive.");
+    }
+    HEALTH.reauthenticated();
+    let queued = match QUEUE.lock().unwrap().retry_all(Utc::now().timestamp()) {
+        Ok(n) => n,
+        Err(e) => {
+            error!("Error saving the retry queue: {:?}", e);
+            0
+        }
+    };
+    info!("Signed in to Drive again, sync resumed.");
+    DResult::ok(format!(
+        "Signed in to Drive, sync resumed. {} queued change(s) will be retried.",
+        queued
+    ))
+}
+
+// The environment in use, then every configured one.
+fn environment(config: &Config) -> DResult {
+    let active = ENVIRONMENT.name();
+    let mut lines = vec![match &active {
+        Some(name) => format!("Using the {} environment.", name),
+        None => String::from("No environment set."),
+    }];
+    for (name, env) in &config.environments {
+        let mut rules = Vec::new();
+        if env.max_upload_kbps > 0 {
+            rules.push(format!("uploads under {} KiB/s", env.max_upload_kbps));
+        }
+        if let Some(secs) = env.poll_interval_secs {
+            rules.push(format!("polls every {}s", secs));
+        }
+        if env.pause_uploads {
+            rules.push(String::from("uploads paused"));
+        } else if env.pause_above_bytes > 0 {
+            rules.push(format!(
+                "files over {} wait",
+                human_bytes(env.pause_above_bytes)
+            ));
+        }
+        lines.push(format!(
+            "{} {}: {}",
+            if active.as_ref() == Some(name) {
+                "*"
+            } else {
+                " "
+            },
+            name,
+            if rules.is_empty() {
+                String::from("no limits")
+            } else {
+                rules.join(", ")
+            }
+        ));
+    }
+    DResult::ok(lines.join("\n"))
+}
+
+// Switch to the environment name (none if None). Changes it held back are retried right away, in case it no longer
+// does.
+fn set_environment(name: Option<String>, config: &Config) -> DResult {
+    if let Err(e) = ENVIRONMENT.set(name.as_deref(), &config.environments) {
+        return DResult::error(e);
+    }
+    let queued = match QUEUE.lock().unwrap().retry_all(Utc::now().timestamp()) {
+        Ok(n) => n,
+        Err(e) => {
+            error!("Error saving the retry queue: {:?}", e);
+            0
+        }
+    };
+    let msg = match &name {
+        Some(name) => format!("Using the {} environment.", name),
+        None => String::from("No environment set."),
+    };
+    info!("{}", msg);
+    if queued > 0 {
+        DResult::ok(format!(
+            "{} {} queued change(s) will be retried.",
+            msg, queued
+        ))
+    } else {
+        DResult::ok(msg)
+    }
+}
+
+// Changes waiting for review, oldest first.
+fn pending() -> DResult {
+    let staged = STAGED.lock().unwrap();
+    if staged.changes().is_empty() {
+        return DResult::ok("Nothing waiting for review.");
+    }
+    let lines: Vec<String> = staged
+        .changes()
+        .iter()
+        .map(|c| format!("{}  {}", human_time(c.time), c.path.display()))
+        .collect();
+    DResult::ok(lines.join("\n"))
+}
+
+// Upload the changes held for review to path (or beneath it), or all of them. Anything that fails stays pending.
+fn approve(
+    path: Option<PathBuf>,
+    tracker: &Arc<Mutex<Tracker>>,
+    drive: &SharedRemote,
+    config: &Config,
+) -> DResult {
+    let path = path.map(|p| rgdrive::canonical_path(&p));
+    let changes = match STAGED.lock().unwrap().take(path.as_deref()) {
+        Ok(c) => c,
+        Err(e) => return DResult::error(format!("Error reading pending changes: {:?}", e)),
+    };
+    if changes.is_empty() {
+        return DResult::error(match path {
+            Some(p) => format!("Nothing pending for {:?}.", p),
+            None => String::from("Nothing waiting for review."),
+        });
+    }
+    let mut failed = Vec::new();
+    let (mut uploaded, mut deleted) = (0, 0);
+    for c in &changes {
+        // Files unsynced since the change are dropped, there's nothing to upload them to.
+        let tf = match tracker.lock().unwrap().find_by_path(&c.path) {
+            Some(tf) => tf.clone(),
+            None => continue,
+        };
+        match update_tracked(&tf, tracker, drive, config) {
+            Ok(true) => uploaded += 1,
+            Ok(false) => deleted += 1,
+            Err(e) => {
+                if let Err(e) = STAGED.lock().unwrap().stage(&c.path, &c.drive_url) {
+                    error!("Error re-staging {:?}: {:?}", c.path, e);
+                }
+                failed.push(format!("{:?}: {}", c.path, e));
+            }
+        }
+    }
+    if failed.is_empty() {
+        let mut msg = format!("Uploaded {} approved change(s).", uploaded);
+        if deleted > 0 {
+            msg.push_str(&format!(
+                " {} file(s) were deleted since, skipped them.",
+                deleted
+            ));
+        }
+        DResult::ok(msg)
+    } else {
+        DResult::error(format!(
+            "Uploaded {} approved change(s), {} failed and are still pending:\n{}",
+            uploaded,
+            failed.len(),
+            failed.join("\n")
+        ))
+    }
+}
+
+// Tracked files watched per lock of the tracker at startup, so commands aren't kept waiting behind a huge sync set.
+const WATCH_CHUNK: usize = 1000;
+
+// Watch every tracked file loaded at startup.
+fn watch_tracked(tracker: Arc<Mutex<Tracker>>) {
+    let start = Instant::now();
+    tracker.lock().unwrap().watch_dirs();
+    while tracker.lock().unwrap().watch_pending(WATCH_CHUNK) > 0 {}
+    let tracker = tracker.lock().unwrap();
+    let watched = tracker
+        .tracked_files
+        .iter()
+        .filter(|tf| tf.wd.is_some())
+        .count();
+    info!(
+        "Watching {} tracked files, took {:.1}s.",
+        watched,
+        start.elapsed().as_secs_f64()
+    );
+}
+
+// Remember the files whose last operation (in the past day) failed, so path status reports them from the start.
+fn load_failures() {
+    let now = Utc::now().timestamp();
+    match journal::entries_between(now - 24 * 3600, now) {
+        Ok(entries) => {
+            for e in journal::last_errors(&entries) {
+                FAILURES.record(&e.path, false);
+            }
+        }
+        Err(e) => warn!("Failed to read journal for recent failures: {:?}", e),
+    }
+}
+
+// Set the soft open file limit to the configured cap, never past the hard limit.
+fn apply_fd_limit(limits: &Limits) {
+    let mut max = match limits.max_open_files {
+        Some(m) => m,
+        None => return,
+    };
+    unsafe {
+        let mut rlim: libc::rlimit = std::mem::zeroed();
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) != 0 {
+            warn!(
+                "Failed to read open file limit, leaving it as is: {:?}",
+                Error::last_os_error()
+            );
+            return;
+        }
+        if rlim.rlim_max != libc::RLIM_INFINITY && max > rlim.rlim_max as u64 {
+            warn!(
+                "Open file limit {} is above the hard limit, using {}.",
+                max, rlim.rlim_max
+            );
+            max = rlim.rlim_max as u64;
+        }
+        rlim.rlim_cur = max as libc::rlim_t;
+        if libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) != 0 {
+            warn!(
+                "Failed to set open file limit to {}: {:?}",
+                max,
+                Error::last_os_error()
+            );
+            return;
+        }
+    }
+    info!("Open file limit set to {}.", max);
+}
+
+// Tell the configured hooks that the daemon's health changed. Failures here are only logged.
+fn alert(t: &Thresholds, reasons: &[String]) {
+    let status = if reasons.is_empty() {
+        "healthy"
+    } else {
+        "unhealthy"
+    };
+    if let Some(url) = &t.webhook {
+        let resp = ureq::post(url)
+            .timeout(Duration::from_secs(10))
+            .send_json(serde_json::json!({ "status": status, "reasons": reasons }));
+        if let Some(e) = resp.synthetic_error() {
+            warn!("Health webhook to {} failed: {}", url, e);
+        } else if !resp.ok() {
+            warn!("Health webhook to {} returned {}", url, resp.status());
+        }
+    }
+    if t.notify {
+        let body = if reasons.is_empty() {
+            String::from("Sync has recovered.")
+        } else {
+            reasons.join("\n")
+        };
+        if let Err(e) = Command::new("notify-send")
+            .arg(format!("rgdrive is {}", status))
+            .arg(body)
+            .status()
+        {
+            warn!("Couldn't send desktop notification: {:?}", e);
+        }
+    }
+}
+
+// Periodically check health against the configured thresholds, alerting whenever it flips.
+fn health_monitor(config: Arc<Config>) {
+    let t = &config.health;
+    loop {
+        thread::sleep(Duration::from_secs(t.check_interval_secs.max(1)));
+        let reasons = HEALTH.evaluate(t);
+        let unhealthy = !reasons.is_empty();
+        if HEALTH.set_unhealthy(unhealthy) == unhealthy {
+            continue;
+        }
+        if unhealthy {
+            error!("Daemon is unhealthy: {}", reasons.join("; "));
+        } else {
+            info!("Daemon is healthy again.");
+        }
+        alert(t, &reasons);
+    }
+}
+
+// Tell systemd's watchdog the daemon is alive, twice per timeout as sd_watchdog_enabled(3) suggests, for as long as
+// none of its loops is stuck (see Health::stalled). Once one is, systemd restarts the daemon.
+fn watchdog_ping(config: Arc<Config>, timeout: Duration) {
+    let stall = Duration::from_secs(config.health.stall_secs.max(1));
+    let mut stalled = false;
+    loop {
+        let reasons = HEALTH.stalled(stall);
+        if reasons.is_empty() {
+            if let Err(e) = watchdog::notify("WATCHDOG=1") {
+                warn!("Couldn't ping the systemd watchdog: {}", e);
+            }
+        } else if !stalled {
+            error!(
+                "Daemon is stuck, leaving it to the systemd watchdog: {}",
+                reasons.join("; ")
+            );
+        }
+        stalled = !reasons.is_empty();
+        thread::sleep(timeout / 2);
+    }
+}
+
+// Reads from stream, failing with TimedOut once deadline has passed however the client paces its writes.
+struct DeadlineReader<'a> {
+    stream: &'a UnixStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out"));
+        }
+        self.stream.set_read_timeout(Some(self.deadline - now))?;
+        let mut s = self.stream;
+        s.read(buf)
+    }
+}
+
+// Pid of the process on the other end of a connection.
+fn peer_pid(s: &UnixStream) -> Option<libc::pid_t> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            s.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret == 0 {
+        Some(cred.pid)
+    } else {
+        None
+    }
+}
+
+// Open connections per client pid, so a single misbehaving client can't occupy every worker.
+struct Clients {
+    active: Mutex<HashMap<libc::pid_t, usize>>,
+    max: usize,
+}
+
+// Command buffer bytes claimed by the connections being handled, out of limits.max_memory_bytes.
+struct Buffers {
+    used: Mutex<u64>,
+    max: u64,
+}
+
+// A connection's claim on the buffer budget, released when dropped.
+struct Reservation {
+    buffers: Arc<Buffers>,
+    bytes: u64,
+}
+
+impl Buffers {
+    fn reserve(buffers: &Arc<Buffers>, bytes: u64) -> Option<Reservation> {
+        let mut used = buffers.used.lock().unwrap();
+        if *used + bytes > buffers.max {
+            return None;
+        }
+        *used += bytes;
+        Some(Reservation {
+            buffers: Arc::clone(buffers),
+            bytes,
+        })
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        *self.buffers.used.lock().unwrap() -= self.bytes;
+    }
+}
+
+// A connection's claim on its client's allowance, released when dropped (after the worker is done with it).
+struct Slot {
+    clients: Arc<Clients>,
+    pid: Option<libc::pid_t>,
+}
+
+impl Clients {
+    fn acquire(clients: &Arc<Clients>, pid: Option<libc::pid_t>) -> Option<Slot> {
+        if let Some(pid) = pid {
+            let mut active = clients.active.lock().unwrap();
+            let n = active.entry(pid).or_insert(0);
+            if *n >= clients.max {
+                return None;
+            }
+            *n += 1;
+        }
+        Some(Slot {
+            clients: Arc::clone(clients),
+            pid,
+        })
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(pid) = self.pid {
+            let mut active = self.clients.active.lock().unwrap();
+            if let Some(n) = active.get_mut(&pid) {
+                *n -= 1;
+                if *n == 0 {
+                    active.remove(&pid);
+                }
+            }
+        }
+    }
+}
+
+// Spawn the connection worker threads. Streams sent on the returned channel are handled by whichever worker is free,
+// the channel only holds limits.max_queued streams so the accept loop can shed anything past that. Each stream travels
+// with its client Slot, which is released once the stream has been handled. A worker sheds the stream instead when its
+// command buffer wouldn't fit in limits.max_memory_bytes.
+fn spawn_workers(
+    tracker: Arc<Mutex<Tracker>>,
+    drive: SharedRemote,
+    config: Arc<Config>,
+) -> SyncSender<(UnixStream, Slot)> {
+    let (tx, rx) = mpsc::sync_channel::<(UnixStream, Slot)>(config.limits.max_queued);
+    let rx = Arc::new(Mutex::new(rx));
+    let buffers = Arc::new(Buffers {
+        used: Mutex::new(0),
+        max: config.limits.max_memory_bytes,
+    });
+    for i in 0..config.limits.worker_threads.max(1) {
+        let (rx, buffers, tracker, drive, config) = (
+            Arc::clone(&rx),
+            Arc::clone(&buffers),
+            Arc::clone(&tracker),
+            Arc::clone(&drive),
+            Arc::clone(&config),
+        );
+        thread::Builder::new()
+            .name(format!("worker-{}", i))
+            .spawn(move || loop {
+                // Only hold the receiver lock while waiting, not while handling the stream.
+                let (stream, _slot) = match rx.lock().unwrap().recv() {
+                    Ok(s) => s,
+                    Err(_) => return,
+                };
+                HEALTH.dequeued();
+                let _running = SHUTDOWN.begin();
+                if SHUTDOWN.requested() {
+                    reject(
+                        &stream,
+                        "Daemon is stopping.",
+                        config.limits.max_buffer_bytes,
+                    );
+                    continue;
+                }
+                // Shed the connection rather than go over the memory budget.
+                let _reservation = match Buffers::reserve(&buffers, config.limits.max_buffer_bytes)
+                {
+                    Some(r) => r,
+                    None => {
+                        warn!("Buffer budget spent, rejecting connection.");
+                        reject(
+                            &stream,
+                            "Daemon is busy, try again shortly.",
+                            config.limits.max_buffer_bytes,
+                        );
+                        continue;
+                    }
+                };
+                handle_stream(
+                    stream,
+                    Arc::clone(&tracker),
+                    Arc::clone(&drive),
+                    Arc::clone(&config),
+                );
+            })
+            .expect("failed to spawn worker thread");
+    }
+    tx
+}
+
+// Give up during startup. The error also goes to stderr as a StartupError line, which `rgdrive --start` relays.
+fn startup_failed<M: Into<String>>(code: Reason, message: M) -> ! {
+    let e = StartupError::new(code, message);
+    error!("{} Unable to continue.", e.message);
+    eprintln!("{}", e.to_line());
+    process::exit(code.exit_code());
+}
+
+fn main() {
+    crash::init();
+    // Check if socket exists already, if it does delete it. Unless another daemon is still listening on it.
+    let socket = socket_path();
+    if DSocket::new(&socket).is_active() {
+        startup_failed(
+            Reason::Socket,
+            format!("Another daemon is already listening on {:?}.", socket),
+        );
+    }
+    if socket.exists() {
+        if let Err(e) = fs::remove_file(&socket) {
+            startup_failed(
+                Reason::Socket,
+                format!("Couldn't remove stale socket {:?}: {}.", socket, e),
+            );
+        }
+    }
+
+    // Create unix domain socket listener on the socket path.
+    let listener = match UnixListener::bind(&socket) {
+        Ok(s) => s,
+        Err(e) => startup_failed(
+            Reason::Socket,
+            format!("Couldn't listen on socket {:?}: {}.", socket, e),
+        ),
+    };
+    info!("Daemon initialized.");
+
+    let config = match Config::load() {
+        Ok(c) => Arc::new(c),
+        Err(e) => startup_failed(
+            Reason::Config,
+            format!(
+                "Error loading config: {}. Run `rgdrive config check` to list every problem.",
+                e
+            ),
+        ),
+    };
+
+    apply_fd_limit(&config.limits);
+    media::configure(&config.media);
+    if let Some(name) = ENVIRONMENT.restore(&config.environments) {
+        info!("Using the {} environment.", name);
+    }
+    // Only path status needs these, no reason to make startup wait on reading the journal.
+    thread::spawn(load_failures);
+
+    // Initialize gdrive api client.
+    let mut remote = match transfer::connect() {
+        Ok(r) => r,
+        Err(ConnectError::Credentials(e)) => startup_failed(Reason::Credentials, e),
+        Err(ConnectError::Auth(e)) => startup_failed(Reason::Auth, e),
+    };
+    if let Err(e) = transfer::check_access(remote.as_mut()) {
+        startup_failed(Reason::Auth, e.to_string());
+    }
+    probe_capabilities(remote.as_mut());
+    if config.poll.interval_secs.is_some() {
+        if let Err(e) = CAPABILITIES.require(Capability::Metadata) {
+            warn!("{} Tracked files won't be checked for remote changes.", e);
+        }
+    } else if config.reconcile.newest_wins {
+        warn!("[reconcile] newest_wins needs [poll] to notice remote changes, it won't do anything without it.");
+    }
+    // Further connections are opened as transfers need them, see Pool.
+    let drive: SharedRemote = Arc::new(Pool::new(
+        remote,
+        transfer::connect,
+        config.transfers.concurrency,
+    ));
+
+    // Register so `rgdrive daemons` can find this profile's daemon.
+    let account = drive.lock().account().unwrap_or_else(|_| String::from("-"));
+    if let Err(e) = Instance::current(socket.clone(), account).register() {
+        warn!("Couldn't register daemon in {:?}: {:?}", daemons_dir(), e);
+    }
+
+    // Tracker hold inotify, and ensures that tracked files exist between sessions. Files are watched in the background,
+    // so a big sync set doesn't hold up startup.
+    let mut tracker = match Tracker::load() {
+        Ok(t) => t,
+        Err(e) => startup_failed(Reason::Other, e),
+    };
+    tracker.events = config.events.clone();
+    tracker.stamp = config.xattrs.enabled;
+    tracker.ignore = config.excludes.ignore.clone();
+    let recovered = batch::recover(&mut tracker);
+    if recovered > 0 {
+        info!(
+            "Tracked {} files left over from interrupted pushes.",
+            recovered
+        );
+    }
+    let tracker = Arc::new(Mutex::new(tracker));
+    let tracker_clone = Arc::clone(&tracker);
+    thread::spawn(move || watch_tracked(tracker_clone));
+
+    // Spawn a new thread which listens for and handles Inotify events.
     let tracker_clone = Arc::clone(&tracker);
     let drive_clone = Arc::clone(&drive);
+    let config_clone = Arc::clone(&config);
     thread::spawn(move || {
-        inotify_listen(tracker_clone, drive_clone);
+        inotify_listen(tracker_clone, drive_clone, config_clone);
+    });
+
+    let config_clone = Arc::clone(&config);
+    thread::spawn(move || health_monitor(config_clone));
+
+    if let Some(timeout) = watchdog::timeout() {
+        info!(
+            "Pinging the systemd watchdog, its timeout is {:?}.",
+            timeout
+        );
+        let config_clone = Arc::clone(&config);
+        thread::spawn(move || watchdog_ping(config_clone, timeout));
+    }
+
+    if let Some(secs) = config.poll.interval_secs {
+        let tracker_clone = Arc::clone(&tracker);
+        let drive_clone = Arc::clone(&drive);
+        let config_clone = Arc::clone(&config);
+        thread::spawn(move || {
+            remote_poll(
+                tracker_clone,
+                drive_clone,
+                config_clone,
+                Duration::from_secs(secs.max(1)),
+            )
+        });
+    }
+
+    if let Some(profile) = config.replica.profile.clone() {
+        let tracker_clone = Arc::clone(&tracker);
+        let interval = Duration::from_secs(config.replica.interval_secs.max(1));
+        thread::spawn(move || replicate(tracker_clone, profile, interval));
+    }
+
+    if config.cache.max_bytes > 0 {
+        let tracker_clone = Arc::clone(&tracker);
+        let config_clone = Arc::clone(&config);
+        let interval = Duration::from_secs(config.cache.interval_secs.max(1));
+        thread::spawn(move || cache_keeper(tracker_clone, config_clone, interval));
+    }
+
+    if let Some(dir) = config.screenshots.dir.clone() {
+        match watch_screenshots(&dir) {
+            Ok(inotify) => {
+                let drive_clone = Arc::clone(&drive);
+                let config_clone = Arc::clone(&config);
+                thread::spawn(move || screenshot_listen(inotify, dir, drive_clone, config_clone));
+            }
+            Err(e) => error!("Failed to watch screenshots directory {:?}: {:?}", dir, e),
+        }
+    }
+
+    let workers = spawn_workers(
+        Arc::clone(&tracker),
+        Arc::clone(&drive),
+        Arc::clone(&config),
+    );
+
+    let clients = Arc::new(Clients {
+        active: Mutex::new(HashMap::new()),
+        max: config.limits.max_connections_per_client.max(1),
     });
 
-    // Listen for and handle incoming streams on the socket.
+    // Listen for incoming streams on the socket and queue them for the workers.
     for stream in listener.incoming() {
+        HEALTH.accepting(true);
         match stream {
-            Ok(mut s) => {
-                handle_stream(s, Arc::clone(&tracker), Arc::clone(&drive));
+            Ok(s) => {
+                if SHUTDOWN.requested() {
+                    reject(&s, "Daemon is stopping.", config.limits.max_buffer_bytes);
+                    HEALTH.accepting(false);
+                    continue;
+                }
+                let slot = match Clients::acquire(&clients, peer_pid(&s)) {
+                    Some(slot) => slot,
+                    None => {
+                        warn!("Client has too many open connections, rejecting connection.");
+                        reject(
+                            &s,
+                            "Too many open connections from this client.",
+                            config.limits.max_buffer_bytes,
+                        );
+                        HEALTH.accepting(false);
+                        continue;
+                    }
+                };
+                HEALTH.enqueued();
+                match workers.try_send((s, slot)) {
+                    Ok(_) => {}
+                    // Every worker is busy and the queue is full, turn the client away instead of piling up connections.
+                    Err(TrySendError::Full((s, _))) => {
+                        HEALTH.rejected();
+                        warn!("Worker queue full, rejecting connection.");
+                        reject(
+                            &s,
+                            "Daemon is busy, try again shortly.",
+                            config.limits.max_buffer_bytes,
+                        );
+                    }
+                    Err(TrySendError::Disconnected(_)) => {
+                        error!("All worker threads have exited.");
+                        break;
+                    }
+                }
             }
             Err(e) => {
                 error!("stream err: {:?}", e);
@@ -352,5 +4164,6 @@ fn main() {
                 break;
             }
         }
+        HEALTH.accepting(false);
     }
 }
@@ -0,0 +1,369 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+
+use crate::capabilities::Capability;
+use crate::export::Export;
+use crate::remote::{
+    Activity, FileChange, Metadata, Quota, Remote, RemoteError, SharedDrive, SharedRemote,
+};
+use crate::transfer::{is_transient, token_expired, ConnectError};
+
+// Drive access tokens last an hour. Getting a new one a little before that means no request is refused for it.
+const TOKEN_EXPIRY: Duration = Duration::from_secs(60 * 60);
+const TOKEN_LIFETIME: Duration = Duration::from_secs(55 * 60);
+
+// How a Session connects, see transfer::connect.
+pub type Open = fn() -> Result<Box<dyn Remote>, ConnectError>;
+
+// A connection to Drive that stays signed in however long the daemon runs. It connects again, which trades the saved
+// refresh token for a new access token, once the access token is about to expire, and once more if Drive refuses a
+// request because it already has. A revoked token (see transfer::token_revoked) isn't retried, only signing in again
+// fixes that.
+pub struct Session {
+    remote: Box<dyn Remote>,
+    open: Open,
+    connected: Instant,
+}
+
+impl Session {
+    pub fn new(open: Open) -> Result<Session, ConnectError> {
+        Ok(Session {
+            remote: open()?,
+            open,
+            connected: Instant::now(),
+        })
+    }
+
+    fn reconnect(&mut self) -> Result<(), RemoteError> {
+        self.remote = (self.open)().map_err(|e| RemoteError::Api(e.to_string()))?;
+        self.connected = Instant::now();
+        Ok(())
+    }
+}
+
+impl Passthrough for Session {
+    fn call<T, F>(&mut self, mut f: F) -> Result<T, RemoteError>
+    where
+        F: FnMut(&mut dyn Remote) -> Result<T, RemoteError>,
+    {
+        if self.connected.elapsed() >= TOKEN_LIFETIME {
+            info!("Access token is about to expire, connecting to Drive again.");
+            // The old connection is kept if that fails (e.g. offline), it's tried again on the next request.
+            if let Err(e) = self.reconnect() {
+                warn!("Couldn't connect to Drive again: {}", e);
+            }
+        }
+        match f(self.remote.as_mut()) {
+            Err(RemoteError::Api(e)) if token_expired(&e) => {
+                info!(
+                    "Drive refused an expired access token, connecting again: {}",
+                    e
+                );
+                self.reconnect()?;
+                f(self.remote.as_mut())
+            }
+            r => r,
+        }
+    }
+
+    fn capabilities_of(&mut self) -> Vec<Capability> {
+        self.remote.capabilities()
+    }
+
+    fn token_expires_of(&mut self) -> Option<i64> {
+        self.remote.token_expires().or_else(|| {
+            let left = TOKEN_EXPIRY.checked_sub(self.connected.elapsed())?;
+            Some(Utc::now().timestamp() + left.as_secs() as i64)
+        })
+    }
+}
+
+// First retry of a Patient call is this long after it failed, doubling with each attempt.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+// The shared remote, with Drive's temporary failures (see transfer::is_transient) retried up to retries times, waiting
+// longer each time, before giving up. For `rgdrive --push --wait`, which answers once a push has definitively
+// succeeded or failed. The remote is only locked for each attempt, not while waiting.
+pub struct Patient {
+    remote: SharedRemote,
+    retries: u32,
+}
+
+impl Patient {
+    pub fn new(remote: SharedRemote, retries: u32) -> Patient {
+        Patient { remote, retries }
+    }
+}
+
+impl Passthrough for Patient {
+    fn call<T, F>(&mut self, mut f: F) -> Result<T, RemoteError>
+    where
+        F: FnMut(&mut dyn Remote) -> Result<T, RemoteError>,
+    {
+        let mut wait = RETRY_BACKOFF;
+        for _ in 0..self.retries {
+            let result = f(self.remote.lock().as_mut());
+            match result {
+                Err(RemoteError::Api(e)) if is_transient(&e) => {
+                    warn!("Drive failed ({}), trying again in {:?}.", e, wait);
+                    thread::sleep(wait);
+                    wait *= 2;
+                }
+                r => return r,
+            }
+        }
+        f(self.remote.lock().as_mut())
+    }
+
+    fn capabilities_of(&mut self) -> Vec<Capability> {
+        self.remote.lock().capabilities()
+    }
+
+    fn token_expires_of(&mut self) -> Option<i64> {
+        self.remote.lock().token_expires()
+    }
+}
+
+// A failure Faulty injects, with roughly what Drive answers for it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    RateLimit,
+    Timeout,
+    ServerError,
+}
+
+impl Fault {
+    fn parse(s: &str) -> Option<Fault> {
+        match s.trim() {
+            "ratelimit" | "429" => Some(Fault::RateLimit),
+            "timeout" => Some(Fault::Timeout),
+            "500" => Some(Fault::ServerError),
+            _ => None,
+        }
+    }
+
+    fn error(self) -> RemoteError {
+        RemoteError::Api(String::from(match self {
+            Fault::RateLimit => "429 Too Many Requests: rateLimitExceeded (injected)",
+            Fault::Timeout => "Request timed out (injected)",
+            Fault::ServerError => "500 Internal Server Error: backendError (injected)",
+        }))
+    }
+}
+
+// A remote that fails calls on purpose, to check retries, the queue, alerts and conflict handling behave before
+// trusting real data to them. Each call fails with probability, as one of faults picked at random. Turned on by
+// $RGDRIVE_INJECT_FAILURES, see transfer::connect.
+pub struct Faulty {
+    remote: Box<dyn Remote>,
+    probability: f64,
+    faults: Vec<Fault>,
+}
+
+impl Faulty {
+    pub fn new(remote: Box<dyn Remote>, probability: f64, faults: Vec<Fault>) -> Faulty {
+        Faulty {
+            remote,
+            probability,
+            faults,
+        }
+    }
+
+    // "<probability>[:<fault>,...]", e.g. "0.1" or "0.5:ratelimit,timeout". Faults are ratelimit (or 429), timeout and
+    // 500, all of them if none are given.
+    pub fn parse(spec: &str) -> Result<(f64, Vec<Fault>), String> {
+        let (probability, faults) = match spec.find(':') {
+            Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+            None => (spec, None),
+        };
+        let probability = probability
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|p| (0.0..=1.0).contains(p))
+            .ok_or_else(|| format!("{:?} isn't a probability between 0 and 1", probability))?;
+        let faults = match faults {
+            Some(faults) => faults
+                .split(',')
+                .map(|f| Fault::parse(f).ok_or_else(|| format!("unknown failure {:?}", f)))
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![Fault::RateLimit, Fault::Timeout, Fault::ServerError],
+        };
+        Ok((probability, faults))
+    }
+
+    fn roll(&self) -> Option<Fault> {
+        let mut bytes = [0; 4];
+        getrandom::getrandom(&mut bytes).ok()?;
+        let n = u32::from_le_bytes(bytes);
+        if f64::from(n) / f64::from(u32::MAX) >= self.probability || self.faults.is_empty() {
+            return None;
+        }
+        Some(self.faults[n as usize % self.faults.len()])
+    }
+}
+
+impl Passthrough for Faulty {
+    fn call<T, F>(&mut self, mut f: F) -> Result<T, RemoteError>
+    where
+        F: FnMut(&mut dyn Remote) -> Result<T, RemoteError>,
+    {
+        match self.roll() {
+            Some(fault) => {
+                debug!("Injecting a {:?} failure.", fault);
+                Err(fault.error())
+            }
+            None => f(self.remote.as_mut()),
+        }
+    }
+
+    fn capabilities_of(&mut self) -> Vec<Capability> {
+        self.remote.capabilities()
+    }
+
+    fn token_expires_of(&mut self) -> Option<i64> {
+        self.remote.token_expires()
+    }
+}
+
+// Remotes that pass every call on to another one, doing something around it.
+trait Passthrough: Send {
+    fn call<T, F>(&mut self, f: F) -> Result<T, RemoteError>
+    where
+        F: FnMut(&mut dyn Remote) -> Result<T, RemoteError>;
+
+    fn capabilities_of(&mut self) -> Vec<Capability>;
+
+    fn token_expires_of(&mut self) -> Option<i64>;
+}
+
+impl<P: Passthrough> Remote for P {
+    fn upload(&mut self, path: &Path) -> Result<String, RemoteError> {
+        self.call(|r| r.upload(path))
+    }
+
+    fn upload_to(&mut self, path: &Path, folder_id: &str) -> Result<String, RemoteError> {
+        self.call(|r| r.upload_to(path, folder_id))
+    }
+
+    fn download(&mut self, url: &str, path: &Path) -> Result<PathBuf, RemoteError> {
+        self.call(|r| r.download(url, path))
+    }
+
+    fn update(&mut self, path: &Path, url: &str) -> Result<(), RemoteError> {
+        self.call(|r| r.update(path, url))
+    }
+
+    fn upload_hashed(
+        &mut self,
+        path: &Path,
+        folder_id: Option<&str>,
+    ) -> Result<(String, Option<String>), RemoteError> {
+        self.call(|r| r.upload_hashed(path, folder_id))
+    }
+
+    fn download_hashed(
+        &mut self,
+        url: &str,
+        path: &Path,
+    ) -> Result<(PathBuf, Option<String>), RemoteError> {
+        self.call(|r| r.download_hashed(url, path))
+    }
+
+    fn update_hashed(&mut self, path: &Path, url: &str) -> Result<Option<String>, RemoteError> {
+        self.call(|r| r.update_hashed(path, url))
+    }
+
+    fn ancestors(&mut self, id: &str) -> Result<Vec<String>, RemoteError> {
+        self.call(|r| r.ancestors(id))
+    }
+
+    fn metadata(&mut self, id: &str) -> Result<Metadata, RemoteError> {
+        self.call(|r| r.metadata(id))
+    }
+
+    fn changes_token(&mut self) -> Result<String, RemoteError> {
+        self.call(|r| r.changes_token())
+    }
+
+    fn changes(
+        &mut self,
+        token: &str,
+        page_size: Option<usize>,
+    ) -> Result<(Vec<FileChange>, String), RemoteError> {
+        self.call(|r| r.changes(token, page_size))
+    }
+
+    fn activity(&mut self, id: &str) -> Result<Vec<Activity>, RemoteError> {
+        self.call(|r| r.activity(id))
+    }
+
+    fn export(&mut self, id: &str, export: &Export, path: &Path) -> Result<PathBuf, RemoteError> {
+        self.call(|r| r.export(id, export, path))
+    }
+
+    fn starred(&mut self) -> Result<Vec<Metadata>, RemoteError> {
+        self.call(|r| r.starred())
+    }
+
+    fn list_folder(&mut self, folder_id: &str) -> Result<Vec<Metadata>, RemoteError> {
+        self.call(|r| r.list_folder(folder_id))
+    }
+
+    fn share(&mut self, id: &str) -> Result<String, RemoteError> {
+        self.call(|r| r.share(id))
+    }
+
+    fn create_folder(&mut self, name: &str, parent_id: &str) -> Result<String, RemoteError> {
+        self.call(|r| r.create_folder(name, parent_id))
+    }
+
+    fn rename(&mut self, id: &str, name: &str) -> Result<(), RemoteError> {
+        self.call(|r| r.rename(id, name))
+    }
+
+    fn trash(&mut self, id: &str) -> Result<(), RemoteError> {
+        self.call(|r| r.trash(id))
+    }
+
+    fn delete(&mut self, id: &str) -> Result<(), RemoteError> {
+        self.call(|r| r.delete(id))
+    }
+
+    fn create_shortcut(
+        &mut self,
+        target_id: &str,
+        name: &str,
+        parent_id: &str,
+    ) -> Result<String, RemoteError> {
+        self.call(|r| r.create_shortcut(target_id, name, parent_id))
+    }
+
+    fn quota(&mut self) -> Result<Quota, RemoteError> {
+        self.call(|r| r.quota())
+    }
+
+    fn shared_drives(&mut self) -> Result<Vec<SharedDrive>, RemoteError> {
+        self.call(|r| r.shared_drives())
+    }
+
+    fn account(&mut self) -> Result<String, RemoteError> {
+        self.call(|r| r.account())
+    }
+
+    fn scopes(&mut self) -> Result<Vec<String>, RemoteError> {
+        self.call(|r| r.scopes())
+    }
+
+    fn token_expires(&mut self) -> Option<i64> {
+        self.token_expires_of()
+    }
+
+    fn capabilities(&mut self) -> Vec<Capability> {
+        self.capabilities_of()
+    }
+}
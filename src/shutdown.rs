@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+// Stopping the daemon without cutting transfers short (`rgdrive --stop`). Once it's requested no new work is started,
+// and the daemon waits for what's running to finish, up to GRACE, before it exits.
+
+// Longest the daemon waits for running work once asked to stop. `rgdrive --stop` waits a little longer than this.
+pub const GRACE: Duration = Duration::from_secs(30);
+
+pub struct Shutdown {
+    requested: AtomicBool,
+    // How much work is under way: commands being handled, passes of the daemon's loops.
+    running: Mutex<usize>,
+    idle: Condvar,
+}
+
+lazy_static! {
+    pub static ref SHUTDOWN: Shutdown = Shutdown {
+        requested: AtomicBool::new(false),
+        running: Mutex::new(0),
+        idle: Condvar::new(),
+    };
+}
+
+// Work the daemon waits for before exiting, for as long as it's held.
+pub struct Running<'a> {
+    shutdown: &'a Shutdown,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        *self.shutdown.running.lock().unwrap() -= 1;
+        self.shutdown.idle.notify_all();
+    }
+}
+
+impl Shutdown {
+    // Stop starting new work. Returns false if it already was.
+    pub fn request(&self) -> bool {
+        !self.requested.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    // Note work starting. Work should check requested after this, not before, so none starts unnoticed once the
+    // daemon is stopping.
+    pub fn begin(&self) -> Running<'_> {
+        *self.running.lock().unwrap() += 1;
+        Running { shutdown: self }
+    }
+
+    // Wait until nothing but own (the caller's own work) is running, or timeout passes. Returns how much else is still
+    // running.
+    pub fn wait_idle(&self, own: usize, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let mut running = self.running.lock().unwrap();
+        while *running > own {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            running = self.idle.wait_timeout(running, deadline - now).unwrap().0;
+        }
+        running.saturating_sub(own)
+    }
+}
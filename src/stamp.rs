@@ -0,0 +1,75 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+
+use crate::remote::drive_id;
+use crate::xattr;
+
+// Synced files carry their Drive id, the md5 they were last synced at and when, in user.rgdrive.* extended
+// attributes. If the tracked files are lost, `rgdrive --rebuild-state` recovers what was synced from them, and other
+// tools can tell which Drive file a local one is without asking the daemon. [xattrs] enabled = false turns it off, for
+// filesystems without extended attributes.
+
+pub const ID_ATTR: &str = "user.rgdrive.id";
+const MD5_ATTR: &str = "user.rgdrive.md5";
+// When it was last synced, a unix timestamp (seconds).
+const SYNCED_ATTR: &str = "user.rgdrive.synced";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stamp {
+    pub drive_id: String,
+    pub md5: Option<String>,
+    pub synced: i64,
+}
+
+impl Stamp {
+    pub fn drive_url(&self) -> String {
+        format!("https://drive.google.com/open?id={}", self.drive_id)
+    }
+}
+
+// Stamp path as synced with drive_url at md5, now.
+pub fn write(path: &Path, drive_url: &str, md5: Option<&str>) -> io::Result<()> {
+    let id = drive_id(drive_url).unwrap_or(drive_url);
+    xattr::set(path, ID_ATTR, id)?;
+    match md5 {
+        Some(m) => xattr::set(path, MD5_ATTR, m)?,
+        None => xattr::remove(path, MD5_ATTR)?,
+    }
+    xattr::set(path, SYNCED_ATTR, &Utc::now().timestamp().to_string())
+}
+
+pub fn read(path: &Path) -> Option<Stamp> {
+    Some(Stamp {
+        drive_id: xattr::get(path, ID_ATTR)?,
+        md5: xattr::get(path, MD5_ATTR),
+        synced: xattr::get(path, SYNCED_ATTR)?.parse().ok()?,
+    })
+}
+
+// Whether path was stamped as synced, as opposed to only carrying a Drive id (see placeholder).
+pub fn is_stamped(path: &Path) -> bool {
+    xattr::get(path, SYNCED_ATTR).is_some()
+}
+
+// Unstamp a file that's no longer synced.
+pub fn clear(path: &Path) -> io::Result<()> {
+    for attr in &[ID_ATTR, MD5_ATTR, SYNCED_ATTR] {
+        xattr::remove(path, attr)?;
+    }
+    Ok(())
+}
+
+// Every stamped file at or beneath path, with its stamp.
+pub fn find(path: &Path) -> Vec<(PathBuf, Stamp)> {
+    let paths = if path.is_dir() {
+        crate::get_subpaths(&path.to_path_buf())
+    } else {
+        vec![path.to_path_buf()]
+    };
+    paths
+        .into_iter()
+        .filter_map(|p| read(&p).map(|s| (p, s)))
+        .collect()
+}
@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Row, NO_PARAMS};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::remote::drive_id;
+use crate::{canonical_path, TrackedFile};
+
+// Sync state of the tracked files, one row each in a SQLite database (see state_path()). It replaces the bincode
+// tracked_files list, which came back empty on any error reading it and had no room for more about each file. Each
+// change writes only the rows it touched, and a batch is a single transaction, so a crash never leaves half of one
+// behind. The cursors table keeps where each reader of Drive's changes feed left off, see poll::Poller.
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS tracked_files (
+    path BLOB PRIMARY KEY,
+    drive_url TEXT NOT NULL,
+    file_id TEXT,
+    revision TEXT,
+    md5 TEXT,
+    mtime INTEGER,
+    synced_at INTEGER,
+    last_error TEXT,
+    mime_type TEXT,
+    remote_name TEXT,
+    export TEXT,
+    events TEXT,
+    media TEXT
+);
+CREATE TABLE IF NOT EXISTS cursors (
+    name TEXT PRIMARY KEY,
+    token TEXT NOT NULL
+)";
+
+// Every SQLite database starts with this.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+// A row keeps its rowid when it's updated, and with it its place in load's order.
+const UPSERT: &str = "INSERT INTO tracked_files (path, drive_url, file_id, revision, md5, mtime, synced_at, last_error, \
+    mime_type, remote_name, export, events, media) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13) \
+    ON CONFLICT (path) DO UPDATE SET drive_url = excluded.drive_url, file_id = excluded.file_id, \
+    revision = excluded.revision, md5 = excluded.md5, mtime = excluded.mtime, synced_at = excluded.synced_at, \
+    last_error = excluded.last_error, mime_type = excluded.mime_type, remote_name = excluded.remote_name, \
+    export = excluded.export, events = excluded.events, media = excluded.media";
+
+pub struct State {
+    conn: Connection,
+}
+
+impl State {
+    // Open the database at path, creating it if needed.
+    pub fn open(path: &Path) -> Result<State, String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{:?}: {}", parent, e))?;
+        }
+        let conn = Connection::open(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        conn.execute_batch(SCHEMA)
+            .map_err(|e| format!("{:?}: {}", path, e))?;
+        Ok(State { conn })
+    }
+
+    // Every tracked file, in the order they were saved.
+    pub fn load(&self) -> rusqlite::Result<Vec<TrackedFile>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT path, drive_url, revision, md5, mtime, synced_at, last_error, mime_type, remote_name, export, \
+             events, media FROM tracked_files ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map(NO_PARAMS, from_row)?;
+        rows.collect()
+    }
+
+    // Save tf, in place of the row saved at its path if there is one.
+    pub fn upsert(&self, tf: &TrackedFile) -> rusqlite::Result<()> {
+        upsert(&self.conn, tf)
+    }
+
+    // Save every one of files in a single transaction, like upsert. Rows at other paths are kept.
+    pub fn upsert_all(&mut self, files: &[TrackedFile]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        for tf in files {
+            upsert(&tx, tf)?;
+        }
+        tx.commit()
+    }
+
+    // Forget the file saved at path, if there is one.
+    pub fn delete(&self, path: &Path) -> rusqlite::Result<()> {
+        self.conn
+            .execute(
+                "DELETE FROM tracked_files WHERE path = ?1",
+                params![path.as_os_str().as_bytes()],
+            )
+            .map(|_| ())
+    }
+
+    // The page token saved as name, None if there isn't one.
+    pub fn cursor(&self, name: &str) -> rusqlite::Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT token FROM cursors WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    // Save each of cursors' tokens under its name, or with None forget it, in a single transaction.
+    pub fn set_cursors(&mut self, cursors: &[(String, Option<String>)]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        for (name, token) in cursors {
+            set_cursor(&tx, name, token.as_deref())?;
+        }
+        tx.commit()
+    }
+}
+
+fn set_cursor(conn: &Connection, name: &str, token: Option<&str>) -> rusqlite::Result<()> {
+    match token {
+        Some(token) => conn.execute(
+            "INSERT INTO cursors (name, token) VALUES (?1, ?2) \
+             ON CONFLICT (name) DO UPDATE SET token = excluded.token",
+            params![name, token],
+        ),
+        None => conn.execute("DELETE FROM cursors WHERE name = ?1", params![name]),
+    }
+    .map(|_| ())
+}
+
+fn upsert(conn: &Connection, tf: &TrackedFile) -> rusqlite::Result<()> {
+    conn.prepare_cached(UPSERT)?.execute(params![
+        tf.path.as_os_str().as_bytes(),
+        tf.drive_url,
+        drive_id(&tf.drive_url),
+        tf.revision,
+        tf.md5,
+        tf.mtime,
+        tf.synced_at,
+        tf.last_error,
+        tf.mime_type,
+        tf.remote_name,
+        to_json(&tf.export),
+        to_json(&tf.events),
+        to_json(&tf.media),
+    ])?;
+    Ok(())
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<TrackedFile> {
+    let path: Vec<u8> = row.get(0)?;
+    Ok(TrackedFile {
+        revision: row.get(2)?,
+        md5: row.get(3)?,
+        mtime: row.get(4)?,
+        synced_at: row.get(5)?,
+        last_error: row.get(6)?,
+        mime_type: row.get(7)?,
+        remote_name: row.get(8)?,
+        export: from_json(row.get(9)?),
+        events: from_json(row.get(10)?),
+        media: from_json(row.get(11)?),
+        ..TrackedFile::new(
+            PathBuf::from(OsStr::from_bytes(&path)),
+            row.get::<_, String>(1)?,
+        )
+    })
+}
+
+// Columns holding more than a plain value keep it as json, NULL for None.
+fn to_json<T: Serialize>(v: &Option<T>) -> Option<String> {
+    v.as_ref().and_then(|v| serde_json::to_string(v).ok())
+}
+
+fn from_json<T: DeserializeOwned>(s: Option<String>) -> Option<T> {
+    serde_json::from_str(&s?).ok()
+}
+
+// Whether the file at path is a SQLite database, as opposed to a bincode tracked_files list.
+pub fn is_database(path: &Path) -> bool {
+    let mut header = [0; 16];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map(|_| &header == SQLITE_HEADER)
+        .unwrap_or(false)
+}
+
+// The tracked files in the database at path, without creating or changing it (e.g. for the cli while the daemon runs).
+pub fn read(path: &Path) -> rusqlite::Result<Vec<TrackedFile>> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    State { conn }.load()
+}
+
+// Move the files of a bincode tracked_files list at legacy into state, then rename it to tracked_files.migrated. A list
+// that can't be read is an error and left where it is, never silently replaced by an empty one. Files already in state
+// stay, the list's take over only at the paths it has too. Returns how many files were moved.
+pub fn migrate(state: &mut State, legacy: &Path) -> Result<usize, String> {
+    let mut buf = Vec::new();
+    File::open(legacy)
+        .and_then(|mut f| f.read_to_end(&mut buf))
+        .map_err(|e| format!("Couldn't read {:?}: {}", legacy, e))?;
+    let files = TrackedFile::decode_all(&buf).map_err(|e| {
+        format!(
+            "Couldn't read the tracked files in {:?} ({}). Move it aside to start without them.",
+            legacy, e
+        )
+    })?;
+    // Unversioned lists may hold paths from before they were canonicalized, and track a file more than once.
+    let canonicalize = TrackedFile::version(&buf) == 0;
+    let mut seen: HashSet<PathBuf> = HashSet::with_capacity(files.len());
+    let mut kept = Vec::with_capacity(files.len());
+    for mut tf in files {
+        if canonicalize {
+            tf.path = canonical_path(&tf.path);
+        }
+        if !seen.insert(tf.path.clone()) {
+            log::warn!("Dropping duplicate tracked file {:?}", tf);
+            continue;
+        }
+        kept.push(tf);
+    }
+    state
+        .upsert_all(&kept)
+        .map_err(|e| format!("Couldn't save the tracked files from {:?}: {}", legacy, e))?;
+    fs::rename(legacy, legacy.with_extension("migrated"))
+        .map_err(|e| format!("Couldn't rename {:?} once migrated: {}", legacy, e))?;
+    Ok(kept.len())
+}
@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+// Cap on remembered sync latencies, the percentiles are over the most recent ones.
+const MAX_LATENCIES: usize = 1000;
+
+// Daemon wide counters, reported by --stats.
+pub struct Stats {
+    // Every inotify event read off the queue.
+    pub events_read: AtomicU64,
+    // MODIFY events folded into an upload already queued from the same read.
+    pub events_coalesced: AtomicU64,
+    // Saves to a file already waiting in the [batching] window, folded into its one upload.
+    pub saves_batched: AtomicU64,
+    // Changes to a file still waiting out its [debounce] quiet period, folded into its one upload.
+    pub saves_debounced: AtomicU64,
+    // Saves not uploaded because Drive already had the file's newest content, e.g. a touch, or a second save whose content
+    // went up with the first one's upload.
+    pub uploads_collapsed: AtomicU64,
+    // Number of times the kernel queue overflowed (IN_Q_OVERFLOW). The kernel doesn't say how many events were lost.
+    pub event_overflows: AtomicU64,
+    // Directory watcher events for editor temp files, dropped before they're looked at.
+    pub events_filtered: AtomicU64,
+    // Polls of Drive's changes feed.
+    pub remote_polls: AtomicU64,
+    // Tracked files found changed on Drive since the previous poll.
+    pub remote_changes: AtomicU64,
+    // Cached files turned back into placeholders to keep [cache] under max_bytes.
+    pub files_evicted: AtomicU64,
+}
+
+pub static STATS: Stats = Stats {
+    events_read: AtomicU64::new(0),
+    events_coalesced: AtomicU64::new(0),
+    saves_batched: AtomicU64::new(0),
+    saves_debounced: AtomicU64::new(0),
+    uploads_collapsed: AtomicU64::new(0),
+    event_overflows: AtomicU64::new(0),
+    events_filtered: AtomicU64::new(0),
+    remote_polls: AtomicU64::new(0),
+    remote_changes: AtomicU64::new(0),
+    files_evicted: AtomicU64::new(0),
+};
+
+impl Stats {
+    pub fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> String {
+        format!(
+            "inotify events read: {}\ninotify events coalesced: {}\nsaves batched: {}\nsaves debounced: {}\n\
+             uploads collapsed: {}\ninotify queue overflows: {}\n\
+             inotify events filtered: {}\n\
+             remote polls: {}\nremote changes seen: {}\n\
+             files evicted: {}\n{}",
+            self.events_read.load(Ordering::Relaxed),
+            self.events_coalesced.load(Ordering::Relaxed),
+            self.saves_batched.load(Ordering::Relaxed),
+            self.saves_debounced.load(Ordering::Relaxed),
+            self.uploads_collapsed.load(Ordering::Relaxed),
+            self.event_overflows.load(Ordering::Relaxed),
+            self.events_filtered.load(Ordering::Relaxed),
+            self.remote_polls.load(Ordering::Relaxed),
+            self.remote_changes.load(Ordering::Relaxed),
+            self.files_evicted.load(Ordering::Relaxed),
+            LATENCY.report(),
+        )
+    }
+}
+
+// Time from a local change being seen to its upload completing, per upload (see status::CHANGED).
+pub struct Latency {
+    // (when it was recorded, milliseconds), oldest first.
+    samples: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+lazy_static! {
+    pub static ref LATENCY: Latency = Latency {
+        samples: Mutex::new(VecDeque::new()),
+    };
+}
+
+// Sync latency percentiles, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percentiles {
+    pub count: usize,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl Latency {
+    pub fn record(&self, latency: Duration) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() >= MAX_LATENCIES {
+            samples.pop_front();
+        }
+        samples.push_back((Instant::now(), latency.as_millis() as u64));
+    }
+
+    // Percentiles of the uploads recorded within the last window (all of those remembered if None). None if there
+    // weren't any.
+    pub fn percentiles(&self, window: Option<Duration>) -> Option<Percentiles> {
+        let mut ms: Vec<u64> = self
+            .samples
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(when, _)| window.map(|w| when.elapsed() <= w).unwrap_or(true))
+            .map(|(_, ms)| *ms)
+            .collect();
+        if ms.is_empty() {
+            return None;
+        }
+        ms.sort_unstable();
+        // Nearest rank, ceil(n * p / 100) counting from 1.
+        let rank = |p: usize| ms[(ms.len() * p - 1) / 100];
+        Some(Percentiles {
+            count: ms.len(),
+            p50: rank(50),
+            p90: rank(90),
+            p99: rank(99),
+            max: ms[ms.len() - 1],
+        })
+    }
+
+    fn report(&self) -> String {
+        match self.percentiles(None) {
+            Some(p) => format!(
+                "sync latencies recorded: {}\nsync latency p50 (ms): {}\nsync latency p90 (ms): {}\n\
+                 sync latency p99 (ms): {}\nsync latency max (ms): {}",
+                p.count, p.p50, p.p90, p.p99, p.max
+            ),
+            None => String::from("sync latencies recorded: 0"),
+        }
+    }
+}
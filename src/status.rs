@@ -0,0 +1,205 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+use crate::{canonical_path, TrackedFile};
+
+// Sync state of a local path, as reported to file manager extensions and prompts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathStatus {
+    // Tracked and the last operation on it succeeded.
+    Synced,
+    // Tracked export, pull only.
+    Export,
+    // Tracked, but the last operation on it failed.
+    Error,
+    // Tracked, with a change waiting to go up (held for review, or queued until sync resumes).
+    Pending,
+    // Tracked, and being uploaded right now.
+    Uploading,
+    // A directory with tracked files somewhere beneath it.
+    Partial,
+    Untracked,
+}
+
+impl PathStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PathStatus::Synced => "synced",
+            PathStatus::Export => "export",
+            PathStatus::Error => "error",
+            PathStatus::Pending => "pending",
+            PathStatus::Uploading => "uploading",
+            PathStatus::Partial => "partial",
+            PathStatus::Untracked => "untracked",
+        }
+    }
+
+    // Single character for shell prompts, nothing for untracked paths so the prompt stays clean.
+    pub fn glyph(&self) -> &'static str {
+        match self {
+            PathStatus::Synced => "✓",
+            PathStatus::Export => "↓",
+            PathStatus::Error => "✗",
+            PathStatus::Pending => "…",
+            PathStatus::Uploading => "↑",
+            PathStatus::Partial => "◐",
+            PathStatus::Untracked => "",
+        }
+    }
+}
+
+impl fmt::Display for PathStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PathStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PathStatus, String> {
+        match s {
+            "synced" => Ok(PathStatus::Synced),
+            "export" => Ok(PathStatus::Export),
+            "error" => Ok(PathStatus::Error),
+            "pending" => Ok(PathStatus::Pending),
+            "uploading" => Ok(PathStatus::Uploading),
+            "partial" => Ok(PathStatus::Partial),
+            "untracked" => Ok(PathStatus::Untracked),
+            _ => Err(format!("Unknown path status {:?}", s)),
+        }
+    }
+}
+
+// Paths whose most recent journaled operation failed. Kept in memory so status queries never read the journal.
+pub struct Failures {
+    paths: Mutex<HashSet<PathBuf>>,
+}
+
+lazy_static! {
+    pub static ref FAILURES: Failures = Failures {
+        paths: Mutex::new(HashSet::new()),
+    };
+}
+
+impl Failures {
+    pub fn record(&self, path: &Path, ok: bool) {
+        let path = canonical_path(path);
+        let mut paths = self.paths.lock().unwrap();
+        if ok {
+            paths.remove(&path);
+        } else {
+            paths.insert(path);
+        }
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths.lock().unwrap().contains(path)
+    }
+}
+
+// Tracked files being uploaded right now.
+pub struct Uploading {
+    paths: Mutex<HashSet<PathBuf>>,
+}
+
+lazy_static! {
+    pub static ref UPLOADING: Uploading = Uploading {
+        paths: Mutex::new(HashSet::new()),
+    };
+}
+
+impl Uploading {
+    pub fn start(&self, path: &Path) {
+        self.paths.lock().unwrap().insert(canonical_path(path));
+    }
+
+    pub fn done(&self, path: &Path) {
+        self.paths.lock().unwrap().remove(&canonical_path(path));
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths.lock().unwrap().contains(path)
+    }
+}
+
+// When the local change waiting to go up for each tracked file was first seen, for the latency journaled with its upload.
+pub struct Changed {
+    since: Mutex<HashMap<PathBuf, Instant>>,
+}
+
+lazy_static! {
+    pub static ref CHANGED: Changed = Changed {
+        since: Mutex::new(HashMap::new()),
+    };
+}
+
+impl Changed {
+    // Note a change to path. Until it's uploaded, later changes are part of the same one.
+    pub fn seen(&self, path: &Path) {
+        self.since
+            .lock()
+            .unwrap()
+            .entry(canonical_path(path))
+            .or_insert_with(Instant::now);
+    }
+
+    // How long ago the change to path that was just uploaded was first seen, None if it wasn't.
+    pub fn uploaded(&self, path: &Path) -> Option<Duration> {
+        self.since
+            .lock()
+            .unwrap()
+            .remove(&canonical_path(path))
+            .map(|t| t.elapsed())
+    }
+
+    // Forget the change to path, there was nothing to upload after all.
+    pub fn forget(&self, path: &Path) {
+        self.since.lock().unwrap().remove(&canonical_path(path));
+    }
+}
+
+// Status of every path, in order, pending being the tracked files with a change waiting to go up. Tracked files are
+// indexed once per call, so big batches stay cheap. A directory takes the state of the tracked files beneath it: error
+// if any of them failed, partial otherwise.
+pub fn statuses(
+    tracked: &[TrackedFile],
+    paths: &[PathBuf],
+    pending: &HashSet<PathBuf>,
+) -> Vec<PathStatus> {
+    let index: HashMap<&Path, &TrackedFile> =
+        tracked.iter().map(|tf| (tf.path.as_path(), tf)).collect();
+    paths
+        .iter()
+        .map(|p| {
+            let path = canonical_path(p);
+            match index.get(path.as_path()) {
+                Some(_) if UPLOADING.contains(&path) => PathStatus::Uploading,
+                Some(_) if FAILURES.contains(&path) => PathStatus::Error,
+                Some(_) if pending.contains(&path) => PathStatus::Pending,
+                Some(tf) if tf.is_export() => PathStatus::Export,
+                Some(_) => PathStatus::Synced,
+                None if path.is_dir() => {
+                    let mut beneath = tracked
+                        .iter()
+                        .filter(|tf| tf.path.starts_with(&path))
+                        .peekable();
+                    if beneath.peek().is_none() {
+                        PathStatus::Untracked
+                    } else if beneath.any(|tf| FAILURES.contains(&tf.path)) {
+                        PathStatus::Error
+                    } else {
+                        PathStatus::Partial
+                    }
+                }
+                None => PathStatus::Untracked,
+            }
+        })
+        .collect()
+}
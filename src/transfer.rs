@@ -0,0 +1,508 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::checksum::md5_file;
+use crate::config::Config;
+use crate::drive::Drive;
+use crate::environment::ENVIRONMENT;
+use crate::exclude::Ignores;
+use crate::paths::{PATHS, ROOT_ID};
+use crate::public::Public;
+use crate::remote::{drive_id, Remote, RemoteError};
+use crate::session::{Faulty, Session};
+use crate::{credentials_path, credentials_path_of, exclude, get_subpaths, trash, versions};
+
+// The pieces of a push or pull shared by the daemon and one-shot (--once) transfers from the cli.
+
+// Where OAuth clients are created and their ids and secrets can be copied from.
+const CREDENTIALS_URL: &str = "https://console.cloud.google.com/apis/credentials";
+// Every OAuth client id ends in this.
+const CLIENT_ID_SUFFIX: &str = ".apps.googleusercontent.com";
+
+#[derive(Debug, PartialEq)]
+pub enum ConnectError {
+    // $GOOGLE_CLIENT_ID or $GOOGLE_CLIENT_SECRET is missing or malformed, or rgdrive hasn't signed in.
+    Credentials(String),
+    // Drive turned the credentials, or the token they got, down.
+    Auth(String),
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConnectError::Credentials(e) | ConnectError::Auth(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// Connect to Drive with the client credentials and sign in from the environment, or saved by --auth. The connection
+// gets new access tokens as they expire, see Session, and with $RGDRIVE_INJECT_FAILURES set some of its requests fail
+// on purpose, see Faulty.
+pub fn connect() -> Result<Box<dyn Remote>, ConnectError> {
+    Ok(Box::new(Session::new(open)?))
+}
+
+fn open() -> Result<Box<dyn Remote>, ConnectError> {
+    let remote = open_remote()?;
+    let spec = match env::var("RGDRIVE_INJECT_FAILURES") {
+        Ok(spec) => spec,
+        Err(_) => return Ok(remote),
+    };
+    match Faulty::parse(&spec) {
+        Ok((probability, faults)) => {
+            warn!(
+                "Injecting {:?} failures into {:.0}% of Drive requests ($RGDRIVE_INJECT_FAILURES).",
+                faults,
+                probability * 100.0
+            );
+            Ok(Box::new(Faulty::new(remote, probability, faults)))
+        }
+        Err(e) => {
+            warn!("Ignoring $RGDRIVE_INJECT_FAILURES, {}.", e);
+            Ok(remote)
+        }
+    }
+}
+
+fn open_remote() -> Result<Box<dyn Remote>, ConnectError> {
+    let (id, secret) = credentials().map_err(ConnectError::Credentials)?;
+    let refresh_token = refresh_token().map_err(ConnectError::Credentials)?;
+    match Drive::connect(&id, &secret, &refresh_token) {
+        Ok(d) => Ok(Box::new(d)),
+        Err(e) => Err(ConnectError::Auth(format!(
+            "Drive didn't accept the sign in: {}. Check $GOOGLE_CLIENT_ID and $GOOGLE_CLIENT_SECRET belong to the \
+            OAuth client rgdrive signed in with, or sign in again with `rgdrive --login`.",
+            e
+        ))),
+    }
+}
+
+// Reach Drive without signing in, for files shared with anyone with the link.
+pub fn connect_anonymous() -> Box<dyn Remote> {
+    Box::new(Public)
+}
+
+// Connect to the [replica] remote: Drive, signed in with the OAuth client saved for profile.
+pub fn connect_replica(profile: &str) -> Result<Box<dyn Remote>, ConnectError> {
+    let path = credentials_path_of(profile);
+    let saved = |var| {
+        saved_credential_in(&path, var).ok_or_else(|| {
+            ConnectError::Credentials(format!(
+                "No OAuth client saved for profile {:?}, run `rgdrive --profile {} --auth`.",
+                profile, profile
+            ))
+        })
+    };
+    let (id, secret) = (saved("GOOGLE_CLIENT_ID")?, saved("GOOGLE_CLIENT_SECRET")?);
+    check_credentials(&id, &secret).map_err(ConnectError::Credentials)?;
+    let refresh_token = saved("GOOGLE_REFRESH_TOKEN")?;
+    match Drive::connect(id.trim(), secret.trim(), refresh_token.trim()) {
+        Ok(d) => Ok(Box::new(d)),
+        Err(e) => Err(ConnectError::Auth(format!(
+            "Drive didn't accept the sign in saved for profile {:?}: {}.",
+            profile, e
+        ))),
+    }
+}
+
+// Client id and secret from the environment, or saved by --migrate-import. The error says which one is wrong, and how
+// to fix it.
+pub fn credentials() -> Result<(String, String), String> {
+    let id = credential("GOOGLE_CLIENT_ID")?;
+    let secret = credential("GOOGLE_CLIENT_SECRET")?;
+    check_credentials(&id, &secret)?;
+    Ok((id.trim().to_string(), secret.trim().to_string()))
+}
+
+// Catch the usual mix-ups before Drive gets to turn the credentials down with a less helpful error.
+pub fn check_credentials(id: &str, secret: &str) -> Result<(), String> {
+    if !id.trim().ends_with(CLIENT_ID_SUFFIX) {
+        return Err(format!(
+            "$GOOGLE_CLIENT_ID doesn't look like an OAuth client id, they end in {}. Copy it from {}.",
+            CLIENT_ID_SUFFIX, CREDENTIALS_URL
+        ));
+    }
+    if id.trim() == secret.trim() {
+        return Err(String::from(
+            "$GOOGLE_CLIENT_SECRET is the same as $GOOGLE_CLIENT_ID, set it to the client's secret.",
+        ));
+    }
+    Ok(())
+}
+
+fn credential(var: &str) -> Result<String, String> {
+    match env::var(var).ok().or_else(|| saved_credential(var)) {
+        Some(v) if !v.trim().is_empty() => Ok(v),
+        Some(_) => Err(format!("${} is set but empty.", var)),
+        None => Err(format!(
+            "${} is not set. Create an OAuth client (Desktop app) at {} and export its id and secret as \
+            $GOOGLE_CLIENT_ID and $GOOGLE_CLIENT_SECRET.",
+            var, CREDENTIALS_URL
+        )),
+    }
+}
+
+// Refresh token from the last sign in (`rgdrive --auth` or --login), or $GOOGLE_REFRESH_TOKEN.
+pub fn refresh_token() -> Result<String, String> {
+    match env::var("GOOGLE_REFRESH_TOKEN")
+        .ok()
+        .or_else(|| saved_credential("GOOGLE_REFRESH_TOKEN"))
+    {
+        Some(t) if !t.trim().is_empty() => Ok(t.trim().to_string()),
+        _ => Err(String::from(
+            "Not signed in to Drive, run `rgdrive --auth` to sign in.",
+        )),
+    }
+}
+
+// VAR=value line in credentials_path(), written by a sign in or by `rgdrive --migrate-import` from a bundle made with
+// --with-tokens.
+fn saved_credential(var: &str) -> Option<String> {
+    saved_credential_in(&credentials_path(), var)
+}
+
+fn saved_credential_in(path: &Path, var: &str) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()?
+        .lines()
+        .find_map(|l| l.strip_prefix(var)?.strip_prefix('='))
+        .map(String::from)
+}
+
+// Save the client credentials, and the refresh token from signing in with them, where credentials() and
+// refresh_token() find them when they aren't in the environment. Only readable by the user, it's a secret.
+pub fn save_credentials(
+    id: &str,
+    secret: &str,
+    refresh_token: Option<&str>,
+) -> Result<(), io::Error> {
+    let path = credentials_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut f = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)?;
+    // mode only applies to a new file.
+    f.set_permissions(fs::Permissions::from_mode(0o600))?;
+    write!(
+        f,
+        "GOOGLE_CLIENT_ID={}\nGOOGLE_CLIENT_SECRET={}\n",
+        id, secret
+    )?;
+    match refresh_token {
+        Some(token) => writeln!(f, "GOOGLE_REFRESH_TOKEN={}", token),
+        None => Ok(()),
+    }
+}
+
+// Make a request Drive has to authorize, so an expired token or one granted without the drive scope is caught at
+// startup rather than on the first upload. Anything else (e.g. being offline) is left for the retry queue.
+pub fn check_access(remote: &mut dyn Remote) -> Result<(), ConnectError> {
+    match remote.metadata(ROOT_ID) {
+        Err(RemoteError::Api(e)) if is_auth_error(&e) => Err(ConnectError::Auth(format!(
+            "Drive refused a test request: {}. The token may have expired or lack the drive scope, remove rgdrive's \
+            access at https://myaccount.google.com/permissions and start again to sign in anew.",
+            e
+        ))),
+        Err(RemoteError::Api(e)) => {
+            warn!("Couldn't check Drive access at startup: {}", e);
+            Ok(())
+        }
+        Err(RemoteError::Unsupported(what)) => Err(ConnectError::Auth(format!(
+            "Couldn't check Drive access, the client doesn't support {}.",
+            what
+        ))),
+        Ok(_) => Ok(()),
+    }
+}
+
+// Whether Drive refused the token itself, which only signing in again fixes.
+pub fn token_revoked(e: &str) -> bool {
+    e.contains("invalid_grant")
+}
+
+// Whether Drive refused an access token that has expired, which connecting again replaces.
+pub fn token_expired(e: &str) -> bool {
+    !token_revoked(e) && e.contains("401")
+}
+
+// Whether a Drive failure is likely to go away on its own: rate limits, server errors and network trouble.
+pub fn is_transient(e: &str) -> bool {
+    let e = e.to_lowercase();
+    [
+        "429",
+        "500",
+        "502",
+        "503",
+        "504",
+        "ratelimitexceeded",
+        "backenderror",
+        "timed out",
+        "connection",
+    ]
+    .iter()
+    .any(|s| e.contains(s))
+}
+
+fn is_auth_error(e: &str) -> bool {
+    let e = e.to_lowercase();
+    [
+        "401",
+        "403",
+        "unauthorized",
+        "invalid_grant",
+        "invalid_client",
+        "insufficient",
+    ]
+    .iter()
+    .any(|s| e.contains(s))
+}
+
+// Whether path no longer exists, e.g. a build artifact or temp file deleted between its change and its upload.
+pub fn vanished(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Err(e) => e.kind() == io::ErrorKind::NotFound,
+        Ok(_) => false,
+    }
+}
+
+// What a pull may do to a file already at its destination.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Overwrite {
+    // Leave it alone, the default.
+    Never,
+    // Replace it if the Drive copy was modified after it.
+    RemoteNewer,
+    // Replace it if it hasn't changed since it was last synced. Only tracked files have a last sync to go by.
+    LocalUnmodified,
+    // Always replace it, --overwrite.
+    Always,
+}
+
+impl Overwrite {
+    pub fn from_name(name: &str) -> Option<Overwrite> {
+        match name {
+            "never" => Some(Overwrite::Never),
+            "remote-newer" => Some(Overwrite::RemoteNewer),
+            "local-unmodified" => Some(Overwrite::LocalUnmodified),
+            "always" => Some(Overwrite::Always),
+            _ => None,
+        }
+    }
+}
+
+// Whether a pull of url may replace the file at path, per mode. synced_md5 is the file's md5 as of its last sync, if
+// it's tracked.
+pub fn check_overwrite(
+    mode: Overwrite,
+    path: &Path,
+    url: &str,
+    synced_md5: Option<&str>,
+    remote: &mut dyn Remote,
+) -> Result<(), String> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    match mode {
+        Overwrite::Always => Ok(()),
+        Overwrite::Never => Err(format!(
+            "Destination {:?} exists but no overwrite flag specified. Rerun with --overwrite to force destination path overwrite.",
+            path
+        )),
+        Overwrite::RemoteNewer => {
+            let id = drive_id(url).ok_or_else(|| format!("{:?} is not a drive url.", url))?;
+            let remote_modified = match remote.metadata(id) {
+                Ok(m) => m.modified,
+                Err(e) => {
+                    return Err(format!(
+                        "Couldn't tell if {} is newer than {:?}: {}",
+                        url, path, e
+                    ))
+                }
+            };
+            let local_modified = fs::metadata(path)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            if remote_modified > local_modified {
+                return Ok(());
+            }
+            Err(format!(
+                "Destination {:?} isn't older than the Drive copy, not overwriting it (--overwrite-mode remote-newer).",
+                path
+            ))
+        }
+        Overwrite::LocalUnmodified => {
+            let synced = synced_md5.ok_or_else(|| {
+                format!(
+                    "Destination {:?} has no record of a last sync, so it can't be told unmodified. Not overwriting it \
+                    (--overwrite-mode local-unmodified).",
+                    path
+                )
+            })?;
+            match md5_file(path) {
+                Ok(md5) if md5 == synced => Ok(()),
+                Ok(_) => Err(format!(
+                    "Destination {:?} changed since it was last synced, not overwriting it (--overwrite-mode \
+                    local-unmodified).",
+                    path
+                )),
+                Err(e) => Err(format!("Couldn't read {:?}: {}", path, e)),
+            }
+        }
+    }
+}
+
+// Keep a version of the file about to be overwritten at path, then move it to the trash. Returns where it went, so it
+// can be put back if the overwrite fails.
+pub fn preserve_before_overwrite(path: &Path, config: &Config) -> Result<Option<PathBuf>, String> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    if config.versions.keep > 0 {
+        match versions::save(path, config.versions.keep) {
+            Ok(v) => info!("Saved version {} of {:?}.", v, path),
+            // The trash still has the old copy.
+            Err(e) => warn!("Failed to save a version of {:?}: {:?}", path, e),
+        }
+    }
+    match trash::trash(path) {
+        Ok(t) => {
+            info!("Moved {:?} to {:?} before overwriting it.", path, t);
+            Ok(Some(t))
+        }
+        Err(e) => Err(format!(
+            "Couldn't move {:?} to the trash, not overwriting it: {}",
+            path, e
+        )),
+    }
+}
+
+pub fn restore_trashed(trashed: Option<PathBuf>, path: &Path) {
+    if let Some(t) = trashed {
+        if let Err(e) = trash::restore(&t, path) {
+            error!("Failed to restore {:?} from {:?}: {:?}", path, t, e);
+        }
+    }
+}
+
+// Folder id uploads should land in: dest (a drive folder url) if given, otherwise the policy folder if policy mode is
+// on, otherwise None for the Drive root. An explicit dest has to pass policy.
+pub fn upload_folder<R: Remote + ?Sized>(
+    remote: &mut R,
+    dest: Option<&str>,
+    config: &Config,
+) -> Result<Option<String>, String> {
+    match dest {
+        Some(url) => checked_folder(remote, url, config).map(Some),
+        None if config.computer.enabled => computer_folder(remote, config).map(Some),
+        None => Ok(config.policy.folder_id().map(String::from)),
+    }
+}
+
+// Id of the folder at url, if uploads into it pass policy.
+pub fn checked_folder<R: Remote + ?Sized>(
+    remote: &mut R,
+    url: &str,
+    config: &Config,
+) -> Result<String, String> {
+    let id = drive_id(url).ok_or_else(|| format!("{:?} is not a drive url.", url))?;
+    // The policy folder itself never lists itself as an ancestor.
+    if config.policy.folder_id() != Some(id) {
+        config.policy.permits(remote, url)?;
+    }
+    Ok(id.to_string())
+}
+
+// Id of this machine's folder (see [computer]), created the first time it's needed.
+pub fn computer_folder<R: Remote + ?Sized>(
+    remote: &mut R,
+    config: &Config,
+) -> Result<String, String> {
+    let parent = match &config.computer.folder {
+        Some(url) => checked_folder(remote, url, config)?,
+        None => config.policy.folder_id().unwrap_or(ROOT_ID).to_string(),
+    };
+    let name = config.computer.name();
+    let existing = PATHS
+        .folder(remote, &parent, &name)
+        .map_err(|e| e.to_string())?;
+    match existing {
+        Some(id) => Ok(id),
+        None => {
+            info!("Creating Drive folder {:?} for this computer.", name);
+            let id = remote
+                .create_folder(&name, &parent)
+                .map_err(|e| format!("Error creating folder {:?}: {}", name, e))?;
+            PATHS.remember(&parent, &name, &id);
+            Ok(id)
+        }
+    }
+}
+
+// What drive_url stands for: the file or folder a shortcut points to, drive_url itself for anything else. Also when the
+// remote can't say, a shortcut then fails to download like it always did.
+pub fn resolve_shortcut<R: Remote + ?Sized>(remote: &mut R, drive_url: &str) -> String {
+    let target = drive_id(drive_url).and_then(|id| match remote.metadata(id) {
+        Ok(m) if m.is_shortcut() => m.target,
+        _ => None,
+    });
+    match target {
+        Some(id) => {
+            info!("{} is a shortcut to {}.", drive_url, id);
+            format!("https://drive.google.com/open?id={}", id)
+        }
+        None => drive_url.to_string(),
+    }
+}
+
+// Upload path into folder, or the Drive root if None.
+pub fn upload<R: Remote + ?Sized>(
+    remote: &mut R,
+    path: &Path,
+    folder: Option<&str>,
+) -> Result<String, RemoteError> {
+    ENVIRONMENT.pace(fs::metadata(path).map(|m| m.len()).unwrap_or(0));
+    match folder {
+        Some(folder) => remote.upload_to(path, folder),
+        None => remote.upload(path),
+    }
+}
+
+// Like upload, also returning the md5 of what was sent.
+pub fn upload_hashed<R: Remote + ?Sized>(
+    remote: &mut R,
+    path: &Path,
+    folder: Option<&str>,
+) -> Result<(String, Option<String>), RemoteError> {
+    ENVIRONMENT.pace(fs::metadata(path).map(|m| m.len()).unwrap_or(0));
+    remote.upload_hashed(path, folder)
+}
+
+// Every file under the directory at path that a push uploads, and how many were skipped by the default excludes (if
+// excludes is set) or ignored (see exclude::Ignores, ignore being the configured patterns).
+pub fn push_paths(path: &Path, excludes: bool, ignore: &[String]) -> (Vec<PathBuf>, usize) {
+    let all = get_subpaths(&path.to_path_buf());
+    let total = all.len();
+    let mut ignores = Ignores::new(path, ignore);
+    let kept: Vec<PathBuf> = all
+        .into_iter()
+        .filter(|p| !(excludes && exclude::is_excluded(p.strip_prefix(path).unwrap_or(p))))
+        .filter(|p| !ignores.is_ignored(p))
+        .collect();
+    let skipped = total - kept.len();
+    (kept, skipped)
+}
@@ -0,0 +1,118 @@
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use crate::home_path;
+
+// Directory (beside the file) old versions go to when they can't be moved into the trash, e.g. on another filesystem.
+pub const BACKUP_DIR: &str = ".rgdrive/backups";
+
+// The freedesktop.org home trash: $XDG_DATA_HOME/Trash, or ~/.local/share/Trash.
+pub fn trash_dir() -> PathBuf {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d).join("Trash"),
+        _ => home_path("/.local/share/Trash"),
+    }
+}
+
+// Move path out of the way before it's deleted or overwritten, so it can be recovered. Goes to the freedesktop trash
+// (restorable from any file manager), or BACKUP_DIR if the trash is on another filesystem. Returns where it went.
+pub fn trash(path: &Path) -> Result<PathBuf, Error> {
+    let path = path.canonicalize()?;
+    match to_trash(&path, &trash_dir()) {
+        Err(ref e) if e.raw_os_error() == Some(libc::EXDEV) => to_backups(&path),
+        r => r,
+    }
+}
+
+fn to_trash(path: &Path, trash: &Path) -> Result<PathBuf, Error> {
+    let files = trash.join("files");
+    let info = trash.join("info");
+    fs::create_dir_all(&files)?;
+    fs::create_dir_all(&info)?;
+    let name = file_name(path)?;
+    // The .trashinfo is created first and exclusively, that's what reserves the name in the trash.
+    let mut n = 1;
+    let (dest, info_path) = loop {
+        let candidate = if n == 1 {
+            name.clone()
+        } else {
+            format!("{}.{}", name, n)
+        };
+        let info_path = info.join(format!("{}.trashinfo", candidate));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(_) => break (files.join(candidate), info_path),
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
+        }
+    };
+    let contents = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        escape(path),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+    let result = fs::write(&info_path, contents).and_then(|_| fs::rename(path, &dest));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&info_path);
+        return Err(e);
+    }
+    Ok(dest)
+}
+
+fn to_backups(path: &Path) -> Result<PathBuf, Error> {
+    let dir = path
+        .parent()
+        .unwrap_or_else(|| Path::new("/"))
+        .join(BACKUP_DIR);
+    fs::create_dir_all(&dir)?;
+    let dest = dir.join(format!(
+        "{}.{}",
+        file_name(path)?,
+        Local::now().format("%Y%m%dT%H%M%S%.f")
+    ));
+    fs::rename(path, &dest)?;
+    Ok(dest)
+}
+
+fn file_name(path: &Path) -> Result<String, Error> {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "path has no file name"))
+}
+
+// Percent-encode a path for a .trashinfo Path= line.
+fn escape(path: &Path) -> String {
+    let mut s = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                s.push(b as char)
+            }
+            _ => s.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    s
+}
+
+// Put a file trash() moved back where it was, e.g. when whatever was meant to replace it failed.
+pub fn restore(trashed: &Path, original: &Path) -> Result<(), Error> {
+    fs::rename(trashed, original)?;
+    let info = trashed
+        .parent()
+        .and_then(|files| files.parent())
+        .map(|trash| trash.join("info"));
+    if let (Some(info), Some(name)) = (info, trashed.file_name()) {
+        let mut name = name.to_os_string();
+        name.push(".trashinfo");
+        let _ = fs::remove_file(info.join(name));
+    }
+    Ok(())
+}
@@ -0,0 +1,244 @@
+// rgdrive self-update: replace the rgdrive/rgdrived binaries with the ones from the latest GitHub release.
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, Permissions};
+use std::io::{self, prelude::*};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use rgdrive::{socket_path, DResult, DSocket};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/cameron-williams/rgdrive/releases/latest";
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+const BINARIES: [&str; 2] = ["rgdrive", "rgdrived"];
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Result<&Asset, String> {
+        self.assets
+            .iter()
+            .find(|a| a.name == name)
+            .ok_or_else(|| format!("Release {} has no {} asset.", self.tag_name, name))
+    }
+}
+
+// Release binaries are published as <binary>-<arch>-<os>, e.g. rgdrived-x86_64-linux.
+fn asset_name(bin: &str) -> String {
+    format!("{}-{}-{}", bin, env::consts::ARCH, env::consts::OS)
+}
+
+// "v1.2.3" -> [1, 2, 3], so versions compare numerically.
+fn parse_version(v: &str) -> Vec<u64> {
+    v.trim_start_matches('v')
+        .split('.')
+        .map(|p| p.parse().unwrap_or(0))
+        .collect()
+}
+
+fn get(url: &str) -> Result<ureq::Response, String> {
+    let resp = ureq::get(url)
+        .set("User-Agent", concat!("rgdrive/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(120))
+        .call();
+    if let Some(e) = resp.synthetic_error() {
+        return Err(format!("{}: {}", url, e));
+    }
+    if !resp.ok() {
+        return Err(format!("{}: {}", url, resp.status_line()));
+    }
+    Ok(resp)
+}
+
+// Parse sha256sum output ("<hex>  <name>" per line) into name -> hex digest.
+fn parse_checksums(s: &str) -> HashMap<String, String> {
+    s.lines()
+        .filter_map(|l| {
+            let mut parts = l.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some(sum), Some(name)) => {
+                    Some((name.trim_start_matches('*').to_string(), sum.to_lowercase()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Download url to a temp file next to dest (same filesystem, so the final rename is atomic) and check its sha256.
+fn download_verified(url: &str, dest: &Path, sha256: &str) -> Result<PathBuf, String> {
+    let tmp = dest.with_extension("update");
+    let result = (|| {
+        let mut reader = get(url)?.into_reader();
+        let mut f = File::create(&tmp).map_err(|e| format!("{:?}: {}", tmp, e))?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader
+                .read(&mut buf)
+                .map_err(|e| format!("{}: {}", url, e))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            f.write_all(&buf[..n])
+                .map_err(|e| format!("{:?}: {}", tmp, e))?;
+        }
+        let digest = format!("{:x}", hasher.finalize());
+        if digest != sha256 {
+            return Err(format!(
+                "Checksum mismatch for {}: expected {}, got {}.",
+                url, sha256, digest
+            ));
+        }
+        fs::set_permissions(&tmp, Permissions::from_mode(0o755))
+            .map_err(|e| format!("{:?}: {}", tmp, e))
+    })();
+    match result {
+        Ok(_) => Ok(tmp),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    answer.trim().eq_ignore_ascii_case("y")
+}
+
+// Stop the running daemon and start it again from the (new) binary.
+fn restart_daemon() -> Result<(), String> {
+    crate::stop_daemon(&DSocket::new(socket_path()))?;
+    crate::start_daemon().map_err(|e| e.to_string())
+}
+
+// Move each staged (tmp, dest) binary into place, all or none. The old binaries are linked aside first, if any rename
+// fails the ones already replaced are put back and the staged files removed.
+fn install(staged: &[(PathBuf, PathBuf)]) -> Result<(), String> {
+    let discard = |staged: &[(PathBuf, PathBuf)]| {
+        for (tmp, _) in staged {
+            let _ = fs::remove_file(tmp);
+        }
+    };
+    // The old binary's backup, None where there wasn't one.
+    let mut backups: Vec<Option<PathBuf>> = Vec::new();
+    for (_, dest) in staged {
+        if !dest.exists() {
+            backups.push(None);
+            continue;
+        }
+        let old = dest.with_extension("old");
+        let _ = fs::remove_file(&old);
+        if let Err(e) = fs::hard_link(dest, &old) {
+            discard(staged);
+            for old in backups.iter().flatten() {
+                let _ = fs::remove_file(old);
+            }
+            return Err(format!("Failed to back up {:?}: {}", dest, e));
+        }
+        backups.push(Some(old));
+    }
+    for (i, (tmp, dest)) in staged.iter().enumerate() {
+        if let Err(e) = fs::rename(tmp, dest) {
+            for ((_, dest), old) in staged[..i].iter().zip(&backups) {
+                let _ = match old {
+                    Some(old) => fs::rename(old, dest),
+                    None => fs::remove_file(dest),
+                };
+            }
+            discard(&staged[i..]);
+            for old in backups.iter().flatten() {
+                let _ = fs::remove_file(old);
+            }
+            return Err(format!("Failed to replace {:?}: {}", dest, e));
+        }
+    }
+    for old in backups.iter().flatten() {
+        let _ = fs::remove_file(old);
+    }
+    Ok(())
+}
+
+fn update(check_only: bool, assume_yes: bool) -> Result<String, String> {
+    let release: Release = get(RELEASES_URL)?
+        .into_json_deserialize()
+        .map_err(|e| format!("Failed to parse release info: {}", e))?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    if parse_version(&release.tag_name) <= parse_version(current) {
+        return Ok(format!("Already up to date ({}).", current));
+    }
+    if check_only {
+        return Ok(format!(
+            "Update available: {} -> {}. Run `rgdrive self-update` to install it.",
+            current, release.tag_name
+        ));
+    }
+
+    let checksums = get(&release.asset(CHECKSUMS_ASSET)?.browser_download_url)?
+        .into_string()
+        .map_err(|e| format!("Failed to read {}: {}", CHECKSUMS_ASSET, e))?;
+    let checksums = parse_checksums(&checksums);
+
+    // Download and verify everything before replacing anything, so a failure never leaves a mismatched pair installed.
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+    let bin_dir = exe.parent().unwrap();
+    let mut staged: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for bin in BINARIES.iter() {
+        let name = asset_name(bin);
+        let asset = release.asset(&name)?;
+        let sum = checksums
+            .get(&name)
+            .ok_or_else(|| format!("{} has no entry for {}.", CHECKSUMS_ASSET, name))?;
+        let dest = bin_dir.join(bin);
+        match download_verified(&asset.browser_download_url, &dest, sum) {
+            Ok(tmp) => staged.push((tmp, dest)),
+            Err(e) => {
+                for (tmp, _) in &staged {
+                    let _ = fs::remove_file(tmp);
+                }
+                return Err(e);
+            }
+        }
+    }
+    install(&staged)?;
+
+    let mut msg = format!("Updated {} -> {}.", current, release.tag_name);
+    if DSocket::new(socket_path()).is_active() {
+        if assume_yes || confirm("Restart the daemon on the new version now?") {
+            restart_daemon()?;
+            msg.push_str(" Daemon restarted.");
+        } else {
+            msg.push_str(" Restart the daemon with `rgdrive --stop && rgdrive --start` to finish.");
+        }
+    }
+    Ok(msg)
+}
+
+pub fn self_update(check_only: bool, assume_yes: bool) -> DResult {
+    match update(check_only, assume_yes) {
+        Ok(msg) => DResult::ok(msg),
+        Err(e) => DResult::error(format!("self-update failed: {}", e)),
+    }
+}
@@ -0,0 +1,125 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Error, ErrorKind};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::Local;
+
+use crate::home_path;
+
+// Timestamp format versions are named by, e.g. 20200131-142501.
+const STAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+// Linux's FICLONE ioctl, _IOW(0x94, 9, int).
+const FICLONE: u64 = 0x4004_9409;
+
+// Root of the saved versions, ~/.local/share/rgdrive/versions.
+pub fn versions_dir() -> PathBuf {
+    home_path("/.local/share/rgdrive/versions")
+}
+
+// Versions of path are kept under versions_dir()/<absolute path>/<timestamp>.
+fn dir_for(path: &Path) -> Result<PathBuf, Error> {
+    let path = path.canonicalize()?;
+    Ok(versions_dir().join(path.strip_prefix("/").unwrap_or(&path)))
+}
+
+// Copy the current contents of path into its versions, keeping only the newest keep. Returns the new version's name.
+pub fn save(path: &Path, keep: usize) -> Result<String, Error> {
+    save_from(path, path, keep)
+}
+
+// Like save, with the contents taken from the file at src (e.g. a download of the Drive copy).
+pub fn save_from(path: &Path, src: &Path, keep: usize) -> Result<String, Error> {
+    let dir = dir_for(path)?;
+    fs::create_dir_all(&dir)?;
+    let stamp = Local::now().format(STAMP_FORMAT).to_string();
+    // Two saves in the same second get a -2, -3, ... suffix.
+    let mut name = stamp.clone();
+    let mut n = 1;
+    while dir.join(&name).exists() {
+        n += 1;
+        name = format!("{}-{}", stamp, n);
+    }
+    clone_file(src, &dir.join(&name))?;
+    let all = list(path)?;
+    if all.len() > keep {
+        for old in &all[..all.len() - keep] {
+            fs::remove_file(dir.join(old))?;
+        }
+    }
+    Ok(name)
+}
+
+// Put the contents of path back to those of version, after saving its current ones as a version of their own. The
+// file is written in place, so it stays the same file (and keeps its watch).
+pub fn rollback(path: &Path, version: &str, keep: usize) -> Result<(), Error> {
+    let saved = version_path(path, version)?;
+    // Held by a hard link meanwhile, saving the current contents may prune the version being restored.
+    let held = versions_dir().join(format!(".rollback-{}", process::id()));
+    let _ = fs::remove_file(&held);
+    fs::hard_link(&saved, &held)?;
+    let result = if path.is_file() {
+        save(path, keep).map(|_| ()).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!(
+                    "couldn't save the current contents of {:?}, not rolling back: {}",
+                    path, e
+                ),
+            )
+        })
+    } else {
+        Ok(())
+    };
+    let result = result.and_then(|_| clone_file(&held, path).map(|_| ()));
+    let _ = fs::remove_file(&held);
+    result
+}
+
+// Copy src to dest, replacing what's there. Where the filesystem can (btrfs, xfs) dest is a reflink sharing src's
+// blocks until either is written, so it's instant and takes no space, elsewhere it's a full copy. dest gets src's
+// permissions, like fs::copy, so a private file's copies stay private. Returns whether it was a reflink.
+pub fn clone_file(src: &Path, dest: &Path) -> Result<bool, Error> {
+    let mut from = File::open(src)?;
+    let mut to = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dest)?;
+    let cloned = unsafe { libc::ioctl(to.as_raw_fd(), FICLONE as _, from.as_raw_fd()) } == 0;
+    if !cloned {
+        io::copy(&mut from, &mut to)?;
+    }
+    to.set_permissions(from.metadata()?.permissions())?;
+    Ok(cloned)
+}
+
+// Saved versions of path, oldest first.
+pub fn list(path: &Path) -> Result<Vec<String>, Error> {
+    let dir = dir_for(path)?;
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut names: Vec<String> = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    // By timestamp, then by -n suffix (-10 after -9).
+    names.sort_by(|a, b| (a.get(..15), a.len(), a).cmp(&(b.get(..15), b.len(), b)));
+    Ok(names)
+}
+
+// Path of a saved version.
+pub fn version_path(path: &Path, version: &str) -> Result<PathBuf, Error> {
+    let p = dir_for(path)?.join(version);
+    if version.contains('/') || !p.is_file() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no version {:?} of {:?}", version, path),
+        ));
+    }
+    Ok(p)
+}
@@ -0,0 +1,39 @@
+use std::env;
+use std::io::{Error, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::Duration;
+
+// systemd's service watchdog. With WatchdogSec= set in the unit, systemd passes the timeout in $WATCHDOG_USEC and
+// restarts the daemon unless it hears WATCHDOG=1 on $NOTIFY_SOCKET at least that often.
+
+// The watchdog timeout systemd set for this process, None when there's no watchdog (not under systemd, no WatchdogSec=,
+// or it's meant for another process).
+pub fn timeout() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    match env::var("WATCHDOG_USEC").ok()?.parse::<u64>() {
+        Ok(usec) if usec > 0 => Some(Duration::from_micros(usec)),
+        _ => None,
+    }
+}
+
+// Send state (e.g. "WATCHDOG=1") to systemd, see sd_notify(3). Nothing to do without $NOTIFY_SOCKET.
+pub fn notify(state: &str) -> Result<(), Error> {
+    let socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    if socket.as_bytes().first() == Some(&b'@') {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "abstract $NOTIFY_SOCKET addresses aren't supported",
+        ));
+    }
+    UnixDatagram::unbound()?.send_to(state.as_bytes(), socket)?;
+    Ok(())
+}
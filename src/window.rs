@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config::Batching;
+
+// Tracked files changed since the current [batching] window opened. However often a file is saved during the window,
+// it's uploaded once when the window closes.
+pub struct Window {
+    length: Duration,
+    max_files: usize,
+    opened: Option<Instant>,
+    paths: Vec<PathBuf>,
+}
+
+impl Window {
+    pub fn new(batching: &Batching) -> Window {
+        Window {
+            length: Duration::from_secs(batching.window_secs),
+            max_files: batching.max_files,
+            opened: None,
+            paths: Vec::new(),
+        }
+    }
+
+    // Whether changes are held at all, a zero length window uploads every save as it happens.
+    pub fn enabled(&self) -> bool {
+        self.length > Duration::from_secs(0)
+    }
+
+    // Note a change to path. Returns false if path was already waiting in this window.
+    pub fn add(&mut self, path: &Path) -> bool {
+        if self.paths.iter().any(|p| p == path) {
+            return false;
+        }
+        if self.opened.is_none() {
+            self.opened = Some(Instant::now());
+        }
+        self.paths.push(path.to_path_buf());
+        true
+    }
+
+    // The window has been open its full length, or holds max_files files.
+    pub fn due(&self, now: Instant) -> bool {
+        match self.opened {
+            Some(opened) => {
+                now.duration_since(opened) >= self.length
+                    || (self.max_files > 0 && self.paths.len() >= self.max_files)
+            }
+            None => false,
+        }
+    }
+
+    // Close the window, returning the files to upload.
+    pub fn drain(&mut self) -> Vec<PathBuf> {
+        self.opened = None;
+        std::mem::take(&mut self.paths)
+    }
+}
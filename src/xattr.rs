@@ -0,0 +1,63 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+// Extended attributes, as used by placeholders and the stamps on synced files. Only user.* attributes are used, which
+// any user can set on their own files.
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+pub fn set(path: &Path, name: &str, value: &str) -> io::Result<()> {
+    let (p, n) = (c_path(path)?, CString::new(name).unwrap());
+    let r = unsafe {
+        libc::setxattr(
+            p.as_ptr(),
+            n.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if r == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// None if the attribute isn't set, can't be read or isn't utf-8.
+pub fn get(path: &Path, name: &str) -> Option<String> {
+    let (p, n) = (c_path(path).ok()?, CString::new(name).unwrap());
+    let mut buf = vec![0u8; 256];
+    let len = unsafe {
+        libc::getxattr(
+            p.as_ptr(),
+            n.as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+        )
+    };
+    if len < 0 {
+        return None;
+    }
+    buf.truncate(len as usize);
+    String::from_utf8(buf).ok()
+}
+
+// Removing an attribute that isn't set is not an error.
+pub fn remove(path: &Path, name: &str) -> io::Result<()> {
+    let (p, n) = (c_path(path)?, CString::new(name).unwrap());
+    if unsafe { libc::removexattr(p.as_ptr(), n.as_ptr()) } == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::ENODATA) {
+        Ok(())
+    } else {
+        Err(e)
+    }
+}
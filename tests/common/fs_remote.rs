@@ -0,0 +1,584 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rgdrive::checksum::{copy_hashed, md5_file};
+use rgdrive::export::Export;
+use rgdrive::paths::ROOT_ID;
+use rgdrive::remote::{
+    drive_id, Activity, FileChange, Metadata, Quota, Remote, RemoteError, SharedDrive, FOLDER_MIME,
+    SHORTCUT_MIME,
+};
+
+// Stand-in for Drive that keeps "uploaded" files in a local directory. Each file is stored as <root>/<id>, with its original
+// name in <root>/<id>.name, its folder (if uploaded into one) in <root>/<id>.parent and its activity in <root>/<id>.activity.
+// Starred files have an empty <root>/<id>.starred, and Docs editors files have their mimeType in <root>/<id>.mime.
+// Shortcuts are empty files with the shortcut mimeType, and the id they point to in <root>/<id>.target.
+// Trashed files are moved to <root>/.trash/<id>, deleted ones are removed along with everything about them. Both are
+// logged in <root>/.removed as "<time>\t<id>" lines, for the changes feed. Its page tokens are
+// times (unix nanos), after the id of the last change listed when a page stops part way through a time.
+// Files shared by link have their permission ("anyone:reader") in <root>/<id>.shared. A storage limit (bytes) can be
+// set in <root>/.quota, usage is the size of everything stored. The signed in account is read from <root>/.account, and
+// a revoked token is simulated with <root>/.revoked, which fails uploads, downloads, updates, quota and My Drive's
+// metadata like Drive would. The next n uploads fail like an overloaded Drive would while <root>/.failing_uploads holds
+// n, and each upload takes the number of milliseconds in <root>/.upload_delay_ms, if there is one, so tests can see
+// transfers overlap. Shared drives are listed in <root>/.drives, one tab separated id, name and role per line. An
+// anonymous FsRemote (like Public) only downloads files shared by link, and nothing else works without an account.
+// FakeGoogle serves it as the Drive api, see google.rs.
+static UPLOADS: AtomicU64 = AtomicU64::new(0);
+
+pub struct FsRemote {
+    root: PathBuf,
+    anonymous: bool,
+}
+
+impl FsRemote {
+    pub fn new<P: Into<PathBuf>>(root: P) -> FsRemote {
+        FsRemote {
+            root: root.into(),
+            anonymous: false,
+        }
+    }
+
+    pub fn anonymous<P: Into<PathBuf>>(root: P) -> FsRemote {
+        FsRemote {
+            anonymous: true,
+            ..FsRemote::new(root)
+        }
+    }
+
+    // Signed in, or for an anonymous remote, id is shared with anyone with the link.
+    fn readable(&self, id: &str) -> Result<(), RemoteError> {
+        if !self.anonymous {
+            return self.authorize();
+        }
+        match fs::read_to_string(self.file(id, Some("shared"))) {
+            Ok(role) if role.starts_with("anyone:") => Ok(()),
+            _ => Err(RemoteError::Api(format!(
+                "404 Not Found: {} isn't shared with anyone with the link.",
+                id
+            ))),
+        }
+    }
+
+    fn file(&self, id: &str, ext: Option<&str>) -> PathBuf {
+        match ext {
+            Some(ext) => self.root.join(format!("{}.{}", id, ext)),
+            None => self.root.join(id),
+        }
+    }
+
+    fn id_for(&self, url: &str) -> Result<String, RemoteError> {
+        match drive_id(url) {
+            Some(id) if self.file(id, None).is_file() => Ok(id.to_string()),
+            _ => Err(RemoteError::Api(format!("File not found: {}", url))),
+        }
+    }
+
+    fn new_id(&mut self) -> Result<String, RemoteError> {
+        fs::create_dir_all(&self.root).map_err(fs_err)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        // Shared by every request FakeGoogle serves.
+        let n = UPLOADS.fetch_add(1, Ordering::Relaxed);
+        Ok(format!("fake{}_{}", nanos, n))
+    }
+
+    fn store(
+        &mut self,
+        path: &Path,
+        parent: Option<&str>,
+    ) -> Result<(String, String), RemoteError> {
+        self.authorize()?;
+        let failing = self.root.join(".failing_uploads");
+        let left: u32 = fs::read_to_string(&failing)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(0);
+        if left > 0 {
+            fs::write(&failing, (left - 1).to_string()).map_err(fs_err)?;
+            return Err(RemoteError::Api(String::from(
+                "503 Service Unavailable: backendError",
+            )));
+        }
+        let delay = fs::read_to_string(self.root.join(".upload_delay_ms"))
+            .ok()
+            .and_then(|s| s.trim().parse().ok());
+        if let Some(ms) = delay {
+            thread::sleep(Duration::from_millis(ms));
+        }
+        let id = self.new_id()?;
+        let md5 = copy_file(path, &self.file(&id, None))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        fs::write(self.file(&id, Some("name")), name.as_bytes()).map_err(fs_err)?;
+        self.log_activity(&id, "create")?;
+        if let Some(parent) = parent {
+            fs::write(self.file(&id, Some("parent")), parent).map_err(fs_err)?;
+        }
+        Ok((format!("https://drive.google.com/open?id={}", id), md5))
+    }
+
+    // Metadata of every file with an <id>.<ext> marker whose contents pass keep.
+    fn find<F: Fn(&str) -> bool>(
+        &mut self,
+        ext: &str,
+        keep: F,
+    ) -> Result<Vec<Metadata>, RemoteError> {
+        let mut files = Vec::new();
+        let entries = match fs::read_dir(&self.root) {
+            Ok(e) => e,
+            Err(_) => return Ok(files),
+        };
+        let suffix = format!(".{}", ext);
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let id = match name.strip_suffix(suffix.as_str()) {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+            if !keep(&fs::read_to_string(entry.path()).unwrap_or_default()) {
+                continue;
+            }
+            files.push(self.metadata(&id)?);
+        }
+        Ok(files)
+    }
+
+    fn authorize(&self) -> Result<(), RemoteError> {
+        if self.anonymous {
+            return Err(RemoteError::Api(String::from(
+                "401 Unauthorized: Login Required.",
+            )));
+        }
+        if self.root.join(".revoked").exists() {
+            return Err(RemoteError::Api(String::from(
+                "401 Unauthorized: invalid_grant, Token has been expired or revoked.",
+            )));
+        }
+        Ok(())
+    }
+
+    // When the file id or its name or folder last changed (unix nanos), 0 if it doesn't exist. By ctime, so a file
+    // written with an old modified time still counts as changed now.
+    fn changed_at(&self, id: &str) -> u128 {
+        [None, Some("name"), Some("parent")]
+            .iter()
+            .filter_map(|ext| fs::metadata(self.file(id, *ext)).ok())
+            .map(|m| m.ctime() as u128 * 1_000_000_000 + m.ctime_nsec() as u128)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn log_removed(&self, id: &str) -> Result<(), RemoteError> {
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.root.join(".removed"))
+            .map_err(fs_err)?;
+        writeln!(f, "{}\t{}", now_nanos(), id).map_err(fs_err)
+    }
+
+    // Activity is kept as "<time>\t<actor>\t<action>" lines in <root>/<id>.activity.
+    fn log_activity(&self, id: &str, action: &str) -> Result<(), RemoteError> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.file(id, Some("activity")))
+            .map_err(fs_err)?;
+        writeln!(f, "{}\trgdrive\t{}", time, action).map_err(fs_err)
+    }
+}
+
+fn modified_nanos(p: &Path) -> u128 {
+    fs::metadata(p)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+// File times are coarser than the clock, so the end of the changes feed is put a little before now, and anything
+// changed around then is listed again rather than missed.
+fn feed_end() -> u128 {
+    now_nanos().saturating_sub(50_000_000)
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+fn fs_err(e: std::io::Error) -> RemoteError {
+    RemoteError::Api(e.to_string())
+}
+
+// Copy from to to like Drive streams a transfer, hashing it on the way. Returns the md5 of what was copied.
+fn copy_file(from: &Path, to: &Path) -> Result<String, RemoteError> {
+    let mut src = File::open(from).map_err(fs_err)?;
+    let mut dest = File::create(to).map_err(fs_err)?;
+    copy_hashed(&mut src, &mut dest)
+        .map(|(_, md5)| md5)
+        .map_err(fs_err)
+}
+
+impl Remote for FsRemote {
+    fn upload(&mut self, path: &Path) -> Result<String, RemoteError> {
+        self.store(path, None).map(|(url, _)| url)
+    }
+
+    fn upload_to(&mut self, path: &Path, folder_id: &str) -> Result<String, RemoteError> {
+        self.store(path, Some(folder_id)).map(|(url, _)| url)
+    }
+
+    fn download(&mut self, url: &str, path: &Path) -> Result<PathBuf, RemoteError> {
+        self.download_hashed(url, path).map(|(written, _)| written)
+    }
+
+    fn update(&mut self, path: &Path, url: &str) -> Result<(), RemoteError> {
+        self.update_hashed(path, url).map(|_| ())
+    }
+
+    fn upload_hashed(
+        &mut self,
+        path: &Path,
+        folder_id: Option<&str>,
+    ) -> Result<(String, Option<String>), RemoteError> {
+        self.store(path, folder_id)
+            .map(|(url, md5)| (url, Some(md5)))
+    }
+
+    fn download_hashed(
+        &mut self,
+        url: &str,
+        path: &Path,
+    ) -> Result<(PathBuf, Option<String>), RemoteError> {
+        let id = self.id_for(url)?;
+        self.readable(&id)?;
+        // Like Drive, downloading into a directory keeps the remote file name.
+        let dest = if path.is_dir() {
+            let name = fs::read_to_string(self.file(&id, Some("name"))).map_err(fs_err)?;
+            path.join(name)
+        } else {
+            path.to_path_buf()
+        };
+        let md5 = copy_file(&self.file(&id, None), &dest)?;
+        Ok((dest, Some(md5)))
+    }
+
+    fn update_hashed(&mut self, path: &Path, url: &str) -> Result<Option<String>, RemoteError> {
+        self.authorize()?;
+        let id = self.id_for(url)?;
+        // The new content only shows once the edit is logged, so a test that sees it can count on the activity too.
+        let part = self.file(&id, Some("part"));
+        let md5 = copy_file(path, &part)?;
+        self.log_activity(&id, "edit")?;
+        fs::rename(&part, self.file(&id, None)).map_err(fs_err)?;
+        Ok(Some(md5))
+    }
+
+    fn ancestors(&mut self, id: &str) -> Result<Vec<String>, RemoteError> {
+        match fs::read_to_string(self.file(id, Some("parent"))) {
+            Ok(parent) => Ok(vec![parent]),
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+
+    // My Drive itself is a folder only a signed in account can look up.
+    fn metadata(&mut self, id: &str) -> Result<Metadata, RemoteError> {
+        if id == ROOT_ID {
+            self.authorize()?;
+            return Ok(Metadata {
+                id: id.to_string(),
+                name: String::from("My Drive"),
+                etag: String::new(),
+                size: 0,
+                mime_type: String::from(FOLDER_MIME),
+                modified: 0,
+                md5: None,
+                target: None,
+                parents: Vec::new(),
+            });
+        }
+        let m = fs::metadata(self.file(id, None))
+            .map_err(|_| RemoteError::Api(format!("File not found: {}", id)))?;
+        let modified = |p: PathBuf| modified_nanos(&p);
+        // Renames and moves change the etag too, like they do on Drive.
+        let current = format!(
+            "{:x}-{:x}-{:x}-{:x}",
+            modified(self.file(id, None)),
+            m.len(),
+            modified(self.file(id, Some("name"))),
+            modified(self.file(id, Some("parent")))
+        );
+        Ok(Metadata {
+            id: id.to_string(),
+            name: fs::read_to_string(self.file(id, Some("name"))).unwrap_or_default(),
+            etag: current,
+            size: m.len(),
+            mime_type: fs::read_to_string(self.file(id, Some("mime")))
+                .unwrap_or_else(|_| String::from("application/octet-stream")),
+            modified: (modified(self.file(id, None)) / 1_000_000_000) as i64,
+            md5: md5_file(self.file(id, None)).ok(),
+            target: fs::read_to_string(self.file(id, Some("target"))).ok(),
+            parents: fs::read_to_string(self.file(id, Some("parent")))
+                .into_iter()
+                .collect(),
+        })
+    }
+
+    fn changes_token(&mut self) -> Result<String, RemoteError> {
+        self.authorize()?;
+        Ok(feed_end().to_string())
+    }
+
+    fn changes(
+        &mut self,
+        token: &str,
+        page_size: Option<usize>,
+    ) -> Result<(Vec<FileChange>, String), RemoteError> {
+        self.authorize()?;
+        let mut fields = token.splitn(2, ':');
+        let since: (u128, &str) = match (fields.next().map(str::parse), fields.next()) {
+            (Some(Ok(at)), id) => (at, id.unwrap_or("")),
+            _ => {
+                return Err(RemoteError::Api(format!(
+                    "400 Bad Request: Invalid pageToken {}",
+                    token
+                )))
+            }
+        };
+        let after = |at: u128, id: &str| (at, id) > since && (at > since.0 || !since.1.is_empty());
+        let end = feed_end();
+        let mut changed: Vec<(u128, String, bool)> = Vec::new();
+        for entry in fs::read_dir(&self.root).map_err(fs_err)?.flatten() {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            if id.contains('.') || !entry.path().is_file() {
+                continue;
+            }
+            let at = self.changed_at(&id);
+            if after(at, &id) {
+                changed.push((at, id, false));
+            }
+        }
+        let removed = fs::read_to_string(self.root.join(".removed")).unwrap_or_default();
+        for line in removed.lines() {
+            let mut fields = line.splitn(2, '\t');
+            let at: u128 = fields.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+            match fields.next() {
+                Some(id) if after(at, id) => changed.push((at, id.to_string(), true)),
+                _ => {}
+            }
+        }
+        changed.sort();
+        let mut next = match end > since.0 {
+            true => end.to_string(),
+            false => token.to_string(),
+        };
+        if let Some(n) = page_size.filter(|n| *n < changed.len()) {
+            changed.truncate(n);
+            next = format!("{}:{}", changed[n - 1].0, changed[n - 1].1);
+        }
+        let mut changes = Vec::new();
+        for (_, id, removed) in changed {
+            let file = match removed {
+                true => None,
+                false => Some(self.metadata(&id)?),
+            };
+            changes.push(FileChange { id, file });
+        }
+        Ok((changes, next))
+    }
+
+    fn activity(&mut self, id: &str) -> Result<Vec<Activity>, RemoteError> {
+        let log = fs::read_to_string(self.file(id, Some("activity"))).unwrap_or_default();
+        Ok(log
+            .lines()
+            .filter_map(|l| {
+                let mut parts = l.splitn(3, '\t');
+                Some(Activity {
+                    time: parts.next()?.parse().ok()?,
+                    actor: parts.next()?.to_string(),
+                    action: parts.next()?.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    // Sheets are stored per tab as <root>/<id>.sheet.<name>, the first tab being the file itself.
+    fn export(&mut self, id: &str, export: &Export, path: &Path) -> Result<PathBuf, RemoteError> {
+        self.readable(id)?;
+        if export.range.is_some() {
+            return Err(RemoteError::Unsupported("export range"));
+        }
+        let src = match &export.sheet {
+            Some(sheet) => self.file(id, Some(&format!("sheet.{}", sheet))),
+            None => self.file(id, None),
+        };
+        if !src.is_file() {
+            return Err(RemoteError::Api(format!(
+                "No such sheet in {}: {:?}",
+                id, export.sheet
+            )));
+        }
+        let dest = if path.is_dir() {
+            let name = fs::read_to_string(self.file(id, Some("name"))).map_err(fs_err)?;
+            path.join(format!("{}.{}", name, export.format))
+        } else {
+            path.to_path_buf()
+        };
+        fs::copy(src, &dest).map_err(fs_err)?;
+        Ok(dest)
+    }
+
+    fn starred(&mut self) -> Result<Vec<Metadata>, RemoteError> {
+        self.find("starred", |_| true)
+    }
+
+    // Files without a parent are in the root.
+    fn list_folder(&mut self, folder_id: &str) -> Result<Vec<Metadata>, RemoteError> {
+        if folder_id == ROOT_ID {
+            let files = self.find("name", |_| true)?;
+            return Ok(files
+                .into_iter()
+                .filter(|m| !self.file(&m.id, Some("parent")).exists())
+                .collect());
+        }
+        self.find("parent", |parent| parent == folder_id)
+    }
+
+    fn share(&mut self, id: &str) -> Result<String, RemoteError> {
+        if !self.file(id, None).is_file() {
+            return Err(RemoteError::Api(format!("File not found: {}", id)));
+        }
+        fs::write(self.file(id, Some("shared")), "anyone:reader").map_err(fs_err)?;
+        self.log_activity(id, "permission_change")?;
+        Ok(format!(
+            "https://drive.google.com/file/d/{}/view?usp=sharing",
+            id
+        ))
+    }
+
+    // Folders are empty files with the folder mimeType.
+    fn create_folder(&mut self, name: &str, parent_id: &str) -> Result<String, RemoteError> {
+        let id = self.new_id()?;
+        fs::write(self.file(&id, None), "").map_err(fs_err)?;
+        fs::write(self.file(&id, Some("name")), name).map_err(fs_err)?;
+        fs::write(self.file(&id, Some("mime")), FOLDER_MIME).map_err(fs_err)?;
+        if parent_id != ROOT_ID {
+            fs::write(self.file(&id, Some("parent")), parent_id).map_err(fs_err)?;
+        }
+        self.log_activity(&id, "create")?;
+        Ok(id)
+    }
+
+    fn rename(&mut self, id: &str, name: &str) -> Result<(), RemoteError> {
+        if !self.file(id, None).is_file() {
+            return Err(RemoteError::Api(format!("File not found: {}", id)));
+        }
+        fs::write(self.file(id, Some("name")), name).map_err(fs_err)?;
+        self.log_activity(id, "rename")
+    }
+
+    fn trash(&mut self, id: &str) -> Result<(), RemoteError> {
+        self.authorize()?;
+        if !self.file(id, None).is_file() {
+            return Err(RemoteError::Api(format!("File not found: {}", id)));
+        }
+        self.log_activity(id, "trash")?;
+        let trash = self.root.join(".trash");
+        fs::create_dir_all(&trash).map_err(fs_err)?;
+        fs::rename(self.file(id, None), trash.join(id)).map_err(fs_err)?;
+        self.log_removed(id)
+    }
+
+    fn delete(&mut self, id: &str) -> Result<(), RemoteError> {
+        self.authorize()?;
+        if !self.file(id, None).is_file() {
+            return Err(RemoteError::Api(format!("File not found: {}", id)));
+        }
+        let sidecar = format!("{}.", id);
+        for entry in fs::read_dir(&self.root).map_err(fs_err)?.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name == id || name.starts_with(&sidecar) {
+                fs::remove_file(entry.path()).map_err(fs_err)?;
+            }
+        }
+        self.log_removed(id)
+    }
+
+    fn create_shortcut(
+        &mut self,
+        target_id: &str,
+        name: &str,
+        parent_id: &str,
+    ) -> Result<String, RemoteError> {
+        self.authorize()?;
+        if !self.file(target_id, None).is_file() {
+            return Err(RemoteError::Api(format!("File not found: {}", target_id)));
+        }
+        let id = self.new_id()?;
+        fs::write(self.file(&id, None), "").map_err(fs_err)?;
+        fs::write(self.file(&id, Some("name")), name).map_err(fs_err)?;
+        fs::write(self.file(&id, Some("mime")), SHORTCUT_MIME).map_err(fs_err)?;
+        fs::write(self.file(&id, Some("target")), target_id).map_err(fs_err)?;
+        if parent_id != ROOT_ID {
+            fs::write(self.file(&id, Some("parent")), parent_id).map_err(fs_err)?;
+        }
+        self.log_activity(&id, "create")?;
+        Ok(id)
+    }
+
+    fn quota(&mut self) -> Result<Quota, RemoteError> {
+        self.authorize()?;
+        let limit = fs::read_to_string(self.root.join(".quota"))
+            .ok()
+            .and_then(|l| l.trim().parse().ok());
+        let usage = match fs::read_dir(&self.root) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                // Marker files all have a dot in their name, stored files are just the id.
+                .filter(|e| !e.file_name().to_string_lossy().contains('.'))
+                .filter_map(|e| e.metadata().ok())
+                .map(|m| m.len())
+                .sum(),
+            Err(_) => 0,
+        };
+        Ok(Quota { limit, usage })
+    }
+
+    // One tab separated id, name and role per line in <root>/.drives.
+    fn shared_drives(&mut self) -> Result<Vec<SharedDrive>, RemoteError> {
+        self.authorize()?;
+        let drives = fs::read_to_string(self.root.join(".drives")).unwrap_or_default();
+        Ok(drives
+            .lines()
+            .filter_map(|l| {
+                let mut fields = l.splitn(3, '\t');
+                Some(SharedDrive {
+                    id: fields.next().filter(|id| !id.is_empty())?.to_string(),
+                    name: fields.next()?.to_string(),
+                    role: fields.next()?.trim().to_string(),
+                })
+            })
+            .collect())
+    }
+
+    fn account(&mut self) -> Result<String, RemoteError> {
+        fs::read_to_string(self.root.join(".account"))
+            .map(|a| a.trim().to_string())
+            .map_err(fs_err)
+    }
+}
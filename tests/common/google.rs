@@ -0,0 +1,577 @@
+// Google's sign in and the Drive api, served from 127.0.0.1 on top of FsRemote so the daemon and cli talk to it exactly
+// like they would to Google ($RGDRIVE_GOOGLE_API). Each directory under base is an account: the refresh token for
+// <base>/<name> is <name>, and access tokens are "<name>:<issued, unix millis>". Signing in hands out the account named
+// in <base>/.sign_in_as, "remote" without one. An account's .revoked makes the token endpoint refuse it like Google
+// does, its .scopes set what the tokens it hands out are good for and its .token_lifetime how many seconds they last
+// (an hour without one), after which Drive refuses them. Links shared with anyone download from "remote" without signing
+// in, like drive.google.com/uc and docs.google.com's exports.
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{TimeZone, Utc};
+use rgdrive::export::{mime_for, Export, DOC_FORMATS, SHEET_FORMATS, SLIDES_FORMATS};
+use rgdrive::paths::ROOT_ID;
+use rgdrive::remote::{
+    drive_id, Metadata, Remote, RemoteError, ACTIVITY_SCOPE, DRIVE_SCOPE, FOLDER_MIME,
+    SHORTCUT_MIME,
+};
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tiny_http::{Header, Request, Response, Server};
+use url::Url;
+
+use super::fs_remote::FsRemote;
+
+type Reply = Response<Cursor<Vec<u8>>>;
+
+pub struct FakeGoogle {
+    server: Arc<Server>,
+    pub url: String,
+}
+
+impl FakeGoogle {
+    pub fn start<P: Into<PathBuf>>(base: P) -> FakeGoogle {
+        let base = base.into();
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let url = format!("http://{}", server.server_addr());
+        let serving = server.clone();
+        thread::spawn(move || {
+            while let Ok(req) = serving.recv() {
+                let base = base.clone();
+                thread::spawn(move || serve(&base, req));
+            }
+        });
+        FakeGoogle { server, url }
+    }
+}
+
+impl Drop for FakeGoogle {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+fn serve(base: &Path, mut req: Request) {
+    let url = Url::parse(&format!("http://fake{}", req.url())).unwrap();
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    let mut body = Vec::new();
+    let _ = req.as_reader().read_to_end(&mut body);
+    let method = req.method().as_str().to_string();
+    let segments: Vec<&str> = url.path().trim_matches('/').split('/').collect();
+    let reply = match (method.as_str(), segments.as_slice()) {
+        ("GET", ["o", "oauth2", "v2", "auth"]) => {
+            let account = fs::read_to_string(base.join(".sign_in_as"))
+                .unwrap_or_else(|_| String::from("remote"));
+            let mut back = Url::parse(&query("redirect_uri").unwrap()).unwrap();
+            back.query_pairs_mut()
+                .append_pair("code", account.trim())
+                .append_pair("state", &query("state").unwrap_or_default());
+            Response::from_data(Vec::new())
+                .with_status_code(302)
+                .with_header(Header::from_bytes("Location", back.as_str()).unwrap())
+        }
+        ("POST", ["token"]) => token(base, &body),
+        ("GET", ["uc"]) if !authorized(&req) => {
+            let id = query("id").unwrap_or_default();
+            shared(base, |remote, dir| remote.download(&open_url(&id), dir))
+        }
+        ("GET", [kind, "d", id, "export"]) if !authorized(&req) && KINDS.contains(kind) => {
+            let export = Export {
+                format: query("format").unwrap_or_default(),
+                sheet: None,
+                range: None,
+            };
+            shared(base, |remote, dir| remote.export(id, &export, dir))
+        }
+        ("POST", ["v2", "activity:query"]) => match account(base, &req) {
+            Ok(root) => activity(&root, &body),
+            Err(reply) => reply,
+        },
+        _ => match account(base, &req) {
+            Ok(root) => drive(&root, &method, &segments, &query, &body),
+            Err(reply) => reply,
+        },
+    };
+    let _ = req.respond(reply);
+}
+
+// The docs.google.com paths files are exported from without signing in.
+const KINDS: [&str; 3] = ["document", "spreadsheets", "presentation"];
+
+fn authorized(req: &Request) -> bool {
+    req.headers().iter().any(|h| h.field.equiv("Authorization"))
+}
+
+// A file shared with anyone, fetched into a scratch dir so it keeps its name, as an attachment. Anything else gets a
+// page asking to sign in instead, like Drive.
+fn shared<F>(base: &Path, fetch: F) -> Reply
+where
+    F: FnOnce(&mut FsRemote, &Path) -> Result<PathBuf, RemoteError>,
+{
+    let scratch = tempfile::tempdir().unwrap();
+    match fetch(
+        &mut FsRemote::anonymous(base.join("remote")),
+        scratch.path(),
+    ) {
+        Ok(path) => {
+            let name = path.file_name().unwrap().to_string_lossy();
+            let disposition = format!("attachment; filename=\"{}\"", name);
+            Response::from_data(fs::read(&path).unwrap())
+                .with_header(Header::from_bytes("Content-Disposition", disposition).unwrap())
+        }
+        Err(_) => {
+            Response::from_data("<html><body>Sign in to continue to Google Drive</body></html>")
+                .with_header(Header::from_bytes("Content-Type", "text/html").unwrap())
+        }
+    }
+}
+
+// Trade a sign in's code, or a refresh token, for an access token.
+fn token(base: &Path, body: &[u8]) -> Reply {
+    let form: Vec<(String, String)> = url::form_urlencoded::parse(body).into_owned().collect();
+    let field = |name: &str| {
+        form.iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.clone())
+            .unwrap_or_default()
+    };
+    let (name, refresh) = match field("grant_type").as_str() {
+        "authorization_code" => (field("code"), true),
+        _ => (field("refresh_token"), false),
+    };
+    let root = base.join(&name);
+    if name.is_empty() || name.contains('/') || root.join(".revoked").exists() {
+        return reply(
+            400,
+            json!({"error": "invalid_grant", "error_description": "Token has been expired or revoked."}),
+        );
+    }
+    let scopes = fs::read_to_string(root.join(".scopes"))
+        .map(|s| s.split_whitespace().collect::<Vec<_>>().join(" "))
+        .unwrap_or_else(|_| format!("{} {}", DRIVE_SCOPE, ACTIVITY_SCOPE));
+    let mut token = json!({
+        "access_token": format!("{}:{}", name, now_millis()),
+        "expires_in": lifetime(&root).unwrap_or(3600),
+        "scope": scopes,
+        "token_type": "Bearer",
+    });
+    if refresh {
+        token["refresh_token"] = json!(name);
+    }
+    reply(200, token)
+}
+
+// The account the request's access token is for, or Drive's answer to a missing one.
+fn account(base: &Path, req: &Request) -> Result<PathBuf, Reply> {
+    let bearer = req
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+        .map(String::from)
+        .ok_or_else(|| {
+            reply(
+                401,
+                json!({"error": {"code": 401, "message": "Login Required.", "errors": [{"reason": "authError"}]}}),
+            )
+        })?;
+    let mut parts = bearer.rsplitn(2, ':');
+    let issued: u128 = parts.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+    let root = base.join(parts.next().unwrap_or_default());
+    if let Some(secs) = lifetime(&root) {
+        if now_millis() >= issued + secs as u128 * 1000 {
+            return Err(reply(
+                401,
+                json!({"error": {"code": 401, "message": "Request had invalid authentication credentials.", "errors": [{"reason": "authError"}]}}),
+            ));
+        }
+    }
+    Ok(root)
+}
+
+fn lifetime(root: &Path) -> Option<u64> {
+    fs::read_to_string(root.join(".token_lifetime"))
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+}
+
+fn drive(
+    root: &Path,
+    method: &str,
+    segments: &[&str],
+    query: &dyn Fn(&str) -> Option<String>,
+    body: &[u8],
+) -> Reply {
+    let mut remote = FsRemote::new(root);
+    let scratch = tempfile::tempdir().unwrap();
+    let result = match (method, segments) {
+        ("POST", ["upload", "drive", "v3", "files"]) => {
+            upload(&mut remote, &scratch, body).map(|id| json!({ "id": id }))
+        }
+        ("GET", ["drive", "v3", "drives"]) => remote.shared_drives().map(|drives| {
+            let drives: Vec<Value> = drives
+                .iter()
+                .map(|d| json!({"id": d.id, "name": d.name, "capabilities": capabilities(&d.role)}))
+                .collect();
+            json!({ "drives": drives })
+        }),
+        ("GET", ["drive", "v3", "changes", "startPageToken"]) => remote
+            .changes_token()
+            .map(|token| json!({ "startPageToken": token })),
+        ("GET", ["drive", "v3", "changes"]) => {
+            let token = query("pageToken").unwrap_or_default();
+            let size: usize = query("pageSize")
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(100);
+            remote.changes(&token, Some(size)).map(|(changes, next)| {
+                let listed: Vec<Value> = changes
+                    .iter()
+                    .map(|c| match &c.file {
+                        Some(m) => {
+                            json!({"fileId": c.id, "removed": false, "file": metadata(m)})
+                        }
+                        None => json!({"fileId": c.id, "removed": true}),
+                    })
+                    .collect();
+                // A full page may have more after it, like Drive.
+                if changes.len() < size {
+                    json!({ "changes": listed, "newStartPageToken": next })
+                } else {
+                    json!({ "changes": listed, "nextPageToken": next })
+                }
+            })
+        }
+        ("GET", ["drive", "v3", "about"]) => {
+            about(&mut remote, &query("fields").unwrap_or_default())
+        }
+        ("POST", ["drive", "v3", "files"]) => make(&mut remote, body).map(|id| json!({ "id": id })),
+        ("POST", ["drive", "v3", "files", id, "permissions"]) => remote
+            .share(id)
+            .map(|_| json!({"id": "anyoneWithLink", "type": "anyone", "role": "reader"})),
+        ("PATCH", ["upload", "drive", "v3", "files", id]) => {
+            let path = scratch.path().join(id);
+            fs::write(&path, body).unwrap();
+            remote
+                .update(&path, &open_url(id))
+                .map(|_| json!({ "id": id }))
+        }
+        ("GET", ["drive", "v3", "files"]) => list(&mut remote, &query("q").unwrap_or_default())
+            .map(|files| {
+                let files: Vec<Value> = files.iter().map(metadata).collect();
+                json!({ "files": files })
+            }),
+        ("GET", ["drive", "v3", "files", id]) if query("alt").as_deref() == Some("media") => {
+            let path = scratch.path().join(id);
+            return match remote.download(&open_url(id), &path) {
+                Ok(_) => Response::from_data(fs::read(&path).unwrap()),
+                Err(e) => error(e),
+            };
+        }
+        ("PATCH", ["drive", "v3", "files", id]) => {
+            let change: Value = serde_json::from_slice(body).unwrap();
+            if let Some(name) = change["name"].as_str() {
+                remote.rename(id, name)
+            } else if change["trashed"] == json!(true) {
+                remote.trash(id)
+            } else {
+                Err(RemoteError::Api(format!(
+                    "400 Bad Request: Can't change {}",
+                    change
+                )))
+            }
+            .map(|_| json!({ "id": id }))
+        }
+        ("DELETE", ["drive", "v3", "files", id]) => {
+            return match remote.delete(id) {
+                Ok(()) => Response::from_data(Vec::new()).with_status_code(204),
+                Err(e) => error(e),
+            };
+        }
+        ("GET", ["drive", "v3", "files", id, "export"]) => {
+            let mime = query("mimeType").unwrap_or_default();
+            let format = SHEET_FORMATS
+                .iter()
+                .chain(DOC_FORMATS)
+                .chain(SLIDES_FORMATS)
+                .find(|f| mime_for(f) == Some(mime.as_str()))
+                .map(|f| f.to_string())
+                .unwrap_or(mime);
+            return exported(&mut remote, &scratch, id, format, None, None);
+        }
+        ("GET", ["v4", "spreadsheets", id]) => {
+            let mut sheets = vec![json!({"properties": {"sheetId": 0, "title": "Sheet1"}})];
+            for (i, title) in sheets_of(root, id).iter().enumerate() {
+                sheets.push(json!({"properties": {"sheetId": i + 1, "title": title}}));
+            }
+            Ok(json!({ "sheets": sheets }))
+        }
+        ("GET", ["spreadsheets", "d", id, "export"]) => {
+            let gid: usize = query("gid").and_then(|g| g.parse().ok()).unwrap_or(0);
+            let sheet = match gid {
+                0 => None,
+                gid => sheets_of(root, id).into_iter().nth(gid - 1),
+            };
+            let format = query("format").unwrap_or_default();
+            return exported(&mut remote, &scratch, id, format, sheet, query("range"));
+        }
+        ("GET", ["drive", "v3", "files", id]) => remote.metadata(id).map(|m| metadata(&m)),
+        _ => return reply(404, json!({"error": {"code": 404, "message": "Not Found"}})),
+    };
+    match result {
+        Ok(v) => reply(200, v),
+        Err(e) => error(e),
+    }
+}
+
+fn exported(
+    remote: &mut FsRemote,
+    scratch: &TempDir,
+    id: &str,
+    format: String,
+    sheet: Option<String>,
+    range: Option<String>,
+) -> Reply {
+    let export = Export {
+        format,
+        sheet,
+        range,
+    };
+    let path = scratch.path().join(id);
+    match remote.export(id, &export, &path) {
+        Ok(_) => Response::from_data(fs::read(&path).unwrap()),
+        Err(e) => error(e),
+    }
+}
+
+// Names of a spreadsheet's sheets after the first, each stored as <id>.sheet.<name>, in the order their gids (from 1)
+// are handed out.
+fn sheets_of(root: &Path, id: &str) -> Vec<String> {
+    let prefix = format!("{}.sheet.", id);
+    let mut sheets: Vec<String> = fs::read_dir(root)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| {
+            let name = e.ok()?.file_name().into_string().ok()?;
+            name.strip_prefix(&prefix).map(String::from)
+        })
+        .collect();
+    sheets.sort();
+    sheets
+}
+
+// What each role on a shared drive can do, the parts of it rgdrive looks at.
+fn capabilities(role: &str) -> Value {
+    let rank = [
+        "reader",
+        "commenter",
+        "writer",
+        "fileOrganizer",
+        "organizer",
+    ]
+    .iter()
+    .position(|r| *r == role)
+    .unwrap_or(0);
+    json!({
+        "canComment": rank >= 1,
+        "canAddChildren": rank >= 2,
+        "canTrashChildren": rank >= 3,
+        "canManageMembers": rank >= 4,
+    })
+}
+
+// Only the fields asked for, like Drive.
+fn about(remote: &mut FsRemote, fields: &str) -> Result<Value, RemoteError> {
+    let mut about = json!({});
+    if fields.contains("storageQuota") {
+        let quota = remote.quota()?;
+        about["storageQuota"] = json!({ "usage": quota.usage.to_string() });
+        if let Some(limit) = quota.limit {
+            about["storageQuota"]["limit"] = json!(limit.to_string());
+        }
+    }
+    if fields.contains("user") {
+        about["user"] = json!({ "emailAddress": remote.account()? });
+    }
+    Ok(about)
+}
+
+// The queries rgdrive lists with.
+fn list(remote: &mut FsRemote, q: &str) -> Result<Vec<Metadata>, RemoteError> {
+    if q == "starred = true and trashed = false" {
+        return remote.starred();
+    }
+    if let Some(folder) = q
+        .strip_prefix('\'')
+        .and_then(|q| q.strip_suffix("' in parents and trashed = false"))
+    {
+        return remote.list_folder(folder);
+    }
+    Err(RemoteError::Api(format!(
+        "400 Bad Request: Invalid query: {}",
+        q
+    )))
+}
+
+// Newest first, rgdrive's own actions as the signed in user's.
+fn activity(root: &Path, body: &[u8]) -> Reply {
+    let query: Value = serde_json::from_slice(body).unwrap();
+    let id = query["itemName"]
+        .as_str()
+        .unwrap()
+        .trim_start_matches("items/");
+    let log = match FsRemote::new(root).activity(id) {
+        Ok(log) => log,
+        Err(e) => return error(e),
+    };
+    let activities: Vec<Value> = log
+        .iter()
+        .rev()
+        .map(|a| {
+            let detail = match a.action.as_str() {
+                "trash" => json!({"delete": {"type": "TRASH"}}),
+                "permission_change" => json!({"permissionChange": {}}),
+                action => json!({ action: {} }),
+            };
+            json!({
+                "timestamp": Utc.timestamp_opt(a.time, 0).unwrap().to_rfc3339(),
+                "actors": [{"user": {"knownUser": {
+                    "personName": format!("people/{}", a.actor),
+                    "isCurrentUser": a.actor == "rgdrive",
+                }}}],
+                "primaryActionDetail": detail,
+            })
+        })
+        .collect();
+    reply(200, json!({ "activities": activities }))
+}
+
+// A file without content: a folder or a shortcut.
+fn make(remote: &mut FsRemote, body: &[u8]) -> Result<String, RemoteError> {
+    let meta: Value = serde_json::from_slice(body).unwrap();
+    let name = meta["name"].as_str().unwrap_or_default();
+    let parent = meta["parents"][0].as_str().unwrap_or(ROOT_ID);
+    match meta["mimeType"].as_str() {
+        Some(FOLDER_MIME) => remote.create_folder(name, parent),
+        Some(SHORTCUT_MIME) => {
+            let target = meta["shortcutDetails"]["targetId"]
+                .as_str()
+                .unwrap_or_default();
+            remote.create_shortcut(target, name, parent)
+        }
+        mime => Err(RemoteError::Api(format!(
+            "400 Bad Request: Can't make a {:?} without content.",
+            mime
+        ))),
+    }
+}
+
+// A multipart/related upload: the metadata part, then the content.
+fn upload(remote: &mut FsRemote, scratch: &TempDir, body: &[u8]) -> Result<String, RemoteError> {
+    let delimiter = body
+        .iter()
+        .position(|&b| b == b'\r')
+        .map(|i| [b"\r\n", &body[..i]].concat())
+        .unwrap_or_default();
+    let parts: Vec<&[u8]> = split(body, &delimiter)
+        .into_iter()
+        .filter_map(|part| {
+            let start = find(part, b"\r\n\r\n")? + 4;
+            Some(&part[start..])
+        })
+        .collect();
+    let meta: Value = serde_json::from_slice(parts[0]).unwrap();
+    let path = scratch.path().join(meta["name"].as_str().unwrap());
+    fs::write(&path, parts[1]).unwrap();
+    let url = match meta["parents"][0].as_str() {
+        Some(folder) => remote.upload_to(&path, folder)?,
+        None => remote.upload(&path)?,
+    };
+    Ok(drive_id(&url).unwrap().to_string())
+}
+
+fn split<'a>(body: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    let mut rest = body;
+    while let Some(i) = find(rest, delimiter) {
+        parts.push(&rest[..i]);
+        rest = &rest[i + delimiter.len()..];
+    }
+    parts.push(rest);
+    parts
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// Files without an <id>.parent are in the root, which has no parents itself.
+fn metadata(m: &Metadata) -> Value {
+    let mut file = json!({
+        "id": m.id,
+        "parents": m.parents,
+        "name": m.name,
+        "mimeType": m.mime_type,
+        "size": m.size.to_string(),
+        "modifiedTime": Utc.timestamp_opt(m.modified, 0).unwrap().to_rfc3339(),
+        "md5Checksum": m.md5,
+        "version": m.etag,
+    });
+    if let Some(target) = &m.target {
+        file["shortcutDetails"] = json!({ "targetId": target });
+    }
+    file
+}
+
+// FsRemote's errors start with the status Drive would answer with, if they say.
+fn error(e: RemoteError) -> Reply {
+    let message = match e {
+        RemoteError::Api(e) => e,
+        RemoteError::Unsupported(what) => format!("501 Not Implemented: {}", what),
+    };
+    let code: u16 = match message.get(..3).and_then(|c| c.parse().ok()) {
+        Some(code) => code,
+        None if message.contains("not found") => 404,
+        None => 500,
+    };
+    let message = match message.find(": ") {
+        Some(i) if message.starts_with(&code.to_string()) => message[i + 2..].to_string(),
+        _ => message,
+    };
+    let reason = match code {
+        401 => "authError",
+        403 => "forbidden",
+        404 => "notFound",
+        429 => "rateLimitExceeded",
+        503 => "backendError",
+        _ => "internalError",
+    };
+    reply(
+        code,
+        json!({"error": {"code": code, "message": message, "errors": [{"reason": reason}]}}),
+    )
+}
+
+fn reply(code: u16, body: Value) -> Reply {
+    Response::from_data(body.to_string())
+        .with_status_code(code)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn open_url(id: &str) -> String {
+    format!("https://drive.google.com/open?id={}", id)
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
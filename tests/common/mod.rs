@@ -0,0 +1,241 @@
+// Harness for end to end tests. Spawns a real rgdrived signed in to FakeGoogle, which serves Drive's api from FsRemote (a
+// directory standing in for Drive), with its own $HOME and socket so tests never touch the user's daemon or config.
+#![allow(dead_code)]
+
+mod fs_remote;
+mod google;
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub use google::FakeGoogle;
+
+use rgdrive::remote::drive_id;
+use rgdrive::state::State;
+use rgdrive::{DCommand, DResult, DSocket, TrackedFile};
+use tempfile::TempDir;
+
+pub struct Harness {
+    pub dir: TempDir,
+    // Google's sign in and Drive api for the scratch dir, see google.rs.
+    pub google: FakeGoogle,
+    daemon: Child,
+}
+
+impl Harness {
+    pub fn start() -> Harness {
+        Harness::start_with_config("")
+    }
+
+    // Start the daemon with the given rgdrive.toml contents.
+    pub fn start_with_config(config: &str) -> Harness {
+        Harness::start_in(tempfile::tempdir().unwrap(), config)
+    }
+
+    // Start the daemon in an existing scratch dir, for tests that need state in place before it starts.
+    pub fn start_in(dir: TempDir, config: &str) -> Harness {
+        Harness::start_in_with_env(dir, config, &[])
+    }
+
+    // Like start_in, with extra environment variables for the daemon.
+    pub fn start_in_with_env(dir: TempDir, config: &str, env: &[(&str, &OsStr)]) -> Harness {
+        let settings = dir
+            .path()
+            .join("home/.config/cameron-williams/rgdrive.toml");
+        fs::create_dir_all(settings.parent().unwrap()).unwrap();
+        fs::create_dir_all(dir.path().join("local")).unwrap();
+        fs::write(&settings, config).unwrap();
+
+        let google = FakeGoogle::start(dir.path());
+        let daemon = signed_in(
+            Command::new(env!("CARGO_BIN_EXE_rgdrived")).env_clear(),
+            &google,
+        )
+        .env("HOME", dir.path().join("home"))
+        .env("RGDRIVE_SOCKET", dir.path().join("rgdrive.sock"))
+        .env("RUST_LOG", "debug")
+        .envs(env.iter().cloned())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(fs::File::create(dir.path().join("rgdrived.err")).unwrap())
+        .spawn()
+        .expect("failed to spawn rgdrived");
+
+        let h = Harness {
+            dir,
+            google,
+            daemon,
+        };
+        assert!(
+            wait_for(|| h.socket().is_active()),
+            "rgdrived never started listening:\n{}",
+            h.log()
+        );
+        // Tracked files are watched in the background, changes made before that would go unnoticed.
+        assert!(
+            wait_for(|| h.log().contains("Watching ")),
+            "rgdrived never watched its tracked files:\n{}",
+            h.log()
+        );
+        h
+    }
+
+    // Stop the daemon and start a new one with the given config, keeping everything else in the scratch dir.
+    pub fn restart_with_config(mut self, config: &str) -> Harness {
+        let dir = std::mem::replace(&mut self.dir, tempfile::tempdir().unwrap());
+        drop(self);
+        Harness::start_in(dir, config)
+    }
+
+    // Start a second daemon sharing this one's $HOME and remote, running as profile. It listens on the profile's own
+    // socket in /tmp, so give it a name no other test run will use.
+    pub fn start_profile(&self, profile: &str) -> Daemon {
+        let child = signed_in(
+            Command::new(env!("CARGO_BIN_EXE_rgdrived")).env_clear(),
+            &self.google,
+        )
+        .env("HOME", self.dir.path().join("home"))
+        .env("RGDRIVE_PROFILE", profile)
+        .env("RUST_LOG", "debug")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(
+            fs::File::create(self.dir.path().join(format!("rgdrived-{}.err", profile))).unwrap(),
+        )
+        .spawn()
+        .expect("failed to spawn rgdrived");
+        let socket = DSocket::new(format!("/tmp/rgdrive-{}.sock", profile));
+        assert!(
+            wait_for(|| socket.is_active()),
+            "rgdrived for profile {} never started listening",
+            profile
+        );
+        Daemon(child)
+    }
+
+    pub fn socket(&self) -> DSocket {
+        DSocket::new(self.dir.path().join("rgdrive.sock"))
+    }
+
+    pub fn send(&self, cmd: DCommand) -> DResult {
+        self.socket().send_command(cmd).unwrap()
+    }
+
+    // Path inside the scratch directory the daemon treats as local disk.
+    pub fn local<P: AsRef<Path>>(&self, p: P) -> PathBuf {
+        self.dir.path().join("local").join(p)
+    }
+
+    // Contents of the fake remote file behind url, None if it doesn't exist.
+    pub fn remote(&self, url: &str) -> Option<String> {
+        let id = drive_id(url)?;
+        fs::read_to_string(self.dir.path().join("remote").join(id)).ok()
+    }
+
+    // Put a file straight into the fake remote, as if someone uploaded it through the Drive UI.
+    pub fn put_remote(&self, id: &str, name: &str, contents: &str) -> String {
+        let remote = self.dir.path().join("remote");
+        fs::create_dir_all(&remote).unwrap();
+        fs::write(remote.join(id), contents).unwrap();
+        fs::write(remote.join(format!("{}.name", id)), name).unwrap();
+        format!("https://drive.google.com/open?id={}", id)
+    }
+
+    pub fn log(&self) -> String {
+        fs::read_to_string(self.dir.path().join("rgdrived.err")).unwrap_or_default()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+// A daemon started by start_profile, killed when dropped.
+pub struct Daemon(Child);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// Poll f until it returns true or ten seconds pass, slow machines included.
+pub fn wait_for<F: FnMut() -> bool>(mut f: F) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(10) {
+        if f() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+// Drive url the daemon has tracked for path, read from its tracked files config.
+pub fn tracked_url(h: &Harness, path: &Path) -> Option<String> {
+    TrackedFile::from_path(h.dir.path().join("home/.config/cameron-williams/state.db"))
+        .into_iter()
+        .find(|tf| tf.path == path)
+        .map(|tf| tf.drive_url)
+}
+
+// Whether the daemon's poller has saved where it's up to in Drive's changes feed.
+pub fn polled(h: &Harness) -> bool {
+    State::open(&h.dir.path().join("home/.config/cameron-williams/state.db"))
+        .ok()
+        .and_then(|s| s.cursor("changes").ok().flatten())
+        .is_some()
+}
+
+// Point cmd at google, signed in to its "remote" account with a made up OAuth client.
+pub fn signed_in<'a>(cmd: &'a mut Command, google: &FakeGoogle) -> &'a mut Command {
+    cmd.env("RGDRIVE_GOOGLE_API", &google.url)
+        .env("GOOGLE_CLIENT_ID", "1234.apps.googleusercontent.com")
+        .env("GOOGLE_CLIENT_SECRET", "s3cret")
+        .env("GOOGLE_REFRESH_TOKEN", "remote")
+}
+
+// Run cmd with input on stdin, and when it asks to sign in (--auth, --login), open the sign in url like the browser
+// would.
+pub fn sign_in(cmd: &mut Command, input: &str) -> Output {
+    let mut child = cmd
+        .env("BROWSER", "true")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+    let browser = thread::spawn(move || {
+        let mut lines = Vec::new();
+        for line in stderr.lines() {
+            let line = line.unwrap();
+            if let Some(url) = line.split("browser: ").nth(1) {
+                ureq::get(url).call();
+            }
+            lines.push(line);
+        }
+        lines
+    });
+    let mut out = child.wait_with_output().unwrap();
+    for line in browser.join().unwrap() {
+        out.stderr.extend_from_slice(line.as_bytes());
+        out.stderr.push(b'\n');
+    }
+    out
+}
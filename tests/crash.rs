@@ -0,0 +1,31 @@
+// crash::init() installs a process wide logger and panic hook, so it gets a test binary of its own.
+use std::env;
+use std::panic;
+
+use log::info;
+use rgdrive::crash::Crash;
+use tempfile::TempDir;
+
+#[test]
+fn a_panic_writes_a_crash_report() {
+    let dir = TempDir::new().unwrap();
+    env::set_var("RGDRIVE_SOCKET", dir.path().join("rgdrive.sock"));
+    rgdrive::crash::init();
+    info!("Daemon initialized.");
+    assert!(panic::catch_unwind(|| panic!("boom")).is_err());
+
+    let crash = Crash::load().expect("no crash report");
+    assert!(dir.path().join("rgdrive.sock.crash").exists());
+    assert_eq!(crash.message, "boom");
+    assert_eq!(crash.version, env!("CARGO_PKG_VERSION"));
+    assert!(crash.location.unwrap().contains("crash.rs"));
+    assert!(!crash.backtrace.is_empty());
+    assert!(crash
+        .events
+        .iter()
+        .any(|e| e.contains("Daemon initialized.")));
+
+    // Only handed out once.
+    assert!(Crash::unreported().is_some());
+    assert!(Crash::unreported().is_none());
+}
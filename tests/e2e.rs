@@ -0,0 +1,3903 @@
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::net::Shutdown;
+use std::os::unix::net::{UnixDatagram, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{polled, sign_in, signed_in, tracked_url, wait_for, FakeGoogle, Harness};
+use rgdrive::checksum;
+use rgdrive::crash::Crash;
+use rgdrive::daemons::{Reason, StartupError};
+use rgdrive::export::Export;
+use rgdrive::placeholder;
+use rgdrive::remote::{drive_id, FOLDER_MIME, SHORTCUT_MIME};
+use rgdrive::stamp;
+use rgdrive::transfer::Overwrite;
+use rgdrive::{
+    decode, decode_response, encode, read_frame, DCommand, DResult, DSocket, TrackedFile,
+    WatchEvent, ACCEPT_ZSTD, HELLO, MAX_FRAME_BYTES,
+};
+
+fn is_ok(r: &DResult) -> bool {
+    r.is_ok()
+}
+
+#[test]
+fn push_uploads_and_tracks() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "hello").unwrap();
+
+    let r = h.send(DCommand::Push(path.clone(), true));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+
+    let url = tracked_url(&h, &path).expect("pushed file wasn't tracked");
+    assert_eq!(h.remote(&url).as_deref(), Some("hello"));
+}
+
+#[test]
+fn local_modify_updates_remote() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), true))));
+    let url = tracked_url(&h, &path).unwrap();
+
+    fs::write(&path, "v2").unwrap();
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("v2")),
+        "remote never updated:\n{}",
+        h.log()
+    );
+}
+
+#[test]
+fn pull_downloads_and_tracks() {
+    let h = Harness::start();
+    let url = h.put_remote("abc123", "report.txt", "remote contents");
+
+    let r = h.send(DCommand::Pull(
+        url.clone(),
+        h.local(""),
+        Overwrite::Never,
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+
+    let path = h.local("report.txt");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "remote contents");
+    assert_eq!(tracked_url(&h, &path), Some(url));
+}
+
+#[test]
+fn pull_into_dir_never_replaces_a_name_differing_in_case() {
+    let h = Harness::start();
+    let url = h.put_remote("abc123", "Report.txt", "remote contents");
+    fs::write(h.local("report.txt"), "local contents").unwrap();
+    let pull = || {
+        h.send(DCommand::Pull(
+            url.clone(),
+            h.local(""),
+            Overwrite::Always,
+            false,
+        ))
+    };
+
+    let r = pull();
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    let path = h.local("Report (2).txt");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "remote contents");
+    assert_eq!(tracked_url(&h, &path), Some(url.clone()));
+    assert_eq!(
+        fs::read_to_string(h.local("report.txt")).unwrap(),
+        "local contents"
+    );
+
+    // Pulling again finds the same copy rather than numbering another.
+    match pull() {
+        DResult::Fields(_, fields) => assert!(
+            fields.contains(&(String::from("path"), path.to_str().unwrap().to_string())),
+            "{:?}",
+            fields
+        ),
+        r => panic!("{:?}\n{}", r, h.log()),
+    }
+    assert!(!h.local("Report (3).txt").exists());
+}
+
+#[test]
+fn pull_refuses_to_overwrite_without_flag() {
+    let h = Harness::start();
+    let url = h.put_remote("abc123", "report.txt", "remote contents");
+    let path = h.local("report.txt");
+    fs::write(&path, "local contents").unwrap();
+
+    assert!(!is_ok(&h.send(DCommand::Pull(
+        url.clone(),
+        path.clone(),
+        Overwrite::Never,
+        false
+    ))));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "local contents");
+
+    assert!(is_ok(&h.send(DCommand::Pull(
+        url,
+        path.clone(),
+        Overwrite::Always,
+        false
+    ))));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "remote contents");
+}
+
+#[test]
+fn pull_overwrite_modes() {
+    // Review holds local edits back, so they stay different from the last sync.
+    let h = Harness::start_with_config("[review]\nenabled = true\n");
+    let url = h.put_remote("abc123", "report.txt", "v1");
+    let path = h.local("report.txt");
+    fs::write(&path, "local").unwrap();
+    let pull = |mode| h.send(DCommand::Pull(url.clone(), path.clone(), mode, false));
+
+    // Written after the Drive copy, and never synced.
+    assert!(!is_ok(&pull(Overwrite::RemoteNewer)));
+    assert!(!is_ok(&pull(Overwrite::LocalUnmodified)));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "local");
+    assert!(Command::new("touch")
+        .args(["-d", "@1000000000"])
+        .arg(&path)
+        .status()
+        .unwrap()
+        .success());
+    assert!(is_ok(&pull(Overwrite::RemoteNewer)));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "v1");
+
+    // Unchanged since that pull.
+    h.put_remote("abc123", "report.txt", "v2");
+    let r = pull(Overwrite::LocalUnmodified);
+    assert!(is_ok(&r), "{:?}", r);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "v2");
+
+    fs::write(&path, "edited").unwrap();
+    h.put_remote("abc123", "report.txt", "v3");
+    match pull(Overwrite::LocalUnmodified) {
+        DResult::Err(e) => assert!(e.contains("changed since it was last synced"), "{}", e),
+        r => panic!("{:?}", r),
+    }
+    assert_eq!(fs::read_to_string(&path).unwrap(), "edited");
+}
+
+#[test]
+fn manual_sync_and_unsync() {
+    let h = Harness::start();
+    let url = h.put_remote("abc123", "report.txt", "old");
+    let path = h.local("report.txt");
+    fs::write(&path, "old").unwrap();
+
+    assert!(is_ok(&h.send(DCommand::FSync(
+        path.clone(),
+        url.clone(),
+        None
+    ))));
+    assert_eq!(tracked_url(&h, &path), Some(url.clone()));
+
+    assert!(is_ok(&h.send(DCommand::FUnSync(path.clone()))));
+    assert_eq!(tracked_url(&h, &path), None);
+}
+
+#[test]
+fn policy_rejects_files_outside_allowed_folder() {
+    let h = Harness::start_with_config("[policy]\nallowed_folder = \"folder1\"\n");
+    h.put_remote("folder1", "Work", "");
+    let outside = h.put_remote("abc123", "report.txt", "old");
+    let path = h.local("report.txt");
+    fs::write(&path, "old").unwrap();
+    match h.send(DCommand::FSync(path.clone(), outside, None)) {
+        DResult::Err(e) => assert!(e.contains("outside of the allowed folder"), "{}", e),
+        r => panic!("{:?}", r),
+    }
+
+    // Uploaded into the folder, and found beneath it when Drive is asked.
+    let pushed = h.local("new.txt");
+    fs::write(&pushed, "new").unwrap();
+    assert!(
+        is_ok(&h.send(DCommand::Push(pushed.clone(), true))),
+        "{}",
+        h.log()
+    );
+    let url = tracked_url(&h, &pushed).unwrap();
+    let id = drive_id(&url).unwrap();
+    assert_eq!(
+        fs::read_to_string(h.dir.path().join(format!("remote/{}.parent", id))).unwrap(),
+        "folder1"
+    );
+    assert!(is_ok(&h.send(DCommand::FSync(path, url, None))));
+}
+
+#[test]
+fn malformed_command_gets_an_error() {
+    let h = Harness::start();
+    let mut s = UnixStream::connect(h.dir.path().join("rgdrive.sock")).unwrap();
+    s.write_all(&[0xff; 32]).unwrap();
+    s.shutdown(Shutdown::Write).unwrap();
+    let r: DResult = decode(&read_frame(&s, MAX_FRAME_BYTES).unwrap(), MAX_FRAME_BYTES).unwrap();
+    assert!(!is_ok(&r));
+
+    // The daemon is still serving afterwards.
+    assert!(is_ok(&h.send(DCommand::Stats)));
+}
+
+#[test]
+fn large_responses_are_compressed_for_clients_that_accept_it() {
+    let h = Harness::start_with_config("[limits]\ncompress_above_bytes = 256\n");
+    let query = DCommand::PathStatusBatch(vec![h.local("nowhere"); 500]);
+    let expected = DResult::ok(vec!["untracked"; 500].join("\n"));
+    let raw = |hello: bool| {
+        let mut s = UnixStream::connect(h.dir.path().join("rgdrive.sock")).unwrap();
+        if hello {
+            s.write_all(HELLO).unwrap();
+            s.write_all(&ACCEPT_ZSTD.to_le_bytes()).unwrap();
+        }
+        s.write_all(&encode(&query, MAX_FRAME_BYTES).unwrap())
+            .unwrap();
+        s.shutdown(Shutdown::Write).unwrap();
+        read_frame(&s, MAX_FRAME_BYTES).unwrap()
+    };
+
+    let plain = raw(false);
+    assert_eq!(
+        decode::<DResult>(&plain, MAX_FRAME_BYTES).unwrap(),
+        expected
+    );
+    let packed = raw(true);
+    assert!(
+        packed.len() < plain.len() / 4,
+        "{} vs {}",
+        packed.len(),
+        plain.len()
+    );
+    assert_eq!(decode_response(&packed, MAX_FRAME_BYTES).unwrap(), expected);
+
+    // DSocket says it accepts compression, small responses still come plain.
+    assert_eq!(h.send(query), expected);
+    assert_eq!(
+        h.send(DCommand::Message(String::from("ping"))),
+        DResult::ok("pong")
+    );
+}
+
+#[test]
+fn health_reflects_failure_rate() {
+    let h = Harness::start_with_config("[health]\nmin_operations = 1\nmax_failure_rate = 0.0\n");
+    assert!(is_ok(&h.send(DCommand::Health)), "{}", h.log());
+
+    let r = h.send(DCommand::Pull(
+        String::from("https://drive.google.com/open?id=missing"),
+        h.local(""),
+        Overwrite::Never,
+        false,
+    ));
+    assert!(!is_ok(&r));
+
+    match h.send(DCommand::Health) {
+        DResult::Err(e) => assert!(e.contains("1 of 1 operations failed"), "{}", e),
+        r => panic!("expected unhealthy, got {:?}", r),
+    }
+}
+
+#[test]
+fn sync_latency_percentiles_and_objective() {
+    // Debouncing holds every upload back by at least quiet_ms.
+    let h = Harness::start_with_config(
+        "[debounce]\nquiet_ms = 300\n[health]\nmin_operations = 1\nmax_sync_latency_ms = 200\n",
+    );
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+    assert!(is_ok(&h.send(DCommand::Health)), "{}", h.log());
+
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v2")));
+    let stat = |name: &str| match h.send(DCommand::Stats) {
+        DResult::Ok(s) => s
+            .lines()
+            .find_map(|l| l.strip_prefix(name))
+            .and_then(|n| n.parse::<u64>().ok()),
+        r => panic!("{:?}", r),
+    };
+    assert!(wait_for(|| stat("sync latencies recorded: ") == Some(1)));
+    let p90 = stat("sync latency p90 (ms): ").unwrap();
+    assert!(p90 >= 300, "{}", p90);
+    assert_eq!(stat("sync latency max (ms): "), Some(p90));
+
+    match h.send(DCommand::Health) {
+        DResult::Err(e) => assert!(e.contains("p90 sync latency was"), "{}", e),
+        r => panic!("expected unhealthy, got {:?}", r),
+    }
+}
+
+#[test]
+fn slow_client_times_out_and_is_limited() {
+    let h = Harness::start_with_config(
+        "[limits]\nrequest_timeout_secs = 1\nmax_connections_per_client = 1\n",
+    );
+    // Let the harness's own startup connection release its slot first.
+    thread::sleep(Duration::from_millis(200));
+
+    // Connect and never finish the command.
+    let mut idle = UnixStream::connect(h.dir.path().join("rgdrive.sock")).unwrap();
+    idle.write_all(&[0]).unwrap();
+
+    // The idle connection holds this process's only slot.
+    match h.send(DCommand::Stats) {
+        DResult::Err(e) => assert!(e.contains("Too many open connections"), "{}", e),
+        r => panic!("expected rejection, got {:?}", r),
+    }
+
+    // Once the deadline passes the idle client is told so, and the slot frees up.
+    let r: DResult = decode(
+        &read_frame(&idle, MAX_FRAME_BYTES).unwrap(),
+        MAX_FRAME_BYTES,
+    )
+    .unwrap();
+    assert_eq!(r, DResult::error("Request timed out."));
+    assert!(wait_for(|| is_ok(&h.send(DCommand::Stats))), "{}", h.log());
+}
+
+#[test]
+fn connections_past_the_memory_budget_are_shed() {
+    let h = Harness::start_with_config(
+        "[limits]\nrequest_timeout_secs = 1\nmax_buffer_bytes = 4096\nmax_memory_bytes = 4096\n",
+    );
+    thread::sleep(Duration::from_millis(200));
+
+    // The idle connection's command buffer takes the whole budget.
+    let mut idle = UnixStream::connect(h.dir.path().join("rgdrive.sock")).unwrap();
+    idle.write_all(&[0]).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    match h.send(DCommand::Stats) {
+        DResult::Err(e) => assert!(e.contains("Daemon is busy"), "{}", e),
+        r => panic!("expected rejection, got {:?}", r),
+    }
+
+    // Once it's dropped the budget is free again.
+    let _ = read_frame(&idle, MAX_FRAME_BYTES);
+    assert!(wait_for(|| is_ok(&h.send(DCommand::Stats))), "{}", h.log());
+}
+
+#[test]
+fn directory_push_tracks_every_upload() {
+    let h = Harness::start();
+    let dir = h.local("docs");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::write(dir.join("a.txt"), "a").unwrap();
+    fs::write(dir.join("sub/b.txt"), "b").unwrap();
+
+    let r = h.send(DCommand::Push(dir.clone(), true));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    for p in &[dir.join("a.txt"), dir.join("sub/b.txt")] {
+        assert!(tracked_url(&h, p).is_some(), "{:?} wasn't tracked", p);
+    }
+    // Nothing is left pending once the batch commits.
+    let pending = h.dir.path().join("home/.config/cameron-williams/pending");
+    assert_eq!(fs::read_dir(pending).unwrap().count(), 0);
+}
+
+#[test]
+fn interrupted_batch_is_tracked_on_startup() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("local/notes.txt");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, "hello").unwrap();
+    let pending = dir.path().join("home/.config/cameron-williams/pending");
+    fs::create_dir_all(&pending).unwrap();
+    let batch = vec![TrackedFile::new(
+        path.clone(),
+        "https://drive.google.com/open?id=uploaded",
+    )];
+    fs::write(pending.join("1-1"), TrackedFile::encode_all(&batch)).unwrap();
+
+    let h = Harness::start_in(dir, "");
+    let url = String::from("https://drive.google.com/open?id=uploaded");
+    assert!(
+        wait_for(|| tracked_url(&h, &path).as_ref() == Some(&url)),
+        "{}",
+        h.log()
+    );
+    assert!(wait_for(|| !pending.join("1-1").exists()));
+}
+
+#[test]
+fn poller_notices_remote_changes() {
+    let h = Harness::start_with_config("[poll]\ninterval_secs = 1\n");
+    let url = h.put_remote("abc123", "report.txt", "v1");
+    let path = h.local("report.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::FSync(path, url.clone(), None))));
+
+    // Give the poller a pass to note where Drive's changes feed ends, then change the file behind its back.
+    let stat = |name: &str| match h.send(DCommand::Stats) {
+        DResult::Ok(s) => s
+            .lines()
+            .find(|l| l.starts_with(name))
+            .and_then(|l| l.rsplit(' ').next())
+            .and_then(|n| n.parse::<u64>().ok())
+            .unwrap_or(0),
+        r => panic!("{:?}", r),
+    };
+    assert!(wait_for(|| polled(&h)), "{}", h.log());
+    h.put_remote("abc123", "report.txt", "v2, edited in the Drive UI");
+
+    assert!(
+        wait_for(|| stat("remote changes seen:") == 1),
+        "{}",
+        h.log()
+    );
+    // Later polls don't list it again.
+    let polls = stat("remote polls:");
+    assert!(wait_for(|| stat("remote polls:") >= polls + 2));
+    assert_eq!(stat("remote changes seen:"), 1);
+}
+
+#[test]
+fn changes_made_while_the_daemon_was_stopped_are_noticed() {
+    let h = Harness::start_with_config("[poll]\ninterval_secs = 1\n");
+    let url = h.put_remote("abc123", "report.txt", "v1");
+    let path = h.local("report.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::FSync(path, url, None))));
+    assert!(wait_for(|| polled(&h)), "{}", h.log());
+
+    // Edited on Drive while no daemon is polling.
+    let h = h.restart_with_config("");
+    h.put_remote("abc123", "report.txt", "v2, edited while stopped");
+    let h = h.restart_with_config("[poll]\ninterval_secs = 1\n");
+    let stat = |name: &str| match h.send(DCommand::Stats) {
+        DResult::Ok(s) => s
+            .lines()
+            .find(|l| l.starts_with(name))
+            .and_then(|l| l.rsplit(' ').next())
+            .and_then(|n| n.parse::<u64>().ok())
+            .unwrap_or(0),
+        r => panic!("{:?}", r),
+    };
+    assert!(
+        wait_for(|| stat("remote changes seen:") == 1),
+        "{}",
+        h.log()
+    );
+}
+
+#[test]
+fn paged_polls_get_through_every_change() {
+    let h = Harness::start();
+    let ids = ["page1", "page2", "page3"];
+    for id in &ids {
+        let url = h.put_remote(id, id, "v1");
+        let path = h.local(id);
+        fs::write(&path, "v1").unwrap();
+        assert!(is_ok(&h.send(DCommand::FSync(path, url, None))));
+    }
+    let h = h.restart_with_config("[poll]\ninterval_secs = 1\npage_size = 1\nconcurrency = 2\n");
+    let stat = |name: &str| match h.send(DCommand::Stats) {
+        DResult::Ok(s) => s
+            .lines()
+            .find(|l| l.starts_with(name))
+            .and_then(|l| l.rsplit(' ').next())
+            .and_then(|n| n.parse::<u64>().ok())
+            .unwrap_or(0),
+        r => panic!("{:?}", r),
+    };
+    assert!(wait_for(|| polled(&h)), "{}", h.log());
+    // One change a poll, so three more polls before they've all been seen.
+    let polls = stat("remote polls:");
+    for id in &ids {
+        h.put_remote(id, id, "v2");
+    }
+    assert!(
+        wait_for(|| stat("remote changes seen:") == 3),
+        "{}",
+        h.log()
+    );
+    assert!(stat("remote polls:") >= polls + 3);
+}
+
+#[test]
+fn watched_folder_downloads_new_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let inbox = dir.path().join("local/inbox");
+    fs::create_dir_all(&inbox).unwrap();
+    let config = format!(
+        "[poll]\ninterval_secs = 1\n\n[[watch]]\nfolder = \"https://drive.google.com/drive/folders/shared1\"\ndest = {:?}\n",
+        inbox
+    );
+    let h = Harness::start_in(dir, &config);
+
+    h.put_remote("new1", "dropped.txt", "from a colleague");
+    fs::write(h.dir.path().join("remote/new1.parent"), "shared1").unwrap();
+    h.put_remote("other", "elsewhere.txt", "not in the folder");
+
+    assert!(
+        wait_for(|| fs::read_to_string(inbox.join("dropped.txt"))
+            .ok()
+            .as_deref()
+            == Some("from a colleague")),
+        "{}",
+        h.log()
+    );
+    assert!(!inbox.join("elsewhere.txt").exists());
+
+    // Inbound only: removing the local copy doesn't bring it back.
+    fs::remove_file(inbox.join("dropped.txt")).unwrap();
+    thread::sleep(Duration::from_millis(1500));
+    assert!(!inbox.join("dropped.txt").exists());
+
+    // Files dropped in later are found among the changes, the folder is only listed the first time.
+    h.put_remote("new2", "later.txt", "another one");
+    fs::write(h.dir.path().join("remote/new2.parent"), "shared1").unwrap();
+    assert!(wait_for(|| inbox.join("later.txt").exists()), "{}", h.log());
+    assert_eq!(
+        h.log().matches("'shared1'%20in%20parents").count(),
+        1,
+        "{}",
+        h.log()
+    );
+
+    // Nor after a restart, which carries on from the folder's cursor.
+    let h = h.restart_with_config(&config);
+    h.put_remote("new3", "after.txt", "and another");
+    fs::write(h.dir.path().join("remote/new3.parent"), "shared1").unwrap();
+    assert!(wait_for(|| inbox.join("after.txt").exists()), "{}", h.log());
+    assert!(!h.log().contains("'shared1'%20in%20parents"), "{}", h.log());
+}
+
+#[test]
+fn activity_lists_remote_changes() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), true))));
+    let url = tracked_url(&h, &path).unwrap();
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v2")));
+
+    match h.send(DCommand::Activity(url)) {
+        DResult::Ok(s) => {
+            let actions: Vec<&str> = s
+                .lines()
+                .skip(1)
+                .filter_map(|l| l.rsplit("  ").next())
+                .collect();
+            assert_eq!(actions, vec!["create", "edit"], "{}", s);
+        }
+        r => panic!("{:?}\n{}", r, h.log()),
+    }
+}
+
+#[test]
+fn pull_starred_syncs_starred_files() {
+    let h = Harness::start();
+    let starred = h.put_remote("star1", "starred.txt", "keep me offline");
+    fs::write(h.dir.path().join("remote/star1.starred"), "").unwrap();
+    h.put_remote("plain", "plain.txt", "not starred");
+    let dir = h.local("");
+
+    let r = h.send(DCommand::PullStarred(dir.clone()));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert_eq!(
+        fs::read_to_string(dir.join("starred.txt")).unwrap(),
+        "keep me offline"
+    );
+    assert_eq!(tracked_url(&h, &dir.join("starred.txt")), Some(starred));
+    assert!(!dir.join("plain.txt").exists());
+
+    // Rerunning skips what's already synced.
+    match h.send(DCommand::PullStarred(dir)) {
+        DResult::Ok(s) => assert!(s.contains("0 pulled, 1 already present"), "{}", s),
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn pull_dir_recreates_folder_tree() {
+    let h = Harness::start_with_config("");
+    let folder = |id: &str, name: &str, parent: Option<&str>| {
+        h.put_remote(id, name, "");
+        fs::write(
+            h.dir.path().join(format!("remote/{}.mime", id)),
+            FOLDER_MIME,
+        )
+        .unwrap();
+        if let Some(p) = parent {
+            fs::write(h.dir.path().join(format!("remote/{}.parent", id)), p).unwrap();
+        }
+    };
+    folder("proj1", "Projects", None);
+    folder("sub1", "Drafts", Some("proj1"));
+    h.put_remote("readme1", "README.md", "top");
+    fs::write(h.dir.path().join("remote/readme1.parent"), "proj1").unwrap();
+    h.put_remote("draft1", "draft.txt", "nested");
+    fs::write(h.dir.path().join("remote/draft1.parent"), "sub1").unwrap();
+    h.put_remote("doc1", "Notes", "");
+    fs::write(
+        h.dir.path().join("remote/doc1.mime"),
+        "application/vnd.google-apps.document",
+    )
+    .unwrap();
+    fs::write(h.dir.path().join("remote/doc1.parent"), "proj1").unwrap();
+    let dir = h.local("Projects");
+
+    let r = h.send(DCommand::PullDir(
+        String::from("https://drive.google.com/drive/folders/proj1"),
+        dir.clone(),
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert_eq!(fs::read_to_string(dir.join("README.md")).unwrap(), "top");
+    assert_eq!(
+        fs::read_to_string(dir.join("Drafts/draft.txt")).unwrap(),
+        "nested"
+    );
+    assert_eq!(
+        tracked_url(&h, &dir.join("Drafts/draft.txt")).as_deref(),
+        Some("https://drive.google.com/open?id=draft1")
+    );
+    assert!(!dir.join("Notes").exists());
+
+    // Rerunning skips what's already synced.
+    match h.send(DCommand::PullDir(String::from("proj1"), dir, false)) {
+        DResult::Ok(s) => assert!(s.contains("0 pulled, 2 already present"), "{}", s),
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn nfd_local_names_match_drive_names() {
+    let h = Harness::start();
+    h.put_remote("trip1", "Trip", "");
+    fs::write(h.dir.path().join("remote/trip1.mime"), FOLDER_MIME).unwrap();
+    h.put_remote("cafe1", "Caf\u{e9}", "");
+    fs::write(h.dir.path().join("remote/cafe1.mime"), FOLDER_MIME).unwrap();
+    fs::write(h.dir.path().join("remote/cafe1.parent"), "trip1").unwrap();
+    h.put_remote("menu1", "menu.txt", "remote menu");
+    fs::write(h.dir.path().join("remote/menu1.parent"), "cafe1").unwrap();
+    h.put_remote("cv1", "R\u{e9}sum\u{e9}.txt", "remote cv");
+    fs::write(h.dir.path().join("remote/cv1.parent"), "trip1").unwrap();
+    // The same names in NFD, as copied over from a Mac.
+    let dir = h.local("Trip");
+    fs::create_dir_all(dir.join("Cafe\u{301}")).unwrap();
+    fs::write(dir.join("Re\u{301}sume\u{301}.txt"), "local cv").unwrap();
+
+    match h.send(DCommand::PullDir(String::from("trip1"), dir.clone(), false)) {
+        DResult::Ok(s) => assert!(s.contains("1 pulled, 1 already present"), "{}", s),
+        r => panic!("{:?}\n{}", r, h.log()),
+    }
+    assert_eq!(
+        fs::read_to_string(dir.join("Cafe\u{301}/menu.txt")).unwrap(),
+        "remote menu"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("Re\u{301}sume\u{301}.txt")).unwrap(),
+        "local cv"
+    );
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+}
+
+#[test]
+fn placeholders_hydrate_on_demand() {
+    let h = Harness::start();
+    h.put_remote("arch1", "Archive", "");
+    fs::write(h.dir.path().join("remote/arch1.mime"), FOLDER_MIME).unwrap();
+    let url = h.put_remote("tax1", "taxes", "large scan");
+    fs::write(h.dir.path().join("remote/tax1.parent"), "arch1").unwrap();
+    let dir = h.local("Archive");
+
+    let r = h.send(DCommand::PullDir(String::from("arch1"), dir.clone(), true));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    let path = dir.join("taxes");
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+    assert_eq!(placeholder::drive_id_of(&path).as_deref(), Some("tax1"));
+    assert_eq!(placeholder::size_of(&path), Some(10));
+    assert_eq!(tracked_url(&h, &path), None);
+
+    let r = h.send(DCommand::Hydrate(dir.clone()));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "large scan");
+    assert_eq!(placeholder::drive_id_of(&path), None);
+    assert_eq!(tracked_url(&h, &path), Some(url));
+    assert!(!is_ok(&h.send(DCommand::Hydrate(dir))));
+}
+
+#[test]
+fn the_cache_evicts_the_least_recently_used_files() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path().join("local/Archive");
+    let config = format!(
+        "[cache]\nmax_bytes = 15\npaths = [{:?}]\ninterval_secs = 3600\n",
+        dir
+    );
+    let h = Harness::start_in(tmp, &config);
+    h.put_remote("arch1", "Archive", "");
+    fs::write(h.dir.path().join("remote/arch1.mime"), FOLDER_MIME).unwrap();
+    for (id, contents) in &[("scan1", "first scan"), ("scan2", "other scan")] {
+        h.put_remote(id, id, contents);
+        fs::write(h.dir.path().join(format!("remote/{}.parent", id)), "arch1").unwrap();
+    }
+    assert!(is_ok(&h.send(DCommand::PullDir(
+        String::from("arch1"),
+        dir.clone(),
+        true
+    ))));
+    let (first, other) = (dir.join("scan1"), dir.join("scan2"));
+
+    let r = h.send(DCommand::Hydrate(first.clone()));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert_eq!(fs::read_to_string(&first).unwrap(), "first scan");
+
+    // Both don't fit, the one hydrated earlier makes room.
+    match h.send(DCommand::Hydrate(other.clone())) {
+        DResult::Ok(s) => assert!(s.contains("Evicted 1 to make room."), "{}", s),
+        r => panic!("{:?}\n{}", r, h.log()),
+    }
+    assert_eq!(fs::read_to_string(&other).unwrap(), "other scan");
+    assert_eq!(placeholder::drive_id_of(&first).as_deref(), Some("scan1"));
+    assert_eq!(placeholder::size_of(&first), Some(10));
+    assert_eq!(tracked_url(&h, &first), None);
+    assert_eq!(
+        h.remote("https://drive.google.com/open?id=scan1")
+            .as_deref(),
+        Some("first scan")
+    );
+
+    // And comes back on demand.
+    assert!(is_ok(&h.send(DCommand::Hydrate(first.clone()))));
+    assert_eq!(fs::read_to_string(&first).unwrap(), "first scan");
+    assert_eq!(placeholder::drive_id_of(&other).as_deref(), Some("scan2"));
+}
+
+#[test]
+fn stamps_rebuild_lost_state() {
+    let mut h = Harness::start();
+    let url = h.put_remote("stamp1", "notes.md", "v1");
+    let path = h.local("notes.md");
+    let r = h.send(DCommand::Pull(
+        url.clone(),
+        path.clone(),
+        Overwrite::Never,
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}", r);
+    let s = stamp::read(&path).unwrap();
+    assert_eq!(s.drive_id, "stamp1");
+    assert_eq!(s.md5, checksum::md5_file(&path).ok());
+
+    // The tracked files are lost while the daemon is down, and the Drive copy changes meanwhile.
+    let dir = std::mem::replace(&mut h.dir, tempfile::tempdir().unwrap());
+    drop(h);
+    fs::remove_file(dir.path().join("home/.config/cameron-williams/state.db")).unwrap();
+    let h = Harness::start_in(dir, "");
+    h.put_remote("stamp1", "notes.md", "v2");
+    assert_eq!(tracked_url(&h, &path), None);
+
+    let r = h.send(DCommand::RebuildState(h.local("")));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert_eq!(tracked_url(&h, &path), Some(url.clone()));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "v2");
+
+    // Unsyncing removes the stamp.
+    assert!(is_ok(&h.send(DCommand::FUnSync(path.clone()))));
+    assert_eq!(stamp::read(&path), None);
+}
+
+#[test]
+fn sheet_export_and_reexport_on_change() {
+    let h = Harness::start_with_config("[poll]\ninterval_secs = 1\n");
+    let url = h.put_remote("sheet1", "Finances", "first,tab\n");
+    fs::write(
+        h.dir.path().join("remote/sheet1.mime"),
+        "application/vnd.google-apps.spreadsheet",
+    )
+    .unwrap();
+    let budget = h.dir.path().join("remote/sheet1.sheet.Budget2024");
+    fs::write(&budget, "month,spent\njan,10\n").unwrap();
+    let export = Export {
+        format: String::from("csv"),
+        sheet: Some(String::from("Budget2024")),
+        range: None,
+    };
+
+    let r = h.send(DCommand::Export(
+        url.clone(),
+        h.local(""),
+        false,
+        export.clone(),
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    let path = h.local("Finances.csv");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "month,spent\njan,10\n");
+    // Exports are tracked with their format, but never watched for upload.
+    let tracked =
+        TrackedFile::from_path(h.dir.path().join("home/.config/cameron-williams/state.db"));
+    let tf = tracked.iter().find(|tf| tf.path == path).unwrap();
+    assert_eq!(tf.export.as_ref(), Some(&export));
+    assert_eq!(
+        tf.mime_type.as_deref(),
+        Some("application/vnd.google-apps.spreadsheet")
+    );
+    assert!(tf.wd.is_none());
+
+    // Editing the Sheet on Drive gets picked up by the poller and re-exported.
+    thread::sleep(Duration::from_millis(1500));
+    fs::write(&budget, "month,spent\njan,10\nfeb,20\n").unwrap();
+    h.put_remote("sheet1", "Finances", "first,tab,edited\n");
+    assert!(
+        wait_for(|| fs::read_to_string(&path).unwrap().contains("feb")),
+        "{}",
+        h.log()
+    );
+
+    // Only csv/tsv can pick a sheet.
+    let pdf = Export {
+        format: String::from("pdf"),
+        ..export
+    };
+    assert!(!is_ok(&h.send(DCommand::Export(
+        url.clone(),
+        h.local("x.pdf"),
+        false,
+        pdf
+    ))));
+    // Formats are checked against the remote file's type.
+    let docx = Export {
+        format: String::from("docx"),
+        sheet: None,
+        range: None,
+    };
+    assert!(!is_ok(&h.send(DCommand::Export(
+        url,
+        h.local("x.docx"),
+        false,
+        docx
+    ))));
+}
+
+#[test]
+fn docs_export_as_markdown() {
+    let h = Harness::start();
+    let url = h.put_remote("doc1", "Notes", "# Notes\n\n- one\n");
+    fs::write(
+        h.dir.path().join("remote/doc1.mime"),
+        "application/vnd.google-apps.document",
+    )
+    .unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+        .env("HOME", h.dir.path().join("home"))
+        .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+        .args(["--pull", &url])
+        .arg(h.local(""))
+        .args(["--export-format", "md"])
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}\n{}", out, h.log());
+    let path = h.local("Notes.md");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "# Notes\n\n- one\n");
+    let tracked =
+        TrackedFile::from_path(h.dir.path().join("home/.config/cameron-williams/state.db"));
+    let tf = tracked.iter().find(|tf| tf.path == path).unwrap();
+    assert_eq!(tf.export.as_ref().map(|e| e.format.as_str()), Some("md"));
+}
+
+#[test]
+fn screenshots_are_shared_and_copied_to_clipboard() {
+    let dir = tempfile::tempdir().unwrap();
+    let shots = dir.path().join("local/Screenshots");
+    fs::create_dir_all(&shots).unwrap();
+    let clip = dir.path().join("clipboard");
+    let config = format!(
+        "[screenshots]\ndir = {:?}\nclipboard = [\"/bin/sh\", \"-c\", \"cat > {}\"]\n",
+        shots,
+        clip.display()
+    );
+    let h = Harness::start_in(dir, &config);
+    // Commands are only answered once startup is done, which includes watching the directory.
+    assert!(is_ok(&h.send(DCommand::Stats)));
+
+    // Tools usually write to a hidden temp file and rename it into place.
+    fs::write(shots.join("notes.txt"), "not an image").unwrap();
+    fs::write(shots.join(".shot.png.tmp"), "png bytes").unwrap();
+    fs::rename(shots.join(".shot.png.tmp"), shots.join("shot.png")).unwrap();
+
+    // The shell creates the file before cat writes to it.
+    assert!(
+        wait_for(|| fs::read_to_string(&clip).is_ok_and(|s| s.ends_with("/view?usp=sharing"))),
+        "{}",
+        h.log()
+    );
+    let link = fs::read_to_string(&clip).unwrap();
+    assert_eq!(h.remote(&link).as_deref(), Some("png bytes"));
+    let id = link.split("/d/").nth(1).unwrap().split('/').next().unwrap();
+    assert!(h.dir.path().join(format!("remote/{}.shared", id)).exists());
+    // Only the image was uploaded.
+    let uploads = fs::read_dir(h.dir.path().join("remote"))
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().extension().is_none())
+        .count();
+    assert_eq!(uploads, 1);
+}
+
+#[test]
+fn editor_temp_files_never_reach_the_screenshot_watcher() {
+    let dir = tempfile::tempdir().unwrap();
+    let shots = dir.path().join("local/Screenshots");
+    fs::create_dir_all(&shots).unwrap();
+    let config = format!(
+        "[screenshots]\ndir = {:?}\nclipboard = [\"/bin/true\"]\n",
+        shots
+    );
+    let h = Harness::start_in(dir, &config);
+    assert!(is_ok(&h.send(DCommand::Stats)));
+
+    for name in &[".shot.png.swp", "shot.png~", "4913", "shot.png.part"] {
+        fs::write(shots.join(name), "junk").unwrap();
+    }
+    assert!(
+        wait_for(|| match h.send(DCommand::Stats) {
+            DResult::Ok(r) => r.contains("inotify events filtered: 4"),
+            _ => false,
+        }),
+        "{}",
+        h.log()
+    );
+    // Nothing was uploaded.
+    assert!(!h.dir.path().join("remote").exists());
+}
+
+#[test]
+fn share_returns_a_link() {
+    let h = Harness::start();
+    let path = h.local("photo.jpg");
+    fs::write(&path, "jpeg bytes").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), true))));
+    let url = tracked_url(&h, &path).unwrap();
+
+    let link = match h.send(DCommand::Share(url)) {
+        DResult::Ok(link) => link,
+        r => panic!("{:?}\n{}", r, h.log()),
+    };
+    assert!(link.ends_with("/view?usp=sharing"), "{}", link);
+    assert_eq!(h.remote(&link).as_deref(), Some("jpeg bytes"));
+    assert!(!is_ok(&h.send(DCommand::Share(String::from(
+        "https://drive.google.com/open?id=missing"
+    )))));
+}
+
+#[test]
+fn aliases_resolve_in_commands() {
+    let h = Harness::start_with_config(
+        "[aliases]\nreports = \"https://drive.google.com/drive/folders/reports1\"\n",
+    );
+    h.put_remote("summary1", "summary.pdf", "q3 numbers");
+    fs::write(h.dir.path().join("remote/summary1.parent"), "reports1").unwrap();
+
+    let path = h.local("summary.pdf");
+    let r = h.send(DCommand::Pull(
+        String::from("@reports/summary.pdf"),
+        path.clone(),
+        Overwrite::Never,
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "q3 numbers");
+    assert_eq!(
+        tracked_url(&h, &path).as_deref(),
+        Some("https://drive.google.com/open?id=summary1")
+    );
+
+    let notes = h.local("notes.txt");
+    fs::write(&notes, "draft").unwrap();
+    let r = h.send(DCommand::PushTo(
+        notes.clone(),
+        String::from("@reports"),
+        true,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    let url = tracked_url(&h, &notes).unwrap();
+    let id = url.rsplit('=').next().unwrap();
+    assert_eq!(
+        fs::read_to_string(h.dir.path().join(format!("remote/{}.parent", id))).unwrap(),
+        "reports1"
+    );
+
+    assert!(!is_ok(&h.send(DCommand::Pull(
+        String::from("@missing/summary.pdf"),
+        h.local("other.pdf"),
+        Overwrite::Never,
+        false
+    ))));
+    assert!(!is_ok(&h.send(DCommand::Pull(
+        String::from("@reports/nope.pdf"),
+        h.local("other.pdf"),
+        Overwrite::Never,
+        false
+    ))));
+}
+
+#[test]
+fn drive_paths_resolve_to_files() {
+    let h = Harness::start();
+    h.put_remote("work1", "Work", "");
+    h.put_remote("specs1", "Specs", "");
+    h.put_remote("plan1", "plan.md", "# Plan");
+    fs::write(h.dir.path().join("remote/specs1.parent"), "work1").unwrap();
+    fs::write(h.dir.path().join("remote/plan1.parent"), "specs1").unwrap();
+
+    let path = h.local("plan.md");
+    let r = h.send(DCommand::Pull(
+        String::from("drive:/Work/Specs/plan.md"),
+        path.clone(),
+        Overwrite::Never,
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "# Plan");
+    assert_eq!(
+        tracked_url(&h, &path).as_deref(),
+        Some("https://drive.google.com/open?id=plan1")
+    );
+
+    // Moving the file out of the cached folder is noticed.
+    fs::write(h.dir.path().join("remote/plan1.parent"), "work1").unwrap();
+    assert!(!is_ok(&h.send(DCommand::Share(String::from(
+        "drive:/Work/Specs/plan.md"
+    )))));
+    assert!(is_ok(
+        &h.send(DCommand::Share(String::from("drive:/Work/plan.md")))
+    ));
+    assert!(!is_ok(
+        &h.send(DCommand::Share(String::from("drive:/Nowhere/plan.md")))
+    ));
+}
+
+#[test]
+fn computer_folder_groups_uploads() {
+    let h = Harness::start_with_config("[computer]\nenabled = true\nname = \"testbox\"\n");
+    let mut parents = Vec::new();
+    for name in &["a.txt", "b.txt"] {
+        let path = h.local(name);
+        fs::write(&path, *name).unwrap();
+        assert!(
+            is_ok(&h.send(DCommand::Push(path.clone(), true))),
+            "{}",
+            h.log()
+        );
+        let url = tracked_url(&h, &path).unwrap();
+        let id = url.rsplit('=').next().unwrap().to_string();
+        parents
+            .push(fs::read_to_string(h.dir.path().join(format!("remote/{}.parent", id))).unwrap());
+    }
+    // The folder is created once, then reused.
+    assert_eq!(parents[0], parents[1]);
+    let folder = h.dir.path().join("remote").join(&parents[0]);
+    assert_eq!(
+        fs::read_to_string(folder.with_extension("name")).unwrap(),
+        "testbox"
+    );
+    assert_eq!(
+        fs::read_to_string(folder.with_extension("mime")).unwrap(),
+        "application/vnd.google-apps.folder"
+    );
+}
+
+#[test]
+fn overwritten_files_go_to_the_trash() {
+    let h = Harness::start();
+    let url = h.put_remote("abc123", "report.txt", "remote v1");
+    let path = h.local("report.txt");
+    assert!(is_ok(&h.send(DCommand::Pull(
+        url.clone(),
+        path.clone(),
+        Overwrite::Never,
+        false
+    ))));
+    fs::write(&path, "local edits").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("local edits")));
+
+    h.put_remote("abc123", "report.txt", "remote v2");
+    assert!(is_ok(&h.send(DCommand::Pull(
+        url.clone(),
+        path.clone(),
+        Overwrite::Always,
+        false
+    ))));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "remote v2");
+
+    let trash = h.dir.path().join("home/.local/share/Trash");
+    assert_eq!(
+        fs::read_to_string(trash.join("files/report.txt")).unwrap(),
+        "local edits"
+    );
+    let info = fs::read_to_string(trash.join("info/report.txt.trashinfo")).unwrap();
+    assert!(info.starts_with("[Trash Info]\nPath=/"), "{}", info);
+    assert!(
+        info.contains("/local/report.txt\nDeletionDate="),
+        "{}",
+        info
+    );
+
+    // The replacement is still synced.
+    fs::write(&path, "after pull").unwrap();
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("after pull")),
+        "{}",
+        h.log()
+    );
+}
+
+#[test]
+fn versions_are_kept_and_rolled_back() {
+    use std::os::unix::fs::PermissionsExt;
+    let h = Harness::start_with_config("[versions]\nkeep = 2\n");
+    let url = h.put_remote("abc123", "report.txt", "v1");
+    let path = h.local("report.txt");
+    assert!(is_ok(&h.send(DCommand::Pull(
+        url.clone(),
+        path.clone(),
+        Overwrite::Never,
+        false
+    ))));
+    for v in &["v2", "v3", "v4"] {
+        // Versions are named by the second they were saved in.
+        thread::sleep(Duration::from_millis(1100));
+        // A private file's versions stay private.
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        h.put_remote("abc123", "report.txt", v);
+        assert!(is_ok(&h.send(DCommand::Pull(
+            url.clone(),
+            path.clone(),
+            Overwrite::Always,
+            false
+        ))));
+    }
+    assert_eq!(fs::read_to_string(&path).unwrap(), "v4");
+
+    let dir = h
+        .dir
+        .path()
+        .join("home/.local/share/rgdrive/versions")
+        .join(path.canonicalize().unwrap().strip_prefix("/").unwrap());
+    let mut kept: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    kept.sort();
+    assert_eq!(kept.len(), 2, "{:?}", kept);
+    assert_eq!(fs::read_to_string(dir.join(&kept[0])).unwrap(), "v2");
+    assert_eq!(fs::read_to_string(dir.join(&kept[1])).unwrap(), "v3");
+    for k in &kept {
+        let mode = fs::metadata(dir.join(k)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "{}", k);
+    }
+
+    let rgdrive = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let listed = rgdrive(&["--versions", path.to_str().unwrap()]);
+    let listed = String::from_utf8_lossy(&listed.stdout);
+    assert!(
+        listed.contains(&kept[0]) && listed.contains(&kept[1]),
+        "{}",
+        listed
+    );
+
+    let out = rgdrive(&["--rollback", path.to_str().unwrap(), &kept[0]]);
+    assert!(out.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "v2");
+    // The version restored was held while the current contents were saved, then let go.
+    let versions = h.dir.path().join("home/.local/share/rgdrive/versions");
+    assert!(fs::read_dir(&versions).unwrap().all(|e| !e
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .starts_with(".rollback")));
+    // Rolling back is synced like any other edit.
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("v2")),
+        "{}",
+        h.log()
+    );
+}
+
+#[test]
+fn directory_push_is_planned_against_quota() {
+    let h = Harness::start_with_config("[planner]\nwarn_files = 100\n");
+    let dir = h.local("photos");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.jpg"), "0123456789").unwrap();
+    fs::write(dir.join("b.jpg"), "0123456789").unwrap();
+
+    let plan = h.send(DCommand::Plan(dir.clone(), true));
+    match &plan {
+        DResult::Ok(p) => assert!(p.starts_with("Push plan: 2 files, 20 B."), "{}", p),
+        _ => panic!("{:?}", plan),
+    }
+
+    // Only 5 bytes of quota left.
+    h.put_remote("abc123", "old.txt", "12345");
+    fs::write(h.dir.path().join("remote/.quota"), "10").unwrap();
+    match h.send(DCommand::Plan(dir.clone(), true)) {
+        DResult::Err(p) => assert!(
+            p.contains("Warning: 20 B won't fit in the 5 B left"),
+            "{}",
+            p
+        ),
+        r => panic!("{:?}", r),
+    }
+
+    let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+        .arg("--push")
+        .arg(&dir)
+        .env("HOME", h.dir.path().join("home"))
+        .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&out.stdout).contains("Warning: 20 B won't fit"));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Not pushing"));
+    assert!(tracked_url(&h, &dir.join("a.jpg")).is_none());
+}
+
+#[test]
+fn directory_push_skips_default_excludes() {
+    let h = Harness::start();
+    let dir = h.local("project");
+    fs::create_dir_all(dir.join("target/debug")).unwrap();
+    fs::create_dir_all(dir.join(".git")).unwrap();
+    fs::write(dir.join("main.rs"), "fn main() {}").unwrap();
+    fs::write(dir.join(".main.rs.swp"), "swap").unwrap();
+    fs::write(dir.join("main.rs~"), "backup").unwrap();
+    fs::write(dir.join("target/debug/main.o"), "obj").unwrap();
+    fs::write(dir.join(".git/HEAD"), "ref").unwrap();
+
+    match h.send(DCommand::Plan(dir.clone(), true)) {
+        DResult::Ok(p) => assert!(
+            p.starts_with("Push plan: 1 files, 12 B. 4 more skipped by the excludes."),
+            "{}",
+            p
+        ),
+        r => panic!("{:?}", r),
+    }
+    assert!(is_ok(&h.send(DCommand::Push(dir.clone(), true))));
+    assert!(tracked_url(&h, &dir.join("main.rs")).is_some());
+    assert!(tracked_url(&h, &dir.join(".main.rs.swp")).is_none());
+    assert!(tracked_url(&h, &dir.join("target/debug/main.o")).is_none());
+
+    // --no-default-excludes pushes everything.
+    assert!(is_ok(&h.send(DCommand::Push(dir.join(".git"), false))));
+    assert!(tracked_url(&h, &dir.join(".git/HEAD")).is_some());
+}
+
+#[test]
+fn drive_copy_keeps_its_own_name() {
+    let h = Harness::start_with_config("");
+    let path = h.local("draft-v3-final.txt");
+    fs::write(&path, "report").unwrap();
+    let r = h.send(DCommand::PushAs(
+        path.clone(),
+        String::from("Report.txt"),
+        None,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    let url = tracked_url(&h, &path).unwrap();
+    let id = drive_id(&url).unwrap().to_string();
+    let name = || fs::read_to_string(h.dir.path().join(format!("remote/{}.name", id))).unwrap();
+    assert_eq!(name(), "Report.txt");
+
+    // The name sticks through updates.
+    fs::write(&path, "report, edited").unwrap();
+    assert!(wait_for(
+        || h.remote(&url).as_deref() == Some("report, edited")
+    ));
+    assert_eq!(name(), "Report.txt");
+
+    assert!(is_ok(&h.send(DCommand::RenameRemote(
+        path.clone(),
+        String::from("Q3 Report.txt")
+    ))));
+    assert_eq!(name(), "Q3 Report.txt");
+    let tracked =
+        TrackedFile::from_path(h.dir.path().join("home/.config/cameron-williams/state.db"));
+    let tf = tracked.iter().find(|tf| tf.path == path).unwrap();
+    assert_eq!(tf.remote_name.as_deref(), Some("Q3 Report.txt"));
+
+    // Only files can be pushed under another name.
+    let r = h.send(DCommand::PushAs(h.local(""), String::from("x"), None));
+    assert!(!is_ok(&r));
+}
+
+#[test]
+fn pull_onto_a_synced_path_needs_relink() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "mine").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), true))));
+    let mine = tracked_url(&h, &path).unwrap();
+    let theirs = h.put_remote("theirs", "notes.txt", "theirs");
+
+    let r = h.send(DCommand::Pull(
+        theirs.clone(),
+        path.clone(),
+        Overwrite::Always,
+        false,
+    ));
+    match &r {
+        DResult::Err(e) => assert!(e.contains("--relink"), "{}", e),
+        _ => panic!("{:?}", r),
+    }
+    assert_eq!(fs::read_to_string(&path).unwrap(), "mine");
+    // Pulling into the directory is caught too.
+    assert!(!is_ok(&h.send(DCommand::Pull(
+        theirs.clone(),
+        h.local(""),
+        Overwrite::Always,
+        false
+    ))));
+
+    let r = h.send(DCommand::Pull(
+        theirs.clone(),
+        path.clone(),
+        Overwrite::Always,
+        true,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    let tracked =
+        TrackedFile::from_path(h.dir.path().join("home/.config/cameron-williams/state.db"));
+    assert_eq!(tracked.iter().filter(|tf| tf.path == path).count(), 1);
+    assert_eq!(tracked_url(&h, &path).as_deref(), Some(theirs.as_str()));
+
+    // Edits now go to the re-linked file only.
+    fs::write(&path, "edited").unwrap();
+    assert!(wait_for(|| h.remote(&theirs).as_deref() == Some("edited")));
+    assert_eq!(h.remote(&mine).as_deref(), Some("mine"));
+}
+
+#[test]
+fn every_spelling_of_a_path_is_tracked_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("local/notes.txt");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, "hello").unwrap();
+    // Tracked twice by an older version, under two spellings.
+    let tracked = dir
+        .path()
+        .join("home/.config/cameron-williams/tracked_files");
+    fs::create_dir_all(tracked.parent().unwrap()).unwrap();
+    // The original format (url, path), from before every saved path was canonical.
+    let files: Vec<(&str, PathBuf)> = vec![
+        ("https://drive.google.com/open?id=first", path.clone()),
+        (
+            "https://drive.google.com/open?id=second",
+            dir.path().join("local/../local/notes.txt"),
+        ),
+    ];
+    fs::write(&tracked, bincode::serialize(&files).unwrap()).unwrap();
+    let h = Harness::start_in(dir, "");
+    assert!(is_ok(&h.send(DCommand::Stats)));
+
+    let link = h.local("link.txt");
+    std::os::unix::fs::symlink(&path, &link).unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(link.clone(), true))));
+    assert!(is_ok(&h.send(DCommand::Push(h.local("./notes.txt"), true))));
+
+    let tracked = TrackedFile::from_path(tracked.with_file_name("state.db"));
+    assert_eq!(tracked.len(), 1, "{:?}", tracked);
+    assert_eq!(tracked[0].path, path);
+    assert_eq!(
+        tracked[0].drive_url,
+        "https://drive.google.com/open?id=first"
+    );
+    // Nothing was uploaded again.
+    assert!(!h.dir.path().join("remote").exists());
+}
+
+#[test]
+fn path_status_batch_reports_every_path() {
+    let h = Harness::start();
+    let dir = h.local("docs");
+    fs::create_dir_all(&dir).unwrap();
+    let synced = dir.join("synced.txt");
+    fs::write(&synced, "a").unwrap();
+    fs::write(dir.join("other.txt"), "b").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(synced.clone(), true))));
+    // A sync whose updates fail (the Drive file doesn't exist).
+    let broken = h.local("broken.txt");
+    fs::write(&broken, "c").unwrap();
+    assert!(is_ok(&h.send(DCommand::FSync(
+        broken.clone(),
+        String::from("https://drive.google.com/open?id=missing"),
+        None
+    ))));
+    fs::write(&broken, "c, edited").unwrap();
+
+    let query = DCommand::PathStatusBatch(vec![
+        synced.clone(),
+        dir.join("./other.txt"),
+        dir.clone(),
+        broken.clone(),
+        h.local("nowhere"),
+    ]);
+    assert!(
+        wait_for(
+            || h.send(query.clone()) == DResult::ok("synced\nuntracked\npartial\nerror\nuntracked")
+        ),
+        "{:?}\n{}",
+        h.send(query.clone()),
+        h.log()
+    );
+}
+
+#[test]
+fn prompt_status_describes_a_directory() {
+    let h = Harness::start();
+    let dir = h.local("project");
+    fs::create_dir_all(&dir).unwrap();
+    let prompt = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .current_dir(&dir)
+            .env("HOME", h.dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+            .arg("prompt-status")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        String::from_utf8(out.stdout).unwrap()
+    };
+    assert_eq!(prompt(&[]), "");
+    assert_eq!(prompt(&["--plain"]), "untracked\n");
+
+    let path = dir.join("main.rs");
+    fs::write(&path, "fn main() {}").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), true))));
+    assert_eq!(prompt(&[]), "◐\n");
+    assert_eq!(prompt(&["main.rs"]), "✓\n");
+
+    // No daemon, no output.
+    let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+        .env("RGDRIVE_SOCKET", h.dir.path().join("nothing.sock"))
+        .arg("prompt-status")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty() && out.stderr.is_empty());
+}
+
+#[test]
+fn once_transfers_without_a_daemon() {
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("local");
+    fs::create_dir_all(&local).unwrap();
+    fs::write(local.join("build.log"), "ok").unwrap();
+    let google = FakeGoogle::start(dir.path());
+    let rgdrive = |args: &[&str]| {
+        signed_in(&mut Command::new(env!("CARGO_BIN_EXE_rgdrive")), &google)
+            .env("HOME", dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", dir.path().join("rgdrive.sock"))
+            .current_dir(&local)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let out = rgdrive(&["--push", "build.log", "--once"]);
+    assert!(out.status.success(), "{:?}", out);
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let url = stdout.trim().rsplit(' ').next().unwrap().to_string();
+    let id = url.rsplit('=').next().unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("remote").join(id)).unwrap(),
+        "ok"
+    );
+
+    let out = rgdrive(&["--pull", &url, "copy.log", "--once"]);
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(fs::read_to_string(local.join("copy.log")).unwrap(), "ok");
+    // Nothing is tracked, and failures show in the exit code.
+    assert!(!dir
+        .path()
+        .join("home/.config/cameron-williams/state.db")
+        .exists());
+    assert!(!rgdrive(&["--pull", &url, "copy.log", "--once"])
+        .status
+        .success());
+}
+
+#[test]
+fn anonymous_pulls_need_no_credentials_or_daemon() {
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("local");
+    let remote = dir.path().join("remote");
+    fs::create_dir_all(&local).unwrap();
+    fs::create_dir_all(&remote).unwrap();
+    for (id, name, shared) in &[("pub1", "notes.txt", true), ("priv1", "secret.txt", false)] {
+        fs::write(remote.join(id), format!("{} contents", name)).unwrap();
+        fs::write(remote.join(format!("{}.name", id)), name).unwrap();
+        if *shared {
+            fs::write(remote.join(format!("{}.shared", id)), "anyone:reader").unwrap();
+        }
+    }
+    let google = FakeGoogle::start(dir.path());
+    let rgdrive = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", dir.path().join("rgdrive.sock"))
+            .env("RGDRIVE_GOOGLE_API", &google.url)
+            .env_remove("GOOGLE_CLIENT_ID")
+            .env_remove("GOOGLE_CLIENT_SECRET")
+            .current_dir(&local)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let url = |id: &str| format!("https://drive.google.com/open?id={}", id);
+
+    let out = rgdrive(&["--pull", &url("pub1"), ".", "--anonymous"]);
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(
+        fs::read_to_string(local.join("notes.txt")).unwrap(),
+        "notes.txt contents"
+    );
+    // Nothing is tracked, existing files are kept without --overwrite, and private files stay private.
+    assert!(!dir
+        .path()
+        .join("home/.config/cameron-williams/state.db")
+        .exists());
+    assert!(!rgdrive(&["--pull", &url("pub1"), ".", "--anonymous"])
+        .status
+        .success());
+    let out = rgdrive(&["--pull", &url("priv1"), ".", "--anonymous"]);
+    assert!(!out.status.success());
+    assert!(
+        String::from_utf8_lossy(&out.stderr).contains("isn't shared"),
+        "{:?}",
+        out
+    );
+    assert!(!local.join("secret.txt").exists());
+    // To a new name, and over the old file with --overwrite.
+    let out = rgdrive(&["--pull", &url("pub1"), "copy.txt", "--anonymous"]);
+    assert!(out.status.success(), "{:?}", out);
+    assert!(local.join("copy.txt").is_file());
+    fs::write(local.join("notes.txt"), "old").unwrap();
+    let out = rgdrive(&["--pull", &url("pub1"), ".", "--anonymous", "--overwrite"]);
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(
+        fs::read_to_string(local.join("notes.txt")).unwrap(),
+        "notes.txt contents"
+    );
+    assert_eq!(fs::read_dir(&local).unwrap().count(), 2);
+}
+
+#[test]
+fn pre_upload_hook_can_block_a_push() {
+    let h = Harness::start_with_config(
+        r#"
+[hooks]
+pre_upload = ["/bin/sh", "-c", "read -r l < \"$0\"; case \"$l\" in *EICAR*) echo \"found EICAR-Test-File\" >&2; exit 1;; esac"]
+"#,
+    );
+    let clean = h.local("report.txt");
+    fs::write(&clean, "quarterly numbers").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(clean.clone(), false))));
+    assert!(tracked_url(&h, &clean).is_some());
+
+    let infected = h.local("invoice.txt");
+    fs::write(&infected, "EICAR payload").unwrap();
+    match h.send(DCommand::Push(infected.clone(), false)) {
+        DResult::Err(e) => assert!(e.contains("found EICAR-Test-File"), "{}", e),
+        r => panic!("infected push went through: {:?}", r),
+    }
+    assert!(tracked_url(&h, &infected).is_none());
+    let uploaded = fs::read_dir(h.dir.path().join("remote"))
+        .unwrap()
+        .filter_map(|e| fs::read_to_string(e.unwrap().path()).ok())
+        .any(|c| c.contains("EICAR"));
+    assert!(!uploaded);
+}
+
+#[test]
+fn review_mode_holds_changes_until_approved() {
+    let h = Harness::start_with_config("[review]\nenabled = true\n");
+    let notes = h.local("notes.txt");
+    let todo = h.local("todo.txt");
+    fs::write(&notes, "v1").unwrap();
+    fs::write(&todo, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(notes.clone(), false))));
+    assert!(is_ok(&h.send(DCommand::Push(todo.clone(), false))));
+    let (notes_url, todo_url) = (
+        tracked_url(&h, &notes).unwrap(),
+        tracked_url(&h, &todo).unwrap(),
+    );
+
+    fs::write(&notes, "v2").unwrap();
+    fs::write(&todo, "v2").unwrap();
+    assert!(wait_for(|| match h.send(DCommand::Pending) {
+        DResult::Ok(s) => s.contains("notes.txt") && s.contains("todo.txt"),
+        _ => false,
+    }));
+    assert_eq!(h.remote(&notes_url).as_deref(), Some("v1"));
+
+    assert!(is_ok(&h.send(DCommand::Approve(Some(notes.clone())))));
+    assert_eq!(h.remote(&notes_url).as_deref(), Some("v2"));
+    assert_eq!(h.remote(&todo_url).as_deref(), Some("v1"));
+    match h.send(DCommand::Pending) {
+        DResult::Ok(s) => assert!(!s.contains("notes.txt") && s.contains("todo.txt"), "{}", s),
+        r => panic!("{:?}", r),
+    }
+
+    assert!(is_ok(&h.send(DCommand::Approve(None))));
+    assert_eq!(h.remote(&todo_url).as_deref(), Some("v2"));
+    assert!(!is_ok(&h.send(DCommand::Approve(None))));
+}
+
+#[test]
+fn saves_are_batched_until_the_window_fills() {
+    let h = Harness::start_with_config("[batching]\nwindow_secs = 3600\nmax_files = 2\n");
+    let notes = h.local("notes.txt");
+    let todo = h.local("todo.txt");
+    fs::write(&notes, "v1").unwrap();
+    fs::write(&todo, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(notes.clone(), false))));
+    assert!(is_ok(&h.send(DCommand::Push(todo.clone(), false))));
+    let (notes_url, todo_url) = (
+        tracked_url(&h, &notes).unwrap(),
+        tracked_url(&h, &todo).unwrap(),
+    );
+
+    for v in &["v2", "v3", "v4"] {
+        fs::write(&notes, v).unwrap();
+        thread::sleep(Duration::from_millis(700));
+    }
+    assert_eq!(h.remote(&notes_url).as_deref(), Some("v1"));
+
+    // A second file fills the window, and each file goes up once with its latest contents.
+    fs::write(&todo, "v2").unwrap();
+    assert!(wait_for(|| h.remote(&notes_url).as_deref() == Some("v4")
+        && h.remote(&todo_url).as_deref() == Some("v2")));
+    match h.send(DCommand::Stats) {
+        DResult::Ok(s) => {
+            let batched = s
+                .lines()
+                .find_map(|l| l.strip_prefix("saves batched: "))
+                .and_then(|n| n.parse::<u64>().ok());
+            assert!(batched >= Some(2), "{}", s);
+        }
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn a_burst_of_writes_is_uploaded_once_it_goes_quiet() {
+    let h = Harness::start_with_config("[debounce]\nquiet_ms = 1500\n");
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+
+    for v in &["v2", "v3", "v4", "v5"] {
+        fs::write(&path, v).unwrap();
+        thread::sleep(Duration::from_millis(600));
+    }
+    assert_eq!(h.remote(&url).as_deref(), Some("v1"));
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v5")));
+    let activity = h
+        .dir
+        .path()
+        .join("remote")
+        .join(format!("{}.activity", drive_id(&url).unwrap()));
+    let edits = fs::read_to_string(activity)
+        .unwrap()
+        .lines()
+        .filter(|l| l.ends_with("\tedit"))
+        .count();
+    assert_eq!(edits, 1);
+    match h.send(DCommand::Stats) {
+        DResult::Ok(s) => {
+            let debounced = s
+                .lines()
+                .find_map(|l| l.strip_prefix("saves debounced: "))
+                .and_then(|n| n.parse::<u64>().ok());
+            assert!(debounced >= Some(3), "{}", s);
+        }
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn saves_drive_already_has_are_not_uploaded_again() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v2")));
+
+    // Saved again without changing anything.
+    fs::write(&path, "v2").unwrap();
+    let collapsed = || match h.send(DCommand::Stats) {
+        DResult::Ok(s) => s
+            .lines()
+            .find_map(|l| l.strip_prefix("uploads collapsed: "))
+            .and_then(|n| n.parse::<u64>().ok()),
+        r => panic!("{:?}", r),
+    };
+    assert!(wait_for(|| collapsed() >= Some(1)));
+    let activity = h
+        .dir
+        .path()
+        .join("remote")
+        .join(format!("{}.activity", drive_id(&url).unwrap()));
+    let edits = || {
+        fs::read_to_string(&activity)
+            .unwrap()
+            .lines()
+            .filter(|l| l.ends_with("\tedit"))
+            .count()
+    };
+    assert_eq!(edits(), 1);
+
+    // A real change still goes up.
+    fs::write(&path, "v3").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v3")));
+    assert_eq!(edits(), 2);
+}
+
+#[test]
+fn saves_are_checked_against_drive_md5_when_none_was_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("local/notes.txt");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, "v1").unwrap();
+    // Synced by a version that didn't keep md5s.
+    let config = dir.path().join("home/.config/cameron-williams");
+    fs::create_dir_all(&config).unwrap();
+    let files = vec![TrackedFile::new(
+        &path,
+        "https://drive.google.com/open?id=notes1",
+    )];
+    fs::write(
+        config.join("tracked_files"),
+        TrackedFile::encode_all(&files),
+    )
+    .unwrap();
+    let h = Harness::start_in(dir, "");
+    let url = h.put_remote("notes1", "notes.txt", "v1");
+    // Tracked files are watched in the background after startup.
+    assert!(wait_for(|| h.log().contains("Watching 1 tracked files")));
+
+    // Saved without changing anything.
+    fs::write(&path, "v1").unwrap();
+    let md5 = checksum::md5_file(&path).ok();
+    assert!(wait_for(|| {
+        TrackedFile::from_path(config.join("state.db"))
+            .first()
+            .map(|tf| tf.md5.clone())
+            == Some(md5.clone())
+    }));
+    let activity = h
+        .dir
+        .path()
+        .join("remote")
+        .join(format!("{}.activity", drive_id(&url).unwrap()));
+    assert!(!activity.exists());
+
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v2")));
+}
+
+#[test]
+fn transfers_record_the_md5_they_streamed() {
+    let h = Harness::start_with_config("");
+    let state = h.dir.path().join("home/.config/cameron-williams/state.db");
+    let md5_of = |p: &Path| {
+        TrackedFile::from_path(&state)
+            .into_iter()
+            .find(|tf| tf.path == p)
+            .and_then(|tf| tf.md5)
+    };
+    // Bigger than a chunk, so the hash spans several.
+    let big: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let expected = format!("{:x}", md5::compute(&big));
+
+    let pushed = h.local("big.bin");
+    fs::write(&pushed, &big).unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(pushed.clone(), true))));
+    assert_eq!(md5_of(&pushed), Some(expected.clone()));
+
+    let url = h.put_remote("big2", "big.bin", "");
+    fs::write(h.dir.path().join("remote/big2"), &big).unwrap();
+    let pulled = h.local("pulled.bin");
+    let r = h.send(DCommand::Pull(url, pulled.clone(), Overwrite::Never, false));
+    assert!(is_ok(&r), "{:?}", r);
+    assert_eq!(md5_of(&pulled), Some(expected));
+
+    // An uploaded change records what was sent.
+    fs::write(&pushed, "changed").unwrap();
+    let changed = checksum::md5_file(&pushed).ok();
+    assert!(wait_for(|| md5_of(&pushed) == changed), "{}", h.log());
+}
+
+#[test]
+fn small_changes_are_coalesced_into_one_revision_per_period() {
+    let h = Harness::start_with_config("[coalesce]\nperiod_secs = 4\nmin_churn_bytes = 8192\n");
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+
+    // The first change starts the period, a small one within it is held back and journaled.
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v2")));
+    fs::write(&path, "v3").unwrap();
+    let journal = h.dir.path().join("home/.config/cameron-williams/journal");
+    assert!(wait_for(|| fs::read_to_string(&journal)
+        .unwrap_or_default()
+        .contains("\"op\":\"coalesce\"")));
+    assert_eq!(h.remote(&url).as_deref(), Some("v2"));
+
+    // A big change goes straight up.
+    let big = "x".repeat(64 * 1024);
+    fs::write(&path, &big).unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some(big.as_str())));
+
+    // And the held back change goes up once the period is over.
+    let edited = format!("y{}", &big[1..]);
+    fs::write(&path, &edited).unwrap();
+    thread::sleep(Duration::from_secs(1));
+    assert!(h.remote(&url).as_deref() == Some(big.as_str()));
+    assert!(wait_for(
+        || h.remote(&url).as_deref() == Some(edited.as_str())
+    ));
+}
+
+#[test]
+fn list_shows_each_files_sync_state() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+    let list = || {
+        let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+            .args(["--format", "json", "--list"])
+            .output()
+            .unwrap();
+        let list: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        list["files"][0].clone()
+    };
+
+    let file = list();
+    assert_eq!(file["state"], "synced", "{}", file);
+    assert!(file["synced_at"].is_i64());
+    assert_eq!(file["in_sync"], true);
+    assert!(file["last_error"].is_null());
+
+    // An update Drive refuses.
+    let stored = h.dir.path().join("remote").join(drive_id(&url).unwrap());
+    fs::remove_file(&stored).unwrap();
+    fs::create_dir(&stored).unwrap();
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(|| list()["state"] == "error"));
+    let file = list();
+    assert_eq!(file["in_sync"], false);
+    assert!(file["last_error"].is_string(), "{}", file);
+
+    let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+        .env("HOME", h.dir.path().join("home"))
+        .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+        .arg("--list")
+        .output()
+        .unwrap();
+    let text = String::from_utf8_lossy(&out.stdout);
+    assert!(text.contains("[error, synced "), "{}", text);
+    assert!(text.contains(", changed locally: "), "{}", text);
+}
+
+#[test]
+fn failed_updates_are_queued_for_retry() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+    // A directory where the Drive file should be makes every update fail.
+    let stored = h.dir.path().join("remote").join(drive_id(&url).unwrap());
+    let break_remote = || {
+        fs::remove_file(&stored).unwrap();
+        fs::create_dir(&stored).unwrap();
+    };
+    let queue = || match h.send(DCommand::Queue) {
+        DResult::Ok(s) => s,
+        r => panic!("{:?}", r),
+    };
+
+    break_remote();
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(|| queue().contains("notes.txt")));
+    let line = queue();
+    assert!(
+        line.contains("update") && line.contains("1 attempt(s)"),
+        "{}",
+        line
+    );
+    let id: u64 = line.split_whitespace().next().unwrap().parse().unwrap();
+
+    assert!(!is_ok(&h.send(DCommand::QueueRetry(id))));
+    assert!(queue().contains("2 attempt(s)"));
+    fs::remove_dir(&stored).unwrap();
+    fs::write(&stored, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::QueueRetry(id))));
+    assert_eq!(h.remote(&url).as_deref(), Some("v2"));
+    assert_eq!(queue(), "Nothing queued.");
+
+    break_remote();
+    fs::write(&path, "v3").unwrap();
+    assert!(wait_for(|| queue().contains("notes.txt")));
+    let id: u64 = queue().split_whitespace().next().unwrap().parse().unwrap();
+    assert!(is_ok(&h.send(DCommand::QueueDrop(id))));
+    assert_eq!(queue(), "Nothing queued.");
+    assert!(!is_ok(&h.send(DCommand::QueueDrop(id))));
+}
+
+#[test]
+fn files_deleted_before_upload_are_skipped_once() {
+    let h =
+        Harness::start_with_config("[batching]\nwindow_secs = 2\n\n[vanished]\nuntrack = true\n");
+    let path = h.local("build.o");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+
+    // Changed, then deleted before the batching window closes.
+    fs::write(&path, "v2").unwrap();
+    thread::sleep(Duration::from_millis(700));
+    fs::remove_file(&path).unwrap();
+    assert!(wait_for(|| tracked_url(&h, &path).is_none()), "{}", h.log());
+
+    let journal =
+        fs::read_to_string(h.dir.path().join("home/.config/cameron-williams/journal")).unwrap();
+    assert_eq!(journal.matches("\"op\":\"vanished\"").count(), 1);
+    assert!(!journal.contains("\"op\":\"update\""));
+    assert_eq!(h.remote(&url).as_deref(), Some("v1"));
+    match h.send(DCommand::Queue) {
+        DResult::Ok(s) => assert_eq!(s, "Nothing queued."),
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn large_sync_sets_are_watched_in_the_background() {
+    let dir = tempfile::tempdir().unwrap();
+    let local = dir.path().join("local");
+    fs::create_dir_all(&local).unwrap();
+    let files: Vec<TrackedFile> = (0..5000)
+        .map(|i| {
+            let p = local.join(format!("{}.txt", i));
+            fs::write(&p, "v1").unwrap();
+            TrackedFile::new(p, format!("https://drive.google.com/open?id=f{}", i))
+        })
+        .collect();
+    let tracked = dir
+        .path()
+        .join("home/.config/cameron-williams/tracked_files");
+    fs::create_dir_all(tracked.parent().unwrap()).unwrap();
+    fs::write(&tracked, TrackedFile::encode_all(&files)).unwrap();
+    let h = Harness::start_in(dir, "");
+    assert!(is_ok(&h.send(DCommand::Stats)));
+    assert!(wait_for(|| h.log().contains("Watching 5000 tracked files")));
+    // Started per file at debug only.
+    assert!(!h.log().contains("INFO  rgdrive] adding"));
+
+    let url = h.put_remote("f4999", "4999.txt", "v1");
+    fs::write(h.local("4999.txt"), "v2").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v2")));
+}
+
+#[test]
+fn folder_ids_are_cached_across_restarts_until_drive_changes_them() {
+    let config = "[computer]\nenabled = true\nname = \"laptop\"\n";
+    let h = Harness::start_with_config(config);
+    let parent = |h: &Harness, p: &Path| {
+        let id = drive_id(&tracked_url(h, p).unwrap()).unwrap().to_string();
+        fs::read_to_string(h.dir.path().join(format!("remote/{}.parent", id))).unwrap()
+    };
+    let a = h.local("a.txt");
+    fs::write(&a, "a").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(a.clone(), false))));
+    let folder = parent(&h, &a);
+    let rename = |h: &Harness, name: &str| {
+        fs::write(h.dir.path().join(format!("remote/{}.name", folder)), name).unwrap()
+    };
+
+    // Renamed without anything polling, the cached id is still used after a restart.
+    rename(&h, "elsewhere");
+    let h = h.restart_with_config(config);
+    let b = h.local("b.txt");
+    fs::write(&b, "b").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(b.clone(), false))));
+    assert_eq!(parent(&h, &b), folder);
+
+    // The poller notices the next rename, and the folder is looked up again.
+    let h = h.restart_with_config(&format!("{}\n[poll]\ninterval_secs = 1\n", config));
+    assert!(wait_for(|| polled(&h)), "{}", h.log());
+    rename(&h, "laptop-old");
+    assert!(wait_for(|| h
+        .log()
+        .contains("changed remotely, dropped them")));
+    let c = h.local("c.txt");
+    fs::write(&c, "c").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(c.clone(), false))));
+    assert_ne!(parent(&h, &c), folder);
+}
+
+#[test]
+fn profiles_run_their_own_daemons_and_are_listed() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("remote")).unwrap();
+    fs::write(dir.path().join("remote/.account"), "cam@example.com\n").unwrap();
+    let h = Harness::start_in(dir, "");
+    let profile = format!("e2e-{}", std::process::id());
+    let _work = h.start_profile(&profile);
+    let rgdrive = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .env_remove("RGDRIVE_SOCKET")
+            .env_remove("RGDRIVE_PROFILE")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}", out);
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    // Registered just after the socket starts listening.
+    assert!(wait_for(|| rgdrive(&["daemons"]).lines().count() == 3));
+    let listed = rgdrive(&["daemons"]);
+    let lines: Vec<&str> = listed.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", listed);
+    assert!(lines[1].starts_with("default") && lines[1].contains("cam@example.com"));
+    assert!(lines[1].ends_with(&*h.dir.path().join("rgdrive.sock").to_string_lossy()));
+    assert!(lines[2].starts_with(&profile));
+    assert!(lines[2].ends_with(&format!("/tmp/rgdrive-{}.sock", profile)));
+
+    // --profile routes to the profile's daemon, which keeps its own tracked files. Before or after a subcommand.
+    let path = h.local("work.txt");
+    fs::write(&path, "work").unwrap();
+    assert!(rgdrive(&["--profile", &profile, "--push", path.to_str().unwrap()]).contains("OK"));
+    let tracked = h.dir.path().join(format!(
+        "home/.config/cameron-williams/profiles/{}/state.db",
+        profile
+    ));
+    assert!(TrackedFile::from_path(tracked)
+        .iter()
+        .any(|tf| tf.path == path));
+    assert_eq!(tracked_url(&h, &path), None);
+    assert!(rgdrive(&["pending", "--profile", &profile]).contains("OK"));
+
+    // Stopped daemons drop out of the list.
+    assert!(rgdrive(&["--stop", "--profile", &profile]).contains("Daemon stopped."));
+    assert!(wait_for(|| rgdrive(&["daemons"]).lines().count() == 2));
+}
+
+#[test]
+fn start_waits_for_the_daemon_and_relays_startup_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let settings = dir
+        .path()
+        .join("home/.config/cameron-williams/rgdrive.toml");
+    fs::create_dir_all(settings.parent().unwrap()).unwrap();
+    fs::write(&settings, "[bogus]\n").unwrap();
+    let socket = dir.path().join("rgdrive.sock");
+    let google = FakeGoogle::start(dir.path());
+    let env = |cmd: &mut Command| {
+        signed_in(cmd.env_clear(), &google)
+            .env("HOME", dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", &socket);
+    };
+    let rgdrive = |args: &[&str]| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_rgdrive"));
+        env(&mut cmd);
+        cmd.args(args).output().unwrap()
+    };
+
+    let out = rgdrive(&["--start"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(
+        stderr.contains("Error loading config") && stderr.contains("(config)"),
+        "{}",
+        stderr
+    );
+
+    // Only reported started once it answers.
+    fs::write(&settings, "").unwrap();
+    let out = rgdrive(&["--start"]);
+    assert!(out.status.success(), "{:?}", out);
+    assert!(String::from_utf8(out.stdout)
+        .unwrap()
+        .contains("Daemon started."));
+    assert_eq!(
+        DSocket::new(&socket).send_command(DCommand::Ready).unwrap(),
+        DResult::ok("ready")
+    );
+
+    // A second daemon doesn't take over the socket from under the first.
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_rgdrived"));
+    env(&mut cmd);
+    let out = cmd.output().unwrap();
+    assert!(!out.status.success());
+    let err = StartupError::from_log(&String::from_utf8(out.stderr).unwrap()).unwrap();
+    assert_eq!(err.code, Reason::Socket);
+    assert_eq!(out.status.code(), Some(3));
+    assert!(DSocket::new(&socket).is_active());
+
+    assert!(rgdrive(&["--stop"]).status.success());
+}
+
+#[test]
+fn bad_credentials_get_a_diagnostic_and_their_own_exit_code() {
+    let dir = tempfile::tempdir().unwrap();
+    let remote = dir.path().join("remote");
+    let rgdrived = |envs: &[(&str, &str)]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_rgdrived"))
+            .env_clear()
+            .env("HOME", dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", dir.path().join("rgdrive.sock"))
+            .envs(envs.iter().cloned())
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+        (out.status.code(), StartupError::from_log(&stderr).unwrap())
+    };
+    let id = ("GOOGLE_CLIENT_ID", "1234-abc.apps.googleusercontent.com");
+
+    let (code, e) = rgdrived(&[]);
+    assert_eq!((code, e.code), (Some(5), Reason::Credentials));
+    assert!(e.message.starts_with("$GOOGLE_CLIENT_ID is not set."));
+    let (code, e) = rgdrived(&[id, ("GOOGLE_CLIENT_SECRET", " ")]);
+    assert_eq!(code, Some(5));
+    assert_eq!(e.message, "$GOOGLE_CLIENT_SECRET is set but empty.");
+    let (code, e) = rgdrived(&[
+        ("GOOGLE_CLIENT_ID", "GOCSPX-secret"),
+        ("GOOGLE_CLIENT_SECRET", "GOCSPX-secret"),
+    ]);
+    assert_eq!(code, Some(5));
+    assert!(e.message.contains("doesn't look like an OAuth client id"));
+
+    // A token Google turns down is caught before anything is uploaded.
+    fs::create_dir_all(&remote).unwrap();
+    fs::write(remote.join(".revoked"), "").unwrap();
+    let google = FakeGoogle::start(dir.path());
+    let (code, e) = rgdrived(&[
+        ("RGDRIVE_GOOGLE_API", &google.url),
+        ("GOOGLE_CLIENT_ID", "1234.apps.googleusercontent.com"),
+        ("GOOGLE_CLIENT_SECRET", "s3cret"),
+        ("GOOGLE_REFRESH_TOKEN", "remote"),
+    ]);
+    assert_eq!((code, e.code), (Some(6), Reason::Auth));
+    assert!(e.message.contains("invalid_grant"), "{}", e.message);
+}
+
+#[test]
+fn expired_access_token_is_renewed() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+
+    // The daemon's token expires a second after it connected, well before this change.
+    fs::write(h.dir.path().join("remote/.token_lifetime"), "1").unwrap();
+    thread::sleep(Duration::from_millis(1100));
+    fs::write(&path, "v2").unwrap();
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("v2")),
+        "remote never updated:\n{}",
+        h.log()
+    );
+    assert!(h.log().contains("expired access token"), "{}", h.log());
+    assert!(wait_for(
+        || h.send(DCommand::Queue) == DResult::ok("Nothing queued.")
+    ));
+}
+
+#[test]
+fn revoked_token_pauses_sync_until_login() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+    let revoked = h.dir.path().join("remote/.revoked");
+    let login = || {
+        sign_in(
+            signed_in(&mut Command::new(env!("CARGO_BIN_EXE_rgdrive")), &h.google)
+                .env("HOME", h.dir.path().join("home"))
+                .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+                .arg("--login"),
+            "",
+        )
+    };
+
+    fs::write(&revoked, "").unwrap();
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(|| match h.send(DCommand::Health) {
+        DResult::Err(e) => e.contains("re-authentication required"),
+        _ => false,
+    }));
+    // Later changes aren't even tried, they wait in the queue.
+    fs::write(&path, "v3").unwrap();
+    assert!(wait_for(|| match h.send(DCommand::Queue) {
+        DResult::Ok(q) => q.contains("run `rgdrive --login`"),
+        _ => false,
+    }));
+    assert_eq!(h.remote(&url).as_deref(), Some("v1"));
+
+    // Signing in fails while Drive still refuses, then resumes sync once it doesn't.
+    assert!(!login().status.success());
+    fs::remove_file(&revoked).unwrap();
+    let out = login();
+    assert!(out.status.success(), "{:?}", out);
+    assert!(String::from_utf8(out.stdout)
+        .unwrap()
+        .contains("sync resumed"));
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v3")));
+    assert!(wait_for(
+        || h.send(DCommand::Queue) == DResult::ok("Nothing queued.")
+    ));
+    if let DResult::Err(e) = h.send(DCommand::Health) {
+        assert!(!e.contains("re-authentication"), "{}", e);
+    }
+}
+
+#[test]
+fn features_the_account_lacks_fail_fast_and_show_in_status() {
+    // Signed in with drive.file, which only sees rgdrive's own files, and without the activity scope.
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("remote")).unwrap();
+    fs::write(
+        dir.path().join("remote/.scopes"),
+        "https://www.googleapis.com/auth/drive.file\n",
+    )
+    .unwrap();
+    let h = Harness::start_in(dir, "[poll]\ninterval_secs = 60\n");
+    let path = h.local("notes.txt");
+    fs::write(&path, "notes").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+
+    match h.send(DCommand::Activity(url.clone())) {
+        DResult::Err(e) => assert_eq!(
+            e,
+            "--activity is not available with the current account/scope (no activity support)."
+        ),
+        r => panic!("{:?}", r),
+    }
+    assert!(!is_ok(&h.send(DCommand::PullStarred(h.local("starred")))));
+    assert!(!h.local("starred").exists());
+    // What works on rgdrive's own files still does.
+    assert!(is_ok(&h.send(DCommand::Share(url))), "{}", h.log());
+
+    let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+        .env("HOME", h.dir.path().join("home"))
+        .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+        .arg("--status")
+        .output()
+        .unwrap();
+    let status = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        status.contains(
+            "available: metadata, export, folders, share, rename, quota, shortcuts, delete\n"
+        ),
+        "{}",
+        status
+    );
+    assert!(status.contains("unavailable: activity (--activity)"));
+    assert!(status.contains("unavailable: starred (--pull-starred)"));
+    assert!(status.contains("unavailable: drives (--drives)"));
+}
+
+#[test]
+fn drives_lists_shared_drives_for_scripts() {
+    let h = Harness::start();
+    fs::create_dir_all(h.dir.path().join("remote")).unwrap();
+    fs::write(
+        h.dir.path().join("remote/.drives"),
+        "0AAteam\tTeam\twriter\n0ABarchive\tOld Archive\treader\n",
+    )
+    .unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+        .env("HOME", h.dir.path().join("home"))
+        .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+        .arg("--drives")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "0AAteam\tTeam\twriter\n0ABarchive\tOld Archive\treader\n"
+    );
+
+    // A listed id works as a --dest.
+    let path = h.local("plan.md");
+    fs::write(&path, "plan").unwrap();
+    let dest = String::from("https://drive.google.com/drive/folders/0AAteam");
+    assert!(is_ok(&h.send(DCommand::PushTo(path.clone(), dest, false))));
+    let url = tracked_url(&h, &path).unwrap();
+    let parent = format!("remote/{}.parent", drive_id(&url).unwrap());
+    assert_eq!(
+        fs::read_to_string(h.dir.path().join(parent)).unwrap(),
+        "0AAteam"
+    );
+}
+
+#[test]
+fn migration_bundle_moves_tracked_files_to_another_home() {
+    let h = Harness::start();
+    let old_home = h.dir.path().join("home");
+    fs::create_dir_all(old_home.join("docs")).unwrap();
+    for name in &["a.txt", "b.txt"] {
+        let path = old_home.join("docs").join(name);
+        fs::write(&path, *name).unwrap();
+        assert!(is_ok(&h.send(DCommand::Push(path, false))));
+    }
+    let url = tracked_url(&h, &old_home.join("docs/a.txt")).unwrap();
+
+    let bundle = h.dir.path().join("rgdrive.bundle");
+    let out = signed_in(&mut Command::new(env!("CARGO_BIN_EXE_rgdrive")), &h.google)
+        .env("HOME", &old_home)
+        .env("RGDRIVE_PASSPHRASE", "correct horse")
+        .arg("--migrate-export")
+        .arg(&bundle)
+        .arg("--with-tokens")
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}", out);
+    assert!(!fs::read(&bundle)
+        .unwrap()
+        .windows(6)
+        .any(|w| w == b"s3cret"));
+
+    // The new machine has a.txt in its own $HOME, but not b.txt.
+    let new = tempfile::tempdir().unwrap();
+    let new_home = new.path().join("home");
+    fs::create_dir_all(new_home.join("docs")).unwrap();
+    fs::write(new_home.join("docs/a.txt"), "a.txt").unwrap();
+    let rgdrive = |passphrase: &str, args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env_clear()
+            .env("HOME", &new_home)
+            .env("RGDRIVE_SOCKET", new.path().join("rgdrive.sock"))
+            .env("RGDRIVE_GOOGLE_API", &h.google.url)
+            .env("RGDRIVE_PASSPHRASE", passphrase)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let import = ["--migrate-import", bundle.to_str().unwrap()];
+
+    let out = rgdrive("wrong horse", &import);
+    assert!(!out.status.success());
+    assert!(String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("Wrong passphrase"));
+
+    let out = rgdrive("correct horse", &import);
+    assert!(out.status.success(), "{:?}", out);
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(
+        stdout.contains("1 tracked files (2 moved to this $HOME)"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains(&format!(
+        "Not found here, left out: {}",
+        new_home.join("docs/b.txt").display()
+    )));
+    assert!(stdout.contains("Daemon started."));
+
+    // Credentials and the sign in came along, and the imported file is watched.
+    let out = rgdrive("", &["config", "check"]);
+    assert!(out.status.success(), "{:?}", out);
+    fs::write(new_home.join("docs/a.txt"), "edited on the new machine").unwrap();
+    assert!(wait_for(
+        || h.remote(&url).as_deref() == Some("edited on the new machine")
+    ));
+
+    // Won't replace what's there now without --force.
+    let out = rgdrive("correct horse", &import);
+    assert!(!out.status.success());
+    assert!(String::from_utf8(out.stderr).unwrap().contains("--force"));
+
+    assert!(rgdrive("", &["--stop"]).status.success());
+}
+
+#[test]
+fn newest_wins_reconciles_remote_changes_and_keeps_the_loser() {
+    let h = Harness::start_with_config(
+        "[poll]\ninterval_secs = 1\n\n[reconcile]\nnewest_wins = true\n",
+    );
+    let url = h.put_remote("abc123", "notes.txt", "v1");
+    let path = h.local("notes.txt");
+    let r = h.send(DCommand::Pull(
+        url.clone(),
+        h.local(""),
+        Overwrite::Never,
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}", r);
+    let versions = h
+        .dir
+        .path()
+        .join("home/.local/share/rgdrive/versions")
+        .join(path.canonicalize().unwrap().strip_prefix("/").unwrap());
+    let kept = |contents: &str| {
+        fs::read_dir(&versions)
+            .map(|d| {
+                d.filter_map(|e| e.ok())
+                    .any(|e| fs::read_to_string(e.path()).unwrap() == contents)
+            })
+            .unwrap_or(false)
+    };
+
+    // Changed on Drive after the local file: pulled, the local copy is kept. It's moved aside before the download.
+    thread::sleep(Duration::from_millis(1100));
+    h.put_remote("abc123", "notes.txt", "v2");
+    assert!(
+        wait_for(|| fs::read_to_string(&path).ok().as_deref() == Some("v2")),
+        "{}",
+        h.log()
+    );
+    assert!(kept("v1"));
+
+    // Changed on Drive, but with an older modified time than the local file: the local file wins.
+    let stale = h.dir.path().join("remote/stale");
+    fs::write(&stale, "v3").unwrap();
+    assert!(Command::new("touch")
+        .args(["-d", "@1000000000"])
+        .arg(&stale)
+        .status()
+        .unwrap()
+        .success());
+    fs::rename(&stale, h.dir.path().join("remote/abc123")).unwrap();
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("v2")),
+        "{}",
+        h.log()
+    );
+    assert!(kept("v3"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "v2");
+}
+
+#[test]
+fn hard_links_share_one_drive_file() {
+    let h = Harness::start();
+    let a = h.local("a.txt");
+    let b = h.local("b.txt");
+    fs::write(&a, "v1").unwrap();
+    fs::hard_link(&a, &b).unwrap();
+
+    assert!(is_ok(&h.send(DCommand::Push(a.clone(), false))));
+    let r = h.send(DCommand::Push(b.clone(), false));
+    assert!(is_ok(&r), "{:?}", r);
+    let url = tracked_url(&h, &a).unwrap();
+    assert_eq!(tracked_url(&h, &b), Some(url.clone()));
+
+    // Links inside a pushed directory are uploaded once too.
+    let dir = h.local("dir");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("c.txt"), "c").unwrap();
+    fs::hard_link(dir.join("c.txt"), dir.join("d.txt")).unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(dir.clone(), false))));
+    let c = tracked_url(&h, &dir.join("c.txt")).unwrap();
+    assert_eq!(tracked_url(&h, &dir.join("d.txt")), Some(c));
+    let stored = fs::read_dir(h.dir.path().join("remote"))
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().extension().is_none())
+        .count();
+    assert_eq!(stored, 2);
+
+    // Unsyncing one link leaves the other watched.
+    assert!(is_ok(&h.send(DCommand::FUnSync(a))));
+    fs::write(&b, "v2").unwrap();
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("v2")),
+        "{}",
+        h.log()
+    );
+}
+
+#[test]
+fn json_output_for_scripts() {
+    let h = Harness::start();
+    let rgdrive = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+            .arg("--format")
+            .arg("json")
+            .args(args)
+            .output()
+            .unwrap();
+        let text = if out.stdout.is_empty() {
+            out.stderr
+        } else {
+            out.stdout
+        };
+        serde_json::from_slice::<serde_json::Value>(&text).unwrap()
+    };
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+
+    let pushed = rgdrive(&["--push", path.to_str().unwrap()]);
+    assert_eq!(pushed["ok"], true, "{}", pushed);
+    assert_eq!(pushed["path"], path.to_str().unwrap());
+    let url = tracked_url(&h, &path).unwrap();
+    assert_eq!(pushed["drive_url"], url.as_str());
+
+    let list = rgdrive(&["--list"]);
+    assert_eq!(list["files"][0]["path"], path.to_str().unwrap());
+    assert_eq!(list["files"][0]["drive_url"], url.as_str());
+    assert_eq!(rgdrive(&["--status"])["running"], true);
+
+    let missing = rgdrive(&[
+        "--pull",
+        "https://drive.google.com/open?id=missing",
+        h.local("missing.txt").to_str().unwrap(),
+    ]);
+    assert_eq!(missing["ok"], false, "{}", missing);
+    assert!(missing["error"].is_string());
+}
+
+#[test]
+fn non_utf8_file_names_sync() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let h = Harness::start();
+    // Latin-1, as left behind by old archives and SMB shares.
+    let path = h.local(OsStr::from_bytes(b"caf\xe9 \\ notes.txt"));
+    fs::write(&path, "v1").unwrap();
+    let escaped = format!(
+        "{}/caf\\xe9 \\\\ notes.txt",
+        path.parent().unwrap().display()
+    );
+    // The scratch dir outlives the restart below.
+    let dir = h.dir.path().to_path_buf();
+    let rgdrive = |args: &[&OsStr]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", dir.join("home"))
+            .env("RGDRIVE_SOCKET", dir.join("rgdrive.sock"))
+            .args(["--format", "json"])
+            .args(args)
+            .output()
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&out.stdout).unwrap()
+    };
+
+    let pushed = rgdrive(&[OsStr::new("--push"), path.as_os_str()]);
+    assert_eq!(pushed["ok"], true, "{}\n{}", pushed, h.log());
+    assert_eq!(pushed["path"], escaped.as_str());
+    let url = tracked_url(&h, &path).expect("pushed file wasn't tracked");
+    assert_eq!(h.remote(&url).as_deref(), Some("v1"));
+
+    // The name survives the tracked files on disk, byte for byte.
+    let h = h.restart_with_config("");
+    assert_eq!(tracked_url(&h, &path).as_deref(), Some(url.as_str()));
+    fs::write(&path, "v2").unwrap();
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("v2")),
+        "remote never updated:\n{}",
+        h.log()
+    );
+    let list = rgdrive(&[OsStr::new("--list")]);
+    assert_eq!(list["files"][0]["path"], escaped.as_str());
+}
+
+#[test]
+fn auth_saves_credentials_for_the_daemon() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let h = Harness::start();
+    let auth = |input: &str| {
+        sign_in(
+            Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+                .env("HOME", h.dir.path().join("home"))
+                .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+                .env("RGDRIVE_GOOGLE_API", &h.google.url)
+                .env_remove("GOOGLE_CLIENT_ID")
+                .env_remove("GOOGLE_CLIENT_SECRET")
+                .env_remove("GOOGLE_REFRESH_TOKEN")
+                .arg("--auth"),
+            input,
+        )
+    };
+    let credentials = h
+        .dir
+        .path()
+        .join("home/.config/cameron-williams/credentials");
+
+    let out = auth("not-a-client-id\nsecret\n");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("doesn't look like an OAuth client id"));
+    assert!(!credentials.exists());
+
+    let out = auth("1234.apps.googleusercontent.com\ns3cret\n");
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(
+        fs::read_to_string(&credentials).unwrap(),
+        "GOOGLE_CLIENT_ID=1234.apps.googleusercontent.com\nGOOGLE_CLIENT_SECRET=s3cret\n\
+        GOOGLE_REFRESH_TOKEN=remote\n"
+    );
+    assert_eq!(
+        fs::metadata(&credentials).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+}
+
+#[test]
+fn own_state_is_never_synced_by_accident() {
+    let h = Harness::start();
+    let home = h.dir.path().join("home");
+    let config = home.join(".config");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(config.join("app.toml"), "x = 1").unwrap();
+    let rgdrive = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", &home)
+            .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let settings = home.join(".config/cameron-williams/rgdrive.toml");
+    let out = rgdrive(&["--push", settings.to_str().unwrap()]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("rgdrive's config and state"));
+    let out = rgdrive(&["--push", config.to_str().unwrap()]);
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("contains rgdrive's config and state"));
+    let log = format!("{}.err", h.dir.path().join("rgdrive.sock").display());
+    let out = rgdrive(&["--sync", &log, "https://drive.google.com/open?id=abc"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("the daemon's log"));
+    let out = rgdrive(&[
+        "--pull",
+        "https://drive.google.com/open?id=abc",
+        settings.to_str().unwrap(),
+    ]);
+    assert!(!out.status.success());
+    assert!(tracked_url(&h, &settings).is_none());
+
+    // Unless asked to.
+    let out = rgdrive(&["--push", settings.to_str().unwrap(), "--allow-own-state"]);
+    assert!(out.status.success(), "{:?}", out);
+    assert!(tracked_url(&h, &settings).is_some());
+
+    // Nor can a watched folder download into it.
+    let watch = h.dir.path().join("watch.toml");
+    fs::write(
+        &watch,
+        format!(
+            "[poll]\ninterval_secs = 60\n\n[[watch]]\nfolder = \"https://drive.google.com/drive/folders/abc\"\ndest = {:?}\n",
+            home.join(".config/cameron-williams")
+        ),
+    )
+    .unwrap();
+    let out = rgdrive(&["config", "check", watch.to_str().unwrap()]);
+    assert!(!out.status.success());
+    assert!(
+        String::from_utf8_lossy(&out.stderr).contains("watch[0].dest can't be rgdrive's own files")
+    );
+}
+
+#[test]
+fn rgdriveignore_files_and_ignore_config_are_honored() {
+    let h = Harness::start_with_config("[excludes]\nignore = [\"*.bak\"]\n");
+    let dir = h.local("project");
+    fs::create_dir_all(dir.join("dist")).unwrap();
+    fs::create_dir_all(dir.join("sub/dist")).unwrap();
+    fs::write(
+        dir.join(".rgdriveignore"),
+        "# build output\n*.log\n!keep.log\n/dist/\n",
+    )
+    .unwrap();
+    fs::write(dir.join("sub/.rgdriveignore"), "secret.txt\n").unwrap();
+    for name in &[
+        "main.rs",
+        "app.log",
+        "keep.log",
+        "notes.bak",
+        "secret.txt",
+        "dist/bundle.js",
+        "sub/dist/page.js",
+        "sub/secret.txt",
+    ] {
+        fs::write(dir.join(name), *name).unwrap();
+    }
+
+    // Ignore rules apply even without the default excludes.
+    let r = h.send(DCommand::TrackDir(dir.clone(), None, false));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    for name in &[
+        "main.rs",
+        "keep.log",
+        "secret.txt",
+        "sub/dist/page.js",
+        ".rgdriveignore",
+    ] {
+        assert!(
+            tracked_url(&h, &dir.join(name)).is_some(),
+            "{} wasn't pushed",
+            name
+        );
+    }
+    for name in &["app.log", "notes.bak", "dist/bundle.js", "sub/secret.txt"] {
+        assert!(
+            tracked_url(&h, &dir.join(name)).is_none(),
+            "{} was pushed",
+            name
+        );
+    }
+
+    // New files in the tracked directory too.
+    fs::write(dir.join("debug.log"), "noise").unwrap();
+    fs::write(dir.join("lib.rs"), "code").unwrap();
+    assert!(
+        wait_for(|| tracked_url(&h, &dir.join("lib.rs")).is_some()),
+        "{}",
+        h.log()
+    );
+    assert!(tracked_url(&h, &dir.join("debug.log")).is_none());
+}
+
+#[test]
+fn recursive_push_picks_up_new_files() {
+    let h = Harness::start();
+    let dir = h.local("notes");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.txt"), "a").unwrap();
+    let r = h.send(DCommand::TrackDir(dir.clone(), None, true));
+    assert!(is_ok(&r), "{:?}", r);
+    assert!(tracked_url(&h, &dir.join("a.txt")).is_some());
+
+    // New files, in the directory or a new subdirectory, are pushed and synced.
+    fs::write(dir.join("b.txt"), "b").unwrap();
+    fs::create_dir(dir.join("sub")).unwrap();
+    fs::write(dir.join("sub/c.txt"), "c").unwrap();
+    fs::write(dir.join("c.txt.swp"), "swap").unwrap();
+    fs::create_dir(dir.join("target")).unwrap();
+    fs::write(dir.join("target/out.o"), "build").unwrap();
+    for (name, contents) in &[("b.txt", "b"), ("sub/c.txt", "c")] {
+        let path = dir.join(name);
+        assert!(wait_for(|| tracked_url(&h, &path).is_some()), "{}", h.log());
+        let url = tracked_url(&h, &path).unwrap();
+        assert_eq!(h.remote(&url).as_deref(), Some(*contents));
+    }
+    fs::write(dir.join("sub/c.txt"), "c, edited").unwrap();
+    let url = tracked_url(&h, &dir.join("sub/c.txt")).unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("c, edited")));
+    assert!(tracked_url(&h, &dir.join("c.txt.swp")).is_none());
+    assert!(tracked_url(&h, &dir.join("target/out.o")).is_none());
+
+    // Still picked up after a restart.
+    let h = h.restart_with_config("");
+    fs::write(dir.join("d.txt"), "d").unwrap();
+    assert!(
+        wait_for(|| tracked_url(&h, &dir.join("d.txt")).is_some()),
+        "{}",
+        h.log()
+    );
+
+    // Unsyncing the directory stops it, what's already synced stays synced.
+    assert!(is_ok(&h.send(DCommand::FUnSync(dir.clone()))));
+    fs::write(dir.join("e.txt"), "e").unwrap();
+    thread::sleep(Duration::from_millis(1500));
+    assert!(tracked_url(&h, &dir.join("e.txt")).is_none());
+    assert!(tracked_url(&h, &dir.join("d.txt")).is_some());
+}
+
+#[test]
+fn watch_events_per_file_and_from_config() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let h = Harness::start();
+    let perms = h.local("perms.txt");
+    let plain = h.local("plain.txt");
+    for (id, path) in &[("perms", &perms), ("plain", &plain)] {
+        fs::write(path, "contents").unwrap();
+        let url = h.put_remote(id, "x.txt", "contents");
+        let events = if *id == "perms" {
+            Some(vec![WatchEvent::Modify, WatchEvent::Attrib])
+        } else {
+            None
+        };
+        assert!(is_ok(&h.send(DCommand::FSync(
+            path.to_path_buf(),
+            url,
+            events
+        ))));
+    }
+    let updated = |h: &Harness, p: &Path| {
+        h.log()
+            .contains(&format!("Successfully updated file: {:?}", p))
+    };
+
+    // Only the file watched for attrib notices a chmod.
+    fs::set_permissions(&perms, fs::Permissions::from_mode(0o600)).unwrap();
+    fs::set_permissions(&plain, fs::Permissions::from_mode(0o600)).unwrap();
+    assert!(wait_for(|| updated(&h, &perms)), "{}", h.log());
+    thread::sleep(Duration::from_millis(1500));
+    assert!(!updated(&h, &plain));
+
+    // [events] applies to files without their own.
+    let h = h.restart_with_config("[events]\ndefault = [\"modify\", \"attrib\"]\n");
+    fs::set_permissions(&plain, fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(wait_for(|| updated(&h, &plain)), "{}", h.log());
+
+    let check = |config: &str| {
+        let file = h.dir.path().join("events.toml");
+        fs::write(&file, config).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .env("GOOGLE_CLIENT_ID", "1234.apps.googleusercontent.com")
+            .env("GOOGLE_CLIENT_SECRET", "secret")
+            .args(["config", "check"])
+            .arg(&file)
+            .output()
+            .unwrap()
+    };
+    assert!(
+        check("[events.paths]\n\"/home/cam/bin\" = [\"modify\", \"attrib\"]\n")
+            .status
+            .success()
+    );
+    let out = check("[events]\ndefault = []\n");
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("No events given"));
+    assert!(!check("[events.paths]\n\"usb\" = [\"modify\"]\n")
+        .status
+        .success());
+    assert!(!check("[events]\ndefault = [\"write\"]\n").status.success());
+}
+
+#[test]
+fn files_on_removable_media_survive_unplugging() {
+    let dir = tempfile::tempdir().unwrap();
+    let mounts = dir.path().join("mounts");
+    let config = format!(
+        "[media]\nmountinfo = {:?}\nby_uuid = {:?}\n",
+        mounts.join("mountinfo"),
+        mounts.join("by-uuid")
+    );
+    let h = Harness::start_in(dir, &config);
+    fs::create_dir_all(mounts.join("by-uuid")).unwrap();
+    std::os::unix::fs::symlink("../../sdz1", mounts.join("by-uuid/ABCD-1234")).unwrap();
+    let mount = |point: Option<&Path>| {
+        let mut info = String::from("22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n");
+        if let Some(p) = point {
+            info.push_str(&format!(
+                "40 22 8:17 / {} rw,nosuid shared:20 - vfat /dev/sdz1 rw\n",
+                p.display()
+            ));
+        }
+        fs::write(mounts.join("mountinfo"), info).unwrap();
+    };
+    let usb = h.local("usb1");
+    fs::create_dir_all(&usb).unwrap();
+    mount(Some(&usb));
+
+    let notes_url = h.put_remote("abc123", "notes.md", "v1");
+    fs::write(usb.join("notes.md"), "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::FSync(
+        usb.join("notes.md"),
+        notes_url.clone(),
+        None
+    ))));
+    let other_url = h.put_remote("def456", "other.md", "r1");
+    let r = h.send(DCommand::Pull(
+        other_url.clone(),
+        usb.clone(),
+        Overwrite::Never,
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}", r);
+    let tracked =
+        TrackedFile::from_path(h.dir.path().join("home/.config/cameron-williams/state.db"));
+    let media = tracked[0].media.clone().unwrap();
+    assert_eq!(media.uuid.as_deref(), Some("ABCD-1234"));
+    assert_eq!(media.mount_point, usb);
+
+    // Unplugged while the daemon was down: still synced, only absent.
+    let stash = h.dir.path().join("stash");
+    fs::rename(&usb, &stash).unwrap();
+    mount(None);
+    let h = h.restart_with_config(&config);
+    assert!(
+        wait_for(|| h.log().contains("isn't mounted")),
+        "{}",
+        h.log()
+    );
+    let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+        .env("HOME", h.dir.path().join("home"))
+        .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+        .arg("--list")
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&out.stdout).contains("(media absent)"));
+
+    // Edited elsewhere meanwhile, on both sides, and plugged back in at another mount point.
+    fs::write(stash.join("notes.md"), "v2, edited on the laptop").unwrap();
+    h.put_remote("def456", "other.md", "r2");
+    let usb = h.local("usb2");
+    fs::rename(&stash, &usb).unwrap();
+    mount(Some(&usb));
+    assert!(
+        wait_for(|| h.remote(&notes_url).as_deref() == Some("v2, edited on the laptop")),
+        "{}",
+        h.log()
+    );
+    // Reading may race the download replacing the file.
+    assert!(wait_for(|| fs::read_to_string(usb.join("other.md"))
+        .map(|s| s == "r2")
+        .unwrap_or(false)));
+    assert_eq!(
+        tracked_url(&h, &usb.join("notes.md")),
+        Some(notes_url.clone())
+    );
+
+    // And it's watched again.
+    fs::write(usb.join("notes.md"), "v3").unwrap();
+    assert!(wait_for(|| h.remote(&notes_url).as_deref() == Some("v3")));
+}
+
+#[test]
+fn report_summarizes_transfers_from_the_journal() {
+    let h = Harness::start();
+    let path = h.local("report.txt");
+    fs::write(&path, "v1").unwrap();
+    // Drive refuses the first push, the second goes through.
+    let remote = h.dir.path().join("remote");
+    fs::create_dir_all(&remote).unwrap();
+    fs::write(remote.join(".failing_uploads"), "1").unwrap();
+    assert!(!is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+    fs::write(&path, "version 3").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("version 3")));
+
+    let report = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .arg("--report")
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let json = || {
+        let out = report(&["--since", "1h", "--format", "json"]);
+        assert!(out.status.success(), "{:?}", out);
+        serde_json::from_slice::<serde_json::Value>(&out.stdout).unwrap()
+    };
+    // The upload is journaled just after Drive has it.
+    assert!(wait_for(|| json()["uploads"] == 2), "{}", json());
+    let r = json();
+    assert_eq!(r["days"].as_array().unwrap().len(), 1, "{}", r);
+    assert_eq!(r["days"][0]["up"], 2 + 9, "{}", r);
+    assert_eq!(r["top"][0]["path"], path.to_str().unwrap(), "{}", r);
+    assert_eq!(r["errors"]["server"], 1, "{}", r);
+    assert!(r["latency_ms"]["average"].is_u64(), "{}", r);
+
+    let text = String::from_utf8(report(&[]).stdout).unwrap();
+    assert!(text.contains("2 uploads, 0 downloads"), "{}", text);
+    assert!(text.contains("Sync latency: "), "{}", text);
+    assert!(!report(&["--since", "soon"]).status.success());
+}
+
+#[test]
+fn push_wait_porcelain_prints_the_url_after_retrying() {
+    let h = Harness::start();
+    let path = h.local("report.txt");
+    fs::write(&path, "quarterly numbers").unwrap();
+    // Drive refuses the first upload, as if it were briefly overloaded.
+    let remote = h.dir.path().join("remote");
+    fs::create_dir_all(&remote).unwrap();
+    fs::write(remote.join(".failing_uploads"), "1").unwrap();
+
+    let push = |path: &Path| {
+        Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+            .arg("--push")
+            .arg(path)
+            .args(["--wait", "--porcelain"])
+            .output()
+            .unwrap()
+    };
+    let out = push(&path);
+    assert!(out.status.success(), "{}", h.log());
+    let url = tracked_url(&h, &path).unwrap();
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), format!("{}\n", url));
+    assert_eq!(h.remote(&url).as_deref(), Some("quarterly numbers"));
+    assert!(h.log().contains("trying again"), "{}", h.log());
+
+    // Pushing it again answers with the same url.
+    let out = push(&path);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), format!("{}\n", url));
+
+    let out = push(&h.local("missing.txt"));
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("does not exist"));
+}
+
+#[test]
+fn transfers_run_side_by_side() {
+    let h = Harness::start_with_config("[transfers]\nconcurrency = 4\n");
+    let dir = h.local("photos");
+    fs::create_dir_all(&dir).unwrap();
+    for name in &["a.jpg", "b.jpg", "c.jpg"] {
+        fs::write(dir.join(name), name).unwrap();
+    }
+    fs::hard_link(dir.join("a.jpg"), dir.join("a-copy.jpg")).unwrap();
+    let single = h.local("notes.txt");
+    fs::write(&single, "notes").unwrap();
+    // Every upload takes a second, four one after another would take four.
+    let remote = h.dir.path().join("remote");
+    fs::create_dir_all(&remote).unwrap();
+    fs::write(remote.join(".upload_delay_ms"), "1000").unwrap();
+
+    let start = Instant::now();
+    let mut push_dir = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+        .env("HOME", h.dir.path().join("home"))
+        .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+        .arg("--push")
+        .arg(&dir)
+        .spawn()
+        .unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(single.clone(), true))));
+    assert!(push_dir.wait().unwrap().success());
+    assert!(
+        start.elapsed() < Duration::from_secs(3),
+        "{:?}",
+        start.elapsed()
+    );
+
+    for name in &["a.jpg", "b.jpg", "c.jpg"] {
+        let url = tracked_url(&h, &dir.join(name)).unwrap();
+        assert_eq!(h.remote(&url).as_deref(), Some(*name));
+    }
+    assert_eq!(
+        tracked_url(&h, &dir.join("a-copy.jpg")),
+        tracked_url(&h, &dir.join("a.jpg"))
+    );
+    assert!(tracked_url(&h, &single).is_some());
+}
+
+#[test]
+fn files_saved_by_renaming_a_new_file_over_them_stay_synced() {
+    let h = Harness::start();
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+
+    // Written to a temp file and renamed over the original, like most editors save.
+    let tmp = h.local(".notes.txt.tmp");
+    fs::write(&tmp, "v2").unwrap();
+    fs::rename(&tmp, &path).unwrap();
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("v2")),
+        "{}",
+        h.log()
+    );
+    // The new file is watched.
+    fs::write(&path, "v3").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v3")));
+
+    // The original moved aside first, then the new one written, like vim does.
+    let backup = h.local("notes.txt~");
+    fs::rename(&path, &backup).unwrap();
+    thread::sleep(Duration::from_millis(700));
+    fs::write(&path, "v4").unwrap();
+    fs::remove_file(&backup).unwrap();
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("v4")),
+        "{}",
+        h.log()
+    );
+    assert_eq!(tracked_url(&h, &path), Some(url));
+
+    // Deleted for good, it's no longer synced.
+    fs::remove_file(&path).unwrap();
+    thread::sleep(Duration::from_secs(4));
+    assert!(wait_for(|| tracked_url(&h, &path).is_none()), "{}", h.log());
+}
+
+#[test]
+fn moved_files_stay_synced_and_are_renamed_on_drive() {
+    let h = Harness::start_with_config("");
+    fs::create_dir_all(h.local("archive")).unwrap();
+    let path = h.local("draft.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+    let name = |url: &str| {
+        let id = drive_id(url).unwrap();
+        fs::read_to_string(h.dir.path().join(format!("remote/{}.name", id))).unwrap()
+    };
+    // Another synced file puts a watch on archive.
+    let other = h.local("archive/old.txt");
+    fs::write(&other, "old").unwrap();
+    assert!(is_ok(&h.send(DCommand::PushAs(
+        other.clone(),
+        String::from("Old notes"),
+        None
+    ))));
+
+    // Renamed in place, the Drive copy is renamed too and changes still go up.
+    let renamed = h.local("final.txt");
+    fs::rename(&path, &renamed).unwrap();
+    assert!(
+        wait_for(|| tracked_url(&h, &renamed).as_ref() == Some(&url)),
+        "{}",
+        h.log()
+    );
+    assert!(tracked_url(&h, &path).is_none());
+    assert!(wait_for(|| name(&url) == "final.txt"), "{}", h.log());
+    fs::write(&renamed, "v2").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v2")));
+
+    // Moved into another watched directory.
+    let filed = h.local("archive/final.txt");
+    fs::rename(&renamed, &filed).unwrap();
+    assert!(wait_for(|| tracked_url(&h, &filed).as_ref() == Some(&url)));
+    fs::write(&filed, "v3").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v3")));
+
+    // One pushed under a name of its own keeps it.
+    let other_url = tracked_url(&h, &other).unwrap();
+    let moved = h.local("archive/older.txt");
+    fs::rename(&other, &moved).unwrap();
+    assert!(wait_for(|| tracked_url(&h, &moved).is_some()));
+    assert_eq!(name(&other_url), "Old notes");
+}
+
+#[test]
+fn moved_files_outside_the_allowed_folder_keep_their_drive_name() {
+    let h = Harness::start_with_config("");
+    let path = h.local("draft.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+    let h = h.restart_with_config("[policy]\nallowed_folder = \"folder1\"\n");
+    h.put_remote("folder1", "Work", "");
+    assert!(wait_for(|| h.log().contains("Watching 1 tracked files")));
+
+    let renamed = h.local("final.txt");
+    fs::rename(&path, &renamed).unwrap();
+    assert!(wait_for(|| tracked_url(&h, &renamed).as_ref() == Some(&url)));
+    assert!(
+        wait_for(|| h.log().contains("Keeping the Drive name of")),
+        "{}",
+        h.log()
+    );
+    // Nor is it renamed when asked to.
+    match h.send(DCommand::RenameRemote(renamed, String::from("Final"))) {
+        DResult::Err(e) => assert!(e.contains("outside of the allowed folder"), "{}", e),
+        r => panic!("{:?}", r),
+    }
+    let id = drive_id(&url).unwrap();
+    assert_eq!(
+        fs::read_to_string(h.dir.path().join(format!("remote/{}.name", id))).unwrap(),
+        "draft.txt"
+    );
+}
+
+#[test]
+fn quitting_finishes_transfers_and_queues_held_changes() {
+    let config = "[batching]\nwindow_secs = 3600\n";
+    let h = Harness::start_with_config(config);
+    let held = h.local("held.txt");
+    fs::write(&held, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(held.clone(), false))));
+    let held_url = tracked_url(&h, &held).unwrap();
+    // Held back by the batching window.
+    fs::write(&held, "v2").unwrap();
+    thread::sleep(Duration::from_millis(1500));
+
+    // Still uploading when the daemon is asked to quit.
+    let remote = h.dir.path().join("remote");
+    fs::write(remote.join(".upload_delay_ms"), "1500").unwrap();
+    let slow = h.local("slow.txt");
+    fs::write(&slow, "slow").unwrap();
+    let socket = h.socket();
+    let pushing = {
+        let slow = slow.clone();
+        thread::spawn(move || socket.send_command(DCommand::Push(slow, false)).unwrap())
+    };
+    thread::sleep(Duration::from_millis(300));
+    let r = h.send(DCommand::Quit);
+    assert_eq!(r, DResult::ok("Daemon stopped."), "{}", h.log());
+    assert!(is_ok(&pushing.join().unwrap()));
+    assert!(wait_for(|| !h.socket().is_active()));
+    fs::remove_file(remote.join(".upload_delay_ms")).unwrap();
+
+    // Both are picked up again once the daemon's back.
+    let h = h.restart_with_config(config);
+    assert!(tracked_url(&h, &slow).is_some());
+    let line = match h.send(DCommand::Queue) {
+        DResult::Ok(s) => s,
+        r => panic!("{:?}", r),
+    };
+    assert!(line.contains("held.txt"), "{}", line);
+    let id: u64 = line.split_whitespace().next().unwrap().parse().unwrap();
+    assert!(is_ok(&h.send(DCommand::QueueRetry(id))));
+    assert_eq!(h.remote(&held_url).as_deref(), Some("v2"));
+}
+
+#[test]
+fn the_systemd_watchdog_is_pinged() {
+    let dir = tempfile::tempdir().unwrap();
+    let notify = dir.path().join("notify.sock");
+    let systemd = UnixDatagram::bind(&notify).unwrap();
+    systemd
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let h = Harness::start_in_with_env(
+        dir,
+        "",
+        &[
+            ("NOTIFY_SOCKET", notify.as_os_str()),
+            ("WATCHDOG_USEC", "400000".as_ref()),
+        ],
+    );
+
+    // Twice per timeout, over and over.
+    for _ in 0..3 {
+        let mut buf = [0; 64];
+        let n = systemd
+            .recv(&mut buf)
+            .unwrap_or_else(|e| panic!("{}\n{}", e, h.log()));
+        assert_eq!(&buf[..n], b"WATCHDOG=1");
+    }
+}
+
+#[test]
+fn a_crash_report_is_flagged_by_status() {
+    let h = Harness::start();
+    // What crash::init()'s panic hook leaves behind, see tests/crash.rs.
+    let crash = Crash {
+        time: 1_600_000_000,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        thread: String::from("worker"),
+        message: String::from("boom"),
+        location: Some(String::from("src/rgdrived.rs:1")),
+        backtrace: String::new(),
+        events: Vec::new(),
+        reported: false,
+    };
+    fs::write(
+        h.dir.path().join("rgdrive.sock.crash"),
+        serde_json::to_string(&crash).unwrap(),
+    )
+    .unwrap();
+
+    // Flagged by the next --status, only that one.
+    let status = || {
+        let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+            .arg("--status")
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let first = status();
+    assert!(first.contains("Crashed"), "{}", first);
+    assert!(first.contains(": boom ("), "{}", first);
+    assert!(!status().contains("Crashed"));
+}
+
+#[test]
+fn status_shows_the_signed_in_account() {
+    // The account is signed in with these scopes from the start.
+    let dir = tempfile::tempdir().unwrap();
+    let remote = dir.path().join("remote");
+    fs::create_dir_all(&remote).unwrap();
+    fs::write(remote.join(".account"), "cam@example.com\n").unwrap();
+    fs::write(
+        remote.join(".scopes"),
+        "https://www.googleapis.com/auth/drive.file\n",
+    )
+    .unwrap();
+    fs::write(remote.join(".token_lifetime"), "3600").unwrap();
+    let h = Harness::start_in(dir, "");
+    let status = |json: bool| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_rgdrive"));
+        cmd.env("HOME", h.dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"));
+        if json {
+            cmd.args(["--format", "json"]);
+        }
+        let out = cmd.arg("--status").output().unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    };
+
+    let text = status(false);
+    assert!(text.contains("Account: cam@example.com\n"), "{}", text);
+    assert!(text.contains("Scopes: https://www.googleapis.com/auth/drive.file\n"));
+    assert!(text.contains("Access token expires: "));
+
+    let json: serde_json::Value = serde_json::from_str(&status(true)).unwrap();
+    let signed_in = &json["signed_in"];
+    assert_eq!(signed_in["account"], "cam@example.com");
+    assert_eq!(
+        signed_in["scopes"][0],
+        "https://www.googleapis.com/auth/drive.file"
+    );
+    let now = chrono::Utc::now().timestamp();
+    let expires = signed_in["token_expires"].as_i64().unwrap();
+    assert!(expires > now && expires <= now + 3600, "{}", signed_in);
+}
+
+#[test]
+fn injected_failures_reach_the_client() {
+    let h = Harness::start_in_with_env(
+        tempfile::tempdir().unwrap(),
+        "",
+        &[("RGDRIVE_INJECT_FAILURES", "1:500".as_ref())],
+    );
+    let path = h.local("notes.txt");
+    fs::write(&path, "notes").unwrap();
+    match h.send(DCommand::Push(path.clone(), false)) {
+        DResult::Err(e) => assert!(e.contains("500 Internal Server Error"), "{}", e),
+        r => panic!("push went through: {:?}", r),
+    }
+    assert!(tracked_url(&h, &path).is_none());
+    assert!(
+        h.log().contains("Injecting [ServerError] failures"),
+        "{}",
+        h.log()
+    );
+
+    // Never injecting any changes nothing.
+    let h = Harness::start_in_with_env(
+        tempfile::tempdir().unwrap(),
+        "",
+        &[("RGDRIVE_INJECT_FAILURES", "0".as_ref())],
+    );
+    let path = h.local("notes.txt");
+    fs::write(&path, "notes").unwrap();
+    let r = h.send(DCommand::Push(path.clone(), false));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert!(tracked_url(&h, &path).is_some());
+}
+
+#[test]
+fn synced_files_are_replicated_to_a_second_remote() {
+    // The backup profile is signed in to its own account, remote-backup.
+    let dir = tempfile::tempdir().unwrap();
+    let credentials = dir
+        .path()
+        .join("home/.config/cameron-williams/profiles/backup/credentials");
+    fs::create_dir_all(credentials.parent().unwrap()).unwrap();
+    fs::write(
+        &credentials,
+        "GOOGLE_CLIENT_ID=1234.apps.googleusercontent.com\nGOOGLE_CLIENT_SECRET=s3cret\n\
+        GOOGLE_REFRESH_TOKEN=remote-backup\n",
+    )
+    .unwrap();
+    let h = Harness::start_in(dir, "[replica]\nprofile = \"backup\"\ninterval_secs = 1\n");
+    let replica = h.dir.path().join("remote-backup");
+    let replicas = h.dir.path().join("home/.config/cameron-williams/replicas");
+    let replica_url = || -> Option<(String, Option<String>)> {
+        let state: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&replicas).ok()?).ok()?;
+        let file = &state["files"][0];
+        Some((
+            file["replica_url"].as_str()?.to_string(),
+            file["last_error"].as_str().map(String::from),
+        ))
+    };
+    let in_replica = |url: &str| fs::read_to_string(replica.join(drive_id(url).unwrap())).ok();
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+
+    assert!(wait_for(|| replica_url().is_some()), "{}", h.log());
+    let (url, _) = replica_url().unwrap();
+    assert_eq!(in_replica(&url).as_deref(), Some("v1"));
+
+    // Changes follow Drive to the same replica copy.
+    fs::write(&path, "v2").unwrap();
+    assert!(
+        wait_for(|| in_replica(&url).as_deref() == Some("v2")),
+        "{}",
+        h.log()
+    );
+    assert_eq!(
+        h.remote(&tracked_url(&h, &path).unwrap()).as_deref(),
+        Some("v2")
+    );
+
+    // A failing replica doesn't hold Drive up, and shows in --list and --status.
+    fs::write(replica.join(".revoked"), "").unwrap();
+    fs::write(&path, "v3").unwrap();
+    assert!(
+        wait_for(|| matches!(replica_url(), Some((_, Some(_))))),
+        "{}",
+        h.log()
+    );
+    assert_eq!(
+        h.remote(&tracked_url(&h, &path).unwrap()).as_deref(),
+        Some("v3")
+    );
+    let rgdrive = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+            .args(args)
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap()
+    };
+    let list: serde_json::Value =
+        serde_json::from_str(&rgdrive(&["--format", "json", "--list"])).unwrap();
+    assert_eq!(list["files"][0]["replica"], "error");
+    let status = rgdrive(&["--status"]);
+    assert!(
+        status.contains("Replica (profile backup): 0 synced, 0 pending, 1 failed, last error: 401"),
+        "{}",
+        status
+    );
+}
+
+#[test]
+fn tracked_files_move_into_the_state_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("local/notes.txt");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, "v1").unwrap();
+    // Left by an older version.
+    let config = dir.path().join("home/.config/cameron-williams");
+    fs::create_dir_all(&config).unwrap();
+    let files = vec![TrackedFile {
+        md5: checksum::md5_file(&path).ok(),
+        synced_at: Some(1_600_000_000),
+        ..TrackedFile::new(&path, "https://drive.google.com/open?id=notes1")
+    }];
+    fs::write(
+        config.join("tracked_files"),
+        TrackedFile::encode_all(&files),
+    )
+    .unwrap();
+    let h = Harness::start_in(dir, "");
+    assert!(is_ok(&h.send(DCommand::Stats)));
+
+    assert!(!config.join("tracked_files").exists());
+    assert!(config.join("tracked_files.migrated").exists());
+    let tracked = TrackedFile::from_path(config.join("state.db"));
+    assert_eq!(tracked.len(), 1);
+    assert_eq!(tracked[0].path, path);
+    assert_eq!(tracked[0].md5, files[0].md5);
+    assert_eq!(tracked[0].synced_at, Some(1_600_000_000));
+
+    // Still synced, and each sync records the local mtime too.
+    let url = h.put_remote("notes1", "notes.txt", "v1");
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(|| h.remote(&url).as_deref() == Some("v2")));
+    let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+    let mtime = mtime
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    assert!(wait_for(|| {
+        TrackedFile::from_path(config.join("state.db"))[0].mtime == Some(mtime)
+    }));
+}
+
+#[test]
+fn an_unreadable_tracked_files_list_stops_startup() {
+    let dir = tempfile::tempdir().unwrap();
+    let tracked = dir
+        .path()
+        .join("home/.config/cameron-williams/tracked_files");
+    fs::create_dir_all(tracked.parent().unwrap()).unwrap();
+    let mut garbage = b"RGDT".to_vec();
+    garbage.extend_from_slice(&1u32.to_le_bytes());
+    garbage.extend_from_slice(&[0xff; 64]);
+    fs::write(&tracked, &garbage).unwrap();
+
+    let google = FakeGoogle::start(dir.path());
+    let out = signed_in(
+        Command::new(env!("CARGO_BIN_EXE_rgdrived")).env_clear(),
+        &google,
+    )
+    .env("HOME", dir.path().join("home"))
+    .env("RGDRIVE_SOCKET", dir.path().join("rgdrive.sock"))
+    .output()
+    .unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let e = StartupError::from_log(&stderr).unwrap();
+    assert_eq!((out.status.code(), e.code), (Some(1), Reason::Other));
+    assert!(e.message.contains("Move it aside"), "{}", e.message);
+    // Never replaced by an empty list.
+    assert_eq!(fs::read(&tracked).unwrap(), garbage);
+}
+
+#[test]
+fn an_unreadable_tracked_dirs_list_stops_startup() {
+    let dir = tempfile::tempdir().unwrap();
+    let dirs = dir.path().join("home/.config/cameron-williams/dirs");
+    fs::create_dir_all(dirs.parent().unwrap()).unwrap();
+    fs::write(&dirs, "[{\"path\": ").unwrap();
+
+    let google = FakeGoogle::start(dir.path());
+    let out = signed_in(
+        Command::new(env!("CARGO_BIN_EXE_rgdrived")).env_clear(),
+        &google,
+    )
+    .env("HOME", dir.path().join("home"))
+    .env("RGDRIVE_SOCKET", dir.path().join("rgdrive.sock"))
+    .output()
+    .unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let e = StartupError::from_log(&stderr).unwrap();
+    assert_eq!((out.status.code(), e.code), (Some(1), Reason::Other));
+    assert!(e.message.contains("tracked directories"), "{}", e.message);
+    assert_eq!(fs::read_to_string(&dirs).unwrap(), "[{\"path\": ");
+}
+
+#[test]
+fn environments_hold_uploads_until_switched() {
+    let h = Harness::start_with_config(
+        "[environments.home]\n\n[environments.office]\npause_uploads = true\n",
+    );
+    let path = h.local("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    assert!(is_ok(&h.send(DCommand::Push(path.clone(), false))));
+    let url = tracked_url(&h, &path).unwrap();
+
+    match h.send(DCommand::SetEnv(Some(String::from("cafe")))) {
+        DResult::Err(e) => assert!(e.contains("home, office"), "{}", e),
+        r => panic!("unexpected {:?}", r),
+    }
+    assert!(is_ok(
+        &h.send(DCommand::SetEnv(Some(String::from("office"))))
+    ));
+    assert!(format!("{:?}", h.send(DCommand::Env)).contains("* office: uploads paused"));
+
+    // Held in the queue while office pauses uploads.
+    fs::write(&path, "v2").unwrap();
+    assert!(wait_for(
+        || format!("{:?}", h.send(DCommand::Queue)).contains("uploads are paused on office")
+    ));
+    assert_eq!(h.remote(&url).as_deref(), Some("v1"));
+
+    // Switching retries it right away, and is remembered.
+    assert!(is_ok(&h.send(DCommand::SetEnv(Some(String::from("home"))))));
+    assert!(
+        wait_for(|| h.remote(&url).as_deref() == Some("v2")),
+        "remote never updated:\n{}",
+        h.log()
+    );
+    let saved = h
+        .dir
+        .path()
+        .join("home/.config/cameron-williams/environment");
+    assert_eq!(fs::read_to_string(&saved).unwrap(), "home");
+    assert!(is_ok(&h.send(DCommand::SetEnv(None))));
+    assert!(!saved.exists());
+}
+
+#[test]
+fn tracked_directories_push_new_files_into_their_dest() {
+    // Drive folder the synced file at path was uploaded into.
+    fn parent(h: &Harness, path: &Path) -> Option<String> {
+        let url = tracked_url(h, path)?;
+        let id = drive_id(&url)?;
+        fs::read_to_string(h.dir.path().join(format!("remote/{}.parent", id))).ok()
+    }
+    let folder = |id: &str| Some(format!("https://drive.google.com/drive/folders/{}", id));
+    let h = Harness::start();
+    let dir = h.local("inbox");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.txt"), "a").unwrap();
+    let r = h.send(DCommand::TrackDir(dir.clone(), folder("inbox1"), true));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert_eq!(parent(&h, &dir.join("a.txt")).as_deref(), Some("inbox1"));
+
+    fs::write(dir.join("b.txt"), "b").unwrap();
+    assert!(
+        wait_for(|| parent(&h, &dir.join("b.txt")).as_deref() == Some("inbox1")),
+        "{}",
+        h.log()
+    );
+
+    // Pushed again with another dest, new files (even after a restart) go there, the others stay where they are.
+    let r = h.send(DCommand::TrackDir(dir.clone(), folder("archive1"), true));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    let h = h.restart_with_config("");
+    fs::write(dir.join("c.txt"), "c").unwrap();
+    assert!(
+        wait_for(|| parent(&h, &dir.join("c.txt")).as_deref() == Some("archive1")),
+        "{}",
+        h.log()
+    );
+    assert_eq!(parent(&h, &dir.join("a.txt")).as_deref(), Some("inbox1"));
+}
+
+#[test]
+fn shortcuts_pull_as_their_targets() {
+    let h = Harness::start_with_config("[shortcuts]\nsymlinks = true\n");
+    let shortcut = |url: &str, folder: &str| match h.send(DCommand::Shortcut(
+        url.to_string(),
+        Some(format!("https://drive.google.com/drive/folders/{}", folder)),
+    )) {
+        DResult::Fields(_, fields) => fields
+            .iter()
+            .find(|(k, _)| k == "shortcut_url")
+            .map(|(_, v)| v.clone())
+            .unwrap(),
+        r => panic!("unexpected {:?}\n{}", r, h.log()),
+    };
+    let folder = |id: &str, name: &str, parent: Option<&str>| {
+        h.put_remote(id, name, "");
+        fs::write(
+            h.dir.path().join(format!("remote/{}.mime", id)),
+            FOLDER_MIME,
+        )
+        .unwrap();
+        if let Some(p) = parent {
+            fs::write(h.dir.path().join(format!("remote/{}.parent", id)), p).unwrap();
+        }
+    };
+    folder("proj1", "Projects", None);
+    folder("links1", "Links", Some("proj1"));
+    let readme = h.put_remote("readme1", "README.md", "top");
+    fs::write(h.dir.path().join("remote/readme1.parent"), "proj1").unwrap();
+    let budget = h.put_remote("budget1", "budget.csv", "numbers");
+
+    // Pulling a shortcut pulls and syncs what it points to.
+    let to_budget = shortcut(&budget, "links1");
+    let path = h.local("budget.csv");
+    let r = h.send(DCommand::Pull(
+        to_budget,
+        path.clone(),
+        Overwrite::Never,
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "numbers");
+    assert_eq!(tracked_url(&h, &path), Some(budget));
+
+    // In a folder, shortcuts to files synced already are linked to them, the others come down as their target.
+    fs::remove_file(&path).unwrap();
+    assert!(is_ok(&h.send(DCommand::FUnSync(path.clone()))));
+    shortcut(&readme, "links1");
+    let dir = h.local("Projects");
+    let r = h.send(DCommand::PullDir(
+        String::from("https://drive.google.com/drive/folders/proj1"),
+        dir.clone(),
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    assert!(r.message().contains("1 shortcuts linked."), "{:?}", r);
+    let link = dir.join("Links/README.md");
+    assert_eq!(fs::read_link(&link).unwrap(), dir.join("README.md"));
+    assert_eq!(fs::read_to_string(&link).unwrap(), "top");
+    assert_eq!(
+        fs::read_to_string(dir.join("Links/budget.csv")).unwrap(),
+        "numbers"
+    );
+    assert_eq!(
+        tracked_url(&h, &dir.join("Links/budget.csv")).as_deref(),
+        Some("https://drive.google.com/open?id=budget1")
+    );
+}
+
+#[test]
+fn ls_lists_a_drive_folder() {
+    let h = Harness::start_with_config("");
+    let remote = h.dir.path().join("remote");
+    h.put_remote("proj1", "Projects", "");
+    fs::write(remote.join("proj1.mime"), FOLDER_MIME).unwrap();
+    h.put_remote("sub1", "drafts", "");
+    fs::write(remote.join("sub1.mime"), FOLDER_MIME).unwrap();
+    fs::write(remote.join("sub1.parent"), "proj1").unwrap();
+    h.put_remote("readme1", "README.md", "top");
+    fs::write(remote.join("readme1.parent"), "proj1").unwrap();
+    h.put_remote("budget1", "budget.csv", "1,2,3");
+    h.put_remote("link1", "Budget", "");
+    fs::write(remote.join("link1.mime"), SHORTCUT_MIME).unwrap();
+    fs::write(remote.join("link1.target"), "budget1").unwrap();
+    fs::write(remote.join("link1.parent"), "proj1").unwrap();
+
+    let ls = |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_rgdrive"))
+            .env("HOME", h.dir.path().join("home"))
+            .env("RGDRIVE_SOCKET", h.dir.path().join("rgdrive.sock"))
+            .arg("--ls")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{:?}\n{}", out, h.log());
+        String::from_utf8(out.stdout).unwrap()
+    };
+    // Folders first, shortcuts with what they point to.
+    let listing = ls(&["https://drive.google.com/drive/folders/proj1"]);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", listing);
+    assert!(lines[0].ends_with("-  drafts/  sub1"), "{}", listing);
+    assert!(
+        lines[1].ends_with("5 B  Budget -> budget1  link1"),
+        "{}",
+        listing
+    );
+    assert!(lines[2].ends_with("3 B  README.md  readme1"), "{}", listing);
+
+    // The Drive root without a folder, and json for scripts.
+    let root: serde_json::Value = serde_json::from_str(&ls(&["--format", "json"])).unwrap();
+    let names: Vec<&str> = root["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["Projects", "budget.csv"]);
+    assert_eq!(root["files"][1]["size"], 5);
+    assert_eq!(root["files"][1]["kind"], "file");
+}
+
+#[test]
+fn pinned_files_are_kept_on_disk_and_current() {
+    let h = Harness::start_with_config("[poll]\ninterval_secs = 1\n");
+    let url = h.put_remote("abc123", "report.txt", "v1");
+    let r = h.send(DCommand::Pull(
+        url.clone(),
+        h.local(""),
+        Overwrite::Never,
+        false,
+    ));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    let path = h.local("report.txt");
+    let r = h.send(DCommand::Pin(url.clone(), None));
+    assert!(is_ok(&r), "{:?}\n{}", r, h.log());
+    match h.send(DCommand::Pins) {
+        DResult::Ok(s) => assert!(s.contains("report.txt"), "{}", s),
+        r => panic!("{:?}", r),
+    }
+
+    // Drive changes come down once the poller has a pass to compare with, and a deleted copy is put back.
+    assert!(wait_for(|| polled(&h)), "{}", h.log());
+    h.put_remote("abc123", "report.txt", "v2, edited in the Drive UI");
+    assert!(
+        wait_for(|| fs::read_to_string(&path).ok().as_deref() == Some("v2, edited in the Drive UI")),
+        "{}",
+        h.log()
+    );
+    fs::remove_file(&path).unwrap();
+    assert!(wait_for(|| path.exists()), "{}", h.log());
+
+    // One that can't be refreshed fails --health.
+    fs::write(h.dir.path().join("remote/.revoked"), "").unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(
+        wait_for(|| match h.send(DCommand::Health) {
+            DResult::Err(e) => e.contains("pinned"),
+            _ => false,
+        }),
+        "{}",
+        h.log()
+    );
+
+    assert!(is_ok(
+        &h.send(DCommand::Unpin(path.to_string_lossy().into_owned()))
+    ));
+    match h.send(DCommand::Pins) {
+        DResult::Ok(s) => assert_eq!(s, "Nothing pinned."),
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn local_deletes_follow_the_deletes_policy() {
+    let h = Harness::start_with_config("");
+    fs::create_dir_all(h.local("scratch")).unwrap();
+    let mut urls = Vec::new();
+    for (id, name) in &[("a1", "a.txt"), ("b1", "scratch/b.txt"), ("c1", "c.txt")] {
+        let url = h.put_remote(id, name, "v1");
+        fs::write(h.local(name), "v1").unwrap();
+        assert!(is_ok(&h.send(DCommand::FSync(
+            h.local(name),
+            url.clone(),
+            None
+        ))));
+        urls.push(url);
+    }
+    let config = format!(
+        "[deletes]\ndefault = \"trash\"\n\n[deletes.paths]\n{:?} = \"delete\"\n",
+        h.local("scratch")
+    );
+    let h = h.restart_with_config(&config);
+    let remote = h.dir.path().join("remote");
+    assert!(wait_for(|| h.log().contains("Watching 3 tracked files")));
+
+    // Deleted files go to the trash, or for good beneath scratch. One moved away keeps its Drive copy.
+    fs::remove_file(h.local("a.txt")).unwrap();
+    fs::remove_file(h.local("scratch/b.txt")).unwrap();
+    fs::rename(h.local("c.txt"), h.dir.path().join("c.txt")).unwrap();
+    thread::sleep(Duration::from_secs(4));
+    assert!(
+        wait_for(|| remote.join(".trash/a1").exists()),
+        "{}",
+        h.log()
+    );
+    assert!(h.remote(&urls[0]).is_none());
+    assert!(wait_for(|| h.remote(&urls[1]).is_none()), "{}", h.log());
+    assert!(!remote.join(".trash/b1").exists());
+    assert!(!remote.join("b1.name").exists());
+    assert!(wait_for(|| tracked_url(&h, &h.local("c.txt")).is_none()));
+    assert_eq!(h.remote(&urls[2]).as_deref(), Some("v1"));
+}
+
+#[test]
+fn local_deletes_keep_drive_copies_outside_the_allowed_folder() {
+    let h = Harness::start_with_config("");
+    h.put_remote("folder1", "Work", "");
+    let mut urls = Vec::new();
+    for (id, name) in &[("in1", "in.txt"), ("out1", "out.txt")] {
+        let url = h.put_remote(id, name, "v1");
+        fs::write(h.local(name), "v1").unwrap();
+        assert!(is_ok(&h.send(DCommand::FSync(
+            h.local(name),
+            url.clone(),
+            None
+        ))));
+        urls.push(url);
+    }
+    fs::write(h.dir.path().join("remote/in1.parent"), "folder1").unwrap();
+    let h = h.restart_with_config(
+        "[policy]\nallowed_folder = \"folder1\"\n\n[deletes]\ndefault = \"delete\"\n",
+    );
+    assert!(wait_for(|| h.log().contains("Watching 2 tracked files")));
+
+    fs::remove_file(h.local("in.txt")).unwrap();
+    fs::remove_file(h.local("out.txt")).unwrap();
+    assert!(wait_for(|| h.remote(&urls[0]).is_none()), "{}", h.log());
+    assert!(
+        wait_for(|| h.log().contains("Keeping the Drive copy of")),
+        "{}",
+        h.log()
+    );
+    assert_eq!(h.remote(&urls[1]).as_deref(), Some("v1"));
+    // Nothing is left queued to try again.
+    match h.send(DCommand::Queue) {
+        DResult::Ok(s) => assert_eq!(s, "Nothing queued."),
+        r => panic!("{:?}", r),
+    }
+}
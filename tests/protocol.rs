@@ -0,0 +1,232 @@
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+use proptest::prelude::*;
+use rgdrive::export::Export;
+use rgdrive::transfer::Overwrite;
+use rgdrive::{
+    decode, decode_response, encode, encode_response, read_frame, DCommand, DResult, ProtocolError,
+    WatchEvent, HELLO,
+};
+
+const LIMIT: u64 = 64 * 1024;
+
+fn any_export() -> impl Strategy<Value = Export> {
+    (".*", proptest::option::of(".*"), proptest::option::of(".*")).prop_map(
+        |(format, sheet, range)| Export {
+            format,
+            sheet,
+            range,
+        },
+    )
+}
+
+fn any_overwrite() -> impl Strategy<Value = Overwrite> {
+    prop_oneof![
+        Just(Overwrite::Never),
+        Just(Overwrite::RemoteNewer),
+        Just(Overwrite::LocalUnmodified),
+        Just(Overwrite::Always),
+    ]
+}
+
+// Any file name bytes, UTF-8 or not.
+fn any_path() -> impl Strategy<Value = PathBuf> {
+    prop::collection::vec(any::<u8>(), 0..32).prop_map(|b| PathBuf::from(OsString::from_vec(b)))
+}
+
+fn any_event() -> impl Strategy<Value = WatchEvent> {
+    proptest::sample::select(WatchEvent::ALL.to_vec())
+}
+
+fn any_command() -> impl Strategy<Value = DCommand> {
+    prop_oneof![
+        (".*", ".*", any_overwrite(), any::<bool>()).prop_map(|(u, p, o, r)| DCommand::Pull(
+            u,
+            PathBuf::from(p),
+            o,
+            r
+        )),
+        (".*", any::<bool>()).prop_map(|(p, e)| DCommand::Push(PathBuf::from(p), e)),
+        (".*", ".*", any::<bool>()).prop_map(|(p, d, e)| DCommand::PushTo(PathBuf::from(p), d, e)),
+        (".*", any::<bool>()).prop_map(|(p, e)| DCommand::Plan(PathBuf::from(p), e)),
+        (
+            ".*",
+            proptest::option::of(".*"),
+            proptest::option::of(".*"),
+            any::<bool>()
+        )
+            .prop_map(|(p, d, n, e)| DCommand::PushWait(PathBuf::from(p), d, n, e)),
+        (".*", ".*", proptest::option::of(".*")).prop_map(|(p, n, d)| DCommand::PushAs(
+            PathBuf::from(p),
+            n,
+            d
+        )),
+        (".*", proptest::option::of(".*"), any::<bool>()).prop_map(|(p, d, e)| DCommand::TrackDir(
+            PathBuf::from(p),
+            d,
+            e
+        )),
+        (".*", ".*").prop_map(|(p, n)| DCommand::RenameRemote(PathBuf::from(p), n)),
+        proptest::collection::vec(".*", 0..20)
+            .prop_map(|ps| DCommand::PathStatusBatch(ps.into_iter().map(PathBuf::from).collect())),
+        Just(DCommand::Pending),
+        Just(DCommand::Queue),
+        any::<u64>().prop_map(DCommand::QueueDrop),
+        any::<u64>().prop_map(DCommand::QueueRetry),
+        proptest::option::of(".*").prop_map(|p| DCommand::Approve(p.map(PathBuf::from))),
+        (
+            ".*",
+            ".*",
+            proptest::option::of(proptest::collection::vec(any_event(), 0..6))
+        )
+            .prop_map(|(p, u, e)| DCommand::FSync(PathBuf::from(p), u, e)),
+        ".*".prop_map(|p| DCommand::FUnSync(PathBuf::from(p))),
+        Just(DCommand::Stats),
+        Just(DCommand::Health),
+        ".*".prop_map(DCommand::Activity),
+        ".*".prop_map(|p| DCommand::PullStarred(PathBuf::from(p))),
+        ".*".prop_map(|p| DCommand::RebuildState(PathBuf::from(p))),
+        (".*", ".*", any::<bool>()).prop_map(|(u, p, h)| DCommand::PullDir(u, PathBuf::from(p), h)),
+        ".*".prop_map(|p| DCommand::Hydrate(PathBuf::from(p))),
+        (".*", ".*", any::<bool>(), any_export()).prop_map(|(u, p, o, e)| DCommand::Export(
+            u,
+            PathBuf::from(p),
+            o,
+            e
+        )),
+        ".*".prop_map(DCommand::Share),
+        Just(DCommand::Ready),
+        Just(DCommand::Login),
+        Just(DCommand::Capabilities),
+        Just(DCommand::Drives),
+        Just(DCommand::Account),
+        Just(DCommand::Env),
+        proptest::option::of(".*").prop_map(DCommand::SetEnv),
+        (".*", proptest::option::of(".*")).prop_map(|(u, f)| DCommand::Shortcut(u, f)),
+        proptest::option::of(".*").prop_map(DCommand::Ls),
+        (".*", proptest::option::of(".*"))
+            .prop_map(|(u, p)| DCommand::Pin(u, p.map(PathBuf::from))),
+        ".*".prop_map(DCommand::Unpin),
+        Just(DCommand::Pins),
+        Just(DCommand::None),
+        ".*".prop_map(DCommand::Message),
+        Just(DCommand::Ok),
+        Just(DCommand::Quit),
+    ]
+}
+
+fn any_result() -> impl Strategy<Value = DResult> {
+    prop_oneof![
+        ".*".prop_map(DResult::Ok),
+        ".*".prop_map(DResult::Err),
+        (".*", prop::collection::vec((".*", ".*"), 0..4)).prop_map(|(m, f)| DResult::Fields(m, f)),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn command_roundtrip(cmd in any_command()) {
+        let buf = encode(&cmd, LIMIT).unwrap();
+        prop_assert_eq!(decode::<DCommand>(&buf, LIMIT).unwrap(), cmd);
+    }
+
+    #[test]
+    fn result_roundtrip(r in any_result()) {
+        let buf = encode(&r, LIMIT).unwrap();
+        prop_assert_eq!(decode::<DResult>(&buf, LIMIT).unwrap(), r);
+    }
+
+    #[test]
+    fn compressed_result_roundtrip(r in any_result(), above in 0..64u64) {
+        let buf = encode_response(&r, LIMIT, Some(above)).unwrap();
+        prop_assert!(buf.len() <= encode(&r, LIMIT).unwrap().len());
+        prop_assert_eq!(decode_response(&buf, LIMIT).unwrap(), r);
+    }
+
+    #[test]
+    fn hello_is_stripped(cmd in any_command(), flags in any::<u32>()) {
+        let mut buf = HELLO.to_vec();
+        buf.extend_from_slice(&flags.to_le_bytes());
+        buf.extend_from_slice(&encode(&cmd, LIMIT).unwrap());
+        prop_assert_eq!(DCommand::from_request(&buf[..], LIMIT).unwrap(), (cmd, flags));
+    }
+
+    #[test]
+    fn byte_paths_roundtrip(p in any_path(), batch in prop::collection::vec(any_path(), 0..4)) {
+        for cmd in [
+            DCommand::Push(p.clone(), false),
+            DCommand::Approve(Some(p)),
+            DCommand::PathStatusBatch(batch),
+        ] {
+            let buf = encode(&cmd, LIMIT).unwrap();
+            prop_assert_eq!(decode::<DCommand>(&buf, LIMIT).unwrap(), cmd);
+        }
+    }
+
+    #[test]
+    fn truncated_command_is_an_error(cmd in any_command(), cut in any::<prop::sample::Index>()) {
+        let buf = encode(&cmd, LIMIT).unwrap();
+        let cut = cut.index(buf.len());
+        prop_assert!(decode::<DCommand>(&buf[..cut], LIMIT).is_err());
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(buf in prop::collection::vec(any::<u8>(), 0..512)) {
+        let _ = decode::<DCommand>(&buf, LIMIT);
+        let _ = decode::<DResult>(&buf, LIMIT);
+        let _ = DCommand::from_stream(&buf[..], LIMIT);
+        let _ = decode_response(&buf, LIMIT);
+    }
+}
+
+#[test]
+fn empty_stream_is_none() {
+    assert_eq!(
+        DCommand::from_stream(&b""[..], LIMIT).unwrap(),
+        DCommand::None
+    );
+}
+
+#[test]
+fn oversized_frame_is_rejected() {
+    let buf = vec![0u8; LIMIT as usize + 1];
+    match read_frame(&buf[..], LIMIT) {
+        Err(ProtocolError::TooLarge(l)) => assert_eq!(l, LIMIT),
+        r => panic!("expected TooLarge, got {:?}", r),
+    }
+    match DCommand::from_stream(&buf[..], LIMIT) {
+        Err(ProtocolError::TooLarge(_)) => {}
+        r => panic!("expected TooLarge, got {:?}", r),
+    }
+}
+
+#[test]
+fn compressed_response_is_bounded_unpacked() {
+    let r = DResult::ok("x".repeat(LIMIT as usize / 2));
+    let buf = encode_response(&r, LIMIT, Some(0)).unwrap();
+    assert!((buf.len() as u64) < LIMIT);
+    match decode_response(&buf, LIMIT / 4) {
+        Err(ProtocolError::TooLarge(_)) => {}
+        r => panic!("expected TooLarge, got {:?}", r),
+    }
+}
+
+#[test]
+fn oversized_message_is_not_encoded() {
+    let cmd = DCommand::Message("x".repeat(LIMIT as usize));
+    match encode(&cmd, LIMIT) {
+        Err(ProtocolError::TooLarge(_)) => {}
+        r => panic!("expected TooLarge, got {:?}", r),
+    }
+}
+
+#[test]
+fn huge_length_prefix_does_not_allocate() {
+    // Message variant followed by a string length of u64::MAX.
+    let mut buf = encode(&DCommand::Message(String::new()), LIMIT).unwrap();
+    let len = buf.len();
+    buf[len - 8..].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(decode::<DCommand>(&buf, LIMIT).is_err());
+}